@@ -54,7 +54,7 @@ async fn main() -> Result<()> {
         temperature: Some(0.8),
         top_p: None,
         timeout: Some(Duration::from_secs(30)),
-        functions: None,
+        ..Default::default()
     };
     
     let response = client.generate_with_options(prompt, options).await?;
@@ -47,14 +47,14 @@ async fn main() -> Result<()> {
         temperature: Some(0.7),
         top_p: None,
         timeout: None,
-        functions: None,
+        ..Default::default()
     };
     
     // Step 4: Start timing the response
     let start_time = Instant::now();
     
     // Step 5: Generate a streaming response
-    let mut stream = client.generate_streaming_with_options(prompt, options).await?;
+    let mut stream = client.generate_streaming_with_options(prompt, options.clone()).await?;
     
     // Step 6: Process the stream chunks as they arrive
     println!("Receiving streaming response:\n");
@@ -1,5 +1,5 @@
 use crate::error::{Result, ToolkitError};
-use crate::utils::rate_limiter;
+use crate::utils::{json, rate_limiter};
 use crate::utils::logging;
 use crate::config;
 use async_trait::async_trait;
@@ -8,8 +8,9 @@ use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use super::{RequestOptions, FunctionDefinition, SHARED_HTTP_CLIENT, headers};
+use super::{GenerateResponse, JsonResponse, Message, RequestOptions, FunctionDefinition, TokenUsage, headers};
 
 // Define constants for hardcoded values
 /// The default Anthropic API version
@@ -20,21 +21,36 @@ pub const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
 pub const ANTHROPIC_API_KEY_PREFIX: &str = "sk-ant-";
 /// Content type for text
 pub const CONTENT_TYPE_TEXT: &str = "text";
-/// Role for user messages
-pub const ROLE_USER: &str = "user";
+/// Content type for a tool call in a response
+pub const CONTENT_TYPE_TOOL_USE: &str = "tool_use";
+/// Response length used when the request doesn't set one; the Messages API requires it
+pub const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 4096;
+/// Name of the tool used to force schema-conformant JSON output
+const STRUCTURED_OUTPUT_TOOL: &str = "structured_output";
+/// Value of the `anthropic-beta` header enabling prompt caching
+pub const PROMPT_CACHING_BETA: &str = "prompt-caching-2024-07-31";
 
+#[derive(Clone)]
 pub struct AnthropicClient {
     api_key: String,
     model: String,
     base_url: String,
     api_version: String,
+    /// HTTP client with the configured request timeout
+    http: reqwest::Client,
+    /// Model retried with when Anthropic doesn't know `model`
+    fallback_model: Option<String>,
+    /// The fallback model once it has replaced `model`, so later requests go straight to it
+    switched_model: Arc<Mutex<Option<String>>>,
 }
 
 #[derive(Serialize)]
 struct AnthropicRequest {
     model: String,
-    max_tokens: Option<u32>,
-    messages: Vec<Message>,
+    max_tokens: u32,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<SystemPrompt>,
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -42,11 +58,56 @@ struct AnthropicRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<FunctionDefinition>>,
+    tools: Option<Vec<AnthropicTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    /// Whether the request marks content for prompt caching, which needs the beta header
+    #[serde(skip)]
+    prompt_caching: bool,
+}
+
+/// The system prompt: plain text, or a text block when it is marked for caching
+#[derive(Serialize)]
+#[serde(untagged)]
+enum SystemPrompt {
+    Text(String),
+    Blocks(Vec<Content>),
+}
+
+/// Marks the end of a prompt prefix Anthropic should cache
+#[derive(Serialize)]
+struct CacheControl {
+    #[serde(rename = "type")]
+    cache_type: &'static str,
 }
 
+impl CacheControl {
+    /// The only cache type: kept for a few minutes after its last use
+    const EPHEMERAL: CacheControl = CacheControl { cache_type: "ephemeral" };
+}
+
+/// A tool definition in the shape the Messages API expects
 #[derive(Serialize)]
-struct Message {
+struct AnthropicTool {
+    name: String,
+    description: String,
+    input_schema: Value,
+}
+
+impl From<&FunctionDefinition> for AnthropicTool {
+    fn from(function: &FunctionDefinition) -> Self {
+        Self {
+            name: function.name.clone(),
+            description: function.description.clone(),
+            input_schema: function.parameters.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AnthropicMessage {
     role: String,
     content: Vec<Content>,
 }
@@ -56,23 +117,63 @@ struct Content {
     #[serde(rename = "type")]
     content_type: String,
     text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+impl Content {
+    fn text(text: &str) -> Self {
+        Self {
+            content_type: CONTENT_TYPE_TEXT.to_string(),
+            text: text.to_string(),
+            cache_control: None,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct AnthropicResponse {
     content: Vec<ResponseContent>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl From<&AnthropicUsage> for TokenUsage {
+    fn from(usage: &AnthropicUsage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct ResponseContent {
     #[serde(rename = "type")]
     content_type: String,
+    /// Set on `text` blocks
+    #[serde(default)]
     text: String,
+    /// Set on `tool_use` blocks
+    #[serde(default)]
+    input: Option<Value>,
 }
 
+/// A single server-sent event from the streaming Messages API
 #[derive(Deserialize)]
 struct StreamingResponse {
-    delta: StreamingDelta,
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    delta: Option<StreamingDelta>,
+    #[serde(default)]
+    error: Option<Value>,
 }
 
 #[derive(Deserialize)]
@@ -82,7 +183,8 @@ struct StreamingDelta {
 }
 
 impl AnthropicClient {
-    pub fn new(api_key: &str, model: &str) -> Result<Self> {
+    /// Create a client, sending requests to `base_url` if set, e.g. a proxy or gateway
+    pub fn new(api_key: &str, model: &str, base_url: Option<&str>) -> Result<Self> {
         if api_key.is_empty() {
             error!("Anthropic API key is not configured");
             return Err(ToolkitError::Config(
@@ -97,63 +199,176 @@ impl AnthropicClient {
         }
         
         let config = config::get_config()?;
-        let base_url = config.base_url.unwrap_or_else(|| 
-            ANTHROPIC_BASE_URL.to_string()
-        );
+        let base_url = super::resolve_base_url(base_url, ANTHROPIC_BASE_URL);
         
         let api_version = ANTHROPIC_API_VERSION.to_string();
 
+        let http = super::build_http_client(Duration::from_secs(config.request_timeout_secs))?;
+        let fallback_model = config.fallback_models.get("anthropic").cloned();
+        
         Ok(Self {
             api_key: api_key.to_string(),
             model: model.to_string(),
             base_url,
             api_version,
+            http,
+            fallback_model,
+            switched_model: Arc::new(Mutex::new(None)),
         })
     }
     
+    /// The model requests are sent to: `model`, or its fallback once Anthropic didn't know it
+    fn request_model(&self) -> String {
+        let switched = self.switched_model.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        switched.clone().unwrap_or_else(|| self.model.clone())
+    }
+    
+    /// URL of the Messages API under the client's base URL
+    fn messages_url(&self) -> String {
+        format!("{}/messages", self.base_url)
+    }
+    
     fn create_request_body(&self, prompt: &str, options: &RequestOptions, stream: bool) -> AnthropicRequest {
-        let content = Content {
-            content_type: CONTENT_TYPE_TEXT.to_string(),
-            text: prompt.to_string(),
-        };
+        self.create_chat_request_body(&[Message::user(prompt)], options, stream)
+    }
+    
+    /// Build the request for a conversation
+    ///
+    /// With [`RequestOptions::provider_cache`], cache breakpoints are put after
+    /// the system prompt and after the last message, so a later request that
+    /// starts the same way, such as a rerun or the next chat turn, reads that
+    /// prefix from Anthropic's cache.
+    fn create_chat_request_body(&self, messages: &[Message], options: &RequestOptions, stream: bool) -> AnthropicRequest {
+        let mut messages: Vec<AnthropicMessage> = messages
+            .iter()
+            .map(|message| AnthropicMessage {
+                role: message.role.as_str().to_string(),
+                content: vec![Content::text(&message.content)],
+            })
+            .collect();
         
-        let message = Message {
-            role: ROLE_USER.to_string(),
-            content: vec![content],
+        let system = match &options.system {
+            Some(system) if options.provider_cache => Some(SystemPrompt::Blocks(vec![Content {
+                cache_control: Some(CacheControl::EPHEMERAL),
+                ..Content::text(system)
+            }])),
+            Some(system) => Some(SystemPrompt::Text(system.clone())),
+            None => None,
         };
+        if options.seed.is_some() {
+            warn!("Anthropic doesn't support seeds; ignoring the seed, so the response may vary");
+        }
+        if options.provider_cache {
+            if let Some(block) = messages.last_mut().and_then(|message| message.content.last_mut()) {
+                block.cache_control = Some(CacheControl::EPHEMERAL);
+            }
+        }
         
-        // Use references instead of cloning when possible
         AnthropicRequest {
-            model: self.model.clone(),
-            max_tokens: options.max_tokens,
-            messages: vec![message],
+            model: self.request_model(),
+            max_tokens: options.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+            messages,
+            system,
             temperature: options.temperature,
             top_p: options.top_p,
             stream: Some(stream),
-            tools: options.functions.clone(),
+            tools: options
+                .functions
+                .as_ref()
+                .map(|functions| functions.iter().map(AnthropicTool::from).collect()),
+            tool_choice: None,
+            // Anthropic has no logit bias, so only the stop sequences carry over
+            stop_sequences: options.stop.clone(),
+            prompt_caching: options.provider_cache,
         }
     }
+    
+    /// Send a streaming request for a conversation and decode the text chunks
+    async fn open_stream(&self, messages: &[Message], options: &RequestOptions) -> Result<super::resume::TextStream> {
+        let request = self.create_chat_request_body(messages, options, true);
+        // A stream reports its usage at the end, so count the most it can use
+        super::record_completion_tokens("anthropic", None, Some(request.max_tokens));
+        let response = self.send_request(request, true, options.timeout).await?;
+        
+        let stream = super::sse::data_events(response.bytes_stream())
+            .map(|event| event.and_then(|data| parse_stream_event(&data)));
+        
+        Ok(Box::pin(stream))
+    }
+    
+    /// Send a non-streaming request and parse the response body
+    async fn send_and_parse(&self, request: AnthropicRequest, timeout: Option<Duration>) -> Result<AnthropicResponse> {
+        let max_tokens = request.max_tokens;
+        let response = self.send_request(request, false, timeout).await?;
+        
+        let response: AnthropicResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse Anthropic API response: {}", e);
+            ToolkitError::Parse(e.to_string())
+        })?;
+        let usage = response.usage.as_ref().map(TokenUsage::from);
+        super::record_completion_tokens("anthropic", usage.as_ref(), Some(max_tokens));
+        Ok(response)
+    }
+    
+    /// Send a non-streaming request and read the reply and its token usage
+    async fn complete(&self, request: AnthropicRequest, timeout: Option<Duration>) -> Result<GenerateResponse> {
+        let response_data = self.send_and_parse(request, timeout).await?;
+        
+        if response_data.content.is_empty() {
+            return Err(ToolkitError::Api("No response from Anthropic API".to_string()));
+        }
+        
+        Ok(GenerateResponse {
+            text: response_data.content[0].text.clone(),
+            usage: response_data.usage.as_ref().map(TokenUsage::from),
+        })
+    }
 
+    /// Send a request, retrying once with the fallback model if Anthropic
+    /// doesn't know the requested one
+    ///
+    /// Once the fallback model works, the client keeps using it, so the
+    /// switch is only made and logged once.
     async fn send_request(
         &self,
-        request: AnthropicRequest,
+        mut request: AnthropicRequest,
         streaming: bool,
         timeout: Option<Duration>,
     ) -> Result<reqwest::Response> {
-        // Use the shared HTTP client
-        let client = &*SHARED_HTTP_CLIENT;
+        match self.send_once(&request, streaming, timeout).await {
+            Err(ToolkitError::ModelNotFound { model, .. }) => {
+                let fallback = super::fallback_model("Anthropic", &model, self.fallback_model.as_deref())?;
+                request.model = fallback.to_string();
+                let response = self.send_once(&request, streaming, timeout)
+                    .await
+                    .map_err(|e| super::fallback_failed(e, &model, fallback))?;
+                *self.switched_model.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(fallback.to_string());
+                Ok(response)
+            }
+            result => result,
+        }
+    }
+
+    async fn send_once(
+        &self,
+        request: &AnthropicRequest,
+        streaming: bool,
+        timeout: Option<Duration>,
+    ) -> Result<reqwest::Response> {
+        let client = &self.http;
         
         // Check rate limits
+        // Refused locally, so this mustn't look like the provider's 429
         if !rate_limiter::can_make_request("anthropic") {
-            return Err(ToolkitError::RateLimit(
-                "Anthropic API rate limit exceeded. Please try again later.".to_string(),
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for Anthropic API. Please try again later.".to_string(),
             ));
         }
         
         // Record this request
         rate_limiter::record_request("anthropic");
         
-        let url = format!("{}/messages", self.base_url);
+        let url = self.messages_url();
         
         let mut builder = client.post(&url)
             .header(headers::X_API_KEY, &self.api_key)
@@ -168,8 +383,12 @@ impl AnthropicClient {
             builder = builder.header(headers::ACCEPT, headers::TEXT_EVENT_STREAM);
         }
         
+        if request.prompt_caching {
+            builder = builder.header(headers::ANTHROPIC_BETA, PROMPT_CACHING_BETA);
+        }
+        
         let response = builder
-            .json(&request)
+            .json(request)
             .send()
             .await
             .map_err(|e| {
@@ -182,20 +401,14 @@ impl AnthropicClient {
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            error!("Anthropic API error: {} - {}", status, error_text);
+            error!("Anthropic API error: {}", status);
             
-            // Check if this is a rate limit error
-            if status.as_u16() == 429 {
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
                 // Record a rate limit failure for a longer backoff
                 rate_limiter::record_rate_limit("anthropic");
-                return Err(ToolkitError::RateLimit(
-                    "Anthropic API rate limit exceeded. Please wait before making more requests.".to_string()
-                ));
             }
             
-            return Err(ToolkitError::Api(
-                format!("Anthropic API error: {} - {}", status, error_text)
-            ));
+            return Err(super::response_error("Anthropic", &request.model, status, &error_text));
         }
         
         Ok(response)
@@ -205,7 +418,7 @@ impl AnthropicClient {
 #[async_trait]
 impl super::AiClient for AnthropicClient {
     fn model_version(&self) -> &str {
-        &self.api_version
+        &self.model
     }
 
     fn base_url(&self) -> &str {
@@ -218,6 +431,10 @@ impl super::AiClient for AnthropicClient {
     }
 
     async fn generate_with_options(&self, prompt: &str, options: RequestOptions) -> Result<String> {
+        self.generate_with_usage(prompt, options).await.map(|response| response.text)
+    }
+
+    async fn generate_with_usage(&self, prompt: &str, options: RequestOptions) -> Result<GenerateResponse> {
         debug!("Generating response with model: {}", self.model);
         
         if !rate_limiter::can_make_request("anthropic") {
@@ -227,21 +444,11 @@ impl super::AiClient for AnthropicClient {
             ));
         }
         
+        super::reserve_prompt_tokens("anthropic", &self.model, prompt, &options)?;
         rate_limiter::record_request("anthropic");
         
         let request = self.create_request_body(prompt, &options, false);
-        let response = self.send_request(request, false, options.timeout).await?;
-        
-        let response_data: AnthropicResponse = response.json().await.map_err(|e| {
-            error!("Failed to parse Anthropic API response: {}", e);
-            ToolkitError::Parse(e.to_string())
-        })?;
-        
-        if response_data.content.is_empty() {
-            return Err(ToolkitError::Api("No response from Anthropic API".to_string()));
-        }
-        
-        Ok(response_data.content[0].text.clone())
+        self.complete(request, options.timeout).await
     }
 
     async fn generate_streaming(&self, prompt: &str) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
@@ -255,6 +462,7 @@ impl super::AiClient for AnthropicClient {
         options: RequestOptions,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
         debug!("Generating streaming response with model: {}", self.model);
+        super::require_capability(&self.model, super::Capability::Streaming)?;
         
         if !rate_limiter::can_make_request("anthropic") {
             warn!("Rate limit exceeded for Anthropic API");
@@ -263,29 +471,38 @@ impl super::AiClient for AnthropicClient {
             ));
         }
         
+        super::reserve_prompt_tokens("anthropic", &self.model, prompt, &options)?;
         rate_limiter::record_request("anthropic");
         
-        let request = self.create_request_body(prompt, &options, true);
-        let response = self.send_request(request, true, options.timeout).await?;
+        let messages = vec![Message::user(prompt)];
+        let stream = self.open_stream(&messages, &options).await?;
         
-        let stream = response.bytes_stream().map(|result| {
-            result.map_err(|e| ToolkitError::Network(e.to_string()))
-                .and_then(|bytes| {
-                    let text = String::from_utf8(bytes.to_vec())
-                        .map_err(|e| ToolkitError::Parse(e.to_string()))?;
-                    
-                    if text.trim().is_empty() {
-                        return Ok("".to_string());
-                    }
-                    
-                    let response: StreamingResponse = serde_json::from_str(&text)
-                        .map_err(|e| ToolkitError::Parse(e.to_string()))?;
-                    
-                    Ok(response.delta.text.clone())
-                })
-        });
+        // A dropped connection is resumed by prefilling the partial reply
+        let client = self.clone();
+        let resume = move |messages: Vec<Message>| {
+            let client = client.clone();
+            let options = options.clone();
+            async move { client.open_stream(&messages, &options).await }
+        };
         
-        Ok(Box::pin(stream))
+        Ok(super::resume::resumable(stream, messages, resume, super::resume::MAX_STREAM_RESUMES))
+    }
+
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        debug!("Generating chat response with model: {}", self.model);
+        
+        if !rate_limiter::can_make_request("anthropic") {
+            warn!("Rate limit exceeded for Anthropic API");
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for Anthropic API. Please try again later.".to_string(),
+            ));
+        }
+        
+        super::reserve_prompt_tokens("anthropic", &self.model, &super::transcript_prompt(messages), &options)?;
+        rate_limiter::record_request("anthropic");
+        
+        let request = self.create_chat_request_body(messages, &options, false);
+        self.complete(request, options.timeout).await.map(|response| response.text)
     }
 
     async fn generate_json(&self, prompt: &str) -> Result<Value> {
@@ -294,7 +511,349 @@ impl super::AiClient for AnthropicClient {
     }
 
     async fn generate_json_with_options(&self, prompt: &str, options: RequestOptions) -> Result<Value> {
-        let text = self.generate_with_options(prompt, options).await?;
-        serde_json::from_str(&text).map_err(|e| ToolkitError::Parse(e.to_string()))
+        if options.functions.is_none() {
+            let text = self.generate_with_options(prompt, options).await?;
+            return json::extract_json(&text);
+        }
+        
+        // With tools offered, the answer is the input of the tool the model calls
+        if !rate_limiter::can_make_request("anthropic") {
+            warn!("Rate limit exceeded for Anthropic API");
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for Anthropic API. Please try again later.".to_string(),
+            ));
+        }
+        
+        super::reserve_prompt_tokens("anthropic", &self.model, prompt, &options)?;
+        rate_limiter::record_request("anthropic");
+        
+        let request = self.create_request_body(prompt, &options, false);
+        let response = self.send_and_parse(request, options.timeout).await?;
+        tool_input(response)
+    }
+
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        self.generate_json_schema_with_usage(prompt, schema, options).await.map(|response| response.value)
+    }
+
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        debug!("Generating schema-constrained JSON with model: {}", self.model);
+        super::require_capability(&self.model, super::Capability::Functions)?;
+        
+        if !rate_limiter::can_make_request("anthropic") {
+            warn!("Rate limit exceeded for Anthropic API");
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for Anthropic API. Please try again later.".to_string(),
+            ));
+        }
+        
+        super::reserve_prompt_tokens("anthropic", &self.model, prompt, &options)?;
+        rate_limiter::record_request("anthropic");
+        
+        // Anthropic has no JSON mode, so offer a single tool whose input is the
+        // schema and force the model to call it
+        let mut request = self.create_request_body(prompt, &options, false);
+        force_structured_output(&mut request, &schema);
+        
+        let response = self.send_and_parse(request, options.timeout).await?;
+        let usage = response.usage.as_ref().map(TokenUsage::from);
+        let value = tool_input(response)?;
+        super::validate_json_schema(&value, &schema)?;
+        Ok(JsonResponse { value, usage })
+    }
+}
+
+/// Make the request answer by calling a tool whose input follows the schema
+fn force_structured_output(request: &mut AnthropicRequest, schema: &Value) {
+    request.tools = Some(vec![AnthropicTool {
+        name: STRUCTURED_OUTPUT_TOOL.to_string(),
+        description: "Respond with data matching the input schema".to_string(),
+        input_schema: schema.clone(),
+    }]);
+    request.tool_choice = Some(serde_json::json!({
+        "type": "tool",
+        "name": STRUCTURED_OUTPUT_TOOL,
+    }));
+}
+
+/// Read the input of the first tool the response calls, e.g. the structured output
+fn tool_input(response: AnthropicResponse) -> Result<Value> {
+    response
+        .content
+        .into_iter()
+        .find(|content| content.content_type == CONTENT_TYPE_TOOL_USE)
+        .and_then(|content| content.input)
+        .ok_or_else(|| ToolkitError::Parse("Anthropic response did not include a tool call".to_string()))
+}
+
+/// Extract the text from a streaming event, ignoring events that carry none
+fn parse_stream_event(data: &str) -> Result<String> {
+    let event: StreamingResponse = serde_json::from_str(data)
+        .map_err(|e| ToolkitError::Parse(e.to_string()))?;
+    
+    match event.event_type.as_str() {
+        "content_block_delta" => Ok(event.delta.map(|delta| delta.text).unwrap_or_default()),
+        "error" => {
+            let error = event.error.unwrap_or_default();
+            let describe = |error: &Value| super::describe_error(error).unwrap_or_else(|| error.to_string());
+            // Errors sent mid-stream carry a type instead of a status code
+            let status = match error["type"].as_str() {
+                Some("overloaded_error") => 529,
+                Some("api_error") => 500,
+                _ => return Err(ToolkitError::Api(format!("Anthropic API error: {}", describe(&error)))),
+            };
+            Err(ToolkitError::ServerError(status, format!("Anthropic API: {}", describe(&error))))
+        }
+        _ => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_client() -> AnthropicClient {
+        AnthropicClient {
+            api_key: "sk-ant-test".to_string(),
+            model: "claude-3-7-sonnet-20250219".to_string(),
+            base_url: ANTHROPIC_BASE_URL.to_string(),
+            api_version: ANTHROPIC_API_VERSION.to_string(),
+            http: reqwest::Client::new(),
+            fallback_model: None,
+            switched_model: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    #[test]
+    fn test_requests_use_the_model_switched_to() {
+        let client = test_client();
+        assert_eq!(client.create_request_body("Hello", &RequestOptions::default(), false).model, "claude-3-7-sonnet-20250219");
+
+        *client.switched_model.lock().unwrap() = Some("claude-3-5-sonnet-latest".to_string());
+        let clone = client.clone();
+        assert_eq!(clone.create_request_body("Hello", &RequestOptions::default(), false).model, "claude-3-5-sonnet-latest");
+    }
+
+    #[test]
+    fn test_custom_base_url_used_for_requests() {
+        let client = AnthropicClient {
+            base_url: super::super::resolve_base_url(Some("https://gateway.example.com/anthropic/v1/"), ANTHROPIC_BASE_URL),
+            ..test_client()
+        };
+        assert_eq!(client.messages_url(), "https://gateway.example.com/anthropic/v1/messages");
+        assert_eq!(super::super::AiClient::base_url(&client), "https://gateway.example.com/anthropic/v1");
+
+        let client = AnthropicClient {
+            base_url: super::super::resolve_base_url(Some(""), ANTHROPIC_BASE_URL),
+            ..test_client()
+        };
+        assert_eq!(client.messages_url(), "https://api.anthropic.com/v1/messages");
+    }
+
+    #[test]
+    fn test_stop_sent_as_stop_sequences() {
+        let options = RequestOptions {
+            stop: Some(vec!["END".to_string()]),
+            logit_bias: Some([(1, 5)].into_iter().collect()),
+            seed: Some(42),
+            ..Default::default()
+        };
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &options, false)).unwrap();
+        assert_eq!(body["stop_sequences"], serde_json::json!(["END"]));
+        assert!(body.get("stop").is_none());
+        assert!(body.get("logit_bias").is_none());
+        assert!(body.get("seed").is_none());
+    }
+
+    #[test]
+    fn test_system_prompt_sent_as_top_level_parameter() {
+        let options = RequestOptions {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..Default::default()
+        };
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &options, false)).unwrap();
+
+        assert_eq!(body["system"], "You are a helpful assistant.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &RequestOptions::default(), false)).unwrap();
+        assert!(body.get("system").is_none());
+    }
+
+    #[test]
+    fn test_request_options_sent_in_body() {
+        let options = RequestOptions {
+            max_tokens: Some(1200),
+            temperature: Some(0.3),
+            top_p: Some(0.9),
+            system: Some("Be terse.".to_string()),
+            ..Default::default()
+        };
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &options, false)).unwrap();
+
+        assert_eq!(body["max_tokens"], 1200);
+        assert!((body["temperature"].as_f64().unwrap() - 0.3).abs() < 1e-6);
+        assert!((body["top_p"].as_f64().unwrap() - 0.9).abs() < 1e-6);
+        assert_eq!(body["system"], "Be terse.");
+        assert_eq!(body["stream"], false);
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &RequestOptions::default(), false)).unwrap();
+        assert_eq!(body["max_tokens"], ANTHROPIC_DEFAULT_MAX_TOKENS);
+        assert!(body.get("temperature").is_none() && body.get("top_p").is_none());
+    }
+
+    #[test]
+    fn test_provider_cache_marks_breakpoints() {
+        let options = RequestOptions {
+            system: Some("Be terse.".to_string()),
+            provider_cache: true,
+            ..Default::default()
+        };
+        let messages = vec![Message::user("Plan a todo app"), Message::assistant("A plan"), Message::user("Shorter")];
+
+        let request = test_client().create_chat_request_body(&messages, &options, false);
+        assert!(request.prompt_caching);
+        let body = serde_json::to_value(request).unwrap();
+        assert_eq!(body["system"][0]["text"], "Be terse.");
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert!(body["messages"][0]["content"][0].get("cache_control").is_none());
+        assert_eq!(body["messages"][2]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert!(body.get("prompt_caching").is_none());
+
+        let options = RequestOptions { system: Some("Be terse.".to_string()), ..Default::default() };
+        let body = serde_json::to_value(test_client().create_chat_request_body(&messages, &options, false)).unwrap();
+        assert_eq!(body["system"], "Be terse.");
+        assert!(body["messages"][2]["content"][0].get("cache_control").is_none());
+    }
+
+    #[test]
+    fn test_chat_history_sent_as_messages() {
+        let messages = vec![
+            Message::user("Plan a todo app"),
+            Message::assistant("Here is a plan"),
+            Message::user("Make it offline-first"),
+        ];
+
+        let body = serde_json::to_value(test_client().create_chat_request_body(&messages, &RequestOptions::default(), false)).unwrap();
+        let sent = body["messages"].as_array().unwrap();
+
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[1]["role"], "assistant");
+        assert_eq!(sent[2]["content"][0]["text"], "Make it offline-first");
+    }
+
+    #[test]
+    fn test_structured_output_forces_tool() {
+        let schema = serde_json::json!({"type": "object", "properties": {"name": {"type": "string"}}});
+        let mut request = test_client().create_request_body("Name it", &RequestOptions::default(), false);
+        force_structured_output(&mut request, &schema);
+
+        let body = serde_json::to_value(request).unwrap();
+        assert_eq!(body["tools"][0]["name"], STRUCTURED_OUTPUT_TOOL);
+        assert_eq!(body["tools"][0]["input_schema"], schema);
+        assert_eq!(body["tool_choice"]["type"], "tool");
+        assert_eq!(body["tool_choice"]["name"], STRUCTURED_OUTPUT_TOOL);
+    }
+
+    #[test]
+    fn test_structured_output_reads_tool_input() {
+        let response: AnthropicResponse = serde_json::from_str(r#"{
+            "content": [
+                {"type": "tool_use", "id": "toolu_1", "name": "structured_output", "input": {"name": "Taskly"}}
+            ],
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        }"#).unwrap();
+        assert_eq!(tool_input(response).unwrap(), serde_json::json!({"name": "Taskly"}));
+
+        let text_only: AnthropicResponse = serde_json::from_str(r#"{"content": [{"type": "text", "text": "Taskly"}]}"#).unwrap();
+        assert!(matches!(tool_input(text_only), Err(ToolkitError::Parse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_call_function_returns_tool_input() {
+        use super::super::AiClient;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Answers one request with a tool_use block, after reading the whole body
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            let body = loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|n| n.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break body.to_string();
+                    }
+                }
+            };
+            let reply = r#"{
+                "content": [
+                    {"type": "text", "text": "Checking the weather."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"location": "New York, NY"}}
+                ],
+                "usage": {"input_tokens": 12, "output_tokens": 8}
+            }"#;
+            let response = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", reply.len(), reply);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            serde_json::from_str::<Value>(&body).unwrap()
+        });
+
+        let client = AnthropicClient {
+            base_url: format!("http://{}", address),
+            ..test_client()
+        };
+        let function = FunctionDefinition {
+            name: "get_weather".to_string(),
+            description: "Get the current weather".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {"location": {"type": "string"}},
+                "required": ["location"]
+            }),
+        };
+
+        let value = client.call_function("What's the weather in New York?", function).await.unwrap();
+        assert_eq!(value, serde_json::json!({"location": "New York, NY"}));
+
+        let sent = server.await.unwrap();
+        assert_eq!(sent["tools"][0]["name"], "get_weather");
+        assert_eq!(sent["tools"][0]["input_schema"]["required"][0], "location");
+    }
+
+    #[test]
+    fn test_parse_stream_event() {
+        let delta = r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#;
+        assert_eq!(parse_stream_event(delta).unwrap(), "Hello");
+
+        let ping = r#"{"type":"ping"}"#;
+        assert_eq!(parse_stream_event(ping).unwrap(), "");
+
+        let overloaded = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        assert!(matches!(parse_stream_event(overloaded), Err(ToolkitError::ServerError(529, _))));
+
+        let invalid = r#"{"type":"error","error":{"type":"invalid_request_error","message":"Bad"}}"#;
+        assert!(matches!(parse_stream_event(invalid), Err(ToolkitError::Api(_))));
     }
 }
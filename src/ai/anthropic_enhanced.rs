@@ -11,6 +11,9 @@ use std::time::Duration;
 pub struct EnhancedAnthropicClient {
     api_key: String,
     model: String,
+    base_url: String,
+    /// HTTP client with the configured request timeout
+    http: reqwest::Client,
 }
 
 #[derive(Serialize)]
@@ -76,7 +79,7 @@ struct ResponseContent {
 }
 
 impl EnhancedAnthropicClient {
-    pub fn new(api_key: &str, model: &str) -> Result<Self> {
+    pub fn new(api_key: &str, model: &str, base_url: Option<&str>) -> Result<Self> {
         if api_key.is_empty() {
             error!("Anthropic API key is not configured");
             return Err(ToolkitError::Config(
@@ -93,9 +96,14 @@ impl EnhancedAnthropicClient {
         debug!("Creating Enhanced Anthropic client with API key: {} and model: {}", 
             config::mask_api_key(api_key), model);
         
+        let config = config::get_config()?;
+        let http = super::build_http_client(Duration::from_secs(config.request_timeout_secs))?;
+        
         Ok(Self {
             api_key: api_key.to_string(),
             model: model.to_string(),
+            base_url: super::resolve_base_url(base_url, "https://api.anthropic.com/v1"),
+            http,
         })
     }
     
@@ -140,7 +148,7 @@ impl super::AiClient for EnhancedAnthropicClient {
     }
 
     fn base_url(&self) -> &str {
-        "https://api.anthropic.com/v1"
+        &self.base_url
     }
 
     async fn generate(&self, prompt: &str) -> Result<String> {
@@ -155,10 +163,11 @@ impl super::AiClient for EnhancedAnthropicClient {
             ));
         }
         
+        super::reserve_prompt_tokens("anthropic_enhanced", &self.model, prompt, &super::RequestOptions::default())?;
         // Record this request
         rate_limiter::record_request("anthropic_enhanced");
         
-        let client = reqwest::Client::new();
+        let client = &self.http;
         
         // Create tools for code generation capabilities
         let tools = Self::create_code_tools();
@@ -184,7 +193,7 @@ impl super::AiClient for EnhancedAnthropicClient {
         
         loop {
             match client
-                .post("https://api.anthropic.com/v1/messages")
+                .post(format!("{}/messages", self.base_url))
                 .header("x-api-key", &self.api_key)
                 .header("anthropic-version", "2023-06-01")
                 .header("content-type", "application/json")
@@ -201,6 +210,8 @@ impl super::AiClient for EnhancedAnthropicClient {
                             error!("Failed to parse Anthropic Enhanced API response: {}", e);
                             ToolkitError::Api(format!("Failed to parse API response: {}", e))
                         })?;
+                        // The usage isn't parsed, so count the most the reply can use
+                        super::record_completion_tokens("anthropic_enhanced", None, Some(request.max_tokens));
                         
                         // Extract text from the response
                         let text = response_body
@@ -222,7 +233,7 @@ impl super::AiClient for EnhancedAnthropicClient {
                         // Clone the status before consuming the response
                         let status = response.status();
                         let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                        error!("Anthropic Enhanced API error: {} - {}", status, error_text);
+                        error!("Anthropic Enhanced API error: {}", status);
                         
                         if retry_count < max_retries {
                             retry_count += 1;
@@ -232,11 +243,7 @@ impl super::AiClient for EnhancedAnthropicClient {
                             continue;
                         }
                         
-                        return Err(ToolkitError::Api(format!(
-                            "API error: {} - {}",
-                            status,
-                            error_text
-                        )));
+                        return Err(super::status_error("Anthropic", status, &error_text));
                     }
                 }
                 Err(e) => {
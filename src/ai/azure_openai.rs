@@ -0,0 +1,186 @@
+//! Client for the Azure OpenAI Service.
+//!
+//! Azure hosts OpenAI models behind a per-resource endpoint, routes requests by
+//! deployment name instead of model, and authenticates with an `api-key`
+//! header. The request and response bodies are the same as OpenAI's, so this
+//! client wraps an [`OpenAiCompatibleClient`] pointed at the Azure endpoint.
+
+use crate::config;
+use crate::error::{Result, ToolkitError};
+use async_trait::async_trait;
+use futures::stream::Stream;
+use log::error;
+use serde_json::Value;
+use std::pin::Pin;
+use super::openai::{AuthScheme, OpenAiCompatibleClient};
+use super::{AiClient, GenerateResponse, JsonResponse, Message, RequestOptions};
+
+/// API version used when the config doesn't set `api_version`
+pub const AZURE_OPENAI_API_VERSION: &str = "2024-06-01";
+
+/// Provider name used for rate limiting
+const PROVIDER: &str = "azure_openai";
+
+/// Build the chat completions URL for a deployment
+///
+/// # Parameters
+///
+/// * `base_url` - The resource endpoint, e.g. `https://my-resource.openai.azure.com`.
+/// * `deployment` - The name of the model deployment.
+/// * `api_version` - The Azure OpenAI API version.
+pub fn chat_completions_url(base_url: &str, deployment: &str, api_version: &str) -> String {
+    format!(
+        "{}/openai/deployments/{}/chat/completions?api-version={}",
+        base_url.trim_end_matches('/'),
+        deployment,
+        api_version
+    )
+}
+
+pub struct AzureOpenAiClient {
+    inner: OpenAiCompatibleClient,
+}
+
+impl AzureOpenAiClient {
+    /// Create a client for the endpoint, deployment and API version in `config`
+    pub fn new(config: &config::Config) -> Result<Self> {
+        let api_key = config.api_key.as_str();
+        if api_key.is_empty() {
+            error!("Azure OpenAI API key is not configured");
+            return Err(ToolkitError::Config(
+                "Azure OpenAI API key is not configured. Please run 'rust-ai-toolkit config' to set up your API key.".to_string(),
+            ));
+        }
+
+        let base_url = config.base_url.clone().filter(|url| !url.is_empty()).ok_or_else(|| {
+            ToolkitError::Config(
+                "Azure OpenAI needs your resource endpoint as base_url, e.g. https://my-resource.openai.azure.com".to_string(),
+            )
+        })?;
+
+        let deployment = config.deployment.clone().filter(|name| !name.is_empty()).ok_or_else(|| {
+            ToolkitError::Config(
+                "Azure OpenAI needs a deployment name. Please run 'rust-ai-toolkit config' to set it.".to_string(),
+            )
+        })?;
+
+        let api_version = config
+            .api_version
+            .clone()
+            .unwrap_or_else(|| AZURE_OPENAI_API_VERSION.to_string());
+        let endpoint = chat_completions_url(&base_url, &deployment, &api_version);
+
+        let inner = OpenAiCompatibleClient::for_endpoint(
+            api_key,
+            &config.model,
+            PROVIDER,
+            base_url,
+            endpoint,
+            AuthScheme::ApiKeyHeader,
+        )?;
+
+        Ok(Self { inner })
+    }
+}
+
+#[async_trait]
+impl AiClient for AzureOpenAiClient {
+    fn model_version(&self) -> &str {
+        self.inner.model_version()
+    }
+
+    fn base_url(&self) -> &str {
+        self.inner.base_url()
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<String> {
+        self.inner.generate(prompt).await
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: RequestOptions) -> Result<String> {
+        self.inner.generate_with_options(prompt, options).await
+    }
+
+    async fn generate_with_usage(&self, prompt: &str, options: RequestOptions) -> Result<GenerateResponse> {
+        self.inner.generate_with_usage(prompt, options).await
+    }
+
+    async fn generate_streaming(&self, prompt: &str) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        self.inner.generate_streaming(prompt).await
+    }
+
+    async fn generate_streaming_with_options(
+        &self,
+        prompt: &str,
+        options: RequestOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        self.inner.generate_streaming_with_options(prompt, options).await
+    }
+
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        self.inner.generate_chat(messages, options).await
+    }
+
+    async fn generate_json(&self, prompt: &str) -> Result<Value> {
+        self.inner.generate_json(prompt).await
+    }
+
+    async fn generate_json_with_options(&self, prompt: &str, options: RequestOptions) -> Result<Value> {
+        self.inner.generate_json_with_options(prompt, options).await
+    }
+
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        self.inner.generate_json_schema_with_options(prompt, schema, options).await
+    }
+
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        self.inner.generate_json_schema_with_usage(prompt, schema, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn azure_config() -> config::Config {
+        config::Config {
+            provider: PROVIDER.to_string(),
+            api_key: "azure-key".to_string(),
+            model: "gpt-4o".to_string(),
+            base_url: Some("https://contoso.openai.azure.com".to_string()),
+            deployment: Some("gpt-4o-prod".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_new_reads_the_passed_config() {
+        let client = AzureOpenAiClient::new(&azure_config()).unwrap();
+        assert_eq!(client.model_version(), "gpt-4o");
+        assert_eq!(client.base_url(), "https://contoso.openai.azure.com");
+
+        let missing_deployment = config::Config { deployment: None, ..azure_config() };
+        assert!(matches!(AzureOpenAiClient::new(&missing_deployment), Err(ToolkitError::Config(_))));
+
+        let missing_endpoint = config::Config { base_url: None, ..azure_config() };
+        assert!(matches!(AzureOpenAiClient::new(&missing_endpoint), Err(ToolkitError::Config(_))));
+    }
+
+    #[test]
+    fn test_chat_completions_url() {
+        assert_eq!(
+            chat_completions_url("https://contoso.openai.azure.com/", "gpt-4o-prod", "2024-06-01"),
+            "https://contoso.openai.azure.com/openai/deployments/gpt-4o-prod/chat/completions?api-version=2024-06-01"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Features each model the toolkit knows about supports.
+//!
+//! Asking a model for something it can't do, like calling a function, fails
+//! at the provider with an error that rarely says why. [`require_capability`]
+//! catches that before the request is sent and names the missing feature.
+
+use crate::error::{Result, ToolkitError};
+use std::fmt;
+
+/// A feature a model may or may not support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Calling functions (tools) described in the request
+    Functions,
+    /// Images in the prompt
+    Vision,
+    /// Replies constrained to a JSON schema by the provider
+    JsonMode,
+    /// Streaming the reply as it's generated
+    Streaming,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Functions => "function calling",
+            Capability::Vision => "image input",
+            Capability::JsonMode => "JSON schema output",
+            Capability::Streaming => "streaming",
+        };
+        f.write_str(name)
+    }
+}
+
+use Capability::{Functions, JsonMode, Streaming, Vision};
+
+/// Capabilities keyed by model name or model family prefix
+///
+/// Dated model versions, e.g. `gpt-4o-2024-08-06`, use the entry with the
+/// longest matching prefix.
+pub const CAPABILITIES: &[(&str, &[Capability])] = &[
+    ("claude-3", &[Functions, Vision, Streaming]),
+    ("claude-sonnet-4", &[Functions, Vision, Streaming]),
+    ("claude-opus-4", &[Functions, Vision, Streaming]),
+    ("claude-2", &[Streaming]),
+    ("claude-instant", &[Streaming]),
+    ("gpt-4o", &[Functions, Vision, JsonMode, Streaming]),
+    ("gpt-4o-2024-05-13", &[Functions, Vision, Streaming]),
+    ("gpt-4.1", &[Functions, Vision, JsonMode, Streaming]),
+    ("gpt-4-turbo", &[Functions, Vision, Streaming]),
+    ("gpt-4", &[Functions, Streaming]),
+    ("gpt-3.5-turbo", &[Functions, Streaming]),
+    ("mistral-large", &[Functions, JsonMode, Streaming]),
+    ("mistral-small", &[Functions, JsonMode, Streaming]),
+];
+
+/// Check whether a model supports a capability
+///
+/// Models without a known entry, such as local or custom models, are assumed
+/// to support everything and left to the provider to refuse.
+pub fn model_supports(model: &str, capability: Capability) -> bool {
+    CAPABILITIES
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .is_none_or(|(_, capabilities)| capabilities.contains(&capability))
+}
+
+/// Fail unless the model supports a capability
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error naming the model and the missing capability.
+pub fn require_capability(model: &str, capability: Capability) -> Result<()> {
+    if model_supports(model, capability) {
+        Ok(())
+    } else {
+        Err(ToolkitError::InvalidInput(format!("Model {} does not support {}", model, capability)))
+    }
+}
+
+/// The capability a provider's clients use for schema-constrained JSON
+///
+/// Anthropic has no JSON mode, so its clients force a function call instead.
+pub fn structured_output_capability(provider: &str) -> Capability {
+    if provider.starts_with("anthropic") {
+        Functions
+    } else {
+        JsonMode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_supports_uses_longest_prefix() {
+        assert!(model_supports("gpt-4o-2024-08-06", JsonMode));
+        assert!(!model_supports("gpt-4o-2024-05-13", JsonMode));
+        assert!(model_supports("claude-3-7-sonnet-20250219", Functions));
+        assert!(!model_supports("claude-2.1", Functions));
+        assert!(!model_supports("gpt-4-0613", Vision));
+        assert!(model_supports("llama3", Functions));
+
+        let error = require_capability("claude-2.1", Functions).unwrap_err();
+        assert!(matches!(&error, ToolkitError::InvalidInput(message) if message == "Model claude-2.1 does not support function calling"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Multi-turn conversations with an AI client.
+//!
+//! [`ChatSession`] keeps the history of a conversation and sends all of it with
+//! every turn, so follow-up questions can refer to earlier answers.
+
+use crate::error::Result;
+use super::{AiClient, Message, RequestOptions};
+
+/// A conversation with an AI model that remembers earlier turns
+///
+/// # Examples
+///
+/// ```no_run
+/// use crate::ai::{self, ChatSession};
+/// use crate::error::Result;
+///
+/// async fn example() -> Result<()> {
+///     let client = ai::get_cached_client().await?;
+///     let mut session = ChatSession::new(client).with_system("You are a project planner.");
+///
+///     session.send("Plan a todo app").await?;
+///     let reply = session.send("Now make it offline-first").await?;
+///     println!("{}", reply);
+///     Ok(())
+/// }
+/// ```
+pub struct ChatSession {
+    /// The client that generates the replies
+    client: Box<dyn AiClient>,
+    /// The conversation so far, oldest first
+    messages: Vec<Message>,
+    /// Options sent with every turn
+    options: RequestOptions,
+}
+
+impl ChatSession {
+    /// Start an empty conversation using the given client
+    pub fn new(client: Box<dyn AiClient>) -> Self {
+        Self {
+            client,
+            messages: Vec::new(),
+            options: RequestOptions::default(),
+        }
+    }
+
+    /// Set the system prompt that frames the whole conversation
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.options.system = Some(system.into());
+        self
+    }
+
+    /// The conversation so far, oldest first
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Send a user message along with the history and record the reply
+    ///
+    /// If the request fails the user message is dropped again, so the turn can
+    /// simply be retried.
+    pub async fn send(&mut self, user_msg: &str) -> Result<String> {
+        self.messages.push(Message::user(user_msg));
+
+        match self.client.generate_chat(&self.messages, self.options.clone()).await {
+            Ok(reply) => {
+                self.messages.push(Message::assistant(reply.clone()));
+                Ok(reply)
+            }
+            Err(e) => {
+                self.messages.pop();
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::Role;
+    use crate::error::ToolkitError;
+    use crate::ai::testing::MockAiClient;
+
+    #[tokio::test]
+    async fn test_send_keeps_history() {
+        let mock = MockAiClient::new()
+            .respond("User: second", "second answer")
+            .respond("User: first", "first answer");
+        let mut session = ChatSession::new(Box::new(mock.clone()));
+
+        assert_eq!(session.send("first").await.unwrap(), "first answer");
+        assert_eq!(session.send("second").await.unwrap(), "second answer");
+        assert_eq!(mock.prompts()[1], "User: first\n\nAssistant: first answer\n\nUser: second");
+
+        let roles: Vec<Role> = session.messages().iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::User, Role::Assistant, Role::User, Role::Assistant]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_turn_is_not_recorded() {
+        let mock = MockAiClient::new().fail("User: fail", ToolkitError::Api("boom".to_string()));
+        let mut session = ChatSession::new(Box::new(mock));
+
+        session.send("first").await.unwrap();
+        assert!(session.send("fail").await.is_err());
+        assert_eq!(session.messages().len(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+//! Context window sizes of the models the toolkit knows about.
+//!
+//! A prompt that doesn't fit the model's context window is rejected by the
+//! provider with a bad request after a full round trip. [`check_context_window`]
+//! catches that before the request is sent, using the same token estimates as
+//! the rest of the toolkit.
+
+use super::anthropic::ANTHROPIC_DEFAULT_MAX_TOKENS;
+use super::RequestOptions;
+use crate::error::{Result, ToolkitError};
+
+/// Context window in tokens, keyed by model name or model family prefix
+///
+/// Dated model versions, e.g. `gpt-4o-2024-08-06`, use the entry with the
+/// longest matching prefix.
+pub const CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("claude-3", 200_000),
+    ("claude-sonnet-4", 200_000),
+    ("claude-opus-4", 200_000),
+    ("claude-2", 100_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("mistral-large", 128_000),
+    ("mistral-small", 32_000),
+];
+
+/// Look up the context window of a model
+///
+/// Returns `None` for models without a known window, such as local or
+/// custom models.
+pub fn model_context_window(model: &str) -> Option<u32> {
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, window)| *window)
+}
+
+/// Response length a provider asks for when the request doesn't set one
+///
+/// Anthropic requires one, so its client always sends a default. Other
+/// providers leave the response length open.
+fn default_max_tokens(model: &str) -> u32 {
+    if model.starts_with("claude") {
+        ANTHROPIC_DEFAULT_MAX_TOKENS
+    } else {
+        0
+    }
+}
+
+/// Check that a prompt and the response it asks for fit the model's context window
+///
+/// The system prompt counts towards the prompt, and `max_tokens`, or the
+/// provider's default when it isn't set, is reserved for the response.
+/// Models without a known window always pass.
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error saying how far over the window the request is.
+pub fn check_context_window(model: &str, prompt: &str, options: &RequestOptions) -> Result<()> {
+    let Some(window) = model_context_window(model) else {
+        return Ok(());
+    };
+
+    let prompt_tokens = super::estimate_prompt_tokens(prompt, options, model) as usize;
+    let max_tokens = options.max_tokens.unwrap_or_else(|| default_max_tokens(model)) as usize;
+    let needed = prompt_tokens + max_tokens;
+
+    if needed > window as usize {
+        return Err(ToolkitError::InvalidInput(format!(
+            "The prompt is about {} tokens and {} more are reserved for the response, {} tokens over the {}-token context window of {}. Shorten the prompt or lower --max-tokens.",
+            prompt_tokens,
+            max_tokens,
+            needed - window as usize,
+            window,
+            model
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_window_uses_longest_prefix() {
+        assert_eq!(model_context_window("gpt-4o-mini-2024-07-18"), Some(128_000));
+        assert_eq!(model_context_window("gpt-4-0613"), Some(8_192));
+        assert_eq!(model_context_window("gpt-4-32k-0613"), Some(32_768));
+        assert_eq!(model_context_window("claude-3-7-sonnet-20250219"), Some(200_000));
+        assert_eq!(model_context_window("llama3"), None);
+    }
+
+    #[test]
+    fn test_check_context_window() {
+        let options = RequestOptions { max_tokens: Some(4000), ..Default::default() };
+        assert!(check_context_window("gpt-4", "Plan a todo app", &options).is_ok());
+
+        // Over 8192 tokens with the reserved response
+        let long_prompt = "word ".repeat(5000);
+        let error = check_context_window("gpt-4", &long_prompt, &options).unwrap_err();
+        assert!(matches!(&error, ToolkitError::InvalidInput(message) if message.contains("over the 8192-token context window")));
+
+        assert!(check_context_window("gpt-4", &long_prompt, &RequestOptions::default()).is_ok());
+        assert!(check_context_window("llama3", &long_prompt.repeat(100), &options).is_ok());
+    }
+
+    #[test]
+    fn test_anthropic_default_response_length_is_reserved() {
+        // Fits the window alone, but not with the response Anthropic is always asked for
+        let prompt = "abcd".repeat(197_000);
+        let error = check_context_window("claude-3-7-sonnet-20250219", &prompt, &RequestOptions::default()).unwrap_err();
+        assert!(matches!(&error, ToolkitError::InvalidInput(message) if message.contains("4096 more are reserved")));
+
+        let short = RequestOptions { max_tokens: Some(100), ..Default::default() };
+        assert!(check_context_window("claude-3-7-sonnet-20250219", &prompt, &short).is_ok());
+    }
+}
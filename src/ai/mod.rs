@@ -33,19 +33,43 @@
 
 mod anthropic;
 mod anthropic_enhanced;
+mod azure_openai;
 mod openai;
-mod cache;
+pub mod cache;
+mod capabilities;
+mod chat;
+mod context_window;
+pub mod pricing;
+mod resume;
+mod retry;
+mod sse;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+mod verbose;
+
+pub use azure_openai::{chat_completions_url as azure_chat_completions_url, AZURE_OPENAI_API_VERSION};
+pub use capabilities::{model_supports, require_capability, structured_output_capability, Capability};
+pub use chat::ChatSession;
+pub use context_window::check_context_window;
+pub use verbose::set_verbose;
+pub use openai::{openai_preset, OPENAI_COMPATIBLE_PRESETS};
 
 use crate::config;
+pub use crate::models::TokenUsage;
 use crate::error::{Result, ToolkitError};
+use crate::utils::json;
 use async_trait::async_trait;
 use futures::stream::Stream;
 use std::pin::Pin;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 use std::time::Duration;
+use std::collections::HashMap;
 use std::sync::Mutex as StdMutex;
+use std::sync::RwLock as StdRwLock;
 use lazy_static::lazy_static;
+use log::{debug, warn};
+use once_cell::sync::OnceCell;
 use reqwest;
 
 /// HTTP header constants for consistent naming
@@ -64,19 +88,201 @@ pub mod headers {
     pub const TEXT_EVENT_STREAM: &str = "text/event-stream";
     /// X-API-Key header
     pub const X_API_KEY: &str = "X-Api-Key";
+    /// API key header used by Azure OpenAI
+    pub const API_KEY: &str = "api-key";
     /// Anthropic version header
     pub const ANTHROPIC_VERSION: &str = "anthropic-version";
+    /// Anthropic header opting in to beta features
+    pub const ANTHROPIC_BETA: &str = "anthropic-beta";
+}
+
+/// The base URL a client sends requests to: the configured one, or the provider's default
+///
+/// An empty setting counts as unset, and a trailing slash is dropped so that
+/// endpoint paths such as `/messages` can be appended.
+pub fn resolve_base_url(configured: Option<&str>, default: &str) -> String {
+    configured
+        .filter(|url| !url.is_empty())
+        .unwrap_or(default)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Connection pool settings of the shared HTTP clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HttpPool {
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+    /// Interval of HTTP/2 keep-alive pings, if sent at all
+    http2_keep_alive: Option<Duration>,
+}
+
+impl HttpPool {
+    fn from_config(config: &config::Config) -> Self {
+        Self {
+            max_idle_per_host: config.pool_max_idle_per_host,
+            idle_timeout: Duration::from_secs(config.pool_idle_timeout_secs),
+            http2_keep_alive: (config.http2_keep_alive_secs > 0).then(|| Duration::from_secs(config.http2_keep_alive_secs)),
+        }
+    }
 }
 
-/// Add a shared HTTP client that can be reused across all AI client instances
+/// The pool settings recorded by [`configure_http`]
+static HTTP_POOL: OnceCell<HttpPool> = OnceCell::new();
+
 lazy_static! {
-    /// Shared HTTP client for all AI clients to use
-    pub(crate) static ref SHARED_HTTP_CLIENT: reqwest::Client = {
-        reqwest::Client::builder()
-            .timeout(Duration::from_secs(300))
-            .build()
-            .expect("Failed to create shared HTTP client")
+    /// HTTP clients shared by every provider client, one per request timeout
+    ///
+    /// Clones of a `reqwest::Client` share its connection pool, so a client
+    /// built for a fallback model, a chat turn or a webhook reuses the
+    /// connections, and TLS sessions, opened by earlier requests.
+    static ref SHARED_HTTP_CLIENTS: StdMutex<HashMap<Duration, reqwest::Client>> = StdMutex::new(HashMap::new());
+}
+
+/// Set up the shared HTTP clients' connection pool from the config loaded at startup
+///
+/// Must be called before the first client is built; later calls have no effect.
+pub fn configure_http(config: &config::Config) {
+    let _ = HTTP_POOL.set(HttpPool::from_config(config));
+}
+
+/// Get the HTTP client a provider uses for its requests
+///
+/// Clients are shared, so every caller with the same timeout gets the same
+/// connection pool. The timeout comes from the `request_timeout_secs` setting
+/// and applies to every request, unless [`RequestOptions::timeout`] sets one
+/// for a single call.
+pub(crate) fn build_http_client(timeout: Duration) -> Result<reqwest::Client> {
+    let mut clients = SHARED_HTTP_CLIENTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(client) = clients.get(&timeout) {
+        return Ok(client.clone());
+    }
+    
+    let pool = *HTTP_POOL.get_or_init(|| HttpPool::from_config(&config::Config::default()));
+    let mut builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .pool_max_idle_per_host(pool.max_idle_per_host)
+        .pool_idle_timeout(pool.idle_timeout);
+    if let Some(interval) = pool.http2_keep_alive {
+        builder = builder.http2_keep_alive_interval(interval).http2_keep_alive_while_idle(true);
+    }
+    let client = builder
+        .build()
+        .map_err(|e| ToolkitError::Network(format!("Failed to create HTTP client: {}", e)))?;
+    
+    debug!("Created shared HTTP client with a {:?} timeout", timeout);
+    clients.insert(timeout, client.clone());
+    Ok(client)
+}
+
+/// Describe a provider's error object, e.g. `{"message": ..., "type": ..., "code": ...}`
+///
+/// Gives the message followed by the type and code, such as
+/// `Incorrect API key provided (invalid_request_error, invalid_api_key)`.
+/// Returns `None` when the object has no message.
+pub(crate) fn describe_error(error: &Value) -> Option<String> {
+    let message = error["message"].as_str()?;
+    let kinds: Vec<&str> = [&error["type"], &error["code"]]
+        .into_iter()
+        .filter_map(Value::as_str)
+        .collect();
+
+    Some(if kinds.is_empty() {
+        message.to_string()
+    } else {
+        format!("{} ({})", message, kinds.join(", "))
+    })
+}
+
+/// Describe the body of an error response
+///
+/// Anthropic and OpenAI both wrap errors as `{"error": {"message", "type",
+/// "code"}}`, which is reduced to [`describe_error`]. Bodies of any other
+/// shape, such as an HTML page from a proxy, are kept as they are.
+pub(crate) fn describe_error_body(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| describe_error(&value["error"]))
+        .unwrap_or_else(|| body.to_string())
+}
+
+/// Turn an unsuccessful HTTP response into an error
+///
+/// 429 becomes [`ToolkitError::RateLimit`] and 5xx
+/// [`ToolkitError::ServerError`], both of which are retried. Any other status,
+/// such as a bad request or a rejected API key, is a fatal
+/// [`ToolkitError::Api`]. The message comes from [`describe_error_body`]; the
+/// full body is logged at debug level.
+///
+/// # Parameters
+///
+/// * `api` - Name of the API for the message, e.g. `"Anthropic"`.
+/// * `status` - The response status.
+/// * `body` - The response body, which usually explains the error.
+pub(crate) fn status_error(api: &str, status: reqwest::StatusCode, body: &str) -> ToolkitError {
+    debug!("{} API error body: {}", api, body);
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        ToolkitError::RateLimit(format!("{} API rate limit exceeded. Please wait before making more requests.", api))
+    } else if status.is_server_error() {
+        ToolkitError::ServerError(status.as_u16(), format!("{} API: {}", api, describe_error_body(body)))
+    } else {
+        ToolkitError::Api(format!("{} API error: {} - {}", api, status, describe_error_body(body)))
+    }
+}
+
+/// Check whether an error response says the requested model doesn't exist
+///
+/// OpenAI and services like it answer with the `model_not_found` code,
+/// Anthropic with a 404 `not_found_error` naming the model.
+pub(crate) fn is_model_not_found(status: reqwest::StatusCode, body: &str) -> bool {
+    let Ok(value) = serde_json::from_str::<Value>(body) else {
+        return false;
     };
+    let error = &value["error"];
+    let message = error["message"].as_str().unwrap_or_default();
+
+    error["code"] == "model_not_found"
+        || (status == reqwest::StatusCode::NOT_FOUND
+            && (error["type"] == "not_found_error" || message.contains("model")))
+}
+
+/// The error for a failed response to a request for `model`
+///
+/// Like [`status_error`], but a response saying the model doesn't exist
+/// becomes `ModelNotFound`, so the client can retry with its fallback model.
+pub(crate) fn response_error(api: &str, model: &str, status: reqwest::StatusCode, body: &str) -> ToolkitError {
+    if is_model_not_found(status, body) {
+        debug!("{} API error body: {}", api, body);
+        ToolkitError::ModelNotFound { model: model.to_string(), fallback: None }
+    } else {
+        status_error(api, status, body)
+    }
+}
+
+/// The fallback model to retry with after `model` wasn't found
+///
+/// # Errors
+///
+/// Returns `ModelNotFound` naming `model` when the provider has no fallback
+/// in the `fallback_models` setting, or the fallback is `model` itself.
+pub(crate) fn fallback_model<'a>(provider: &str, model: &str, fallback: Option<&'a str>) -> Result<&'a str> {
+    let fallback = fallback
+        .filter(|fallback| !fallback.is_empty() && *fallback != model)
+        .ok_or_else(|| ToolkitError::ModelNotFound { model: model.to_string(), fallback: None })?;
+
+    warn!("{} does not know the model {}; retrying with the fallback model {}", provider, model, fallback);
+    Ok(fallback)
+}
+
+/// Name both models when the retry with the fallback model fails because it isn't found either
+pub(crate) fn fallback_failed(error: ToolkitError, model: &str, fallback: &str) -> ToolkitError {
+    match error {
+        ToolkitError::ModelNotFound { .. } => ToolkitError::ModelNotFound {
+            model: model.to_string(),
+            fallback: Some(fallback.to_string()),
+        },
+        other => other,
+    }
 }
 
 /// Configuration options for AI model requests.
@@ -115,12 +321,36 @@ pub struct RequestOptions {
     pub top_p: Option<f32>,
     
     /// Maximum time to wait for a response from the AI provider.
-    /// If `None`, a default timeout will be used.
+    /// If `None`, the configured `request_timeout_secs` will be used.
     pub timeout: Option<Duration>,
     
     /// List of function definitions for function calling capabilities.
     /// If `None`, function calling will not be used.
     pub functions: Option<Vec<FunctionDefinition>>,
+    
+    /// System prompt that sets the model's role and behavior.
+    /// Sent using the provider's dedicated system role when available.
+    /// If `None`, no system prompt will be sent.
+    pub system: Option<String>,
+    
+    /// Ask the provider to cache the start of the prompt server-side, so
+    /// requests repeating it are cheaper. Only Anthropic supports this; other
+    /// providers ignore it.
+    pub provider_cache: bool,
+    
+    /// Sequences that end generation when the model produces them.
+    /// Sent as `stop` to OpenAI-compatible APIs and `stop_sequences` to Anthropic.
+    /// If `None`, the model stops on its own.
+    pub stop: Option<Vec<String>>,
+    
+    /// Bias added to the likelihood of token IDs, from -100 to 100.
+    /// Only OpenAI-compatible APIs support this; other providers ignore it.
+    pub logit_bias: Option<HashMap<u32, i32>>,
+    
+    /// Seed for sampling, so repeated requests return the same response where
+    /// the provider supports it. Reproducibility is best-effort: only
+    /// OpenAI-compatible APIs accept a seed, and even they don't guarantee it.
+    pub seed: Option<u64>,
 }
 
 impl Default for RequestOptions {
@@ -131,10 +361,104 @@ impl Default for RequestOptions {
             top_p: None,
             timeout: None,
             functions: None,
+            system: None,
+            provider_cache: false,
+            stop: None,
+            logit_bias: None,
+            seed: None,
+        }
+    }
+}
+
+/// A generated response together with the token usage reported by the provider.
+///
+/// # Examples
+///
+/// ```no_run
+/// use crate::ai::{AiClient, RequestOptions};
+/// use crate::error::Result;
+///
+/// async fn example(client: &dyn AiClient) -> Result<()> {
+///     let response = client.generate_with_usage("Hello", RequestOptions::default()).await?;
+///     if let Some(usage) = response.usage {
+///         println!("Used {} tokens", usage.total());
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct GenerateResponse {
+    /// The generated text.
+    pub text: String,
+    
+    /// Token usage for the request.
+    /// `None` if the provider didn't report it, e.g. for cached responses.
+    pub usage: Option<TokenUsage>,
+}
+
+/// A schema-conformant JSON response along with its token usage.
+#[derive(Debug, Clone)]
+pub struct JsonResponse {
+    /// The generated JSON value.
+    pub value: Value,
+    
+    /// Token usage for the request.
+    /// `None` if the provider didn't report it.
+    pub usage: Option<TokenUsage>,
+}
+
+/// The author of a message in a conversation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// A message written by the user.
+    User,
+    
+    /// A reply generated by the AI model.
+    Assistant,
+}
+
+impl Role {
+    /// The role name used by the provider APIs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
         }
     }
 }
 
+/// A single turn in a multi-turn conversation.
+///
+/// # Examples
+///
+/// ```
+/// use crate::ai::{Message, Role};
+///
+/// let message = Message::user("Can we drop the mobile app from the MVP?");
+/// assert_eq!(message.role, Role::User);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    /// Who wrote the message.
+    pub role: Role,
+    
+    /// The text of the message.
+    pub content: String,
+}
+
+impl Message {
+    /// Create a message from the user.
+    pub fn user(content: impl Into<String>) -> Self {
+        Self { role: Role::User, content: content.into() }
+    }
+    
+    /// Create a reply from the AI model.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self { role: Role::Assistant, content: content.into() }
+    }
+}
+
 /// Function definition for function calling capabilities with AI models.
 ///
 /// This struct represents a function that can be called by the AI model during
@@ -254,8 +578,36 @@ pub trait AiClient: Send + Sync {
     /// Returns an error if the request fails, times out, or if the AI provider
     /// returns an error response.
     async fn generate_with_options(&self, prompt: &str, options: RequestOptions) -> Result<String> {
-        // Default implementation falls back to standard generate
-        self.generate(prompt).await
+        // Default implementation falls back to standard generate, keeping any
+        // system prompt by placing it ahead of the user prompt
+        match options.system {
+            Some(system) => self.generate(&format!("{}\n\n{}", system, prompt)).await,
+            None => self.generate(prompt).await,
+        }
+    }
+    
+    /// Generate a response and report the provider's token usage.
+    ///
+    /// This works like [`generate_with_options`](Self::generate_with_options) but
+    /// also returns the token counts for the request when the provider reports them.
+    ///
+    /// # Parameters
+    ///
+    /// * `prompt` - The input prompt to send to the AI model.
+    /// * `options` - Configuration options for the request.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the generated text and optional token usage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out, or if the AI provider
+    /// returns an error response.
+    async fn generate_with_usage(&self, prompt: &str, options: RequestOptions) -> Result<GenerateResponse> {
+        // Default implementation has no usage information
+        let text = self.generate_with_options(prompt, options).await?;
+        Ok(GenerateResponse { text, usage: None })
     }
     
     /// Generate a streaming response from the AI model.
@@ -314,6 +666,29 @@ pub trait AiClient: Send + Sync {
         Ok(Box::pin(futures::stream::once(async move { Ok(response) })))
     }
 
+    /// Continue a multi-turn conversation.
+    ///
+    /// The full history is sent with every call, so the model sees earlier
+    /// turns. The last message is normally the user's latest question.
+    ///
+    /// # Parameters
+    ///
+    /// * `messages` - The conversation so far, oldest first.
+    /// * `options` - Configuration options for the request.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the model's reply if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out, or if the AI provider
+    /// returns an error response.
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        // Default implementation flattens the conversation into a single prompt
+        self.generate_with_options(&transcript_prompt(messages), options).await
+    }
+
     /// Generate a structured JSON response.
     ///
     /// This method attempts to generate a response that can be parsed as JSON,
@@ -332,9 +707,9 @@ pub trait AiClient: Send + Sync {
     /// Returns an error if the request fails, times out, if the AI provider
     /// returns an error response, or if the response cannot be parsed as valid JSON.
     async fn generate_json(&self, prompt: &str) -> Result<Value> {
-        // Default implementation attempts to parse the text response as JSON
+        // Default implementation extracts the JSON from the text response
         let text = self.generate(prompt).await?;
-        serde_json::from_str(&text).map_err(|e| ToolkitError::Parse(e.to_string()))
+        json::extract_json(&text)
     }
 
     /// Generate a structured JSON response with configurable parameters.
@@ -356,9 +731,138 @@ pub trait AiClient: Send + Sync {
     /// Returns an error if the request fails, times out, if the AI provider
     /// returns an error response, or if the response cannot be parsed as valid JSON.
     async fn generate_json_with_options(&self, prompt: &str, options: RequestOptions) -> Result<Value> {
-        // Default implementation attempts to parse the text response as JSON
+        // Default implementation extracts the JSON from the text response
         let text = self.generate_with_options(prompt, options).await?;
-        serde_json::from_str(&text).map_err(|e| ToolkitError::Parse(e.to_string()))
+        json::extract_json(&text)
+    }
+
+    /// Generate a structured JSON response, asking again when the reply isn't valid JSON.
+    ///
+    /// When a reply can't be parsed, the model is sent the prompt again along
+    /// with its broken reply and the parse error, and asked for valid JSON
+    /// only. This works with any provider, whether or not it has a JSON mode.
+    ///
+    /// # Parameters
+    ///
+    /// * `prompt` - The input prompt to send to the AI model.
+    /// * `max_attempts` - Most requests to make, the first one included.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the parsed JSON value if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error straight away if a request fails, or the `Parse`
+    /// error for the last reply if none of them could be parsed.
+    async fn generate_json_retry(&self, prompt: &str, max_attempts: u32) -> Result<Value> {
+        let mut request = prompt.to_string();
+        let mut attempt = 1;
+        loop {
+            let text = self.generate(&request).await?;
+            match json::extract_json(&text) {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < max_attempts => {
+                    warn!("Response isn't valid JSON, asking again ({}/{}): {}", attempt, max_attempts, e);
+                    request = format!(
+                        "{}\n\n## Fix Your Previous Answer\nYour previous response could not be parsed as JSON: {}\n\nPrevious response:\n{}\n\nRespond again with valid JSON only, without code fences or commentary.",
+                        prompt, e, text
+                    );
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Generate a JSON response that conforms to a JSON schema.
+    ///
+    /// Providers that support it constrain the model to the schema; the result
+    /// is validated against the schema either way.
+    ///
+    /// # Parameters
+    ///
+    /// * `prompt` - The input prompt to send to the AI model.
+    /// * `schema` - The JSON schema the response must satisfy.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schema-conformant JSON value if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out, if the AI provider
+    /// returns an error response, or a `Parse` error listing the validation
+    /// errors if the response doesn't match the schema.
+    async fn generate_json_schema(&self, prompt: &str, schema: Value) -> Result<Value> {
+        self.generate_json_schema_with_options(prompt, schema, RequestOptions::default()).await
+    }
+
+    /// Generate a schema-conformant JSON response with configurable parameters.
+    ///
+    /// See [`generate_json_schema`](AiClient::generate_json_schema).
+    ///
+    /// # Parameters
+    ///
+    /// * `prompt` - The input prompt to send to the AI model.
+    /// * `schema` - The JSON schema the response must satisfy.
+    /// * `options` - Configuration options for the request.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schema-conformant JSON value if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out, if the AI provider
+    /// returns an error response, or a `Parse` error listing the validation
+    /// errors if the response doesn't match the schema.
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        // Default implementation spells the schema out in the prompt and checks the result
+        let prompt = format!(
+            "{}\n\nRespond with JSON only, matching this JSON schema:\n{}",
+            prompt,
+            serde_json::to_string_pretty(&schema).map_err(|e| ToolkitError::Parse(e.to_string()))?
+        );
+        let value = self.generate_json_with_options(&prompt, options).await?;
+        validate_json_schema(&value, &schema)?;
+        Ok(value)
+    }
+
+    /// Generate a schema-conformant JSON response and report its token usage.
+    ///
+    /// This works like [`generate_json_schema_with_options`](Self::generate_json_schema_with_options)
+    /// but also returns the token counts for the request when the provider reports them.
+    ///
+    /// # Parameters
+    ///
+    /// * `prompt` - The input prompt to send to the AI model.
+    /// * `schema` - The JSON schema the response must satisfy.
+    /// * `options` - Configuration options for the request.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schema-conformant JSON value and optional token usage.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, times out, if the AI provider
+    /// returns an error response, or a `Parse` error listing the validation
+    /// errors if the response doesn't match the schema.
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        // Default implementation has no usage information
+        let value = self.generate_json_schema_with_options(prompt, schema, options).await?;
+        Ok(JsonResponse { value, usage: None })
     }
 
     /// Call a function using the AI model.
@@ -377,8 +881,9 @@ pub trait AiClient: Send + Sync {
     ///
     /// # Errors
     ///
-    /// Returns an error if the request fails, times out, or if the AI provider
-    /// returns an error response.
+    /// Returns an `InvalidInput` error without sending anything if the model
+    /// doesn't support function calling, or an error if the request fails,
+    /// times out, or if the AI provider returns an error response.
     ///
     /// # Examples
     ///
@@ -414,54 +919,209 @@ pub trait AiClient: Send + Sync {
         prompt: &str,
         function: FunctionDefinition,
     ) -> Result<Value> {
+        require_capability(self.model_version(), Capability::Functions)?;
         let mut options = RequestOptions::default();
         options.functions = Some(vec![function]);
         self.generate_json_with_options(prompt, options).await
     }
+    
+    /// Turn text into an embedding vector, for comparing how similar texts are.
+    ///
+    /// Not every provider offers embeddings; by default this fails with a
+    /// configuration error naming the model.
+    ///
+    /// # Parameters
+    ///
+    /// * `text` - The text to embed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the embedding vector if successful.
+    async fn embed(&self, _text: &str) -> Result<Vec<f32>> {
+        Err(embeddings_unsupported(&format!("The provider of {}", self.model_version())))
+    }
 }
 
-/// Get a client configured according to the current configuration.
+/// The error for a provider without an embeddings API
+pub(crate) fn embeddings_unsupported(provider: &str) -> ToolkitError {
+    ToolkitError::Config(format!(
+        "{} doesn't offer embeddings. Switch the provider to openai, mistral or together to use them",
+        provider
+    ))
+}
+
+/// Estimate how many tokens a prompt will use with `model`, including its system prompt.
 ///
-/// This function returns a new client each time it's called, which may not be
-/// efficient for multiple rapid requests. Consider using `get_cached_client` instead
-/// for better performance in most cases.
+/// See [`estimate_tokens`](crate::utils::tokens::estimate_tokens).
+pub fn estimate_prompt_tokens(prompt: &str, options: &RequestOptions, model: &str) -> u32 {
+    let system = options
+        .system
+        .as_deref()
+        .map(|system| crate::utils::tokens::estimate_tokens(system, model))
+        .unwrap_or(0);
+    (crate::utils::tokens::estimate_tokens(prompt, model) + system) as u32
+}
+
+/// Check a JSON value against a JSON schema.
 ///
-/// # Returns
+/// Returns a `Parse` error listing every validation error when the value
+/// doesn't match, or an `InvalidInput` error if the schema itself is invalid.
+pub fn validate_json_schema(value: &Value, schema: &Value) -> Result<()> {
+    let compiled = jsonschema::JSONSchema::compile(schema)
+        .map_err(|e| ToolkitError::InvalidInput(format!("Invalid JSON schema: {}", e)))?;
+
+    if let Err(errors) = compiled.validate(value) {
+        let errors: Vec<String> = errors
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                if path.is_empty() {
+                    e.to_string()
+                } else {
+                    format!("{}: {}", path, e)
+                }
+            })
+            .collect();
+        return Err(ToolkitError::Parse(format!(
+            "Response does not match the JSON schema: {}",
+            errors.join("; ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Flatten a conversation into a single prompt with one labelled turn per message.
 ///
-/// A `Result` containing a boxed `AiClient` trait object if successful.
+/// Used by clients without native multi-turn support, and for estimating the
+/// size of a conversation.
+pub fn transcript_prompt(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|message| match message.role {
+            Role::User => format!("User: {}", message.content),
+            Role::Assistant => format!("Assistant: {}", message.content),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Reserve the estimated prompt tokens against a provider's tokens-per-minute budget.
 ///
-/// # Errors
+/// Returns a `TokenBudget` error when the budget for the current minute would
+/// be exceeded, so that clients wrapped with retries wait for it to free up and
+/// try again. Call this before recording the request, since a refused prompt
+/// is never sent.
+pub(crate) fn reserve_prompt_tokens(provider: &str, model: &str, prompt: &str, options: &RequestOptions) -> Result<()> {
+    let estimated = estimate_prompt_tokens(prompt, options, model);
+
+    if !crate::utils::rate_limiter::can_spend_tokens(provider, estimated) {
+        return Err(ToolkitError::TokenBudget(format!(
+            "about {} prompt tokens for {} would exceed the tokens-per-minute budget",
+            estimated, provider
+        )));
+    }
+
+    crate::utils::rate_limiter::record_tokens(provider, estimated);
+    Ok(())
+}
+
+/// Count a response's completion tokens against a provider's tokens-per-minute budget.
 ///
-/// Returns an error if the configuration is invalid or if initialization fails for any reason.
+/// [`reserve_prompt_tokens`] only covers the prompt. Responses that don't
+/// report their usage, such as streams, count as `max_tokens`, the most they
+/// can use.
+pub(crate) fn record_completion_tokens(provider: &str, usage: Option<&TokenUsage>, max_tokens: Option<u32>) {
+    let completion = usage
+        .map(|usage| u32::try_from(usage.output_tokens).unwrap_or(u32::MAX))
+        .or(max_tokens);
+    if let Some(count) = completion {
+        crate::utils::rate_limiter::record_tokens(provider, count);
+    }
+}
+
+/// Builds the client for a registered provider from the current configuration
+pub type ProviderFactory = Box<dyn Fn(&config::Config) -> Result<Box<dyn AiClient>> + Send + Sync>;
+
+lazy_static! {
+    /// Providers added with [`register_provider`], by name
+    static ref PROVIDER_REGISTRY: StdRwLock<HashMap<String, ProviderFactory>> = StdRwLock::new(HashMap::new());
+}
+
+/// Register a provider so `get_client` can build clients for it
+///
+/// When the config's `provider` is `name`, `factory` is called with the
+/// config to build the client, ahead of the built-in providers, so a
+/// registered provider can also replace a built-in one. Register providers
+/// before the first request: [`get_cached_client`] keeps the client it
+/// builds first. Registering the same name again replaces the factory.
 ///
 /// # Examples
 ///
 /// ```no_run
-/// use crate::ai;
-/// use crate::error::Result;
+/// use rust_ai_toolkit::ai;
+/// # use async_trait::async_trait;
+/// # use rust_ai_toolkit::ai::AiClient;
+/// # use rust_ai_toolkit::error::Result;
+/// #
+/// # struct MyClient {
+/// #     model: String,
+/// # }
+/// #
+/// # impl MyClient {
+/// #     fn new(_api_key: &str, model: &str) -> Result<Self> {
+/// #         Ok(Self { model: model.to_string() })
+/// #     }
+/// # }
+/// #
+/// # #[async_trait]
+/// # impl AiClient for MyClient {
+/// #     fn model_version(&self) -> &str {
+/// #         &self.model
+/// #     }
+/// #
+/// #     fn base_url(&self) -> &str {
+/// #         "https://ai.example.com"
+/// #     }
+/// #
+/// #     async fn generate(&self, prompt: &str) -> Result<String> {
+/// #         Ok(prompt.to_string())
+/// #     }
+/// # }
 ///
-/// async fn example() -> Result<()> {
-///     let client = ai::get_client().await?;
-///     let response = client.generate("Hello, AI!").await?;
-///     println!("AI says: {}", response);
-///     Ok(())
-/// }
+/// ai::register_provider("my_provider", Box::new(|config| {
+///     Ok(Box::new(MyClient::new(&config.api_key, &config.model)?))
+/// }));
 /// ```
-pub async fn get_client() -> Result<Box<dyn AiClient>> {
-    let config = crate::config::get_config()?;
+pub fn register_provider(name: &str, factory: ProviderFactory) {
+    PROVIDER_REGISTRY
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(name.to_string(), factory);
+}
+
+/// Build a client for the configured provider, registered providers first
+fn build_client(config: &config::Config) -> Result<Box<dyn AiClient>> {
+    {
+        let registry = PROVIDER_REGISTRY.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(factory) = registry.get(&config.provider) {
+            return factory(config);
+        }
+    }
     
     match config.provider.as_str() {
         "anthropic" => {
             let client = anthropic::AnthropicClient::new(
                 &config.api_key,
                 &config.model,
+                config.base_url.as_deref(),
             )?;
             Ok(Box::new(client))
         }
         "openai" => {
-            let client = openai::OpenAiClient::new(
+            let client = openai::OpenAiCompatibleClient::new(
                 &config.api_key,
                 &config.model,
+                config.base_url.as_deref(),
             )?;
             Ok(Box::new(client))
         }
@@ -469,16 +1129,101 @@ pub async fn get_client() -> Result<Box<dyn AiClient>> {
             let client = anthropic_enhanced::EnhancedAnthropicClient::new(
                 &config.api_key,
                 &config.model,
+                config.base_url.as_deref(),
             )?;
             Ok(Box::new(client))
         }
-        _ => Err(ToolkitError::Config(format!(
-            "Unsupported AI provider: {}",
-            config.provider
-        ))),
+        "azure_openai" => {
+            let client = azure_openai::AzureOpenAiClient::new(config)?;
+            Ok(Box::new(client))
+        }
+        #[cfg(any(test, feature = "test-utils"))]
+        "mock" => Ok(Box::new(testing::MockAiClient::new().with_model(&config.model))),
+        name => match openai::openai_preset(name) {
+            Some(preset) => {
+                let client = openai::OpenAiCompatibleClient::for_preset(
+                    &config.api_key,
+                    &config.model,
+                    preset,
+                    config.base_url.as_deref(),
+                )?;
+                Ok(Box::new(client))
+            }
+            None => Err(ToolkitError::Config(format!(
+                "Unsupported AI provider: {}",
+                config.provider
+            ))),
+        },
     }
 }
 
+/// Model given with `--model`, used instead of the configured one
+static MODEL_OVERRIDE: OnceCell<String> = OnceCell::new();
+
+/// Use a different model than the configured one for the rest of the process
+///
+/// Set from the `--model` flag. Only clients built afterwards use it, so call
+/// this before the first request. A model the configured provider doesn't
+/// offer is still used, with a warning. Only the first call has an effect.
+pub fn set_model_override(model: &str) {
+    match config::get_config() {
+        Ok(config) if !config::is_valid_model(model, &config.provider) => warn!(
+            "Model {} doesn't look like a {} model; using it anyway",
+            model, config.provider
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("Couldn't check model {} against the configured provider: {}", model, e),
+    }
+    
+    if MODEL_OVERRIDE.set(model.to_string()).is_err() {
+        warn!("Model was already overridden; ignoring the new value");
+    }
+}
+
+/// Load the configuration clients are built from, with any model override applied
+fn client_config() -> Result<config::Config> {
+    let mut config = config::get_config()?;
+    if let Some(model) = MODEL_OVERRIDE.get() {
+        config.model = model.clone();
+    }
+    Ok(config)
+}
+
+/// Get a client configured according to the current configuration.
+///
+/// This function returns a new client each time it's called, which may not be
+/// efficient for multiple rapid requests. Consider using `get_cached_client` instead
+/// for better performance in most cases.
+///
+/// # Returns
+///
+/// A `Result` containing a boxed `AiClient` trait object if successful.
+///
+/// # Errors
+///
+/// Returns an error if the configuration is invalid or if initialization fails for any reason.
+///
+/// # Examples
+///
+/// ```no_run
+/// use crate::ai;
+/// use crate::error::Result;
+///
+/// async fn example() -> Result<()> {
+///     let client = ai::get_client().await?;
+///     let response = client.generate("Hello, AI!").await?;
+///     println!("AI says: {}", response);
+///     Ok(())
+/// }
+/// ```
+pub async fn get_client() -> Result<Box<dyn AiClient>> {
+    let config = client_config()?;
+    
+    crate::utils::rate_limiter::set_token_limit(&config.provider, config.rate_limits.tokens_per_minute);
+    
+    build_client(&config)
+}
+
 // Initialize the global shared HTTP client for reuse
 lazy_static! {
     /// Global cached HTTP client instance to avoid repeated client creation
@@ -519,13 +1264,12 @@ lazy_static! {
 /// ```
 pub async fn get_cached_client() -> Result<Box<dyn AiClient>> {
     // First check if we already have a client
-    {
-        let client_lock = GLOBAL_CACHED_CLIENT.lock().unwrap();
-        if client_lock.is_some() {
-            // We already have a client, create a new cached wrapper for it
-            let inner_client = get_client().await?;
-            return Ok(Box::new(cache::CachedAiClient::new(inner_client)));
-        }
+    // The lock must not be held across an await point
+    let initialized = GLOBAL_CACHED_CLIENT.lock().unwrap().is_some();
+    if initialized {
+        // We already have a client, create a new cached wrapper for it
+        let inner_client = get_client().await?;
+        return decorate(inner_client);
     }
     
     // If we don't have a client yet, create one and store it
@@ -535,14 +1279,46 @@ pub async fn get_cached_client() -> Result<Box<dyn AiClient>> {
     let cached_client = Box::new(cache::CachedAiClient::new(inner_client)) as Box<dyn AiClient + Send + Sync>;
     
     // Store the new cached client
-    let mut client_lock = GLOBAL_CACHED_CLIENT.lock().unwrap();
-    *client_lock = Some(cached_client);
+    *GLOBAL_CACHED_CLIENT.lock().unwrap() = Some(cached_client);
     
     // Return a new cached wrapper around a fresh client
     // This is intentional - each call gets a fresh wrapper but we're just ensuring
     // the cache singleton is initialized
     let inner_client = get_client().await?;
-    Ok(Box::new(cache::CachedAiClient::new(inner_client)))
+    decorate(inner_client)
+}
+
+/// Get a cached client that sends requests to `model` on the configured provider
+///
+/// For side requests, such as context summaries, that can go to a cheaper
+/// model than the stages. `--model` doesn't change it.
+pub async fn get_cached_client_for_model(model: &str) -> Result<Box<dyn AiClient>> {
+    let mut config = config::get_config()?;
+    config.model = model.to_string();
+    
+    crate::utils::rate_limiter::set_token_limit(&config.provider, config.rate_limits.tokens_per_minute);
+    
+    decorate(build_client(&config)?)
+}
+
+/// Wrap a client in the layers every request goes through
+///
+/// Responses are cached and transient failures retried. With `--no-cache`,
+/// cached responses are skipped but fresh ones still stored. With
+/// `--verbose`, each request and its response are also printed.
+fn decorate(client: Box<dyn AiClient>) -> Result<Box<dyn AiClient>> {
+    let cached = cache::CachedAiClient::new(client).with_bypass(cache::is_bypassed());
+    let client = wrap_with_retry(Box::new(cached))?;
+    if verbose::is_verbose() {
+        return Ok(Box::new(verbose::VerboseAiClient::new(client)));
+    }
+    Ok(client)
+}
+
+/// Wrap a client so transient failures are retried according to the configuration
+fn wrap_with_retry(client: Box<dyn AiClient>) -> Result<Box<dyn AiClient>> {
+    let config = crate::config::get_config()?;
+    Ok(Box::new(retry::RetryingAiClient::new(client, &config.provider, config.max_retries)))
 }
 
 /// A proxy AI client that forwards requests to another client
@@ -586,6 +1362,10 @@ impl<'a> AiClient for ProxyAiClient<'a> {
         self.inner.generate_streaming_with_options(prompt, options).await
     }
     
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        self.inner.generate_chat(messages, options).await
+    }
+    
     async fn generate_json(&self, prompt: &str) -> Result<Value> {
         self.inner.generate_json(prompt).await
     }
@@ -594,6 +1374,24 @@ impl<'a> AiClient for ProxyAiClient<'a> {
         self.inner.generate_json_with_options(prompt, options).await
     }
     
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        self.inner.generate_json_schema_with_options(prompt, schema, options).await
+    }
+    
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        self.inner.generate_json_schema_with_usage(prompt, schema, options).await
+    }
+    
     async fn call_function(
         &self,
         prompt: &str,
@@ -601,4 +1399,133 @@ impl<'a> AiClient for ProxyAiClient<'a> {
     ) -> Result<Value> {
         self.inner.call_function(prompt, function).await
     }
+    
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.inner.embed(text).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::testing::{MockAiClient, MockReply};
+
+    #[test]
+    fn test_describe_error_body() {
+        let openai = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "param": null, "code": "invalid_api_key"}}"#;
+        assert_eq!(describe_error_body(openai), "Incorrect API key provided (invalid_request_error, invalid_api_key)");
+
+        let anthropic = r#"{"type": "error", "error": {"type": "authentication_error", "message": "invalid x-api-key"}}"#;
+        assert_eq!(describe_error_body(anthropic), "invalid x-api-key (authentication_error)");
+
+        assert_eq!(describe_error_body("<html>Bad Gateway</html>"), "<html>Bad Gateway</html>");
+        assert_eq!(describe_error_body(r#"{"detail": "Not found"}"#), r#"{"detail": "Not found"}"#);
+    }
+
+    #[test]
+    fn test_model_not_found() {
+        let openai = r#"{"error": {"message": "The model `gpt-5x` does not exist or you do not have access to it.", "type": "invalid_request_error", "param": null, "code": "model_not_found"}}"#;
+        assert!(is_model_not_found(reqwest::StatusCode::NOT_FOUND, openai));
+
+        let anthropic = r#"{"type": "error", "error": {"type": "not_found_error", "message": "model: claude-3-sonnet-20240229"}}"#;
+        assert!(is_model_not_found(reqwest::StatusCode::NOT_FOUND, anthropic));
+
+        let bad_key = r#"{"error": {"message": "Incorrect API key provided", "code": "invalid_api_key"}}"#;
+        assert!(!is_model_not_found(reqwest::StatusCode::UNAUTHORIZED, bad_key));
+        assert!(!is_model_not_found(reqwest::StatusCode::NOT_FOUND, "<html>Not Found</html>"));
+
+        let error = response_error("Anthropic", "claude-old", reqwest::StatusCode::NOT_FOUND, anthropic);
+        assert!(matches!(&error, ToolkitError::ModelNotFound { model, fallback: None } if model == "claude-old"));
+
+        assert_eq!(fallback_model("anthropic", "claude-old", Some("claude-new")).unwrap(), "claude-new");
+        assert!(matches!(fallback_model("anthropic", "claude-old", None), Err(ToolkitError::ModelNotFound { .. })));
+        assert!(fallback_model("anthropic", "claude-old", Some("claude-old")).is_err());
+
+        let error = fallback_failed(error, "claude-old", "claude-new");
+        assert!(error.to_string().contains("neither 'claude-old' nor its fallback 'claude-new'"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_json_retry_feeds_back_the_parse_error() {
+        let client = MockAiClient::new()
+            .once("List the stories", MockReply::Text("{\"stories\": [1, 2,]}".to_string()))
+            .respond("List the stories", "{\"stories\": [1, 2]}");
+
+        let value = client.generate_json_retry("List the stories", 3).await.unwrap();
+
+        assert_eq!(value, serde_json::json!({"stories": [1, 2]}));
+        let prompts = client.prompts();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].starts_with("List the stories\n\n## Fix Your Previous Answer"));
+        assert!(prompts[1].contains("{\"stories\": [1, 2,]}"));
+        assert!(prompts[1].contains("trailing comma"));
+
+        let client = MockAiClient::new()
+            .once("List the stories", MockReply::Text("not json".to_string()))
+            .respond("List the stories", "still not json");
+        let error = client.generate_json_retry("List the stories", 2).await.unwrap_err();
+        assert!(matches!(error, ToolkitError::Parse(_)));
+        assert_eq!(client.prompts().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_http_clients_share_connections() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Answers every request with "ok", keeping the connection open
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let server = tokio::spawn({
+            let connections = connections.clone();
+            async move {
+                loop {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(async move {
+                        let mut request = Vec::new();
+                        let mut buffer = [0; 1024];
+                        while let Ok(read @ 1..) = socket.read(&mut buffer).await {
+                            request.extend_from_slice(&buffer[..read]);
+                            while let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                                request.drain(..end + 4);
+                                let reply = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+                                if socket.write_all(reply).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    });
+                }
+            }
+        });
+
+        // A timeout no other test uses, so the clients are this test's own
+        let timeout = Duration::from_secs(4321);
+        let url = format!("http://{}/", address);
+        for _ in 0..2 {
+            let client = build_http_client(timeout).unwrap();
+            assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "ok");
+        }
+        server.abort();
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_registered_provider_is_used() {
+        let config = config::Config {
+            provider: "registry_test".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(build_client(&config), Err(ToolkitError::Config(_))));
+
+        register_provider("registry_test", Box::new(|config| {
+            assert_eq!(config.provider, "registry_test");
+            Ok(Box::new(MockAiClient::new().with_model("registered")))
+        }));
+        assert_eq!(build_client(&config).unwrap().model_version(), "registered");
+    }
 }
@@ -1,26 +1,111 @@
 use crate::error::{Result, ToolkitError};
-use crate::utils::rate_limiter;
+use crate::utils::{json, rate_limiter};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use log::{debug, error, warn};
 use crate::config;
+use std::collections::HashMap;
 use std::time::Duration;
 use futures::stream::{Stream, StreamExt};
 use std::pin::Pin;
 use serde_json::Value;
-use super::{RequestOptions, FunctionDefinition, SHARED_HTTP_CLIENT, headers};
+use super::{GenerateResponse, JsonResponse, Message, RequestOptions, FunctionDefinition, TokenUsage, headers};
 
-pub struct OpenAiClient {
+/// Default base URL for the OpenAI API
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Model used for OpenAI embeddings
+pub const OPENAI_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+/// A service with an OpenAI-compatible chat completions API that can be
+/// selected by name as the provider
+#[derive(Debug)]
+pub struct OpenAiPreset {
+    /// Provider name used in the config, e.g. `"mistral"`
+    pub name: &'static str,
+    /// Name shown in the config wizard
+    pub display_name: &'static str,
+    /// Base URL used when the config doesn't set `base_url`
+    pub base_url: &'static str,
+    /// Environment variable that can hold the API key
+    pub api_key_env: &'static str,
+    /// Models offered by the config wizard, the default first
+    pub models: &'static [&'static str],
+    /// Model used for embeddings, if the service offers them
+    pub embedding_model: Option<&'static str>,
+}
+
+/// The OpenAI-compatible services known by name
+pub const OPENAI_COMPATIBLE_PRESETS: &[OpenAiPreset] = &[
+    OpenAiPreset {
+        name: "mistral",
+        display_name: "Mistral",
+        base_url: "https://api.mistral.ai/v1",
+        api_key_env: "RUST_AI_TOOLKIT_MISTRAL_API_KEY",
+        models: &["mistral-large-latest", "mistral-small-latest", "codestral-latest", "open-mistral-nemo"],
+        embedding_model: Some("mistral-embed"),
+    },
+    OpenAiPreset {
+        name: "groq",
+        display_name: "Groq",
+        base_url: "https://api.groq.com/openai/v1",
+        api_key_env: "RUST_AI_TOOLKIT_GROQ_API_KEY",
+        models: &["llama-3.3-70b-versatile", "llama-3.1-8b-instant", "mixtral-8x7b-32768", "gemma2-9b-it"],
+        embedding_model: None,
+    },
+    OpenAiPreset {
+        name: "together",
+        display_name: "Together AI",
+        base_url: "https://api.together.xyz/v1",
+        api_key_env: "RUST_AI_TOOLKIT_TOGETHER_API_KEY",
+        models: &[
+            "meta-llama/Llama-3.3-70B-Instruct-Turbo",
+            "Qwen/Qwen2.5-72B-Instruct-Turbo",
+            "deepseek-ai/DeepSeek-V3",
+            "mistralai/Mixtral-8x7B-Instruct-v0.1",
+        ],
+        embedding_model: Some("BAAI/bge-base-en-v1.5"),
+    },
+];
+
+/// Look up an OpenAI-compatible preset by provider name
+pub fn openai_preset(name: &str) -> Option<&'static OpenAiPreset> {
+    OPENAI_COMPATIBLE_PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// Client for OpenAI and any service that speaks its chat completions API
+#[derive(Clone)]
+pub struct OpenAiCompatibleClient {
     api_key: String,
     model: String,
     base_url: String,
-    api_version: String,
+    /// HTTP client with the configured request timeout
+    http: reqwest::Client,
+    /// Provider name used for rate limiter bookkeeping
+    provider: &'static str,
+    /// Full URL of the chat completions endpoint
+    endpoint: String,
+    /// How the API key is sent
+    auth: AuthScheme,
+    /// Model used for embeddings, if the service offers them
+    embedding_model: Option<&'static str>,
+    /// Model retried with when the service doesn't know `model`
+    fallback_model: Option<String>,
+}
+
+/// How an OpenAI-compatible API expects the API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuthScheme {
+    /// `Authorization: Bearer <key>`, as used by OpenAI
+    Bearer,
+    /// `api-key: <key>`, as used by Azure OpenAI
+    ApiKeyHeader,
 }
 
 #[derive(Serialize)]
 struct OpenAiRequest {
     model: String,
-    messages: Vec<Message>,
+    messages: Vec<OpenAiMessage>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -31,10 +116,34 @@ struct OpenAiRequest {
     stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     functions: Option<Vec<FunctionDefinition>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<HashMap<u32, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Serialize)]
-struct Message {
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize)]
+struct OpenAiMessage {
     role: String,
     content: String,
 }
@@ -42,6 +151,14 @@ struct Message {
 #[derive(Deserialize)]
 struct OpenAiResponse {
     choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
 }
 
 #[derive(Deserialize)]
@@ -68,73 +185,197 @@ struct StreamingChoice {
 #[derive(Deserialize, Debug)]
 struct StreamingDelta {
     #[serde(default)]
-    content: String,
+    content: Option<String>,
 }
 
-impl OpenAiClient {
-    pub fn new(api_key: &str, model: &str) -> Result<Self> {
+impl OpenAiCompatibleClient {
+    /// Create a client, sending requests to `base_url` if set, e.g. a proxy or gateway
+    pub fn new(api_key: &str, model: &str, base_url: Option<&str>) -> Result<Self> {
+        Self::with_default_base_url(api_key, model, "openai", "OpenAI", base_url, OPENAI_BASE_URL, Some(OPENAI_EMBEDDING_MODEL))
+    }
+    
+    /// Create a client for a named OpenAI-compatible service
+    ///
+    /// The preset's base URL is used unless `base_url` is set.
+    pub fn for_preset(api_key: &str, model: &str, preset: &'static OpenAiPreset, base_url: Option<&str>) -> Result<Self> {
+        Self::with_default_base_url(
+            api_key,
+            model,
+            preset.name,
+            preset.display_name,
+            base_url,
+            preset.base_url,
+            preset.embedding_model,
+        )
+    }
+    
+    fn with_default_base_url(
+        api_key: &str,
+        model: &str,
+        provider: &'static str,
+        display_name: &str,
+        base_url: Option<&str>,
+        default_base_url: &str,
+        embedding_model: Option<&'static str>,
+    ) -> Result<Self> {
         if api_key.is_empty() {
-            error!("OpenAI API key is not configured");
-            return Err(ToolkitError::Config(
-                "OpenAI API key is not configured. Please run 'rust-ai-toolkit config' to set up your API key.".to_string(),
-            ));
+            error!("{} API key is not configured", display_name);
+            return Err(ToolkitError::Config(format!(
+                "{} API key is not configured. Please run 'rust-ai-toolkit config' to set up your API key.",
+                display_name
+            )));
         }
         
+        let base_url = super::resolve_base_url(base_url, default_base_url);
+        let endpoint = format!("{}/chat/completions", base_url);
+        
+        let mut client = Self::for_endpoint(api_key, model, provider, base_url, endpoint, AuthScheme::Bearer)?;
+        client.embedding_model = embedding_model;
+        Ok(client)
+    }
+    
+    /// Create a client for another service that speaks the OpenAI chat completions API
+    ///
+    /// # Parameters
+    ///
+    /// * `provider` - Provider name used for rate limiting.
+    /// * `base_url` - Base URL reported by the client.
+    /// * `endpoint` - Full URL of the chat completions endpoint.
+    /// * `auth` - How the API key is sent.
+    pub(crate) fn for_endpoint(
+        api_key: &str,
+        model: &str,
+        provider: &'static str,
+        base_url: String,
+        endpoint: String,
+        auth: AuthScheme,
+    ) -> Result<Self> {
         let config = config::get_config()?;
-        let base_url = config.base_url.clone().unwrap_or_else(|| 
-            "https://api.openai.com/v1".to_string()
-        );
+        let http = super::build_http_client(Duration::from_secs(config.request_timeout_secs))?;
         
-        // Use a hardcoded API version since it's not in the Config struct
-        let api_version = "2024-02-15".to_string();
-
         Ok(Self {
             api_key: api_key.to_string(),
             model: model.to_string(),
             base_url,
-            api_version,
+            http,
+            provider,
+            endpoint,
+            auth,
+            embedding_model: None,
+            fallback_model: config.fallback_models.get(provider).cloned(),
         })
     }
     
     fn create_request_body(&self, prompt: &str, options: &RequestOptions, stream: bool) -> OpenAiRequest {
+        self.create_chat_request_body(&[Message::user(prompt)], options, stream)
+    }
+    
+    fn create_chat_request_body(&self, messages: &[Message], options: &RequestOptions, stream: bool) -> OpenAiRequest {
+        let mut request_messages = Vec::with_capacity(messages.len() + 1);
+        
+        // OpenAI takes the system prompt as a leading message
+        if let Some(system) = &options.system {
+            request_messages.push(OpenAiMessage {
+                role: "system".to_string(),
+                content: system.clone(),
+            });
+        }
+        
+        request_messages.extend(messages.iter().map(|message| OpenAiMessage {
+            role: message.role.as_str().to_string(),
+            content: message.content.clone(),
+        }));
+        
         OpenAiRequest {
             model: self.model.clone(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
+            messages: request_messages,
             max_tokens: options.max_tokens,
             temperature: options.temperature,
             top_p: options.top_p,
             stream: Some(stream),
             functions: options.functions.clone(),
+            response_format: None,
+            stop: options.stop.clone(),
+            logit_bias: options.logit_bias.clone(),
+            seed: options.seed,
         }
     }
+    
+    /// Send a non-streaming request and read the reply and its token usage
+    async fn complete(&self, request: OpenAiRequest, timeout: Option<Duration>) -> Result<GenerateResponse> {
+        let max_tokens = request.max_tokens;
+        let response = self.send_request(request, false, timeout).await?;
+        
+        let response_data: OpenAiResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse OpenAI API response: {}", e);
+            ToolkitError::Parse(e.to_string())
+        })?;
+        
+        if response_data.choices.is_empty() {
+            return Err(ToolkitError::Api("No response from OpenAI API".to_string()));
+        }
+        
+        let usage = response_data.usage.map(|usage| TokenUsage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        });
+        super::record_completion_tokens(self.provider, usage.as_ref(), max_tokens);
+        
+        Ok(GenerateResponse {
+            text: response_data.choices[0].message.content.clone(),
+            usage,
+        })
+    }
 
+    /// Send a chat completions request, retrying once with the fallback model
+    /// if the service doesn't know the requested one
     async fn send_request(
         &self,
-        request: OpenAiRequest,
+        mut request: OpenAiRequest,
         streaming: bool,
         timeout: Option<Duration>,
     ) -> Result<reqwest::Response> {
-        // Use the shared HTTP client instead of creating a new one
-        let client = &*SHARED_HTTP_CLIENT;
+        match self.post(&self.endpoint, &request.model, &request, streaming, timeout).await {
+            Err(ToolkitError::ModelNotFound { model, .. }) => {
+                let fallback = super::fallback_model(self.provider, &model, self.fallback_model.as_deref())?;
+                request.model = fallback.to_string();
+                self.post(&self.endpoint, &request.model, &request, streaming, timeout)
+                    .await
+                    .map_err(|e| super::fallback_failed(e, &model, fallback))
+            }
+            result => result,
+        }
+    }
+
+    /// POST a JSON body asking for `model` to one of the API's endpoints
+    async fn post(
+        &self,
+        url: &str,
+        model: &str,
+        body: &impl Serialize,
+        streaming: bool,
+        timeout: Option<Duration>,
+    ) -> Result<reqwest::Response> {
+        let client = &self.http;
         
         // Check rate limits
-        if !rate_limiter::can_make_request("openai") {
-            return Err(ToolkitError::RateLimit(
-                "OpenAI API rate limit exceeded. Please try again later.".to_string(),
+        // Refused locally, so this mustn't look like the provider's 429
+        if !rate_limiter::can_make_request(self.provider) {
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for OpenAI API. Please try again later.".to_string(),
             ));
         }
         
         // Record this request
-        rate_limiter::record_request("openai");
+        rate_limiter::record_request(self.provider);
         
-        let url = format!("{}/chat/completions", self.base_url);
-        
-        let mut builder = client.post(&url)
-            .header(headers::AUTHORIZATION, format!("{}{}", headers::BEARER_PREFIX, &self.api_key))
+        let mut builder = client.post(url)
             .header(headers::CONTENT_TYPE, headers::APPLICATION_JSON);
+        
+        builder = match self.auth {
+            AuthScheme::Bearer => builder.header(headers::AUTHORIZATION, format!("{}{}", headers::BEARER_PREFIX, &self.api_key)),
+            AuthScheme::ApiKeyHeader => builder.header(headers::API_KEY, &self.api_key),
+        };
             
         if let Some(t) = timeout {
             builder = builder.timeout(t);
@@ -145,33 +386,27 @@ impl OpenAiClient {
         }
         
         let response = builder
-            .json(&request)
+            .json(body)
             .send()
             .await
             .map_err(|e| {
                 error!("Failed to send request to OpenAI API: {}", e);
                 // Record failure for rate limiting
-                let backoff = rate_limiter::record_failure("openai");
+                let backoff = rate_limiter::record_failure(self.provider);
                 ToolkitError::Api(format!("Failed to send request to OpenAI API: {}. Backing off for {}ms", e, backoff))
             })?;
             
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            error!("OpenAI API error: {} - {}", status, error_text);
+            error!("OpenAI API error: {}", status);
             
-            // Check if this is a rate limit error
-            if status.as_u16() == 429 {
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
                 // Record a rate limit failure for a longer backoff
-                rate_limiter::record_rate_limit("openai");
-                return Err(ToolkitError::RateLimit(
-                    "OpenAI API rate limit exceeded. Please wait before making more requests.".to_string()
-                ));
+                rate_limiter::record_rate_limit(self.provider);
             }
             
-            return Err(ToolkitError::Api(
-                format!("OpenAI API error: {} - {}", status, error_text)
-            ));
+            return Err(super::response_error("OpenAI", model, status, &error_text));
         }
         
         Ok(response)
@@ -179,9 +414,9 @@ impl OpenAiClient {
 }
 
 #[async_trait]
-impl super::AiClient for OpenAiClient {
+impl super::AiClient for OpenAiCompatibleClient {
     fn model_version(&self) -> &str {
-        &self.api_version
+        &self.model
     }
 
     fn base_url(&self) -> &str {
@@ -194,30 +429,24 @@ impl super::AiClient for OpenAiClient {
     }
 
     async fn generate_with_options(&self, prompt: &str, options: RequestOptions) -> Result<String> {
+        self.generate_with_usage(prompt, options).await.map(|response| response.text)
+    }
+
+    async fn generate_with_usage(&self, prompt: &str, options: RequestOptions) -> Result<GenerateResponse> {
         debug!("Generating response with model: {}", self.model);
         
-        if !rate_limiter::can_make_request("openai") {
+        if !rate_limiter::can_make_request(self.provider) {
             warn!("Rate limit exceeded for OpenAI API");
             return Err(ToolkitError::Api(
                 "Rate limit exceeded for OpenAI API. Please try again later.".to_string(),
             ));
         }
         
-        rate_limiter::record_request("openai");
+        super::reserve_prompt_tokens(self.provider, &self.model, prompt, &options)?;
+        rate_limiter::record_request(self.provider);
         
         let request = self.create_request_body(prompt, &options, false);
-        let response = self.send_request(request, false, options.timeout).await?;
-        
-        let response_data: OpenAiResponse = response.json().await.map_err(|e| {
-            error!("Failed to parse OpenAI API response: {}", e);
-            ToolkitError::Parse(e.to_string())
-        })?;
-        
-        if response_data.choices.is_empty() {
-            return Err(ToolkitError::Api("No response from OpenAI API".to_string()));
-        }
-        
-        Ok(response_data.choices[0].message.content.clone())
+        self.complete(request, options.timeout).await
     }
 
     async fn generate_streaming(&self, prompt: &str) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
@@ -231,41 +460,45 @@ impl super::AiClient for OpenAiClient {
         options: RequestOptions,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
         debug!("Generating streaming response with model: {}", self.model);
+        super::require_capability(&self.model, super::Capability::Streaming)?;
         
-        if !rate_limiter::can_make_request("openai") {
+        if !rate_limiter::can_make_request(self.provider) {
             warn!("Rate limit exceeded for OpenAI API");
             return Err(ToolkitError::Api(
                 "Rate limit exceeded for OpenAI API. Please try again later.".to_string(),
             ));
         }
         
-        rate_limiter::record_request("openai");
+        super::reserve_prompt_tokens(self.provider, &self.model, prompt, &options)?;
+        rate_limiter::record_request(self.provider);
         
         let request = self.create_request_body(prompt, &options, true);
+        // A stream doesn't report its usage, so count the most it can use
+        super::record_completion_tokens(self.provider, None, request.max_tokens);
         let response = self.send_request(request, true, options.timeout).await?;
         
-        let stream = response.bytes_stream().map(|result| {
-            result.map_err(|e| ToolkitError::Network(e.to_string()))
-                .and_then(|bytes| {
-                    let text = String::from_utf8(bytes.to_vec())
-                        .map_err(|e| ToolkitError::Parse(e.to_string()))?;
-                    
-                    if text.trim().is_empty() {
-                        return Ok("".to_string());
-                    }
-                    
-                    let response: StreamingResponse = serde_json::from_str(&text)
-                        .map_err(|e| ToolkitError::Parse(e.to_string()))?;
-                    
-                    if response.choices.is_empty() {
-                        return Ok("".to_string());
-                    }
-                    
-                    Ok(response.choices[0].delta.content.clone())
-                })
-        });
+        let stream = super::sse::data_events(response.bytes_stream())
+            .map(|event| event.and_then(|data| parse_stream_event(&data)));
+        
+        // Chat completions can't continue a partial reply, so keep what arrived
+        Ok(super::resume::keep_partial(Box::pin(stream)))
+    }
+
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        debug!("Generating chat response with model: {}", self.model);
+        
+        if !rate_limiter::can_make_request(self.provider) {
+            warn!("Rate limit exceeded for OpenAI API");
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for OpenAI API. Please try again later.".to_string(),
+            ));
+        }
+        
+        super::reserve_prompt_tokens(self.provider, &self.model, &super::transcript_prompt(messages), &options)?;
+        rate_limiter::record_request(self.provider);
         
-        Ok(Box::pin(stream))
+        let request = self.create_chat_request_body(messages, &options, false);
+        self.complete(request, options.timeout).await.map(|response| response.text)
     }
 
     async fn generate_json(&self, prompt: &str) -> Result<Value> {
@@ -275,6 +508,193 @@ impl super::AiClient for OpenAiClient {
 
     async fn generate_json_with_options(&self, prompt: &str, options: RequestOptions) -> Result<Value> {
         let text = self.generate_with_options(prompt, options).await?;
-        serde_json::from_str(&text).map_err(|e| ToolkitError::Parse(e.to_string()))
+        json::extract_json(&text)
+    }
+
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        self.generate_json_schema_with_usage(prompt, schema, options).await.map(|response| response.value)
+    }
+
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        debug!("Generating schema-constrained JSON with model: {}", self.model);
+        super::require_capability(&self.model, super::Capability::JsonMode)?;
+        
+        if !rate_limiter::can_make_request(self.provider) {
+            warn!("Rate limit exceeded for OpenAI API");
+            return Err(ToolkitError::Api(
+                "Rate limit exceeded for OpenAI API. Please try again later.".to_string(),
+            ));
+        }
+        
+        super::reserve_prompt_tokens(self.provider, &self.model, prompt, &options)?;
+        rate_limiter::record_request(self.provider);
+        
+        let mut request = self.create_request_body(prompt, &options, false);
+        request.response_format = Some(json_schema_response_format(&schema));
+        
+        let response = self.complete(request, options.timeout).await?;
+        let value = json::extract_json(&response.text)?;
+        super::validate_json_schema(&value, &schema)?;
+        Ok(JsonResponse { value, usage: response.usage })
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let model = self.embedding_model.ok_or_else(|| super::embeddings_unsupported(self.provider))?;
+        debug!("Embedding {} characters with model: {}", text.len(), model);
+        
+        let url = format!("{}/embeddings", self.base_url.trim_end_matches('/'));
+        let request = EmbeddingRequest { model, input: text };
+        let response = self.post(&url, model, &request, false, None).await?;
+        
+        let response_data: EmbeddingResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse embeddings response: {}", e);
+            ToolkitError::Parse(e.to_string())
+        })?;
+        
+        response_data
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .ok_or_else(|| ToolkitError::Api("No embedding in the response".to_string()))
+    }
+}
+
+/// The `response_format` that makes OpenAI constrain its reply to a JSON schema
+fn json_schema_response_format(schema: &Value) -> Value {
+    serde_json::json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "structured_output",
+            "schema": schema,
+            "strict": true,
+        }
+    })
+}
+
+/// Extract the text from a streaming chunk; the final `[DONE]` marker carries none
+fn parse_stream_event(data: &str) -> Result<String> {
+    if data == "[DONE]" {
+        return Ok(String::new());
+    }
+    
+    let response: StreamingResponse = serde_json::from_str(data)
+        .map_err(|e| ToolkitError::Parse(e.to_string()))?;
+    
+    Ok(response
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.delta.content)
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_client() -> OpenAiCompatibleClient {
+        OpenAiCompatibleClient {
+            api_key: "sk-test".to_string(),
+            model: "gpt-4".to_string(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            http: reqwest::Client::new(),
+            provider: "openai",
+            endpoint: "https://api.openai.com/v1/chat/completions".to_string(),
+            auth: AuthScheme::Bearer,
+            embedding_model: Some(OPENAI_EMBEDDING_MODEL),
+            fallback_model: None,
+        }
+    }
+
+    #[test]
+    fn test_system_prompt_prepended_as_message() {
+        let options = RequestOptions {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..Default::default()
+        };
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &options, false)).unwrap();
+        let messages = body["messages"].as_array().unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[0]["content"], "You are a helpful assistant.");
+        assert_eq!(messages[1]["role"], "user");
+    }
+
+    #[test]
+    fn test_stop_and_logit_bias_sent() {
+        let options = RequestOptions {
+            stop: Some(vec!["\n\n".to_string()]),
+            logit_bias: Some([(50256, -100)].into_iter().collect()),
+            seed: Some(42),
+            ..Default::default()
+        };
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &options, false)).unwrap();
+        assert_eq!(body["stop"], serde_json::json!(["\n\n"]));
+        assert_eq!(body["logit_bias"], serde_json::json!({"50256": -100}));
+        assert_eq!(body["seed"], 42);
+
+        let body = serde_json::to_value(test_client().create_request_body("Hello", &RequestOptions::default(), false)).unwrap();
+        assert!(body.get("stop").is_none());
+        assert!(body.get("logit_bias").is_none());
+        assert!(body.get("seed").is_none());
+    }
+
+    #[test]
+    fn test_chat_history_follows_system_prompt() {
+        let options = RequestOptions {
+            system: Some("You are a helpful assistant.".to_string()),
+            ..Default::default()
+        };
+        let messages = vec![
+            Message::user("Plan a todo app"),
+            Message::assistant("Here is a plan"),
+            Message::user("Make it offline-first"),
+        ];
+
+        let body = serde_json::to_value(test_client().create_chat_request_body(&messages, &options, false)).unwrap();
+        let sent = body["messages"].as_array().unwrap();
+
+        let roles: Vec<&str> = sent.iter().map(|m| m["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        assert_eq!(sent[3]["content"], "Make it offline-first");
+    }
+
+    #[test]
+    fn test_json_schema_response_format() {
+        let schema = serde_json::json!({"type": "object", "properties": {}, "required": [], "additionalProperties": false});
+        let mut request = test_client().create_request_body("List features", &RequestOptions::default(), false);
+        request.response_format = Some(json_schema_response_format(&schema));
+
+        let body = serde_json::to_value(request).unwrap();
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(body["response_format"]["json_schema"]["schema"], schema);
+
+        let plain = serde_json::to_value(test_client().create_request_body("Hello", &RequestOptions::default(), false)).unwrap();
+        assert!(plain.get("response_format").is_none());
+    }
+
+    #[test]
+    fn test_parse_stream_event() {
+        let chunk = r#"{"id":"c1","choices":[{"index":0,"delta":{"content":"Hello"}}]}"#;
+        assert_eq!(parse_stream_event(chunk).unwrap(), "Hello");
+
+        let role_only = r#"{"id":"c1","choices":[{"index":0,"delta":{"role":"assistant","content":null}}]}"#;
+        assert_eq!(parse_stream_event(role_only).unwrap(), "");
+
+        assert_eq!(parse_stream_event("[DONE]").unwrap(), "");
     }
 }
@@ -0,0 +1,83 @@
+//! Per-token prices of the models the toolkit knows about.
+//!
+//! Used to estimate what a run will cost before it starts. Prices are list
+//! prices in US dollars and may lag behind the providers' pricing pages, so
+//! estimates are a guide rather than a bill.
+
+/// Price of a model's input and output tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    /// US dollars per million prompt tokens
+    pub input_per_million: f64,
+    /// US dollars per million completion tokens
+    pub output_per_million: f64,
+}
+
+impl ModelPricing {
+    const fn new(input_per_million: f64, output_per_million: f64) -> Self {
+        Self { input_per_million, output_per_million }
+    }
+
+    /// Cost in US dollars of a request with the given token counts
+    pub fn cost(&self, input_tokens: usize, output_tokens: usize) -> f64 {
+        (input_tokens as f64 * self.input_per_million + output_tokens as f64 * self.output_per_million) / 1_000_000.0
+    }
+}
+
+/// Prices keyed by model name or model family prefix
+///
+/// Dated model versions, e.g. `claude-3-7-sonnet-20250219`, use the entry
+/// with the longest matching prefix.
+pub const PRICING: &[(&str, ModelPricing)] = &[
+    ("claude-3-7-sonnet", ModelPricing::new(3.0, 15.0)),
+    ("claude-3-5-sonnet", ModelPricing::new(3.0, 15.0)),
+    ("claude-3-5-haiku", ModelPricing::new(0.8, 4.0)),
+    ("claude-3-opus", ModelPricing::new(15.0, 75.0)),
+    ("claude-3-sonnet", ModelPricing::new(3.0, 15.0)),
+    ("claude-3-haiku", ModelPricing::new(0.25, 1.25)),
+    ("claude-sonnet-4", ModelPricing::new(3.0, 15.0)),
+    ("claude-opus-4", ModelPricing::new(15.0, 75.0)),
+    ("gpt-4o-mini", ModelPricing::new(0.15, 0.6)),
+    ("gpt-4o-2024-05-13", ModelPricing::new(5.0, 15.0)),
+    ("gpt-4o", ModelPricing::new(2.5, 10.0)),
+    ("gpt-4.1-mini", ModelPricing::new(0.4, 1.6)),
+    ("gpt-4.1", ModelPricing::new(2.0, 8.0)),
+    ("gpt-4-turbo", ModelPricing::new(10.0, 30.0)),
+    ("gpt-4", ModelPricing::new(30.0, 60.0)),
+    ("gpt-3.5-turbo", ModelPricing::new(0.5, 1.5)),
+    ("mistral-large", ModelPricing::new(2.0, 6.0)),
+    ("mistral-small", ModelPricing::new(0.2, 0.6)),
+];
+
+/// Look up the price of a model
+///
+/// Returns `None` for models without a known price, such as local or
+/// custom models.
+pub fn pricing_for(model: &str) -> Option<ModelPricing> {
+    PRICING
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, pricing)| *pricing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_uses_longest_prefix() {
+        assert_eq!(pricing_for("gpt-4o-mini-2024-07-18"), Some(ModelPricing::new(0.15, 0.6)));
+        assert_eq!(pricing_for("gpt-4o"), Some(ModelPricing::new(2.5, 10.0)));
+        assert_eq!(pricing_for("gpt-4-0613"), Some(ModelPricing::new(30.0, 60.0)));
+        assert_eq!(pricing_for("claude-3-7-sonnet-20250219"), Some(ModelPricing::new(3.0, 15.0)));
+        assert_eq!(pricing_for("llama3"), None);
+    }
+
+    #[test]
+    fn test_cost() {
+        let pricing = ModelPricing::new(3.0, 15.0);
+        assert!((pricing.cost(1_000_000, 0) - 3.0).abs() < 1e-9);
+        assert!((pricing.cost(2000, 1000) - 0.021).abs() < 1e-9);
+    }
+}
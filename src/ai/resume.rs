@@ -0,0 +1,160 @@
+//! Recovery from response streams that drop mid-generation.
+//!
+//! A network error part way through a long streamed response would otherwise
+//! throw away everything received so far. [`resumable`] keeps the text that
+//! has arrived and, for providers that can continue a partial assistant
+//! message, re-requests the rest and splices it onto the same stream. When the
+//! stream can't be resumed the error becomes
+//! [`ToolkitError::StreamInterrupted`] carrying the partial text.
+
+use crate::error::{Result, ToolkitError};
+use futures::stream::{self, Stream, StreamExt};
+use log::warn;
+use std::future::Future;
+use std::pin::Pin;
+use super::Message;
+
+/// A stream of generated text chunks
+pub(crate) type TextStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// How many times a dropped stream is resumed before giving up
+pub(crate) const MAX_STREAM_RESUMES: usize = 2;
+
+struct ResumeState<F> {
+    stream: TextStream,
+    /// The conversation that produced the stream
+    messages: Vec<Message>,
+    /// Text received so far, across resumes
+    partial: String,
+    resumes_left: usize,
+    resume: F,
+    finished: bool,
+}
+
+/// Keep the text received so far if the stream fails part way through
+///
+/// Used by providers that can't continue a partial reply: a network error
+/// after some text has arrived is reported as
+/// [`ToolkitError::StreamInterrupted`] so the caller can decide what to do
+/// with it.
+pub(crate) fn keep_partial(stream: TextStream) -> TextStream {
+    resumable(stream, Vec::new(), |_| async { Err(ToolkitError::Network("Streams can't be resumed".to_string())) }, 0)
+}
+
+/// Resume a stream after a network error by continuing the partial reply
+///
+/// # Parameters
+///
+/// * `stream` - The stream returned by the first request.
+/// * `messages` - The conversation sent with the first request.
+/// * `resume` - Starts a new stream for a conversation that ends with the
+///   partial assistant reply.
+/// * `max_resumes` - How many times to resume before giving up.
+pub(crate) fn resumable<F, Fut>(stream: TextStream, messages: Vec<Message>, resume: F, max_resumes: usize) -> TextStream
+where
+    F: Fn(Vec<Message>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<TextStream>> + Send + 'static,
+{
+    let state = ResumeState {
+        stream,
+        messages,
+        partial: String::new(),
+        resumes_left: max_resumes,
+        resume,
+        finished: false,
+    };
+
+    Box::pin(stream::unfold(state, |mut state| async move {
+        if state.finished {
+            return None;
+        }
+
+        loop {
+            let message = match state.stream.next().await {
+                Some(Ok(chunk)) => {
+                    state.partial.push_str(&chunk);
+                    return Some((Ok(chunk), state));
+                }
+                None => return None,
+                // Nothing to keep yet, so the plain error is as good as it gets
+                Some(Err(ToolkitError::Network(message))) if !state.partial.is_empty() => message,
+                Some(Err(e)) => {
+                    state.finished = true;
+                    return Some((Err(e), state));
+                }
+            };
+
+            if state.resumes_left > 0 {
+                state.resumes_left -= 1;
+                warn!("Response stream dropped after {} characters, resuming: {}", state.partial.len(), message);
+
+                // Providers reject a prefilled reply that ends in whitespace
+                let mut messages = state.messages.clone();
+                messages.push(Message::assistant(state.partial.trim_end()));
+
+                match (state.resume)(messages).await {
+                    Ok(stream) => {
+                        state.stream = stream;
+                        continue;
+                    }
+                    Err(e) => warn!("Failed to resume the response stream: {}", e),
+                }
+            }
+
+            state.finished = true;
+            let error = ToolkitError::StreamInterrupted {
+                message,
+                partial: state.partial.clone(),
+            };
+            return Some((Err(error), state));
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::Role;
+    use std::sync::{Arc, Mutex};
+
+    fn chunks(items: Vec<Result<&'static str>>) -> TextStream {
+        Box::pin(stream::iter(items.into_iter().map(|item| item.map(str::to_string))))
+    }
+
+    #[tokio::test]
+    async fn test_resume_splices_continuation() {
+        let first = chunks(vec![Ok("Step 1. "), Ok("Step"), Err(ToolkitError::Network("reset".to_string()))]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+
+        let stream = resumable(
+            first,
+            vec![Message::user("Plan it")],
+            move |messages| {
+                recorded.lock().unwrap().push(messages);
+                async { Ok(chunks(vec![Ok(" 2.")])) }
+            },
+            MAX_STREAM_RESUMES,
+        );
+
+        let text: Vec<String> = stream.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(text.concat(), "Step 1. Step 2.");
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0][1].role, Role::Assistant);
+        assert_eq!(seen[0][1].content, "Step 1. Step");
+    }
+
+    #[tokio::test]
+    async fn test_keep_partial_reports_received_text() {
+        let stream = keep_partial(chunks(vec![Ok("Hello"), Err(ToolkitError::Network("reset".to_string())), Ok("lost")]));
+        let items: Vec<Result<String>> = stream.collect().await;
+
+        assert_eq!(items.len(), 2);
+        match &items[1] {
+            Err(ToolkitError::StreamInterrupted { partial, .. }) => assert_eq!(partial, "Hello"),
+            other => panic!("unexpected item: {:?}", other),
+        }
+    }
+}
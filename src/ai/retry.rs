@@ -0,0 +1,257 @@
+//! Automatic retry with backoff for AI clients.
+//!
+//! This module provides [`RetryingAiClient`], a decorator around any [`AiClient`]
+//! that retries transient failures (rate limits and server errors) using the
+//! backoff delays computed by [`rate_limiter`](crate::utils::rate_limiter).
+//! Requests held back by the local token budget wait for it to free up.
+//! Retrying stops early once the provider's circuit breaker opens, which only
+//! provider failures count towards.
+
+use crate::error::{Result, ToolkitError};
+use crate::utils::{cancel, rate_limiter};
+use super::{AiClient, FunctionDefinition, GenerateResponse, JsonResponse, Message, RequestOptions};
+use async_trait::async_trait;
+use futures::stream::Stream;
+use log::{debug, warn};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Check whether an error is transient and worth retrying.
+///
+/// See [`ToolkitError::is_retryable`]. Errors that aren't retryable are
+/// returned to the caller immediately.
+pub fn is_retryable(err: &ToolkitError) -> bool {
+    err.is_retryable()
+}
+
+/// An AI client wrapper that retries transient failures with backoff
+pub struct RetryingAiClient {
+    /// The inner AI client that does the actual work
+    inner: Box<dyn AiClient + Send + Sync>,
+    /// Provider name used for rate limiter bookkeeping
+    provider: String,
+    /// Maximum number of retries after the initial attempt
+    max_retries: u32,
+}
+
+impl RetryingAiClient {
+    /// Create a new retrying AI client that wraps another client
+    pub fn new(inner: Box<dyn AiClient>, provider: &str, max_retries: u32) -> Self {
+        Self {
+            inner,
+            provider: provider.to_string(),
+            max_retries,
+        }
+    }
+
+    /// Run an operation, retrying it while it fails with a retryable error
+    ///
+    /// Gives up with [`ToolkitError::Cancelled`] as soon as the user presses
+    /// Ctrl-C, dropping the request in flight or the backoff in progress.
+    async fn with_retry<T, F, Fut>(&self, operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        cancel::until_cancelled(self.retry_loop(operation)).await
+    }
+
+    async fn retry_loop<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match operation().await {
+                Ok(value) => {
+                    rate_limiter::record_success(&self.provider);
+                    return Ok(value);
+                }
+                Err(e) if attempt < self.max_retries && is_retryable(&e) && !e.is_provider_failure() => {
+                    // Held back before it was sent, so the provider hasn't failed
+                    attempt += 1;
+                    let wait_ms = rate_limiter::token_wait_ms(&self.provider);
+                    warn!("Request to {} held back ({}). Retrying ({}/{}) in {}ms",
+                        self.provider, e, attempt, self.max_retries, wait_ms);
+                    tokio::time::sleep(Duration::from_millis(wait_ms)).await;
+                }
+                Err(e) if attempt < self.max_retries && is_retryable(&e) => {
+                    attempt += 1;
+                    let backoff_ms = rate_limiter::record_failure(&self.provider);
+                    // Don't wait out a backoff the open circuit would refuse anyway
+                    if rate_limiter::circuit_state(&self.provider) == rate_limiter::CircuitState::Open {
+                        warn!("Giving up on request to {}: it keeps failing ({})", self.provider, e);
+                        return Err(e);
+                    }
+                    warn!("Request to {} failed ({}). Retrying ({}/{}) in {}ms",
+                        self.provider, e, attempt, self.max_retries, backoff_ms);
+                    tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                }
+                Err(e) => {
+                    debug!("Giving up on request to {} after {} retries", self.provider, attempt);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AiClient for RetryingAiClient {
+    fn model_version(&self) -> &str {
+        self.inner.model_version()
+    }
+
+    fn base_url(&self) -> &str {
+        self.inner.base_url()
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<String> {
+        self.with_retry(|| self.inner.generate(prompt)).await
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: RequestOptions) -> Result<String> {
+        self.with_retry(|| self.inner.generate_with_options(prompt, options.clone())).await
+    }
+
+    async fn generate_with_usage(&self, prompt: &str, options: RequestOptions) -> Result<GenerateResponse> {
+        self.with_retry(|| self.inner.generate_with_usage(prompt, options.clone())).await
+    }
+
+    async fn generate_streaming(&self, prompt: &str) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        // Only establishing the stream is retried; errors mid-stream are passed through
+        self.with_retry(|| self.inner.generate_streaming(prompt)).await
+    }
+
+    async fn generate_streaming_with_options(
+        &self,
+        prompt: &str,
+        options: RequestOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        self.with_retry(|| self.inner.generate_streaming_with_options(prompt, options.clone())).await
+    }
+
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        self.with_retry(|| self.inner.generate_chat(messages, options.clone())).await
+    }
+
+    async fn generate_json(&self, prompt: &str) -> Result<Value> {
+        self.with_retry(|| self.inner.generate_json(prompt)).await
+    }
+
+    async fn generate_json_with_options(&self, prompt: &str, options: RequestOptions) -> Result<Value> {
+        self.with_retry(|| self.inner.generate_json_with_options(prompt, options.clone())).await
+    }
+
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        self.with_retry(|| self.inner.generate_json_schema_with_options(prompt, schema.clone(), options.clone())).await
+    }
+
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        self.with_retry(|| self.inner.generate_json_schema_with_usage(prompt, schema.clone(), options.clone())).await
+    }
+
+    async fn call_function(
+        &self,
+        prompt: &str,
+        function: FunctionDefinition,
+    ) -> Result<Value> {
+        self.with_retry(|| self.inner.call_function(prompt, function.clone())).await
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.with_retry(|| self.inner.embed(text)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::testing::{MockAiClient, MockReply};
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&ToolkitError::RateLimit("slow down".to_string())));
+        assert!(is_retryable(&ToolkitError::ServerError(503, "overloaded".to_string())));
+        assert!(!is_retryable(&ToolkitError::Api(
+            "OpenAI API error: 400 Bad Request - invalid".to_string()
+        )));
+        assert!(!is_retryable(&ToolkitError::Parse("bad json".to_string())));
+    }
+
+    #[test]
+    fn test_status_errors_are_typed() {
+        use reqwest::StatusCode;
+
+        let rate_limited = crate::ai::status_error("OpenAI", StatusCode::TOO_MANY_REQUESTS, "slow down");
+        let unavailable = crate::ai::status_error("OpenAI", StatusCode::SERVICE_UNAVAILABLE, "overloaded");
+        let bad_request = crate::ai::status_error("OpenAI", StatusCode::BAD_REQUEST, "invalid");
+
+        assert!(matches!(rate_limited, ToolkitError::RateLimit(_)));
+        assert!(matches!(unavailable, ToolkitError::ServerError(503, _)));
+        assert!(matches!(bad_request, ToolkitError::Api(_)));
+        assert!(is_retryable(&rate_limited) && is_retryable(&unavailable) && !is_retryable(&bad_request));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_transient_errors() {
+        let mock = MockAiClient::new()
+            .once("prompt", MockReply::Error(ToolkitError::RateLimit("slow down".to_string())))
+            .once("prompt", MockReply::Error(ToolkitError::ServerError(502, "upstream".to_string())))
+            .respond("prompt", "ok");
+        let client = RetryingAiClient::new(Box::new(mock.clone()), "retry_test", 3);
+
+        assert_eq!(client.generate("prompt").await.unwrap(), "ok");
+        assert_eq!(mock.prompts().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up_after_max_retries() {
+        let mock = MockAiClient::new().rate_limit("prompt");
+        let client = RetryingAiClient::new(Box::new(mock.clone()), "retry_test", 2);
+
+        let result = client.generate("prompt").await;
+        assert!(matches!(result, Err(ToolkitError::RateLimit(_))));
+        assert_eq!(mock.prompts().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_budget_does_not_trip_circuit() {
+        let mock = MockAiClient::new().fail("prompt", ToolkitError::TokenBudget("over budget".to_string()));
+        let retries = rate_limiter::CIRCUIT_FAILURE_THRESHOLD + 2;
+        let client = RetryingAiClient::new(Box::new(mock.clone()), "budget_test", retries);
+
+        let result = client.generate("prompt").await;
+
+        assert!(matches!(result, Err(ToolkitError::TokenBudget(_))));
+        assert_eq!(mock.prompts().len(), retries as usize + 1);
+        assert_eq!(rate_limiter::circuit_state("budget_test"), rate_limiter::CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_fatal_errors() {
+        let mock = MockAiClient::new()
+            .fail("prompt", ToolkitError::Api("OpenAI API error: 401 Unauthorized - bad key".to_string()));
+        let client = RetryingAiClient::new(Box::new(mock.clone()), "retry_test", 3);
+
+        let result = client.generate("prompt").await;
+        assert!(matches!(result, Err(ToolkitError::Api(_))));
+        assert_eq!(mock.prompts().len(), 1);
+    }
+}
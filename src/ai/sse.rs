@@ -0,0 +1,85 @@
+//! Decoding of server-sent event (SSE) streams.
+//!
+//! Both Anthropic and OpenAI stream responses as server-sent events. Network
+//! chunks don't line up with event boundaries, so lines are buffered until
+//! complete before the `data:` payloads are handed to the provider's parser.
+
+use crate::error::{Result, ToolkitError};
+use futures::stream::{self, Stream, StreamExt};
+
+/// Incremental decoder that turns raw bytes into SSE `data:` payloads
+#[derive(Debug, Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Feed a chunk of bytes and return the payloads of any complete `data:` lines
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut payloads = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8(line).map_err(|e| ToolkitError::Parse(e.to_string()))?;
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if let Some(data) = line.strip_prefix("data:") {
+                payloads.push(data.trim_start().to_string());
+            }
+        }
+
+        Ok(payloads)
+    }
+}
+
+/// Split a byte stream into the `data:` payloads of its server-sent events
+pub(crate) fn data_events<S, B>(bytes: S) -> impl Stream<Item = Result<String>> + Send
+where
+    S: Stream<Item = reqwest::Result<B>> + Send,
+    B: AsRef<[u8]>,
+{
+    bytes
+        .scan(SseDecoder::default(), |decoder, chunk| {
+            let payloads = chunk
+                .map_err(|e| ToolkitError::Network(e.to_string()))
+                .and_then(|chunk| decoder.push(chunk.as_ref()));
+            futures::future::ready(Some(payloads))
+        })
+        .flat_map(|payloads| {
+            let items: Vec<Result<String>> = match payloads {
+                Ok(payloads) => payloads.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(items)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_buffers_partial_lines() {
+        let mut decoder = SseDecoder::default();
+
+        assert!(decoder.push(b"event: content_block_delta\ndata: {\"a\":").unwrap().is_empty());
+        assert_eq!(decoder.push(b"1}\r\n\ndata: [DONE]\n").unwrap(), vec!["{\"a\":1}", "[DONE]"]);
+        assert!(decoder.push(b": keep-alive comment\n").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_data_events_across_chunks() {
+        let chunks: Vec<reqwest::Result<&'static [u8]>> = vec![
+            Ok(b"data: first\n\nda"),
+            Ok(b"ta: second\n\n"),
+        ];
+
+        let events: Vec<String> = data_events(stream::iter(chunks))
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(events, vec!["first", "second"]);
+    }
+}
@@ -0,0 +1,300 @@
+//! A scriptable AI client for running the toolkit without a network.
+//!
+//! Built with the `test-utils` feature, [`MockAiClient`] answers prompts from
+//! canned replies picked by what the prompt contains, and can fail with any
+//! error, such as a rate limit, to exercise error handling. Setting the
+//! provider to `mock` in the config makes every command use one, so whole
+//! pipelines run offline. To script the replies those commands get, register
+//! a configured client under the `mock` name:
+//!
+//! ```ignore
+//! let mock = MockAiClient::new()
+//!     .respond("Architecture", "## Architecture\n\nA single binary ...")
+//!     .once("Implementation", MockReply::Error(ToolkitError::RateLimit("slow down".into())));
+//! let shared = mock.clone();
+//! ai::register_provider("mock", Box::new(move |_| Ok(Box::new(shared.clone()))));
+//! // ... run stages, then inspect `mock.prompts()`
+//! ```
+
+use super::{AiClient, RequestOptions};
+use crate::error::{Result, ToolkitError};
+use async_trait::async_trait;
+use futures::stream::{self, Stream};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Reply given when no rule matches; long enough to pass the stages' output checks
+const DEFAULT_REPLY: &str = "## Mock Response\n\n\
+This response comes from the mock AI provider, which never contacts a real model. \
+It stands in for generated content so that commands, stages and templates can be \
+exercised offline.\n\n\
+- First point of the mock response\n\
+- Second point of the mock response\n\
+- Third point of the mock response\n";
+
+/// What a [`MockAiClient`] answers a prompt with
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// Respond with this text; streamed a word at a time
+    Text(String),
+    /// Stream these chunks as they are, or respond with them joined
+    Chunks(Vec<String>),
+    /// Fail with this error
+    Error(ToolkitError),
+}
+
+/// A reply for prompts containing `pattern`
+struct Rule {
+    pattern: String,
+    reply: MockReply,
+    /// How many more prompts the rule answers; `None` for all of them
+    remaining: Option<usize>,
+}
+
+#[derive(Default)]
+struct MockState {
+    rules: Vec<Rule>,
+    default_reply: Option<MockReply>,
+    prompts: Vec<String>,
+}
+
+/// AI client that answers from a script instead of calling a provider
+///
+/// Rules are tried in the order they were added, and the first whose
+/// pattern the prompt contains gives the reply. Clones share the script and
+/// the recorded prompts.
+#[derive(Clone)]
+pub struct MockAiClient {
+    model: String,
+    /// How long every reply takes
+    delay: Option<Duration>,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Default for MockAiClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockAiClient {
+    /// Create a client that gives every prompt the same generic Markdown reply
+    pub fn new() -> Self {
+        Self {
+            model: "mock-model".to_string(),
+            delay: None,
+            state: Arc::new(Mutex::new(MockState::default())),
+        }
+    }
+
+    /// Report a different model name
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// Take `delay` to answer each prompt, like a real provider would
+    ///
+    /// Useful to have concurrent requests overlap.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Reply to prompts no rule matches with `reply`
+    pub fn with_default(self, reply: MockReply) -> Self {
+        self.lock().default_reply = Some(reply);
+        self
+    }
+
+    /// Reply to every prompt containing `pattern` with `reply`
+    pub fn on(self, pattern: &str, reply: MockReply) -> Self {
+        self.add_rule(pattern, reply, None)
+    }
+
+    /// Reply to the next prompt containing `pattern` with `reply`, then fall through to later rules
+    ///
+    /// Useful to fail once and succeed on the retry.
+    pub fn once(self, pattern: &str, reply: MockReply) -> Self {
+        self.add_rule(pattern, reply, Some(1))
+    }
+
+    /// Respond to prompts containing `pattern` with `text`
+    pub fn respond(self, pattern: &str, text: &str) -> Self {
+        self.on(pattern, MockReply::Text(text.to_string()))
+    }
+
+    /// Stream `chunks` in reply to prompts containing `pattern`
+    pub fn stream(self, pattern: &str, chunks: &[&str]) -> Self {
+        self.on(pattern, MockReply::Chunks(chunks.iter().map(|chunk| chunk.to_string()).collect()))
+    }
+
+    /// Fail prompts containing `pattern` with `error`
+    pub fn fail(self, pattern: &str, error: ToolkitError) -> Self {
+        self.on(pattern, MockReply::Error(error))
+    }
+
+    /// Fail prompts containing `pattern` as if the provider's rate limit was hit
+    pub fn rate_limit(self, pattern: &str) -> Self {
+        self.fail(pattern, ToolkitError::RateLimit("mock provider rate limit".to_string()))
+    }
+
+    /// Every prompt received so far, oldest first, with any system prompt ahead of it
+    pub fn prompts(&self) -> Vec<String> {
+        self.lock().prompts.clone()
+    }
+
+    fn add_rule(self, pattern: &str, reply: MockReply, remaining: Option<usize>) -> Self {
+        self.lock().rules.push(Rule { pattern: pattern.to_string(), reply, remaining });
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Record a prompt and pick its reply, taking the configured delay
+    async fn reply_for(&self, prompt: &str) -> MockReply {
+        let reply = self.pick_reply(prompt);
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        reply
+    }
+
+    fn pick_reply(&self, prompt: &str) -> MockReply {
+        let mut state = self.lock();
+        state.prompts.push(prompt.to_string());
+
+        let rule = state
+            .rules
+            .iter_mut()
+            .find(|rule| rule.remaining != Some(0) && prompt.contains(&rule.pattern));
+        match rule {
+            Some(rule) => {
+                if let Some(remaining) = rule.remaining.as_mut() {
+                    *remaining -= 1;
+                }
+                rule.reply.clone()
+            }
+            None => state.default_reply.clone().unwrap_or_else(|| MockReply::Text(DEFAULT_REPLY.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl AiClient for MockAiClient {
+    fn model_version(&self) -> &str {
+        &self.model
+    }
+
+    fn base_url(&self) -> &str {
+        "mock://"
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<String> {
+        match self.reply_for(prompt).await {
+            MockReply::Text(text) => Ok(text),
+            MockReply::Chunks(chunks) => Ok(chunks.concat()),
+            MockReply::Error(error) => Err(error),
+        }
+    }
+
+    async fn generate_streaming(&self, prompt: &str) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        self.generate_streaming_with_options(prompt, RequestOptions::default()).await
+    }
+
+    async fn generate_streaming_with_options(
+        &self,
+        prompt: &str,
+        options: RequestOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let prompt = match options.system {
+            Some(system) => format!("{}\n\n{}", system, prompt),
+            None => prompt.to_string(),
+        };
+        let chunks = match self.reply_for(&prompt).await {
+            MockReply::Text(text) => text.split_inclusive(' ').map(String::from).collect(),
+            MockReply::Chunks(chunks) => chunks,
+            MockReply::Error(error) => return Err(error),
+        };
+        Ok(Box::pin(stream::iter(chunks.into_iter().map(Ok))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    async fn collect(stream: Pin<Box<dyn Stream<Item = Result<String>> + Send>>) -> Vec<String> {
+        stream.map(|chunk| chunk.unwrap()).collect().await
+    }
+
+    #[tokio::test]
+    async fn test_respond_matches_prompts_containing_the_pattern() {
+        let mock = MockAiClient::new()
+            .respond("plan", "the plan")
+            .respond("p", "anything with a p");
+
+        assert_eq!(mock.generate("write the plan").await.unwrap(), "the plan");
+        assert_eq!(mock.generate("write the plan").await.unwrap(), "the plan");
+        assert_eq!(mock.generate("a prompt").await.unwrap(), "anything with a p");
+        assert!(mock.generate("no rule matches").await.unwrap().len() >= 200);
+    }
+
+    #[tokio::test]
+    async fn test_once_answers_a_single_prompt() {
+        let mock = MockAiClient::new()
+            .once("flaky", MockReply::Error(ToolkitError::Network("connection reset".to_string())))
+            .respond("flaky", "worked the second time");
+
+        assert!(matches!(mock.generate("a flaky prompt").await, Err(ToolkitError::Network(_))));
+        assert_eq!(mock.generate("a flaky prompt").await.unwrap(), "worked the second time");
+        assert_eq!(mock.generate("a flaky prompt").await.unwrap(), "worked the second time");
+    }
+
+    #[tokio::test]
+    async fn test_fail_returns_the_error() {
+        let mock = MockAiClient::new().fail("broken", ToolkitError::Api("bad request".to_string()));
+
+        assert!(matches!(mock.generate("broken prompt").await, Err(ToolkitError::Api(_))));
+        assert!(matches!(mock.generate_streaming("broken prompt").await, Err(ToolkitError::Api(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_fails_as_retryable() {
+        let mock = MockAiClient::new().rate_limit("busy");
+
+        let error = mock.generate("are you busy?").await.unwrap_err();
+        assert!(matches!(error, ToolkitError::RateLimit(_)));
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_stream_sends_the_chunks() {
+        let mock = MockAiClient::new().stream("story", &["Once ", "upon ", "a time"]);
+
+        assert_eq!(collect(mock.generate_streaming("tell me a story").await.unwrap()).await, vec!["Once ", "upon ", "a time"]);
+        assert_eq!(mock.generate("tell me a story").await.unwrap(), "Once upon a time");
+
+        // Text replies stream a word at a time
+        let mock = MockAiClient::default().with_default(MockReply::Text("one two".to_string()));
+        assert_eq!(collect(mock.generate_streaming("no rule matches").await.unwrap()).await, vec!["one ", "two"]);
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_the_script_and_prompts() {
+        let mock = MockAiClient::new().with_model("mock-large").once("hello", MockReply::Text("hi".to_string()));
+        let clone = mock.clone();
+
+        assert_eq!(clone.model_version(), "mock-large");
+        assert_eq!(clone.generate("hello").await.unwrap(), "hi");
+        assert_ne!(mock.generate("hello").await.unwrap(), "hi");
+
+        let options = RequestOptions { system: Some("Be brief".to_string()), ..Default::default() };
+        collect(clone.generate_streaming_with_options("anything else", options).await.unwrap()).await;
+        assert_eq!(mock.prompts(), vec!["hello", "hello", "Be brief\n\nanything else"]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Printing every AI request and response for `--verbose`.
+//!
+//! [`VerboseAiClient`] is a decorator around any [`AiClient`] that prints the
+//! prompt, the request options and the raw response of each call to stderr,
+//! whatever the log level. Stdout is left alone so JSON output stays parseable.
+
+use crate::error::Result;
+use super::{AiClient, FunctionDefinition, GenerateResponse, JsonResponse, Message, RequestOptions};
+use async_trait::async_trait;
+use futures::stream::{self, Stream, StreamExt};
+use serde_json::Value;
+use std::fmt::Display;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Print every AI request and response for the rest of the process (`--verbose`)
+///
+/// Only clients created afterwards are affected.
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// Check whether `--verbose` is on
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Format the block printed before a request is sent
+pub fn format_request(call: &str, model: &str, prompt: &str, options: Option<&RequestOptions>) -> String {
+    let mut block = format!("──── {} request to {} ────\n", call, model);
+    if let Some(options) = options {
+        block.push_str(&format!("options: {:?}\n", options));
+    }
+    block.push_str("prompt:\n");
+    block.push_str(prompt);
+    block
+}
+
+/// Format the block printed once a response has arrived
+pub fn format_response<T: Display>(response: &Result<T>) -> String {
+    match response {
+        Ok(text) => format!("──── response ────\n{}\n──── end ────", text),
+        Err(e) => format!("──── error ────\n{}\n──── end ────", e),
+    }
+}
+
+/// An AI client wrapper that prints each request and response to stderr
+pub struct VerboseAiClient {
+    /// The inner AI client that does the actual work
+    inner: Box<dyn AiClient + Send + Sync>,
+}
+
+impl VerboseAiClient {
+    /// Create a new verbose AI client that wraps another client
+    pub fn new(inner: Box<dyn AiClient>) -> Self {
+        Self { inner }
+    }
+
+    fn print_request(&self, call: &str, prompt: &str, options: Option<&RequestOptions>) {
+        eprintln!("{}", format_request(call, self.inner.model_version(), prompt, options));
+    }
+
+    /// Print the response of a call that returns the whole response at once
+    fn print_response<T: Display>(response: Result<T>) -> Result<T> {
+        eprintln!("{}", format_response(&response));
+        response
+    }
+
+    /// Pass a stream through, printing the full response once it ends
+    fn print_stream(
+        response: Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let stream = match response {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("{}", format_response::<String>(&Err(e.clone())));
+                return Err(e);
+            }
+        };
+
+        let received = Arc::new(Mutex::new(String::new()));
+        let collector = Arc::clone(&received);
+        let stream = stream
+            .inspect(move |chunk| {
+                if let Ok(chunk) = chunk {
+                    collector.lock().unwrap().push_str(chunk);
+                }
+            })
+            .chain(stream::once(async move {
+                let text = received.lock().unwrap().clone();
+                eprintln!("{}", format_response(&Ok(text)));
+                None
+            }).filter_map(|end: Option<Result<String>>| async move { end }));
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[async_trait]
+impl AiClient for VerboseAiClient {
+    fn model_version(&self) -> &str {
+        self.inner.model_version()
+    }
+
+    fn base_url(&self) -> &str {
+        self.inner.base_url()
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<String> {
+        self.print_request("generate", prompt, None);
+        Self::print_response(self.inner.generate(prompt).await)
+    }
+
+    async fn generate_with_options(&self, prompt: &str, options: RequestOptions) -> Result<String> {
+        self.print_request("generate", prompt, Some(&options));
+        Self::print_response(self.inner.generate_with_options(prompt, options).await)
+    }
+
+    async fn generate_with_usage(&self, prompt: &str, options: RequestOptions) -> Result<GenerateResponse> {
+        self.print_request("generate", prompt, Some(&options));
+        let response = self.inner.generate_with_usage(prompt, options).await;
+        match &response {
+            Ok(generated) => {
+                eprintln!("{}", format_response(&Ok(&generated.text)));
+                if let Some(usage) = generated.usage {
+                    eprintln!("usage: {} input, {} output tokens", usage.input_tokens, usage.output_tokens);
+                }
+            }
+            Err(e) => eprintln!("{}", format_response::<String>(&Err(e.clone()))),
+        }
+        response
+    }
+
+    async fn generate_streaming(&self, prompt: &str) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        self.print_request("streaming", prompt, None);
+        Self::print_stream(self.inner.generate_streaming(prompt).await)
+    }
+
+    async fn generate_streaming_with_options(
+        &self,
+        prompt: &str,
+        options: RequestOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        self.print_request("streaming", prompt, Some(&options));
+        Self::print_stream(self.inner.generate_streaming_with_options(prompt, options).await)
+    }
+
+    async fn generate_chat(&self, messages: &[Message], options: RequestOptions) -> Result<String> {
+        let transcript: Vec<String> = messages
+            .iter()
+            .map(|message| format!("[{:?}] {}", message.role, message.content))
+            .collect();
+        self.print_request("chat", &transcript.join("\n"), Some(&options));
+        Self::print_response(self.inner.generate_chat(messages, options).await)
+    }
+
+    async fn generate_json(&self, prompt: &str) -> Result<Value> {
+        self.print_request("JSON", prompt, None);
+        Self::print_response(self.inner.generate_json(prompt).await)
+    }
+
+    async fn generate_json_with_options(&self, prompt: &str, options: RequestOptions) -> Result<Value> {
+        self.print_request("JSON", prompt, Some(&options));
+        Self::print_response(self.inner.generate_json_with_options(prompt, options).await)
+    }
+
+    async fn generate_json_schema_with_options(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<Value> {
+        self.print_request("JSON schema", prompt, Some(&options));
+        eprintln!("schema: {}", schema);
+        Self::print_response(self.inner.generate_json_schema_with_options(prompt, schema, options).await)
+    }
+
+    async fn generate_json_schema_with_usage(
+        &self,
+        prompt: &str,
+        schema: Value,
+        options: RequestOptions,
+    ) -> Result<JsonResponse> {
+        self.print_request("JSON schema", prompt, Some(&options));
+        eprintln!("schema: {}", schema);
+        let response = self.inner.generate_json_schema_with_usage(prompt, schema, options).await;
+        match &response {
+            Ok(generated) => {
+                eprintln!("{}", format_response(&Ok(&generated.value)));
+                if let Some(usage) = generated.usage {
+                    eprintln!("usage: {} input, {} output tokens", usage.input_tokens, usage.output_tokens);
+                }
+            }
+            Err(e) => eprintln!("{}", format_response::<Value>(&Err(e.clone()))),
+        }
+        response
+    }
+
+    async fn call_function(
+        &self,
+        prompt: &str,
+        function: FunctionDefinition,
+    ) -> Result<Value> {
+        self.print_request(&format!("function {}", function.name), prompt, None);
+        Self::print_response(self.inner.call_function(prompt, function).await)
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.print_request("embedding", text, None);
+        let response = self.inner.embed(text).await;
+        let summary = response.as_ref().map(|vector| format!("{} dimensions", vector.len())).map_err(Clone::clone);
+        eprintln!("{}", format_response(&summary));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ToolkitError;
+
+    #[test]
+    fn test_blocks_are_delimited() {
+        let options = RequestOptions { max_tokens: Some(100), ..Default::default() };
+        let request = format_request("generate", "gpt-4o", "Plan a todo app", Some(&options));
+        assert!(request.starts_with("──── generate request to gpt-4o ────\n"));
+        assert!(request.contains("max_tokens: Some(100)"));
+        assert!(request.ends_with("prompt:\nPlan a todo app"));
+
+        assert_eq!(format_response(&Ok("Done")), "──── response ────\nDone\n──── end ────");
+        let failed: Result<String> = Err(ToolkitError::Api("bad request".to_string()));
+        assert!(format_response(&failed).starts_with("──── error ────\nAPI error: bad request"));
+    }
+}
@@ -4,9 +4,11 @@ use crate::error::Result;
 use colored::Colorize;
 use dialoguer::{theme::ColorfulTheme, Select};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
+use once_cell::sync::OnceCell;
 use log::{debug, warn};
 use reqwest;
 use serde_json;
@@ -14,8 +16,21 @@ use serde_json;
 // Environment variable names for API keys
 const ANTHROPIC_API_KEY_ENV: &str = "RUST_AI_TOOLKIT_ANTHROPIC_API_KEY";
 const OPENAI_API_KEY_ENV: &str = "RUST_AI_TOOLKIT_OPENAI_API_KEY";
+const AZURE_OPENAI_API_KEY_ENV: &str = "RUST_AI_TOOLKIT_AZURE_OPENAI_API_KEY";
 const CUSTOM_API_KEY_ENV: &str = "RUST_AI_TOOLKIT_CUSTOM_API_KEY";
 
+/// Environment variable that overrides the configuration directory
+pub const CONFIG_DIR_ENV: &str = "RUST_AI_TOOLKIT_CONFIG_DIR";
+
+/// Prefix of the environment variables that override config fields
+pub const CONFIG_ENV_PREFIX: &str = "RUST_AI_TOOLKIT_";
+
+/// Name of the configuration directory inside the home directory
+const DEFAULT_CONFIG_DIR_NAME: &str = ".rust-ai-toolkit";
+
+/// Configuration directory set with the `--config-dir` flag
+static CONFIG_DIR_OVERRIDE: OnceCell<PathBuf> = OnceCell::new();
+
 // Extension trait for String and &str to work with colored crate
 pub trait ColorizeExt {
     fn green(&self) -> String;
@@ -131,6 +146,12 @@ pub struct Config {
     pub base_url: Option<String>,
     pub model: String,
     pub projects_dir: PathBuf,
+    /// Azure OpenAI deployment that requests are routed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    /// Azure OpenAI API version; defaults to a recent stable version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
     
     // New configuration options
     /// Cache TTL in seconds for project data
@@ -143,6 +164,125 @@ pub struct Config {
     pub log_level: String,
     /// Rate limit settings per minute for each provider
     pub rate_limits: ProviderRateLimits,
+    /// Maximum number of retries for transient AI request failures
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Timeout in seconds for requests to the AI provider
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// Maximum number of project files read at the same time
+    #[serde(default = "default_max_concurrent_io")]
+    pub max_concurrent_io: usize,
+    /// Idle connections kept open per host for later requests (0 closes them after each request)
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    /// Seconds an idle connection is kept open
+    #[serde(default = "default_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: u64,
+    /// Seconds between HTTP/2 keep-alive pings on open connections (0 disables them)
+    #[serde(default = "default_http2_keep_alive_secs")]
+    pub http2_keep_alive_secs: u64,
+    /// Shortest AI response, in characters, a stage accepts as its output (0 disables the check)
+    #[serde(default = "default_min_response_chars")]
+    pub min_response_chars: usize,
+    /// Send later stages a summary of long earlier outputs instead of the full text
+    #[serde(default)]
+    pub summarize_context: bool,
+    /// Estimated tokens above which an earlier output is summarized
+    #[serde(default = "default_summary_threshold_tokens")]
+    pub summary_threshold_tokens: usize,
+    /// Model that writes the summaries, e.g. a smaller one; defaults to `model`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_model: Option<String>,
+    /// URL that receives a JSON POST whenever a stage finishes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Text placed before every stage prompt, e.g. a compliance notice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_prefix: Option<String>,
+    /// Text placed after every stage prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_suffix: Option<String>,
+    /// Language stage output is written in, e.g. `French`
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Model to retry with, per provider, when the configured model is not found
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fallback_models: HashMap<String, String>,
+    /// Post-processors that save parts of completed stage output as files, e.g. `diagrams`
+    #[serde(default = "default_post_processors")]
+    pub post_processors: Vec<String>,
+    /// Dependencies replacing a stage's default ones, keyed by stage number, e.g. `5 = [2]`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub stage_overrides: HashMap<String, Vec<u8>>,
+    /// Extra stages declared by the user, run after the built-in stages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_stages: Vec<CustomStageConfig>,
+}
+
+/// A user-defined stage declared in the `[[custom_stages]]` config array
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CustomStageConfig {
+    /// Stage number; must be greater than the number of built-in stages
+    pub number: u8,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Name of the template to render for this stage
+    pub template: String,
+    /// Stage numbers that must be completed before this stage can run
+    #[serde(default)]
+    pub depends_on: Vec<u8>,
+    /// JSON schema file the stage's output must match, relative to the config directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<PathBuf>,
+    /// How many times to ask again, with the schema errors, when the output doesn't match
+    #[serde(default)]
+    pub schema_retries: u32,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_request_timeout_secs() -> u64 {
+    300
+}
+
+fn default_max_concurrent_io() -> usize {
+    16
+}
+
+pub(crate) fn default_pool_max_idle_per_host() -> usize {
+    8
+}
+
+pub(crate) fn default_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+pub(crate) fn default_http2_keep_alive_secs() -> u64 {
+    30
+}
+
+pub(crate) fn default_min_response_chars() -> usize {
+    200
+}
+
+fn default_summary_threshold_tokens() -> usize {
+    2000
+}
+
+fn default_language() -> String {
+    "English".to_string()
+}
+
+pub(crate) fn default_post_processors() -> Vec<String> {
+    vec!["diagrams".to_string()]
+}
+
+fn default_azure_openai_rpm() -> u32 {
+    60
 }
 
 /// Rate limit settings for different providers
@@ -150,7 +290,16 @@ pub struct Config {
 pub struct ProviderRateLimits {
     pub anthropic: u32,
     pub openai: u32,
+    #[serde(default = "default_azure_openai_rpm")]
+    pub azure_openai: u32,
     pub custom: u32,
+    /// Tokens per minute allowed for the active provider (0 disables the limit)
+    #[serde(default = "default_tokens_per_minute")]
+    pub tokens_per_minute: u32,
+}
+
+fn default_tokens_per_minute() -> u32 {
+    40_000
 }
 
 impl Default for ProviderRateLimits {
@@ -158,15 +307,18 @@ impl Default for ProviderRateLimits {
         Self {
             anthropic: 30,  // 30 requests per minute
             openai: 60,     // 60 requests per minute
+            azure_openai: default_azure_openai_rpm(),
             custom: 30,     // 30 requests per minute
+            tokens_per_minute: default_tokens_per_minute(),
         }
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
-        let home_dir = dirs::home_dir().expect("Could not find home directory");
-        let projects_dir = home_dir.join(".rust-ai-toolkit").join("projects");
+        let projects_dir = config_dir_path()
+            .expect("Could not find home directory")
+            .join("projects");
         
         Self {
             provider: "anthropic".to_string(),
@@ -174,6 +326,8 @@ impl Default for Config {
             base_url: None,
             model: "claude-3-7-sonnet-20250219".to_string(),
             projects_dir,
+            deployment: None,
+            api_version: None,
             
             // Default values for new options
             project_cache_ttl: 3600,        // 1 hour
@@ -181,6 +335,24 @@ impl Default for Config {
             max_cache_size_mb: 1000,        // 1 GB
             log_level: "info".to_string(),
             rate_limits: ProviderRateLimits::default(),
+            max_retries: default_max_retries(),
+            request_timeout_secs: default_request_timeout_secs(),
+            max_concurrent_io: default_max_concurrent_io(),
+            pool_max_idle_per_host: default_pool_max_idle_per_host(),
+            pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+            http2_keep_alive_secs: default_http2_keep_alive_secs(),
+            min_response_chars: default_min_response_chars(),
+            summarize_context: false,
+            summary_threshold_tokens: default_summary_threshold_tokens(),
+            summary_model: None,
+            webhook_url: None,
+            prompt_prefix: None,
+            prompt_suffix: None,
+            language: default_language(),
+            fallback_models: HashMap::new(),
+            post_processors: default_post_processors(),
+            stage_overrides: HashMap::new(),
+            custom_stages: Vec::new(),
         }
     }
 }
@@ -201,15 +373,44 @@ fn get_env_var_name(provider: &str) -> &'static str {
     match provider {
         "anthropic" | "anthropic_enhanced" => ANTHROPIC_API_KEY_ENV,
         "openai" => OPENAI_API_KEY_ENV,
-        _ => CUSTOM_API_KEY_ENV,
+        "azure_openai" => AZURE_OPENAI_API_KEY_ENV,
+        other => crate::ai::openai_preset(other)
+            .map(|preset| preset.api_key_env)
+            .unwrap_or(CUSTOM_API_KEY_ENV),
     }
 }
 
 pub fn get_config() -> Result<Config> {
+    let mut config = read_config_file()?;
+    
+    apply_env_overrides(&mut config, |name| env::var(name).ok())?;
+    
+    let problems = crate::stages::dependency_override_problems(&config);
+    if !problems.is_empty() {
+        return Err(crate::error::ToolkitError::Config(format!("Invalid stage_overrides: {}", problems.join("; "))));
+    }
+    
+    // Check for API key in environment variables
+    let env_var_name = get_env_var_name(&config.provider);
+    if let Ok(api_key) = env::var(env_var_name) {
+        if !api_key.is_empty() {
+            debug!("Using API key from environment variable: {}", env_var_name);
+            config.api_key = api_key;
+        }
+    } else if !config.api_key.is_empty() {
+        // If we're using an API key from config, warn the user
+        warn!("Using API key from config file. Consider using environment variable {} for better security.", env_var_name);
+    }
+    
+    Ok(config)
+}
+
+/// Read `config.toml` as saved, without environment variable overrides
+fn read_config_file() -> Result<Config> {
     let config_dir = get_config_dir()?;
     let config_path = config_dir.join("config.toml");
     
-    let mut config = if !config_path.exists() {
+    let config = if !config_path.exists() {
         Config::default()
     } else {
         // Try to parse the existing config
@@ -224,19 +425,74 @@ pub fn get_config() -> Result<Config> {
         }
     };
     
-    // Check for API key in environment variables
-    let env_var_name = get_env_var_name(&config.provider);
-    if let Ok(api_key) = env::var(env_var_name) {
-        if !api_key.is_empty() {
-            debug!("Using API key from environment variable: {}", env_var_name);
-            config.api_key = api_key;
-        }
-    } else if !config.api_key.is_empty() {
-        // If we're using an API key from config, warn the user
-        warn!("Using API key from config file. Consider using environment variable {} for better security.", env_var_name);
+    Ok(config)
+}
+
+/// Read an override for a config field, e.g. `RUST_AI_TOOLKIT_MODEL` for `model`
+///
+/// Unset and empty variables don't override anything.
+fn env_override<T: std::str::FromStr>(lookup: &impl Fn(&str) -> Option<String>, field: &str) -> Result<Option<T>> {
+    let name = format!("{}{}", CONFIG_ENV_PREFIX, field.to_uppercase());
+    let value = match lookup(&name).map(|value| value.trim().to_string()) {
+        Some(value) if !value.is_empty() => value,
+        _ => return Ok(None),
+    };
+    
+    debug!("Using {} from the environment", name);
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| crate::error::ToolkitError::Config(format!("{} has an invalid value: {}", name, value)))
+}
+
+/// Override config fields from `RUST_AI_TOOLKIT_*` environment variables
+///
+/// Every field except `custom_stages` and `stage_overrides` can be set, named after the field in
+/// upper case: `RUST_AI_TOOLKIT_PROVIDER`, `RUST_AI_TOOLKIT_MODEL`,
+/// `RUST_AI_TOOLKIT_BASE_URL` and so on. `RUST_AI_TOOLKIT_FALLBACK_MODEL`
+/// sets the fallback model of the active provider. Rate limits use a `RATE_LIMIT_`
+/// prefix, e.g. `RUST_AI_TOOLKIT_RATE_LIMIT_OPENAI`, except
+/// `RUST_AI_TOOLKIT_TOKENS_PER_MINUTE`. API keys keep their per-provider
+/// variables. `lookup` reads a variable, so tests don't touch the process
+/// environment.
+fn apply_env_overrides(config: &mut Config, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+    if let Some(value) = env_override(&lookup, "provider")? { config.provider = value; }
+    if let Some(value) = env_override(&lookup, "model")? { config.model = value; }
+    if let Some(value) = env_override(&lookup, "base_url")? { config.base_url = Some(value); }
+    if let Some(value) = env_override(&lookup, "projects_dir")? { config.projects_dir = value; }
+    if let Some(value) = env_override(&lookup, "deployment")? { config.deployment = Some(value); }
+    if let Some(value) = env_override(&lookup, "api_version")? { config.api_version = Some(value); }
+    if let Some(value) = env_override(&lookup, "project_cache_ttl")? { config.project_cache_ttl = value; }
+    if let Some(value) = env_override(&lookup, "response_cache_ttl")? { config.response_cache_ttl = value; }
+    if let Some(value) = env_override(&lookup, "max_cache_size_mb")? { config.max_cache_size_mb = value; }
+    if let Some(value) = env_override(&lookup, "log_level")? { config.log_level = value; }
+    if let Some(value) = env_override(&lookup, "max_retries")? { config.max_retries = value; }
+    if let Some(value) = env_override(&lookup, "request_timeout_secs")? { config.request_timeout_secs = value; }
+    if let Some(value) = env_override(&lookup, "max_concurrent_io")? { config.max_concurrent_io = value; }
+    if let Some(value) = env_override(&lookup, "pool_max_idle_per_host")? { config.pool_max_idle_per_host = value; }
+    if let Some(value) = env_override(&lookup, "pool_idle_timeout_secs")? { config.pool_idle_timeout_secs = value; }
+    if let Some(value) = env_override(&lookup, "http2_keep_alive_secs")? { config.http2_keep_alive_secs = value; }
+    if let Some(value) = env_override(&lookup, "min_response_chars")? { config.min_response_chars = value; }
+    if let Some(value) = env_override(&lookup, "summarize_context")? { config.summarize_context = value; }
+    if let Some(value) = env_override(&lookup, "summary_threshold_tokens")? { config.summary_threshold_tokens = value; }
+    if let Some(value) = env_override(&lookup, "summary_model")? { config.summary_model = Some(value); }
+    if let Some(value) = env_override(&lookup, "webhook_url")? { config.webhook_url = Some(value); }
+    if let Some(value) = env_override(&lookup, "prompt_prefix")? { config.prompt_prefix = Some(value); }
+    if let Some(value) = env_override(&lookup, "prompt_suffix")? { config.prompt_suffix = Some(value); }
+    if let Some(value) = env_override(&lookup, "language")? { config.language = value; }
+    if let Some(value) = env_override(&lookup, "fallback_model")? { config.fallback_models.insert(config.provider.clone(), value); }
+    if let Some(value) = env_override::<String>(&lookup, "post_processors")? {
+        config.post_processors = value.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect();
     }
     
-    Ok(config)
+    let limits = &mut config.rate_limits;
+    if let Some(value) = env_override(&lookup, "rate_limit_anthropic")? { limits.anthropic = value; }
+    if let Some(value) = env_override(&lookup, "rate_limit_openai")? { limits.openai = value; }
+    if let Some(value) = env_override(&lookup, "rate_limit_azure_openai")? { limits.azure_openai = value; }
+    if let Some(value) = env_override(&lookup, "rate_limit_custom")? { limits.custom = value; }
+    if let Some(value) = env_override(&lookup, "tokens_per_minute")? { limits.tokens_per_minute = value; }
+    
+    Ok(())
 }
 
 /// Migrate from an older config version to the current version
@@ -263,11 +519,31 @@ fn migrate_config(content: &str, config_path: &PathBuf) -> Result<Config> {
         base_url: legacy_config.base_url,
         model: legacy_config.model,
         projects_dir: legacy_config.projects_dir,
+        deployment: None,
+        api_version: None,
         project_cache_ttl: 3600,        // 1 hour
         response_cache_ttl: 3600,       // 1 hour
         max_cache_size_mb: 1000,        // 1 GB
         log_level: "info".to_string(),
         rate_limits: ProviderRateLimits::default(),
+        max_retries: default_max_retries(),
+        request_timeout_secs: default_request_timeout_secs(),
+        max_concurrent_io: default_max_concurrent_io(),
+        pool_max_idle_per_host: default_pool_max_idle_per_host(),
+        pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+        http2_keep_alive_secs: default_http2_keep_alive_secs(),
+        min_response_chars: default_min_response_chars(),
+        summarize_context: false,
+        summary_threshold_tokens: default_summary_threshold_tokens(),
+        summary_model: None,
+        webhook_url: None,
+        prompt_prefix: None,
+        prompt_suffix: None,
+        language: default_language(),
+        fallback_models: HashMap::new(),
+        post_processors: default_post_processors(),
+        stage_overrides: HashMap::new(),
+        custom_stages: Vec::new(),
     };
     
     // Save the migrated config
@@ -301,12 +577,157 @@ pub fn save_config(config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn get_config_dir() -> Result<PathBuf> {
-    let home_dir = dirs::home_dir().ok_or_else(|| {
-        crate::error::ToolkitError::Config("Could not find home directory".to_string())
+/// Write the saved configuration to `path` for sharing
+///
+/// Environment variable overrides aren't exported. The API key is left out
+/// unless `include_key` is set.
+pub fn export_config(path: &Path, include_key: bool) -> Result<()> {
+    let config = read_config_file()?;
+    fs::write(path, exported_config(config, include_key)?)?;
+    Ok(())
+}
+
+/// Render a configuration as an exported TOML file
+fn exported_config(mut config: Config, include_key: bool) -> Result<String> {
+    let mut content = String::new();
+    if !include_key && !config.api_key.is_empty() {
+        config.api_key.clear();
+        content.push_str(&format!(
+            "# The API key was left out. Set {} or run `rust-ai-toolkit config` to add one.\n",
+            get_env_var_name(&config.provider)
+        ));
+    }
+    content.push_str(&toml::to_string(&config).map_err(|e| {
+        crate::error::ToolkitError::Config(format!("Failed to serialize config: {}", e))
+    })?);
+    Ok(content)
+}
+
+/// Validate the configuration in `path` and save it as this machine's configuration
+///
+/// A file without an API key keeps the one already saved.
+///
+/// # Errors
+///
+/// Returns a `Config` error listing every invalid setting; nothing is saved
+/// unless all of them are valid.
+pub fn import_config(path: &Path) -> Result<Config> {
+    let content = fs::read_to_string(path)?;
+    let mut config = toml::from_str::<Config>(&content).map_err(|e| {
+        crate::error::ToolkitError::Config(format!("Failed to parse {}: {}", path.display(), e))
     })?;
     
-    let config_dir = home_dir.join(".rust-ai-toolkit");
+    let problems = config_problems(&config);
+    if !problems.is_empty() {
+        return Err(crate::error::ToolkitError::Config(format!(
+            "Invalid settings in {}: {}",
+            path.display(),
+            problems.join("; ")
+        )));
+    }
+    
+    if config.api_key.is_empty() {
+        config.api_key = read_config_file().map(|current| current.api_key).unwrap_or_default();
+    }
+    
+    save_config(&config)?;
+    Ok(config)
+}
+
+/// Check an imported configuration with the same rules as `config`, returning what's wrong
+fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    
+    let known_provider = matches!(config.provider.as_str(), "anthropic" | "anthropic_enhanced" | "openai" | "azure_openai" | "custom")
+        || crate::ai::openai_preset(&config.provider).is_some()
+        || (cfg!(feature = "test-utils") && config.provider == "mock");
+    if !known_provider {
+        problems.push(format!("unknown provider '{}'", config.provider));
+    } else if !is_valid_model(&config.model, &config.provider) {
+        problems.push(format!("model '{}' isn't offered by {}", config.model, config.provider));
+    }
+    if !config.api_key.is_empty() && !is_valid_api_key_format(&config.api_key, &config.provider) {
+        problems.push("the API key is too short".to_string());
+    }
+    for (name, url) in [("base_url", &config.base_url), ("webhook_url", &config.webhook_url)] {
+        if let Some(url) = url.as_deref().filter(|url| !is_valid_url(url)) {
+            problems.push(format!("{} '{}' must start with http:// or https://", name, url));
+        }
+    }
+    if !is_valid_log_level(&config.log_level) {
+        problems.push(format!("log_level '{}' must be trace, debug, info, warn or error", config.log_level));
+    }
+    for (name, ttl) in [("project_cache_ttl", config.project_cache_ttl), ("response_cache_ttl", config.response_cache_ttl)] {
+        if !is_valid_ttl(ttl) {
+            problems.push(format!("{} must be greater than 0", name));
+        }
+    }
+    if !is_valid_cache_size(config.max_cache_size_mb) {
+        problems.push(format!("max_cache_size_mb {} must be between 1 and 10000", config.max_cache_size_mb));
+    }
+    let limits = &config.rate_limits;
+    for (name, limit) in [("anthropic", limits.anthropic), ("openai", limits.openai), ("azure_openai", limits.azure_openai), ("custom", limits.custom)] {
+        if !is_valid_rate_limit(limit) {
+            problems.push(format!("rate_limits.{} {} must be between 1 and 1000", name, limit));
+        }
+    }
+    if !is_valid_max_retries(config.max_retries) {
+        problems.push(format!("max_retries {} must be 10 or fewer", config.max_retries));
+    }
+    if !is_valid_request_timeout(config.request_timeout_secs) {
+        problems.push(format!("request_timeout_secs {} must be between 5 and 600", config.request_timeout_secs));
+    }
+    for name in &config.post_processors {
+        if !crate::stages::postprocess::POST_PROCESSORS.contains(&name.as_str()) {
+            problems.push(format!(
+                "unknown post-processor '{}'; available: {}",
+                name,
+                crate::stages::postprocess::POST_PROCESSORS.join(", ")
+            ));
+        }
+    }
+    if let Some(model) = config.summary_model.as_deref().filter(|model| known_provider && !is_valid_model(model, &config.provider)) {
+        problems.push(format!("summary_model '{}' isn't offered by {}", model, config.provider));
+    }
+    problems.extend(crate::stages::dependency_override_problems(config));
+    
+    problems
+}
+
+/// Use a different configuration directory for the rest of the process
+///
+/// Set from the `--config-dir` flag and takes precedence over the
+/// `RUST_AI_TOOLKIT_CONFIG_DIR` environment variable. Only the first call has
+/// an effect.
+pub fn set_config_dir(dir: PathBuf) {
+    if CONFIG_DIR_OVERRIDE.set(dir).is_err() {
+        warn!("Configuration directory was already set; ignoring the new value");
+    }
+}
+
+/// Pick the configuration directory from the flag, the environment variable and the home directory, in that order
+fn resolve_config_dir(flag: Option<PathBuf>, env_dir: Option<PathBuf>, home_dir: Option<PathBuf>) -> Option<PathBuf> {
+    flag.or(env_dir)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .or_else(|| home_dir.map(|home| home.join(DEFAULT_CONFIG_DIR_NAME)))
+}
+
+/// Work out the toolkit's configuration directory without creating it
+fn config_dir_path() -> Result<PathBuf> {
+    resolve_config_dir(
+        CONFIG_DIR_OVERRIDE.get().cloned(),
+        env::var_os(CONFIG_DIR_ENV).map(PathBuf::from),
+        dirs::home_dir(),
+    )
+    .ok_or_else(|| crate::error::ToolkitError::Config("Could not find home directory".to_string()))
+}
+
+/// Get the toolkit's configuration directory, creating it if needed
+///
+/// This is `~/.rust-ai-toolkit` unless overridden with `--config-dir` or the
+/// `RUST_AI_TOOLKIT_CONFIG_DIR` environment variable.
+pub fn get_config_dir() -> Result<PathBuf> {
+    let config_dir = config_dir_path()?;
     
     if !config_dir.exists() {
         fs::create_dir_all(&config_dir)?;
@@ -322,9 +743,11 @@ enum ConfigStep {
     Model,
     BaseUrl,
     RateLimits,
+    Timeout,
     CacheTTL,
     CacheSize,
     LogLevel,
+    Notifications,
     Confirmation,
 }
 
@@ -336,10 +759,12 @@ impl ConfigStep {
             ConfigStep::ApiKey => ConfigStep::Model,
             ConfigStep::Model => ConfigStep::BaseUrl,
             ConfigStep::BaseUrl => ConfigStep::RateLimits,
-            ConfigStep::RateLimits => ConfigStep::CacheTTL,
+            ConfigStep::RateLimits => ConfigStep::Timeout,
+            ConfigStep::Timeout => ConfigStep::CacheTTL,
             ConfigStep::CacheTTL => ConfigStep::CacheSize,
             ConfigStep::CacheSize => ConfigStep::LogLevel,
-            ConfigStep::LogLevel => ConfigStep::Confirmation,
+            ConfigStep::LogLevel => ConfigStep::Notifications,
+            ConfigStep::Notifications => ConfigStep::Confirmation,
             ConfigStep::Confirmation => ConfigStep::Confirmation,
         }
     }
@@ -352,10 +777,12 @@ impl ConfigStep {
             ConfigStep::Model => ConfigStep::ApiKey,
             ConfigStep::BaseUrl => ConfigStep::Model,
             ConfigStep::RateLimits => ConfigStep::BaseUrl,
-            ConfigStep::CacheTTL => ConfigStep::RateLimits,
+            ConfigStep::Timeout => ConfigStep::RateLimits,
+            ConfigStep::CacheTTL => ConfigStep::Timeout,
             ConfigStep::CacheSize => ConfigStep::CacheTTL,
             ConfigStep::LogLevel => ConfigStep::CacheSize,
-            ConfigStep::Confirmation => ConfigStep::LogLevel,
+            ConfigStep::Notifications => ConfigStep::LogLevel,
+            ConfigStep::Confirmation => ConfigStep::Notifications,
         }
     }
 }
@@ -364,6 +791,9 @@ pub async fn configure_ai() -> Result<()> {
     use dialoguer::{Input, Password, Confirm};
     use colored::Colorize;
     
+    // The wizard reads the terminal throughout, so Ctrl-C quits it
+    let _waiting = crate::utils::cancel::waiting_for_input();
+    
     // Load current configuration
     let mut config = get_config()?;
     let mut current_step = ConfigStep::Provider;
@@ -386,27 +816,24 @@ pub async fn configure_ai() -> Result<()> {
                 }
                 
                 // Choose provider
-                let providers = vec!["Anthropic (Claude)", "Anthropic Enhanced (Claude Code)", "OpenAI", "Custom API"];
+                let mut providers = vec![
+                    ("anthropic", "Anthropic (Claude)"),
+                    ("anthropic_enhanced", "Anthropic Enhanced (Claude Code)"),
+                    ("openai", "OpenAI"),
+                    ("azure_openai", "Azure OpenAI"),
+                ];
+                providers.extend(crate::ai::OPENAI_COMPATIBLE_PRESETS.iter().map(|preset| (preset.name, preset.display_name)));
+                providers.push(("custom", "Custom API"));
+                
+                let labels: Vec<&str> = providers.iter().map(|(_, label)| *label).collect();
                 let provider_idx = Select::with_theme(&theme)
                     .with_prompt("Select AI provider")
-                    .default(match config.provider.as_str() {
-                        "anthropic" => 0,
-                        "anthropic_enhanced" => 1,
-                        "openai" => 2,
-                        "custom" => 3,
-                        _ => 0,
-                    })
-                    .items(&providers)
+                    .default(providers.iter().position(|(name, _)| *name == config.provider).unwrap_or(0))
+                    .items(&labels)
                     .interact()
                     .map_err(|e| crate::error::ToolkitError::Config(format!("Selection error: {}", e)))?;
                 
-                config.provider = match provider_idx {
-                    0 => "anthropic".to_string(),
-                    1 => "anthropic_enhanced".to_string(),
-                    2 => "openai".to_string(),
-                    3 => "custom".to_string(),
-                    _ => "anthropic".to_string(),
-                };
+                config.provider = providers[provider_idx].0.to_string();
                 
                 // Navigation options
                 let actions = vec!["Continue", "Back"];
@@ -438,9 +865,14 @@ pub async fn configure_ai() -> Result<()> {
                         println!("{}", "OpenAI API keys typically start with 'sk-'.".cyan());
                         println!("{}\n", "You can find your API key in the OpenAI dashboard: https://platform.openai.com/api-keys".cyan());
                     },
-                    _ => {
-                        println!("{}\n", "Enter the API key for your custom provider.".cyan());
-                    }
+                    "azure_openai" => {
+                        println!("{}", "Azure OpenAI uses one of the two keys of your Azure OpenAI resource.".cyan());
+                        println!("{}\n", "You can find them under 'Keys and Endpoint' for the resource in the Azure portal.".cyan());
+                    },
+                    other => match crate::ai::openai_preset(other) {
+                        Some(preset) => println!("{}\n", format!("Enter your {} API key.", preset.display_name).cyan()),
+                        None => println!("{}\n", "Enter the API key for your custom provider.".cyan()),
+                    },
                 }
                 
                 // Show current value if any (masked)
@@ -529,7 +961,7 @@ pub async fn configure_ai() -> Result<()> {
                         "claude-3-sonnet-20240229",
                         "claude-3-haiku-20240307",
                     ],
-                    "openai" => vec![
+                    "openai" | "azure_openai" => vec![
                         "gpt-4o-2024-05-13",
                         "gpt-4-turbo-2024-04-09",
                         "gpt-4o",
@@ -537,7 +969,9 @@ pub async fn configure_ai() -> Result<()> {
                         "gpt-4",
                         "gpt-3.5-turbo",
                     ],
-                    _ => vec!["custom-model"],
+                    other => crate::ai::openai_preset(other)
+                        .map(|preset| preset.models.to_vec())
+                        .unwrap_or_else(|| vec!["custom-model"]),
                 };
                 
                 let default_idx = models.iter().position(|&m| m == config.model).unwrap_or(0);
@@ -552,7 +986,7 @@ pub async fn configure_ai() -> Result<()> {
                 config.model = models[model_idx].to_string();
                 
                 // Or allow custom input for model
-                if config.provider == "custom" {
+                if config.provider == "custom" || crate::ai::openai_preset(&config.provider).is_some() {
                     let custom_model = Input::<String>::with_theme(&theme)
                         .with_prompt("Or enter a custom model name")
                         .allow_empty(true)
@@ -578,6 +1012,12 @@ pub async fn configure_ai() -> Result<()> {
                     }
                 }
                 
+                // Stage 7 asks for its user stories as schema-constrained JSON
+                let structured = crate::ai::structured_output_capability(&config.provider);
+                if !crate::ai::model_supports(&config.model, structured) {
+                    println!("{}", format!("Warning: {} does not support {}, which Stage 7 (User Story Extraction) needs.", config.model, structured).yellow());
+                }
+                
                 // Navigation options
                 let actions = vec!["Continue", "Back"];
                 let action_idx = Select::with_theme(&theme)
@@ -613,11 +1053,16 @@ pub async fn configure_ai() -> Result<()> {
                 let default_url = match config.provider.as_str() {
                     "anthropic" | "anthropic_enhanced" => "https://api.anthropic.com/v1",
                     "openai" => "https://api.openai.com/v1",
-                    _ => "",
+                    "azure_openai" => "https://<resource>.openai.azure.com",
+                    other => crate::ai::openai_preset(other).map_or("", |preset| preset.base_url),
                 };
                 
+                if config.provider == "azure_openai" {
+                    println!("{}\n", "Azure OpenAI needs your resource endpoint, e.g. https://my-resource.openai.azure.com".cyan());
+                }
+                
                 // Ask if they want to use a custom base URL
-                let use_custom_url = config.provider == "custom" || 
+                let use_custom_url = matches!(config.provider.as_str(), "custom" | "azure_openai") || 
                     Confirm::with_theme(&theme)
                         .with_prompt("Do you want to use a custom base URL?")
                         .default(config.base_url.is_some())
@@ -652,6 +1097,32 @@ pub async fn configure_ai() -> Result<()> {
                     println!("Using default base URL for {}", config.provider);
                 }
                 
+                // Azure routes requests by deployment rather than by model
+                if config.provider == "azure_openai" {
+                    let deployment = Input::<String>::with_theme(&theme)
+                        .with_prompt("Deployment name")
+                        .with_initial_text(config.deployment.clone().unwrap_or_default())
+                        .validate_with(|input: &String| {
+                            if input.trim().is_empty() {
+                                Err("Deployment name is required")
+                            } else {
+                                Ok(())
+                            }
+                        })
+                        .interact()
+                        .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                    config.deployment = Some(deployment.trim().to_string());
+                    
+                    let api_version = Input::<String>::with_theme(&theme)
+                        .with_prompt("API version")
+                        .with_initial_text(config.api_version.clone().unwrap_or_else(|| {
+                            crate::ai::AZURE_OPENAI_API_VERSION.to_string()
+                        }))
+                        .interact()
+                        .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                    config.api_version = Some(api_version.trim().to_string()).filter(|v| !v.is_empty());
+                }
+                
                 // Navigation options
                 let actions = vec!["Continue", "Back"];
                 let action_idx = Select::with_theme(&theme)
@@ -680,7 +1151,9 @@ pub async fn configure_ai() -> Result<()> {
                 println!("Current rate limits (requests per minute):");
                 println!("  - Anthropic: {}", config.rate_limits.anthropic.to_string().yellow());
                 println!("  - OpenAI: {}", config.rate_limits.openai.to_string().yellow());
-                println!("  - Custom: {}\n", config.rate_limits.custom.to_string().yellow());
+                println!("  - Azure OpenAI: {}", config.rate_limits.azure_openai.to_string().yellow());
+                println!("  - Custom: {}", config.rate_limits.custom.to_string().yellow());
+                println!("Current token limit: {} tokens per minute\n", config.rate_limits.tokens_per_minute.to_string().yellow());
                 
                 // Configure rate limits for each provider
                 println!("Configure rate limits for each provider (requests per minute):");
@@ -711,6 +1184,19 @@ pub async fn configure_ai() -> Result<()> {
                     .interact()
                     .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
                 
+                let azure_openai_rate = Input::<u32>::with_theme(&theme)
+                    .with_prompt("Azure OpenAI rate limit")
+                    .with_initial_text(config.rate_limits.azure_openai.to_string())
+                    .validate_with(|input: &u32| {
+                        if is_valid_rate_limit(*input) {
+                            Ok(())
+                        } else {
+                            Err("Rate limit must be between 1 and 1000")
+                        }
+                    })
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                
                 let custom_rate = Input::<u32>::with_theme(&theme)
                     .with_prompt("Custom provider rate limit")
                     .with_initial_text(config.rate_limits.custom.to_string())
@@ -726,8 +1212,69 @@ pub async fn configure_ai() -> Result<()> {
                 
                 config.rate_limits.anthropic = anthropic_rate;
                 config.rate_limits.openai = openai_rate;
+                config.rate_limits.azure_openai = azure_openai_rate;
                 config.rate_limits.custom = custom_rate;
                 
+                config.rate_limits.tokens_per_minute = Input::<u32>::with_theme(&theme)
+                    .with_prompt("Tokens per minute limit (0 to disable)")
+                    .with_initial_text(config.rate_limits.tokens_per_minute.to_string())
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                
+                config.max_retries = Input::<u32>::with_theme(&theme)
+                    .with_prompt("Maximum retries for failed requests")
+                    .with_initial_text(config.max_retries.to_string())
+                    .validate_with(|input: &u32| {
+                        if is_valid_max_retries(*input) {
+                            Ok(())
+                        } else {
+                            Err("Maximum retries must be between 0 and 10")
+                        }
+                    })
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                
+                // Navigation options
+                let actions = vec!["Continue", "Back"];
+                let action_idx = Select::with_theme(&theme)
+                    .with_prompt("What would you like to do?")
+                    .default(0)
+                    .items(&actions)
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Selection error: {}", e)))?;
+                
+                current_step = match action_idx {
+                    0 => current_step.next(),
+                    1 => current_step.prev(),
+                    _ => current_step.next(),
+                };
+            },
+            
+            ConfigStep::Timeout => {
+                // Display header
+                println!("\n{}\n", "Request Timeout Configuration".green().bold());
+                
+                // Help text
+                println!("{}", "Configure how long to wait for the AI provider to respond.".cyan());
+                println!("{}\n", "Shorter timeouts fail faster in interactive use; long generations may need more time.".cyan());
+                
+                // Show current value
+                println!("Current request timeout: {} seconds\n", config.request_timeout_secs.to_string().yellow());
+                
+                // Configure the timeout
+                config.request_timeout_secs = Input::<u64>::with_theme(&theme)
+                    .with_prompt("Request timeout (seconds)")
+                    .with_initial_text(config.request_timeout_secs.to_string())
+                    .validate_with(|input: &u64| {
+                        if is_valid_request_timeout(*input) {
+                            Ok(())
+                        } else {
+                            Err("Request timeout must be between 5 and 600 seconds")
+                        }
+                    })
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                
                 // Navigation options
                 let actions = vec!["Continue", "Back"];
                 let action_idx = Select::with_theme(&theme)
@@ -886,6 +1433,50 @@ pub async fn configure_ai() -> Result<()> {
                 };
             },
             
+            ConfigStep::Notifications => {
+                // Display header
+                println!("\n{}\n", "Notification Configuration".green().bold());
+                
+                // Help text
+                println!("{}", "Optionally post a JSON message to a webhook (e.g. Slack or Discord) whenever a stage finishes.".cyan());
+                println!("{}\n", "Leave the URL empty to turn notifications off.".cyan());
+                
+                // Show current value
+                println!("Current webhook URL: {}\n", config.webhook_url.clone().unwrap_or_else(|| "none".to_string()).yellow());
+                
+                // Configure the webhook
+                let webhook_url = Input::<String>::with_theme(&theme)
+                    .with_prompt("Webhook URL")
+                    .with_initial_text(config.webhook_url.clone().unwrap_or_default())
+                    .allow_empty(true)
+                    .validate_with(|input: &String| {
+                        if input.trim().is_empty() || is_valid_url(input.trim()) {
+                            Ok(())
+                        } else {
+                            Err("URLs should start with http:// or https://")
+                        }
+                    })
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Input error: {}", e)))?;
+                
+                config.webhook_url = Some(webhook_url.trim().to_string()).filter(|url| !url.is_empty());
+                
+                // Navigation options
+                let actions = vec!["Continue", "Back"];
+                let action_idx = Select::with_theme(&theme)
+                    .with_prompt("What would you like to do?")
+                    .default(0)
+                    .items(&actions)
+                    .interact()
+                    .map_err(|e| crate::error::ToolkitError::Config(format!("Selection error: {}", e)))?;
+                
+                current_step = match action_idx {
+                    0 => current_step.next(),
+                    1 => current_step.prev(),
+                    _ => current_step.next(),
+                };
+            },
+            
             ConfigStep::Confirmation => {
                 // Display header
                 println!("\n{}\n", "Configuration Summary".green().bold());
@@ -895,15 +1486,45 @@ pub async fn configure_ai() -> Result<()> {
                 println!("API Key: {}", mask_api_key(&config.api_key).yellow());
                 println!("Model: {}", config.model.yellow());
                 println!("Base URL: {}", config.base_url.clone().unwrap_or_else(|| "default".to_string()).yellow());
+                if config.provider == "azure_openai" {
+                    println!("Deployment: {}", config.deployment.clone().unwrap_or_default().yellow());
+                    println!("API version: {}", config.api_version.clone().unwrap_or_else(|| crate::ai::AZURE_OPENAI_API_VERSION.to_string()).yellow());
+                }
                 println!("\nRate limits (requests per minute):");
                 println!("  - Anthropic: {}", config.rate_limits.anthropic.to_string().yellow());
                 println!("  - OpenAI: {}", config.rate_limits.openai.to_string().yellow());
+                println!("  - Azure OpenAI: {}", config.rate_limits.azure_openai.to_string().yellow());
                 println!("  - Custom: {}", config.rate_limits.custom.to_string().yellow());
+                println!("Token limit: {} tokens per minute", config.rate_limits.tokens_per_minute.to_string().yellow());
+                println!("Maximum retries: {}", config.max_retries.to_string().yellow());
+                println!("Request timeout: {} seconds", config.request_timeout_secs.to_string().yellow());
+                println!("Concurrent project reads: {}", config.max_concurrent_io.to_string().yellow());
+                println!(
+                    "Connection pool: {} idle per host, kept {} seconds, HTTP/2 pings every {} seconds",
+                    config.pool_max_idle_per_host.to_string().yellow(),
+                    config.pool_idle_timeout_secs.to_string().yellow(),
+                    config.http2_keep_alive_secs.to_string().yellow()
+                );
+                println!("Minimum response length: {} characters", config.min_response_chars.to_string().yellow());
+                if config.summarize_context {
+                    println!(
+                        "Context summaries: outputs over {} tokens, written by {}",
+                        config.summary_threshold_tokens.to_string().yellow(),
+                        config.summary_model.as_deref().unwrap_or(&config.model).yellow()
+                    );
+                }
                 println!("\nCache settings:");
                 println!("  - Project cache TTL: {} seconds", config.project_cache_ttl.to_string().yellow());
                 println!("  - Response cache TTL: {} seconds", config.response_cache_ttl.to_string().yellow());
                 println!("  - Maximum cache size: {} MB", config.max_cache_size_mb.to_string().yellow());
                 println!("\nLog level: {}", config.log_level.yellow());
+                println!("Webhook URL: {}", config.webhook_url.clone().unwrap_or_else(|| "none".to_string()).yellow());
+                println!("Prompt prefix: {}", config.prompt_prefix.clone().unwrap_or_else(|| "none".to_string()).yellow());
+                println!("Prompt suffix: {}", config.prompt_suffix.clone().unwrap_or_else(|| "none".to_string()).yellow());
+                println!("Output language: {}", config.language.yellow());
+                println!("Fallback model: {}", config.fallback_models.get(&config.provider).cloned().unwrap_or_else(|| "none".to_string()).yellow());
+                let post_processors = if config.post_processors.is_empty() { "none".to_string() } else { config.post_processors.join(", ") };
+                println!("Post-processors: {}", post_processors.yellow());
                 
                 // Ask if they want to validate the API key
                 let validate_key = if !config.api_key.is_empty() {
@@ -918,7 +1539,7 @@ pub async fn configure_ai() -> Result<()> {
                 
                 if validate_key {
                     println!("Validating API key with a test request...");
-                    match test_api_key(&config.provider, &config.api_key, &config.model, config.base_url.clone()).await {
+                    match test_api_key(&config).await {
                         Ok(_) => {
                             println!("{}", "API key validation successful!".green());
                         },
@@ -954,9 +1575,8 @@ pub async fn configure_ai() -> Result<()> {
                     println!("\n{}", "Configuration saved successfully.".green());
                     
                     // Configure rate limiter with new settings
-                    crate::utils::rate_limiter::set_rate_limit("anthropic", config.rate_limits.anthropic);
-                    crate::utils::rate_limiter::set_rate_limit("openai", config.rate_limits.openai);
-                    crate::utils::rate_limiter::set_rate_limit("custom", config.rate_limits.custom);
+                    crate::utils::rate_limiter::configure(&config);
+                    crate::utils::rate_limiter::set_token_limit(&config.provider, config.rate_limits.tokens_per_minute);
                     
                     break; // Exit the loop
                 } else {
@@ -1036,17 +1656,21 @@ fn is_valid_api_key_format(api_key: &str, provider: &str) -> bool {
 /// # Returns
 ///
 /// `true` if the model is valid for the provider, `false` otherwise.
-fn is_valid_model(model: &str, provider: &str) -> bool {
+pub(crate) fn is_valid_model(model: &str, provider: &str) -> bool {
     match provider {
         "anthropic" | "anthropic_enhanced" => {
             // Anthropic models
             model.contains("claude")
         }
-        "openai" => {
+        "openai" | "azure_openai" => {
             // OpenAI models
             model.contains("gpt")
         }
-        _ => true, // For custom providers, accept any model name
+        other => match crate::ai::openai_preset(other) {
+            // Presets list the models they offer
+            Some(preset) => preset.models.contains(&model),
+            None => true, // For custom providers, accept any model name
+        },
     }
 }
 
@@ -1102,27 +1726,58 @@ fn is_valid_rate_limit(rate_limit: u32) -> bool {
     rate_limit >= 1 && rate_limit <= 1000
 }
 
+/// Validates the maximum number of retries.
+///
+/// # Parameters
+///
+/// * `max_retries` - The number of retries to validate.
+///
+/// # Returns
+///
+/// `true` if the value is valid (10 or fewer), `false` otherwise.
+fn is_valid_max_retries(max_retries: u32) -> bool {
+    max_retries <= 10
+}
+
+/// Validates a request timeout in seconds.
+///
+/// # Parameters
+///
+/// * `secs` - The timeout to validate.
+///
+/// # Returns
+///
+/// `true` if the timeout is valid (between 5 and 600 seconds), `false` otherwise.
+fn is_valid_request_timeout(secs: u64) -> bool {
+    (5..=600).contains(&secs)
+}
+
 /// Tests API key validity by making a test request to the provider's API.
 ///
 /// # Parameters
 ///
-/// * `provider` - The provider name.
-/// * `api_key` - The API key to test.
-/// * `model` - The model to use for the test.
-/// * `base_url` - Optional base URL for the API.
+/// * `config` - The configuration being edited, with the provider, API key,
+///   model, base URL and, for Azure OpenAI, the deployment to test.
 ///
 /// # Returns
 ///
 /// `Ok(())` if the API key is valid, an error otherwise.
-async fn test_api_key(provider: &str, api_key: &str, model: &str, base_url: Option<String>) -> Result<()> {
+async fn test_api_key(config: &Config) -> Result<()> {
     use crate::error::ToolkitError;
     
+    let provider = config.provider.as_str();
+    let api_key = config.api_key.as_str();
+    let model = config.model.as_str();
+    let base_url = config.base_url.clone();
+    
     // Create a temporary config with the provided values
     let mut temp_config = Config::default();
     temp_config.provider = provider.to_string();
     temp_config.api_key = api_key.to_string();
     temp_config.model = model.to_string();
     temp_config.base_url = base_url.clone(); // Clone here to avoid move
+    temp_config.deployment = config.deployment.clone();
+    temp_config.api_version = config.api_version.clone();
     
     // Temporarily save the config
     let config_dir = get_config_dir()?;
@@ -1143,6 +1798,11 @@ async fn test_api_key(provider: &str, api_key: &str, model: &str, base_url: Opti
             top_p: None,
             timeout: Some(std::time::Duration::from_secs(10)),
             functions: None,
+            system: None,
+            provider_cache: false,
+            stop: None,
+            logit_bias: None,
+            seed: None,
         };
         
         // Create a minimal HTTP client to test the API key
@@ -1155,7 +1815,7 @@ async fn test_api_key(provider: &str, api_key: &str, model: &str, base_url: Opti
         match provider {
             "anthropic" | "anthropic_enhanced" => {
                 // Anthropic API test
-                let url = base_url.clone().unwrap_or_else(|| "https://api.anthropic.com/v1/messages".to_string());
+                let url = format!("{}/messages", crate::ai::resolve_base_url(base_url.as_deref(), "https://api.anthropic.com/v1"));
                 
                 let request_body = serde_json::json!({
                     "model": model,
@@ -1182,13 +1842,13 @@ async fn test_api_key(provider: &str, api_key: &str, model: &str, base_url: Opti
                     return Err(ToolkitError::Api(format!(
                         "API key validation failed ({}): {}",
                         status,
-                        error_text
+                        crate::ai::describe_error_body(&error_text)
                     )));
                 }
             },
             "openai" => {
                 // OpenAI API test
-                let url = base_url.clone().unwrap_or_else(|| "https://api.openai.com/v1/chat/completions".to_string());
+                let url = format!("{}/chat/completions", crate::ai::resolve_base_url(base_url.as_deref(), "https://api.openai.com/v1"));
                 
                 let request_body = serde_json::json!({
                     "model": model,
@@ -1214,12 +1874,84 @@ async fn test_api_key(provider: &str, api_key: &str, model: &str, base_url: Opti
                     return Err(ToolkitError::Api(format!(
                         "API key validation failed ({}): {}",
                         status,
-                        error_text
+                        crate::ai::describe_error_body(&error_text)
                     )));
                 }
             },
-            _ => {
-                return Err(ToolkitError::Config(format!("Unsupported provider for validation: {}", provider)));
+            "azure_openai" => {
+                // Azure OpenAI test against the configured deployment
+                let base_url = base_url.clone().ok_or_else(|| {
+                    ToolkitError::Config("Azure OpenAI needs your resource endpoint as the base URL".to_string())
+                })?;
+                let deployment = config.deployment.clone().ok_or_else(|| {
+                    ToolkitError::Config("Azure OpenAI needs a deployment name".to_string())
+                })?;
+                let api_version = config.api_version.clone()
+                    .unwrap_or_else(|| crate::ai::AZURE_OPENAI_API_VERSION.to_string());
+                let url = crate::ai::azure_chat_completions_url(&base_url, &deployment, &api_version);
+                
+                let request_body = serde_json::json!({
+                    "max_tokens": 10,
+                    "messages": [
+                        {"role": "user", "content": "test"}
+                    ]
+                });
+                
+                let response = client
+                    .post(&url)
+                    .header("api-key", api_key)
+                    .header("Content-Type", "application/json")
+                    .json(&request_body)
+                    .send()
+                    .await
+                    .map_err(|e| ToolkitError::Network(e.to_string()))?;
+                
+                // Capture status before consuming response with text()
+                let status = response.status();
+                if !status.is_success() {
+                    let error_text = response.text().await.unwrap_or_default();
+                    return Err(ToolkitError::Api(format!(
+                        "API key validation failed ({}): {}",
+                        status,
+                        crate::ai::describe_error_body(&error_text)
+                    )));
+                }
+            },
+            other => {
+                // OpenAI-compatible presets take the same request as OpenAI
+                let preset = crate::ai::openai_preset(other).ok_or_else(|| {
+                    ToolkitError::Config(format!("Unsupported provider for validation: {}", provider))
+                })?;
+                let base_url = base_url.clone().unwrap_or_else(|| preset.base_url.to_string());
+                let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+                
+                let request_body = serde_json::json!({
+                    "model": model,
+                    "max_tokens": 10,
+                    "messages": [
+                        {"role": "user", "content": "test"}
+                    ]
+                });
+                
+                let response = client
+                    .post(&url)
+                    .header("Authorization", format!("Bearer {}", api_key))
+                    .header("Content-Type", "application/json")
+                    .json(&request_body)
+                    .send()
+                    .await
+                    .map_err(|e| ToolkitError::Network(e.to_string()))?;
+                
+                // Capture status before consuming response with text()
+                let status = response.status();
+                if !status.is_success() {
+                    let error_text = response.text().await.unwrap_or_default();
+                    return Err(ToolkitError::Api(format!(
+                        "API key validation failed ({}): {}",
+                        status,
+                        crate::ai::describe_error_body(&error_text)
+                    )));
+                }
             }
         }
         
@@ -1232,4 +1964,92 @@ async fn test_api_key(provider: &str, api_key: &str, model: &str, base_url: Opti
     }
     
     result
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_overrides() {
+        let env: std::collections::HashMap<&str, &str> = [
+            ("RUST_AI_TOOLKIT_PROVIDER", "openai"),
+            ("RUST_AI_TOOLKIT_MODEL", "gpt-4o"),
+            ("RUST_AI_TOOLKIT_BASE_URL", ""),
+            ("RUST_AI_TOOLKIT_MAX_RETRIES", "5"),
+            ("RUST_AI_TOOLKIT_MAX_CONCURRENT_IO", "4"),
+            ("RUST_AI_TOOLKIT_RATE_LIMIT_OPENAI", "120"),
+            ("RUST_AI_TOOLKIT_FALLBACK_MODEL", "gpt-4o-mini"),
+        ]
+        .into_iter()
+        .collect();
+        let lookup = |name: &str| env.get(name).map(|value| value.to_string());
+
+        let mut config = Config::default();
+        apply_env_overrides(&mut config, lookup).unwrap();
+
+        assert_eq!(config.provider, "openai");
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.base_url, None);
+        assert_eq!(config.max_retries, 5);
+        assert_eq!(config.max_concurrent_io, 4);
+        assert_eq!(config.rate_limits.openai, 120);
+        assert_eq!(config.fallback_models["openai"], "gpt-4o-mini");
+        assert_eq!(config.log_level, "info");
+
+        let invalid = |name: &str| (name == "RUST_AI_TOOLKIT_MAX_RETRIES").then(|| "lots".to_string());
+        assert!(apply_env_overrides(&mut config, invalid).is_err());
+    }
+
+    #[test]
+    fn test_resolve_config_dir_precedence() {
+        let flag = Some(PathBuf::from("/tmp/flag"));
+        let env_dir = Some(PathBuf::from("/tmp/env"));
+        let home = Some(PathBuf::from("/home/user"));
+
+        assert_eq!(resolve_config_dir(flag, env_dir.clone(), home.clone()), Some(PathBuf::from("/tmp/flag")));
+        assert_eq!(resolve_config_dir(None, env_dir, home.clone()), Some(PathBuf::from("/tmp/env")));
+        assert_eq!(resolve_config_dir(None, None, home.clone()), Some(PathBuf::from("/home/user/.rust-ai-toolkit")));
+        assert_eq!(resolve_config_dir(None, Some(PathBuf::new()), home), Some(PathBuf::from("/home/user/.rust-ai-toolkit")));
+        assert_eq!(resolve_config_dir(None, None, None), None);
+    }
+    #[test]
+    fn test_exported_config_redacts_the_key() {
+        let config = Config { api_key: "sk-ant-secret-key".to_string(), ..Config::default() };
+        let exported = exported_config(config, false).unwrap();
+        assert!(!exported.contains("sk-ant-secret-key"));
+        assert!(exported.starts_with("# The API key was left out. Set RUST_AI_TOOLKIT_ANTHROPIC_API_KEY"));
+        assert_eq!(toml::from_str::<Config>(&exported).unwrap().api_key, "");
+
+        let config = Config { api_key: "sk-ant-secret-key".to_string(), ..Config::default() };
+        assert!(exported_config(config, true).unwrap().contains("sk-ant-secret-key"));
+    }
+
+    #[test]
+    fn test_config_problems() {
+        assert!(config_problems(&Config::default()).is_empty());
+
+        let mut config = Config {
+            model: "gpt-4o".to_string(),
+            webhook_url: Some("hooks.example.com".to_string()),
+            max_retries: 50,
+            ..Config::default()
+        };
+        config.rate_limits.openai = 0;
+        let problems = config_problems(&config);
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].contains("model 'gpt-4o' isn't offered by anthropic"));
+
+        config = Config { provider: "acme".to_string(), ..Config::default() };
+        assert_eq!(config_problems(&config), vec!["unknown provider 'acme'".to_string()]);
+    }
+
+    #[test]
+    fn test_preset_providers() {
+        assert!(is_valid_model("mistral-large-latest", "mistral"));
+        assert!(!is_valid_model("gpt-4o", "groq"));
+        assert!(is_valid_model("anything", "custom"));
+        assert_eq!(get_env_var_name("together"), "RUST_AI_TOOLKIT_TOGETHER_API_KEY");
+        assert_eq!(get_env_var_name("unknown"), CUSTOM_API_KEY_ENV);
+    }
+}
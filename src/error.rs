@@ -48,18 +48,37 @@ pub enum ToolkitError {
     #[error("Configuration error: {0}. Try running 'rust-ai-toolkit config' to reconfigure.")]
     Config(String),
     
+    /// The provider doesn't know the requested model, e.g. a retired
+    /// snapshot. `fallback` is set when the fallback model failed too.
+    #[error("{}", model_not_found_message(model, fallback.as_deref()))]
+    ModelNotFound { model: String, fallback: Option<String> },
+    
     /// Errors when a requested project cannot be found.
     #[error("Project not found: {0}. Check the project ID or look in the configured projects directory.")]
     ProjectNotFound(String),
     
+    /// Errors when another process holds the lock on a project.
+    #[error("Project is locked: {0}. Another process is saving it; wait for it to finish and try again.")]
+    ProjectLocked(String),
+    
     /// Errors when a requested stage is invalid.
-    #[error("Stage not found: {0}. Stages must be between 1 and 6.")]
+    #[error("Stage not found: {0}. Built-in stages are numbered 1 to 7; other stages must be declared in the config.")]
     StageNotFound(u8),
     
+    /// The provider failed with a 5xx status code. These are usually
+    /// temporary, so the request is worth retrying.
+    #[error("Server error {0}: {1}. The provider is having problems; try again shortly.")]
+    ServerError(u16, String),
+    
     /// Network-related errors, such as connection failures or timeouts.
     #[error("Network error: {0}. Please check your internet connection and try again.")]
     Network(String),
     
+    /// A streamed response was cut off by a network failure. `partial` holds
+    /// the text received before the connection dropped.
+    #[error("Network error: {message}. The response stream was interrupted after {} characters.", partial.chars().count())]
+    StreamInterrupted { message: String, partial: String },
+    
     /// Serialization or deserialization errors.
     #[error("Serialization error: {0}. The file might be corrupted or in an invalid format.")]
     Serialization(String),
@@ -76,6 +95,11 @@ pub enum ToolkitError {
     #[error("Template error: {0}. There was an issue with template rendering or loading.")]
     TemplateError(String),
     
+    /// A template was registered for this run but couldn't be saved, e.g.
+    /// because the templates directory is read-only.
+    #[error("Template '{name}' was not saved: {reason}. It only applies to this run; make the templates directory writable to keep it.")]
+    TemplateNotPersisted { name: String, reason: String },
+    
     /// Parsing errors, such as invalid JSON or TOML.
     #[error("Parse error: {0}. Failed to parse response or data.")]
     Parse(String),
@@ -84,11 +108,125 @@ pub enum ToolkitError {
     #[error("Rate limit exceeded: {0}. Please wait before making more requests.")]
     RateLimit(String),
     
+    /// The toolkit's own tokens-per-minute budget held the request back before
+    /// it was sent. The provider wasn't contacted.
+    #[error("Token budget exceeded: {0}. Wait for the budget to free up or raise tokens_per_minute in the config.")]
+    TokenBudget(String),
+    
+    /// The user interrupted the operation with Ctrl-C.
+    #[error("Cancelled by user")]
+    Cancelled,
+    
     /// Unknown or unexpected errors.
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
+impl ToolkitError {
+    /// Get a stable, machine-readable code for the error category.
+    ///
+    /// These codes are part of the CLI's JSON output and must not change.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            ToolkitError::Io(_) => "io",
+            ToolkitError::Api(_) => "api",
+            ToolkitError::ServerError(..) => "server_error",
+            ToolkitError::Config(_) => "config",
+            ToolkitError::ModelNotFound { .. } => "model_not_found",
+            ToolkitError::ProjectNotFound(_) => "project_not_found",
+            ToolkitError::StageNotFound(_) => "stage_not_found",
+            ToolkitError::ProjectLocked(_) => "project_locked",
+            ToolkitError::Network(_) => "network",
+            ToolkitError::StreamInterrupted { .. } => "stream_interrupted",
+            ToolkitError::Serialization(_) => "serialization",
+            ToolkitError::File(_) => "file",
+            ToolkitError::InvalidInput(_) => "invalid_input",
+            ToolkitError::TemplateError(_) => "template",
+            ToolkitError::TemplateNotPersisted { .. } => "template_not_persisted",
+            ToolkitError::Parse(_) => "parse",
+            ToolkitError::RateLimit(_) => "rate_limit",
+            ToolkitError::TokenBudget(_) => "token_budget",
+            ToolkitError::Cancelled => "cancelled",
+            ToolkitError::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Check whether the error is transient and the operation worth retrying.
+    ///
+    /// Rate limits, server errors, interrupted streams and requests held back
+    /// by the token budget are retryable.
+    /// Everything else (authentication failures, bad requests, parse errors) is not.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ToolkitError::RateLimit(_)
+                | ToolkitError::TokenBudget(_)
+                | ToolkitError::ServerError(..)
+                | ToolkitError::StreamInterrupted { .. }
+        )
+    }
+
+    /// Check whether the error came from the provider failing the request.
+    ///
+    /// Only 429 responses, server errors and interrupted streams count
+    /// towards a provider's circuit breaker. Requests the toolkit refused
+    /// itself, such as those held back by the token budget, never reached
+    /// the provider and don't count.
+    pub fn is_provider_failure(&self) -> bool {
+        matches!(
+            self,
+            ToolkitError::RateLimit(_) | ToolkitError::ServerError(..) | ToolkitError::StreamInterrupted { .. }
+        )
+    }
+
+    /// Get the process exit code for the error category.
+    ///
+    /// Related categories share a code so scripts can branch on broad classes
+    /// of failure: 2 for bad input, 3 for configuration, 4 for missing projects
+    /// or stages, 5 for API errors, 6 for rate limits, 7 for network failures,
+    /// 8 for file system errors, 9 for data format errors, 130 for Ctrl-C (the
+    /// shell convention for SIGINT) and 1 for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ToolkitError::InvalidInput(_) => 2,
+            ToolkitError::Config(_) | ToolkitError::ModelNotFound { .. } => 3,
+            ToolkitError::ProjectNotFound(_) | ToolkitError::StageNotFound(_) => 4,
+            ToolkitError::Api(_) | ToolkitError::ServerError(..) => 5,
+            ToolkitError::RateLimit(_) | ToolkitError::TokenBudget(_) => 6,
+            ToolkitError::Network(_) | ToolkitError::StreamInterrupted { .. } => 7,
+            ToolkitError::Io(_)
+            | ToolkitError::File(_)
+            | ToolkitError::ProjectLocked(_)
+            | ToolkitError::TemplateNotPersisted { .. } => 8,
+            ToolkitError::Serialization(_) | ToolkitError::TemplateError(_) | ToolkitError::Parse(_) => 9,
+            ToolkitError::Cancelled => 130,
+            ToolkitError::Unknown(_) => 1,
+        }
+    }
+
+    /// Convert the error to a JSON object with `code`, `message` and `retryable` fields.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "code": self.error_code(),
+            "message": self.to_string(),
+            "retryable": self.is_retryable(),
+        })
+    }
+}
+
+fn model_not_found_message(model: &str, fallback: Option<&str>) -> String {
+    match fallback {
+        Some(fallback) => format!(
+            "Model not found: neither '{}' nor its fallback '{}' is available. Choose another model with 'rust-ai-toolkit config' or update [fallback_models] in config.toml.",
+            model, fallback
+        ),
+        None => format!(
+            "Model not found: '{}'. The provider may have retired it; choose another model with 'rust-ai-toolkit config' or set a fallback for the provider under [fallback_models] in config.toml.",
+            model
+        ),
+    }
+}
+
 /// Colorize an error message for display in the terminal.
 ///
 /// This function takes a `ToolkitError` and returns a colorized string
@@ -156,3 +294,49 @@ impl From<toml::ser::Error> for ToolkitError {
 }
 
 // Add more From implementations as needed
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_json() {
+        let err = ToolkitError::RateLimit("slow down".to_string());
+        let json = err.to_json();
+
+        assert_eq!(json["code"], "rate_limit");
+        assert_eq!(json["retryable"], true);
+        assert_eq!(json["message"], err.to_string());
+        assert_eq!(err.exit_code(), 6);
+    }
+
+    #[test]
+    fn test_error_codes_are_distinct() {
+        let errors = [
+            ToolkitError::Io(String::new()),
+            ToolkitError::Api(String::new()),
+            ToolkitError::ServerError(503, String::new()),
+            ToolkitError::Config(String::new()),
+            ToolkitError::ModelNotFound { model: String::new(), fallback: None },
+            ToolkitError::ProjectNotFound(String::new()),
+            ToolkitError::StageNotFound(9),
+            ToolkitError::ProjectLocked(String::new()),
+            ToolkitError::Network(String::new()),
+            ToolkitError::StreamInterrupted { message: String::new(), partial: String::new() },
+            ToolkitError::Serialization(String::new()),
+            ToolkitError::File(String::new()),
+            ToolkitError::InvalidInput(String::new()),
+            ToolkitError::TemplateError(String::new()),
+            ToolkitError::TemplateNotPersisted { name: String::new(), reason: String::new() },
+            ToolkitError::Parse(String::new()),
+            ToolkitError::RateLimit(String::new()),
+            ToolkitError::TokenBudget(String::new()),
+            ToolkitError::Cancelled,
+            ToolkitError::Unknown(String::new()),
+        ];
+
+        let codes: std::collections::HashSet<&str> = errors.iter().map(|e| e.error_code()).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(errors.iter().all(|e| e.exit_code() != 0));
+    }
+}
@@ -10,4 +10,5 @@ pub mod utils;
 // Re-export frequently used types
 pub use error::Result;
 pub use error::ToolkitError;
-pub use models::Project; 
\ No newline at end of file
+pub use models::Project;
+pub use utils::tokens::estimate_tokens; 
\ No newline at end of file
@@ -6,20 +6,101 @@ mod prompts;
 mod stages;
 mod utils;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use error::{Result, ToolkitError, colorize_error};
-use log::{debug, error, info};
-use dirs;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+
+/// How much of each value `context` shows when listing them
+const CONTEXT_PREVIEW_CHARS: usize = 80;
 
 #[derive(Parser)]
 #[command(name = "rust-ai-toolkit")]
 #[command(about = "A toolkit for automating staged approach to project planning with AI", long_about = None)]
 struct Cli {
+    /// Output format for results and errors
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    
+    /// Directory for config.toml, templates and projects [default: ~/.rust-ai-toolkit, or $RUST_AI_TOOLKIT_CONFIG_DIR]
+    #[arg(long, global = true)]
+    config_dir: Option<std::path::PathBuf>,
+    
+    /// Don't show progress indicators
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    
+    /// Don't post stage results to the configured webhook
+    #[arg(long, global = true)]
+    no_notify: bool,
+    
+    /// Print each AI request and response in full, whatever the log level
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    
+    /// Ask the AI again instead of reusing cached responses
+    #[arg(long, global = true)]
+    no_cache: bool,
+    
+    /// Use this model instead of the configured one
+    #[arg(long, global = true, value_name = "NAME")]
+    model: Option<String>,
+    
+    /// Write stage output in this language instead of the configured one, e.g. French
+    #[arg(long, global = true, value_name = "LANGUAGE")]
+    lang: Option<String>,
+    
+    /// Answer yes to every confirmation without asking
+    #[arg(short, long, global = true)]
+    yes: bool,
+    
     #[command(subcommand)]
     command: Commands,
 }
 
+/// How the CLI reports results and errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable, colored text
+    Text,
+    /// Machine-readable JSON
+    Json,
+}
+
+/// The stages `run-stage` runs: a single stage number or `all`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StageSelection {
+    /// Every stage whose dependencies are met, concurrently where possible
+    All,
+    /// A single stage
+    Number(u8),
+}
+
+impl std::str::FromStr for StageSelection {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("all") {
+            return Ok(StageSelection::All);
+        }
+        match s.parse::<u8>() {
+            Ok(number) if number >= 1 => Ok(StageSelection::Number(number)),
+            _ => Err(format!("expected a stage number from 1 to 255 or 'all', got '{}'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for StageSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StageSelection::All => write!(f, "all"),
+            StageSelection::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize a new project plan
@@ -29,33 +110,370 @@ enum Commands {
         name: String,
         
         /// Brief description of the project idea
-        #[arg(short, long)]
-        description: String,
+        #[arg(short, long, required_unless_present_any = ["from_file", "template"])]
+        description: Option<String>,
+        
+        /// Read the project idea from a file instead (`-` reads stdin)
+        #[arg(long, value_name = "PATH", conflicts_with = "description")]
+        from_file: Option<std::path::PathBuf>,
+        
+        /// Start from a scaffold such as web-app or cli-tool
+        #[arg(long, value_name = "NAME")]
+        template: Option<String>,
     },
     
     /// Run a specific stage of the planning process
     RunStage {
-        /// Stage number to run (1-5)
+        #[arg(
+            short,
+            long,
+            help = format!("Stage number to run (1-{}, a custom stage from the config, or 'all')", stages::STAGE_COUNT)
+        )]
+        stage: StageSelection,
+        
+        /// Project ID to run the stage for (asks when omitted in a terminal)
+        #[arg(short, long)]
+        project: Option<String>,
+        
+        /// Extra instructions to steer this run (e.g. "focus on mobile")
+        #[arg(short, long)]
+        instructions: Option<String>,
+        
+        /// Print the response as it is generated (default when stdout is a terminal)
+        #[arg(long, conflicts_with = "no_stream")]
+        stream: bool,
+        
+        /// Wait for the full response instead of streaming it
+        #[arg(long)]
+        no_stream: bool,
+        
+        /// Print the prompt that would be sent without calling the AI
+        #[arg(long)]
+        dry_run: bool,
+        
+        /// Maximum number of tokens in the response (overrides the stage default)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_tokens: Option<u32>,
+        
+        /// Sampling temperature from 0.0 to 2.0 (overrides the stage default)
+        #[arg(long)]
+        temperature: Option<f32>,
+        
+        /// Sampling seed for reproducible output, where the provider supports it
+        #[arg(long)]
+        seed: Option<u64>,
+        
+        /// How much detail to ask for: concise or detailed (default)
+        #[arg(long)]
+        style: Option<stages::OutputStyle>,
+        
+        /// Write each completed stage to a Markdown file in this directory
+        #[arg(long)]
+        output_dir: Option<std::path::PathBuf>,
+        
+        /// Refine and extend the stage's existing output instead of starting over
+        #[arg(long)]
+        append: bool,
+        
+        /// With `--stage all`, keep running the other stages when one fails and summarize at the end
+        #[arg(long)]
+        continue_on_error: bool,
+    },
+    
+    /// Run the next incomplete stage of a project
+    Resume {
+        /// Project ID to resume
+        #[arg(short, long)]
+        project: String,
+    },
+    
+    /// Clear a stage's output so it runs fresh
+    Reset {
+        /// Project ID to reset the stage for
+        #[arg(short, long)]
+        project: String,
+        
+        /// Stage number to reset
         #[arg(short, long)]
         stage: u8,
         
-        /// Project ID to run the stage for
+        /// Also reset every stage that depends on it
+        #[arg(long)]
+        cascade: bool,
+    },
+    
+    /// Show or change the data passed between a project's stages
+    Context {
+        /// Project ID to inspect
+        #[arg(short, long)]
+        project: String,
+        
+        /// Set a value, e.g. `--set initial_plan="..."`; an empty value removes the key
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        set: Vec<(String, String)>,
+        
+        /// Print a single value in full
+        #[arg(long, value_name = "KEY")]
+        get: Option<String>,
+    },
+    
+    /// Show or change the template variables available to every stage of a project
+    Vars {
+        /// Project ID to inspect
+        #[arg(short, long)]
+        project: String,
+        
+        /// Set a variable, e.g. `--set team_size=4`
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        set: Vec<(String, String)>,
+        
+        /// Remove a variable
+        #[arg(long, value_name = "KEY")]
+        unset: Vec<String>,
+    },
+    
+    /// Chat with the AI to refine a project's latest stage output
+    Chat {
+        /// Project ID to discuss
         #[arg(short, long)]
         project: String,
     },
     
+    /// Send a one-off prompt to the configured AI and print the response
+    Ask {
+        /// The prompt; read from --file or stdin if left out
+        prompt: Option<String>,
+        
+        /// Read the prompt from this file
+        #[arg(short, long, value_name = "FILE", conflicts_with = "prompt")]
+        file: Option<std::path::PathBuf>,
+        
+        /// Print the response as it's generated
+        #[arg(long)]
+        stream: bool,
+        
+        /// Sampling seed for reproducible output, where the provider supports it
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    
     /// List all projects
-    List,
+    List {
+        /// Include archived projects
+        #[arg(long)]
+        all: bool,
+        
+        /// Only projects created on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long)]
+        since: Option<String>,
+        
+        /// Only projects created on or before this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long)]
+        until: Option<String>,
+    },
+    
+    /// Summarize all projects: stage completion, oldest/newest and tokens used
+    Stats,
+    
+    /// Show the version, effective settings and directories, for bug reports
+    Info,
+    
+    /// Search project descriptions and stage output for text
+    Search {
+        /// Text to look for (case-insensitive)
+        query: String,
+        
+        /// Only search the output of this stage
+        #[arg(short, long)]
+        stage: Option<u8>,
+        
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+    },
+    
+    /// List the projects most similar to one, using embeddings
+    Related {
+        /// Project ID to find similar projects for
+        #[arg(short, long)]
+        project: String,
+        
+        /// How many projects to list
+        #[arg(short = 'n', long, default_value_t = 5)]
+        top: usize,
+    },
+    
+    /// Archive a project to hide it from the project list
+    Archive {
+        /// Project ID to archive
+        #[arg(short, long)]
+        project: String,
+    },
+    
+    /// Restore an archived project to the project list
+    Unarchive {
+        /// Project ID to unarchive
+        #[arg(short, long)]
+        project: String,
+    },
+    
+    /// Find project directories that can't be loaded and move or delete them
+    Clean {
+        /// Only report broken project directories
+        #[arg(long)]
+        dry_run: bool,
+        
+        /// Move broken project directories into the quarantine folder without asking
+        #[arg(long, conflicts_with_all = ["dry_run", "delete"])]
+        quarantine: bool,
+        
+        /// Delete broken project directories without asking which action to take
+        #[arg(long, conflicts_with = "dry_run")]
+        delete: bool,
+    },
+    
+    /// Rename a project, optionally changing its ID
+    Rename {
+        /// Project ID to rename
+        #[arg(short, long)]
+        project: String,
+        
+        /// New name for the project
+        #[arg(short, long)]
+        name: String,
+        
+        /// New ID for the project
+        #[arg(long)]
+        id: Option<String>,
+    },
     
     /// Show the status of a project
     Status {
-        /// Project ID to show status for
+        /// Project ID to show status for (asks when omitted in a terminal)
         #[arg(short, long)]
-        project: String,
+        project: Option<String>,
+    },
+    
+    /// Print the output of one stage of a project
+    Show {
+        /// Project ID to show (asks when omitted in a terminal)
+        #[arg(short, long)]
+        project: Option<String>,
+        
+        /// Stage number to show
+        #[arg(short, long)]
+        stage: u8,
+        
+        /// Render the Markdown with headings, lists and code blocks styled (terminal only)
+        #[arg(long)]
+        render: bool,
+    },
+    
+    /// Regenerate one `##` section of a stage's output, keeping the rest as it is
+    Regen {
+        /// Project ID to regenerate the section for (asks when omitted in a terminal)
+        #[arg(short, long)]
+        project: Option<String>,
+        
+        /// Stage number whose output holds the section
+        #[arg(short, long)]
+        stage: u8,
+        
+        /// Heading of the section, e.g. "Timeline"
+        section: String,
+    },
+    
+    /// Rerun a stage whenever the project's idea.md changes, until Ctrl-C
+    Watch {
+        /// Project ID to watch (asks when omitted in a terminal)
+        #[arg(short, long)]
+        project: Option<String>,
+        
+        /// Stage number to rerun
+        #[arg(short, long, default_value_t = 1)]
+        stage: u8,
     },
     
     /// Configure AI provider settings
-    Config,
+    Config {
+        /// Write the saved settings to this TOML file instead of configuring interactively
+        #[arg(long, value_name = "FILE", conflicts_with = "import")]
+        export: Option<std::path::PathBuf>,
+        
+        /// Validate the settings in this TOML file and save them as the configuration
+        #[arg(long, value_name = "FILE")]
+        import: Option<std::path::PathBuf>,
+        
+        /// Include the API key in the exported file
+        #[arg(long, requires = "export")]
+        include_key: bool,
+    },
+    
+    /// Export a project's stage outputs to a single document
+    Export {
+        /// Project ID to export
+        #[arg(short, long)]
+        project: String,
+        
+        /// Output format (markdown, html, pdf)
+        ///
+        /// `--format` is the global output format, so this one is `-f`/`--to`.
+        #[arg(short = 'f', long = "to", id = "export_format", value_name = "FORMAT", default_value = "markdown")]
+        format: utils::export::ExportFormat,
+        
+        /// File to write to; prints to stdout when omitted
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+        
+        /// Lay the document out with an export template, e.g. `default` for `export_default.hbs`
+        #[arg(short, long, value_name = "NAME")]
+        template: Option<String>,
+    },
+    
+    /// Create a project from a plan written outside the toolkit
+    Import {
+        /// Name of the project
+        #[arg(short, long)]
+        name: String,
+        
+        /// Markdown file holding the plan (`-` reads stdin)
+        #[arg(short, long, value_name = "PATH")]
+        file: std::path::PathBuf,
+        
+        /// Stage the plan is the output of
+        #[arg(short, long, default_value_t = 1)]
+        stage: u8,
+    },
+    
+    /// Manage prompt templates
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum TemplateAction {
+    /// List all available templates
+    List,
+    
+    /// Show the raw content of a template
+    Show {
+        /// Name of the template
+        name: String,
+    },
+    
+    /// Open a template in $EDITOR
+    Edit {
+        /// Name of the template
+        name: String,
+    },
+    
+    /// Restore a template (or all templates) to the built-in default
+    Reset {
+        /// Name of the template; resets all defaults when omitted
+        name: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -68,20 +486,75 @@ async fn main() -> Result<()> {
     
     info!("Starting Rust AI Toolkit");
     let cli = Cli::parse();
+    let format = cli.format;
+    utils::ui::set_progress_enabled(!cli.quiet && format == OutputFormat::Text);
+    utils::notify::set_notifications_enabled(!cli.no_notify);
+    utils::ui::set_assume_yes(cli.yes);
+    ai::set_verbose(cli.verbose);
+    ai::cache::set_bypass(cli.no_cache);
+    
+    if let Some(config_dir) = &cli.config_dir {
+        config::set_config_dir(config_dir.clone());
+    }
     
+    // Size the caches and set the rate limits from the config now, rather than
+    // reading it again on their first use. A broken config is reported by the
+    // command itself.
+    if let Ok(config) = config::get_config() {
+        ai::cache::configure(&config);
+        ai::configure_http(&config);
+        utils::cache::configure(&config);
+        utils::rate_limiter::configure(&config);
+    }
+    
+    // Must come after the config directory, since it reads the config to
+    // check the model against the provider
+    if let Some(model) = &cli.model {
+        ai::set_model_override(model);
+    }
+    if let Some(lang) = &cli.lang {
+        stages::set_language_override(lang);
+    }
+    
+    // Ctrl-C drops the running command, along with any request in flight,
+    // instead of leaving a half-finished stage behind
+    utils::cancel::install_ctrl_c_handler();
+    
+    let verbose = cli.verbose;
+    let result = utils::cancel::until_cancelled(run(cli)).await;
+    if verbose {
+        eprintln!("response cache: {}", ai::cache::metrics());
+    }
+    
+    match result {
+        Err(ToolkitError::Cancelled) if format == OutputFormat::Text => {
+            eprintln!("\n{}", "Cancelled by user".yellow());
+            std::process::exit(ToolkitError::Cancelled.exit_code());
+        }
+        Err(e) if format == OutputFormat::Json => {
+            // Scripts branch on the JSON error code and the category exit code
+            eprintln!("{}", e.to_json());
+            std::process::exit(e.exit_code());
+        }
+        result => result,
+    }
+}
+
+/// Run the parsed command
+async fn run(cli: Cli) -> Result<()> {
     // Create AI client once when needed with caching
     let ai_client = match &cli.command {
-        Commands::Init { .. } | Commands::RunStage { .. } | Commands::Status { .. } | Commands::Config => {
+        Commands::RunStage { dry_run: true, .. } => None,
+        Commands::Init { .. } | Commands::RunStage { .. } | Commands::Status { .. } | Commands::Watch { .. } | Commands::Regen { .. } | Commands::Config { export: None, import: None, .. } => {
             Some(ai::get_cached_client().await?)
         }
         _ => None,
     };
     
     // Initialize prompt manager
-    let home_dir = dirs::home_dir().expect("Failed to find home directory");
-    let config_dir = home_dir.join(".rust-ai-toolkit");
+    let config_dir = config::get_config_dir()?;
     let templates_dir = config_dir.join("templates");
-    let prompt_manager = match prompts::PromptManager::new(&templates_dir) {
+    let mut prompt_manager = match prompts::PromptManager::new(&templates_dir) {
         Ok(pm) => {
             debug!("Prompt manager initialized with template directory: {:?}", templates_dir);
             pm
@@ -95,21 +568,33 @@ async fn main() -> Result<()> {
         }
     };
     
-    // Initialize all default templates if they don't exist
+    // Initialize all default templates if they don't exist. The prompt
+    // manager already has them in memory, so a read-only templates directory
+    // only costs the user the editable copies.
     for (name, content) in prompts::DEFAULT_TEMPLATES.iter() {
         let template_path = templates_dir.join(format!("{}.hbs", name));
         if !template_path.exists() {
             debug!("Creating default template: {}", name);
-            std::fs::create_dir_all(&templates_dir).ok();
-            std::fs::write(&template_path, content).ok();
+            let written = std::fs::create_dir_all(&templates_dir).and_then(|_| std::fs::write(&template_path, content));
+            if let Err(e) = written {
+                warn!("Cannot save the default templates to {}, using the built-in ones: {}", templates_dir.display(), e);
+                break;
+            }
         }
     }
     
     match cli.command {
-        Commands::Init { name, description } => {
+        Commands::Init { name, description, from_file, template } => {
             info!("Initializing new project: {}", name);
+            let scaffold = template.as_deref().map(utils::scaffold::load_scaffold).transpose()?;
+            let description = match (description, from_file, &scaffold) {
+                (_, Some(path), _) => stages::init::read_idea_file(&path)?,
+                (Some(description), None, _) => description,
+                (None, None, Some(scaffold)) if !scaffold.description.trim().is_empty() => scaffold.description.clone(),
+                (None, None, _) => return Err(ToolkitError::InvalidInput("Either --description or --from-file is required".to_string())),
+            };
             println!("{}", "Initializing new project...".green());
-            match stages::init::run_init(&name, &description).await {
+            match stages::init::run_init(&name, &description, scaffold.as_ref()).await {
                 Ok(_) => {
                     info!("Project initialization successful: {}", name);
                     Ok(())
@@ -121,24 +606,173 @@ async fn main() -> Result<()> {
                 }
             }
         }
-        Commands::RunStage { stage, project } => {
+        Commands::RunStage { stage: StageSelection::All, dry_run: true, .. } => Err(ToolkitError::InvalidInput(
+            "--dry-run previews a single stage; pass a stage number instead of 'all'".to_string(),
+        )),
+        Commands::RunStage { stage: StageSelection::All, append: true, .. } => Err(ToolkitError::InvalidInput(
+            "--append refines a single stage; pass a stage number instead of 'all'".to_string(),
+        )),
+        Commands::RunStage { stage: StageSelection::All, stream: true, .. } => Err(ToolkitError::InvalidInput(
+            "--stream prints a single stage as it's generated, and 'all' may run several at once; pass a stage number instead of 'all'".to_string(),
+        )),
+        Commands::RunStage { stage: StageSelection::Number(_), continue_on_error: true, .. } => Err(ToolkitError::InvalidInput(
+            "--continue-on-error applies to runs of several stages; pass 'all' as the stage".to_string(),
+        )),
+        Commands::RunStage { stage: StageSelection::Number(stage), project, instructions, dry_run: true, style, append, .. } => {
+            let project = resolve_project(project).await?;
+            info!("Previewing stage {} for project {}", stage, project);
+            handle_dry_run_command(stage, &project, instructions, style, append)
+        }
+        Commands::RunStage { stage: StageSelection::All, project, instructions, dry_run: false, max_tokens, temperature, seed, style, output_dir, continue_on_error, .. } => {
+            let project = resolve_project(project).await?;
+            info!("Running all available stages for project {}", project);
+            
+            if let Some(temperature) = temperature {
+                stages::validate_temperature(temperature)?;
+            }
+            
+            println!("{} {}", "Running all available stages for project".green(), project.yellow());
+            let mut context = run_context(instructions, max_tokens, temperature, seed, style, output_dir, false);
+            if continue_on_error {
+                context.set(stages::CONTINUE_ON_ERROR_KEY, "true");
+            }
+            handle_run_all_command(&project, context).await
+        }
+        Commands::RunStage { stage: StageSelection::Number(stage), project, instructions, stream, no_stream, dry_run: false, max_tokens, temperature, seed, style, output_dir, append, .. } => {
+            let project = resolve_project(project).await?;
             info!("Running stage {} for project {}", stage, project);
             
+            if let Some(temperature) = temperature {
+                stages::validate_temperature(temperature)?;
+            }
+            
             println!("{} {} {}", "Running stage".green(), stage.to_string().yellow(), "for project".green());
             
-            handle_run_stage_command(stage, &project).await
+            let stream = stream || (!no_stream && std::io::stdout().is_terminal());
+            let context = run_context(instructions, max_tokens, temperature, seed, style, output_dir, append);
+            handle_run_stage_command(stage, &project, stream, context).await
         }
-        Commands::List => {
+        Commands::Resume { project } => {
+            info!("Resuming project {}", project);
+            handle_resume_command(&project).await
+        }
+        Commands::Reset { project, stage, cascade } => {
+            info!("Resetting stage {} of project {}", stage, project);
+            let reset = stages::reset_stage_async(&project, stage, cascade).await?;
+            let reset: Vec<String> = reset.iter().map(|number| number.to_string()).collect();
+            utils::ui::print_success(&format!("Reset stage(s) {} of project {}", reset.join(", "), project));
+            Ok(())
+        }
+        Commands::Context { project, set, get } => {
+            info!("Showing context of project {}", project);
+            handle_context_command(&project, set, get, cli.format)
+        }
+        Commands::Vars { project, set, unset } => {
+            info!("Showing variables of project {}", project);
+            handle_vars_command(&project, set, unset, cli.format)
+        }
+        Commands::Chat { project } => {
+            info!("Starting chat for project {}", project);
+            handle_chat_command(&project).await
+        }
+        Commands::Ask { prompt, file, stream, seed } => {
+            let prompt = read_ask_prompt(prompt, file.as_deref())?;
+            info!("Asking the AI a one-off question");
+            handle_ask_command(&prompt, stream, seed, cli.format).await
+        }
+        Commands::List { all, since, until } => {
             info!("Listing all projects");
             println!("{}", "Listing all projects...".green());
-            handle_list_command().await
+            handle_list_command(all, since, until).await
+        }
+        Commands::Stats => {
+            info!("Summarizing all projects");
+            handle_stats_command(cli.format).await
+        }
+        Commands::Info => {
+            info!("Showing installation details");
+            handle_info_command(cli.format).await
+        }
+        Commands::Search { query, stage, regex } => {
+            info!("Searching projects for {:?}", query);
+            handle_search_command(&query, stage, regex, cli.format).await
+        }
+        Commands::Related { project, top } => {
+            info!("Finding projects related to {}", project);
+            handle_related_command(&project, top, cli.format).await
+        }
+        Commands::Archive { project } => {
+            info!("Archiving project {}", project);
+            utils::project::set_archived(&project, true)?;
+            utils::ui::print_success(&format!("Archived project {}", project));
+            Ok(())
+        }
+        Commands::Unarchive { project } => {
+            info!("Unarchiving project {}", project);
+            utils::project::set_archived(&project, false)?;
+            utils::ui::print_success(&format!("Unarchived project {}", project));
+            Ok(())
+        }
+        Commands::Rename { project, name, id } => {
+            info!("Renaming project {}", project);
+            let renamed = utils::project::rename_project(&project, &name, id.as_deref())?;
+            utils::ui::print_success(&format!("Renamed project {} to '{}'", renamed.id, renamed.name));
+            if renamed.id != project {
+                utils::ui::print_info(&format!("Project ID changed from {} to {}", project, renamed.id));
+            }
+            Ok(())
+        }
+        Commands::Clean { dry_run, quarantine, delete } => {
+            info!("Looking for broken project directories");
+            let action = if quarantine {
+                Some(CleanAction::Quarantine)
+            } else if delete {
+                Some(CleanAction::Delete)
+            } else {
+                None
+            };
+            handle_clean_command(dry_run, action)
         }
         Commands::Status { project } => {
+            let project = resolve_project(project).await?;
             info!("Showing status for project: {}", project);
             println!("{} {}", "Showing status for project".green(), project.yellow());
             handle_show_command(&project).await
         }
-        Commands::Config => {
+        Commands::Show { project, stage, render } => {
+            let project = resolve_project(project).await?;
+            info!("Showing stage {} of project {}", stage, project);
+            handle_show_stage_command(&project, stage, render, cli.format)
+        }
+        Commands::Regen { project, stage, section } => {
+            let project = resolve_project(project).await?;
+            info!("Regenerating section '{}' of stage {} for project {}", section, stage, project);
+            handle_regen_command(&project, stage, &section).await
+        }
+        Commands::Watch { project, stage } => {
+            let project = resolve_project(project).await?;
+            info!("Watching project {} to rerun stage {}", project, stage);
+            handle_watch_command(&project, stage).await
+        }
+        Commands::Config { export: Some(path), include_key, .. } => {
+            config::export_config(&path, include_key)?;
+            utils::ui::print_success(&format!("Exported the configuration to {}", path.display()));
+            if !include_key {
+                utils::ui::print_info("The API key was left out; pass --include-key to export it.");
+            }
+            Ok(())
+        }
+        Commands::Config { import: Some(path), .. } => {
+            let imported = config::import_config(&path)?;
+            utils::ui::print_success(&format!("Imported the configuration from {}", path.display()));
+            println!("Provider: {}", imported.provider);
+            println!("Model: {}", imported.model);
+            if imported.api_key.is_empty() {
+                utils::ui::print_warning("No API key is saved yet; run `rust-ai-toolkit config` or set it in the environment.");
+            }
+            Ok(())
+        }
+        Commands::Config { .. } => {
             info!("Configuring AI provider settings");
             println!("{}", "Configuring AI provider settings...".green());
             match config::configure_ai().await {
@@ -153,12 +787,433 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Export { project, format, output, template } => {
+            info!("Exporting project {} as {}", project, format);
+            handle_export_command(&prompt_manager, &project, format, output.as_deref(), template.as_deref())
+        }
+        Commands::Import { name, file, stage } => {
+            info!("Importing {} into stage {} of a new project", file.display(), stage);
+            stages::init::run_import(&name, &file, stage)?;
+            Ok(())
+        }
+        Commands::Template { action } => {
+            info!("Managing templates");
+            handle_template_command(&mut prompt_manager, action)
+        }
+    }
+}
+
+/// Handle the export command to write a project out as a single document
+fn handle_export_command(
+    prompt_manager: &prompts::PromptManager,
+    project_id: &str,
+    format: utils::export::ExportFormat,
+    output: Option<&std::path::Path>,
+    template: Option<&str>,
+) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    
+    if format.is_binary() && output.is_none() {
+        return Err(ToolkitError::InvalidInput(format!(
+            "The {} format requires an output file. Use --output <FILE>.",
+            format
+        )));
+    }
+    
+    let project = utils::project::load_project(project_id)?;
+    
+    let content = match template {
+        Some(name) => utils::export::export_project_with_template(&project, format, prompt_manager, name)?,
+        None => utils::export::export_project(&project, format)?,
+    };
+    utils::export::write_export(&content, output)?;
+    
+    if let Some(path) = output {
+        utils::ui::print_success(&format!("Project exported to {}", path.display()));
+    }
+    
+    Ok(())
+}
+
+/// Handle the template command to list, show, edit, and reset templates
+fn handle_template_command(prompt_manager: &mut prompts::PromptManager, action: TemplateAction) -> Result<()> {
+    match action {
+        TemplateAction::List => {
+            let mut names = prompt_manager.get_template_names();
+            names.sort();
+            
+            println!("{}", "Available templates:".green());
+            for name in names {
+                let source = if prompt_manager.template_path(&name).exists() {
+                    "file"
+                } else {
+                    "built-in"
+                };
+                println!("  {} ({})", name.yellow(), source);
+            }
+            
+            let mut partials = prompt_manager.get_partial_names();
+            if !partials.is_empty() {
+                partials.sort();
+                println!("{}", "Partials (include with {{> name}}):".green());
+                for name in partials {
+                    println!("  {}", name.yellow());
+                }
+            }
+            
+            println!("{}", "Helpers:".green());
+            for (name, usage) in prompts::PromptManager::list_helpers() {
+                println!("  {} {}", name.yellow(), usage);
+            }
+            Ok(())
+        }
+        TemplateAction::Show { name } => {
+            println!("{}", prompt_manager.template_source(&name)?);
+            Ok(())
+        }
+        TemplateAction::Edit { name } => {
+            // Seed the file from the current source so the editor has something to work with
+            let path = prompt_manager.template_path(&name);
+            if !path.exists() {
+                let content = prompt_manager.template_source(&name).unwrap_or_default();
+                std::fs::write(&path, content)?;
+            }
+            
+            utils::ui::open_in_editor(&path)?;
+            prompt_manager.reload_template(&name)?;
+            utils::ui::print_success(&format!("Template '{}' updated.", name));
+            Ok(())
+        }
+        TemplateAction::Reset { name: Some(name) } => {
+            prompt_manager.reset_template(&name)?;
+            utils::ui::print_success(&format!("Template '{}' restored to default.", name));
+            Ok(())
+        }
+        TemplateAction::Reset { name: None } => {
+            let names = prompt_manager.reset_all_templates()?;
+            utils::ui::print_success(&format!("Restored {} default templates.", names.len()));
+            Ok(())
+        }
     }
 }
 
 /// Handle the list command to show all projects
-async fn handle_list_command() -> Result<()> {
-    utils::project::list_projects_async().await
+async fn handle_list_command(include_archived: bool, since: Option<String>, until: Option<String>) -> Result<()> {
+    let filter = utils::project::ProjectFilter {
+        include_archived,
+        since: since.as_deref().map(|date| utils::project::parse_date(date, false)).transpose()?,
+        until: until.as_deref().map(|date| utils::project::parse_date(date, true)).transpose()?,
+    };
+    
+    if let (Some(since), Some(until)) = (filter.since, filter.until) {
+        if since > until {
+            return Err(ToolkitError::InvalidInput("--since must not be later than --until".to_string()));
+        }
+    }
+    
+    utils::project::list_projects_async(&filter).await
+}
+
+/// Handle the stats command
+async fn handle_stats_command(format: OutputFormat) -> Result<()> {
+    let projects = utils::project::get_all_projects_async().await?;
+    let stats = utils::stats::summarize(&projects);
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    
+    if stats.total_projects == 0 {
+        utils::ui::print_info("No projects found. Create one with 'rust-ai-toolkit init'.");
+        return Ok(());
+    }
+    
+    println!("{}", "Project statistics".green().bold());
+    println!("  Projects: {} ({} archived)", stats.total_projects.to_string().yellow(), stats.archived_projects);
+    println!("  Stages completed: {}/{}", stats.completed_stages.to_string().yellow(), stats.total_stages);
+    for (label, project) in [("Oldest", &stats.oldest), ("Newest", &stats.newest)] {
+        if let Some(project) = project {
+            println!("  {}: {} ({}), created {}", label, project.name.cyan(), project.id, project.created_at.format("%Y-%m-%d"));
+        }
+    }
+    println!(
+        "  Tokens used: {} ({} input, {} output)",
+        stats.tokens.total().to_string().yellow(),
+        stats.tokens.input_tokens,
+        stats.tokens.output_tokens
+    );
+    
+    println!("{}", "Stage completion:".green());
+    for stage in &stats.stages {
+        println!("  Stage {} ({}): {}/{} projects completed", stage.number, stage.name, stage.completed, stage.projects);
+    }
+    
+    Ok(())
+}
+
+/// Handle the info command to print details worth including in a bug report
+async fn handle_info_command(format: OutputFormat) -> Result<()> {
+    let info = utils::info::collect().await?;
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    
+    let features = if info.features.is_empty() { "none".to_string() } else { info.features.join(", ") };
+    println!("{} {}", "rust-ai-toolkit".green().bold(), info.version.yellow());
+    println!("  Features: {}", features);
+    println!("  Config file: {}", info.config_file.display());
+    println!("  Templates: {}", info.templates_dir.display());
+    
+    match (&info.config, &info.config_error) {
+        (Some(config), _) => {
+            println!("{}", "Settings in effect:".green());
+            println!("  Provider: {}", config.provider);
+            println!("  Model: {}", config.model);
+            println!("  Base URL: {}", config.base_url.as_deref().unwrap_or("provider default"));
+            println!("  API key: {}", config.api_key.as_deref().unwrap_or("not set"));
+            println!("  Projects: {}", config.projects_dir.display());
+            println!(
+                "  Response cache: up to {} MB, entries kept {}s",
+                config.response_cache_max_mb, config.response_cache_ttl_secs
+            );
+            println!("  Project cache: entries kept {}s", config.project_cache_ttl_secs);
+        }
+        (None, error) => {
+            utils::ui::print_warning(&format!("The config can't be loaded: {}", error.as_deref().unwrap_or("unknown error")));
+        }
+    }
+    
+    Ok(())
+}
+
+/// Handle the search command
+async fn handle_search_command(query: &str, stage: Option<u8>, regex: bool, format: OutputFormat) -> Result<()> {
+    let results = utils::project::search_all_projects(query, regex, stage).await?;
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+    
+    if results.is_empty() {
+        utils::ui::print_info(&format!("No matches for {:?}", query));
+        return Ok(());
+    }
+    
+    for result in &results {
+        let location = match result.stage {
+            Some(stage) => format!("stage {}", stage),
+            None => "description".to_string(),
+        };
+        let more = if result.matches > 1 { format!(" (+{} more)", result.matches - 1) } else { String::new() };
+        println!("{} {}{}", result.project_id.yellow(), location.cyan(), more.dimmed());
+        println!("  {}{}{}", result.before, result.matched.red().bold(), result.after);
+    }
+    
+    Ok(())
+}
+
+/// List the projects most similar to a project
+async fn handle_related_command(project_id: &str, top: usize, format: OutputFormat) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let related = utils::related::find_related(project_id, top).await?;
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&related)?);
+        return Ok(());
+    }
+    
+    println!("{}", format!("Projects most similar to {}:", project_id).green());
+    for project in &related {
+        println!("  {} {} {}", format!("{:.3}", project.score).yellow(), project.id, project.name.cyan());
+    }
+    
+    Ok(())
+}
+
+/// Show a project's template variables, after applying any changes
+fn handle_vars_command(project_id: &str, set: Vec<(String, String)>, unset: Vec<String>, format: OutputFormat) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let changed = !set.is_empty() || !unset.is_empty();
+    let project = utils::project::update_variables(project_id, set, &unset)?;
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&project.variables)?);
+        return Ok(());
+    }
+    
+    if changed {
+        utils::ui::print_success(&format!("Updated the variables of project {}", project_id));
+    }
+    
+    if project.variables.is_empty() {
+        utils::ui::print_info(&format!("Project {} has no variables. Add one with --set KEY=VALUE.", project_id));
+        return Ok(());
+    }
+    
+    let mut entries: Vec<(&String, &String)> = project.variables.iter().collect();
+    entries.sort();
+    for (key, value) in entries {
+        println!("{} = {}", key.yellow(), value);
+    }
+    
+    Ok(())
+}
+
+/// Parse a `KEY=VALUE` argument
+fn parse_key_value(arg: &str) -> std::result::Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {:?}", arg)),
+    }
+}
+
+/// Show a project's saved stage context, after applying any `--set` values
+fn handle_context_command(project_id: &str, set: Vec<(String, String)>, get: Option<String>, format: OutputFormat) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let mut project = utils::project::load_project(project_id)?;
+    
+    if !set.is_empty() {
+        let values: HashMap<String, String> = set.into_iter().collect();
+        utils::project::save_context(&project, &values)?;
+        project = utils::project::load_project(project_id)?;
+        if get.is_none() && format == OutputFormat::Text {
+            let mut keys: Vec<&String> = values.keys().collect();
+            keys.sort();
+            let keys: Vec<&str> = keys.into_iter().map(String::as_str).collect();
+            utils::ui::print_success(&format!("Updated {} for project {}", keys.join(", "), project_id));
+            return Ok(());
+        }
+    }
+    
+    if let Some(key) = get {
+        let value = project.context.get(&key).ok_or_else(|| {
+            ToolkitError::InvalidInput(format!("Project {} has no context value {:?}", project_id, key))
+        })?;
+        match format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+            OutputFormat::Text => println!("{}", value),
+        }
+        return Ok(());
+    }
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&project.context)?);
+        return Ok(());
+    }
+    
+    if project.context.is_empty() {
+        utils::ui::print_info(&format!("Project {} has no saved context yet", project_id));
+        return Ok(());
+    }
+    
+    let mut entries: Vec<(&String, &String)> = project.context.iter().collect();
+    entries.sort();
+    for (key, value) in entries {
+        let first_line = value.lines().next().unwrap_or_default();
+        let preview: String = first_line.chars().take(CONTEXT_PREVIEW_CHARS).collect();
+        let more = if preview.len() < value.trim_end().len() { "…" } else { "" };
+        println!("{} {}", key.yellow(), format!("({} chars)", value.chars().count()).dimmed());
+        println!("  {}{}", preview, more);
+    }
+    
+    Ok(())
+}
+
+/// What `clean` does with the broken project directories it finds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CleanAction {
+    Quarantine,
+    Delete,
+    Leave,
+}
+
+/// Handle the clean command, asking what to do with broken projects unless `action` says
+///
+/// Without a terminal to ask on, or under `--yes`, the action has to be given
+/// with `--quarantine` or `--delete`.
+fn handle_clean_command(dry_run: bool, action: Option<CleanAction>) -> Result<()> {
+    let broken = utils::project::find_broken_projects()?;
+    
+    if broken.is_empty() {
+        utils::ui::print_success("No broken project directories found");
+        return Ok(());
+    }
+    
+    println!("{}", format!("Found {} broken project director{}:", broken.len(), if broken.len() == 1 { "y" } else { "ies" }).yellow());
+    for project in &broken {
+        println!("  {} - {}", project.path.display().to_string().yellow(), project.reason);
+    }
+    
+    if dry_run {
+        utils::ui::print_info("Dry run: nothing was changed");
+        return Ok(());
+    }
+    
+    // A directory outside the projects directory may not be a project at all,
+    // so deletion is only offered for the ones that certainly are
+    let deletable: Vec<&utils::project::BrokenProject> = broken.iter().filter(|project| project.deletable).collect();
+    let quarantine = format!("Move them into {}", utils::project::QUARANTINE_DIR);
+    let delete = if deletable.len() == broken.len() {
+        "Delete them".to_string()
+    } else {
+        format!("Delete the {} in the projects directory, leaving the rest", deletable.len())
+    };
+    let action = match action {
+        Some(action) => action,
+        None if utils::ui::assume_yes() || !utils::ui::is_interactive() => {
+            return Err(ToolkitError::InvalidInput(
+                "Pass --quarantine or --delete to choose what happens to them without asking".to_string(),
+            ));
+        }
+        None => {
+            let mut options = vec![(CleanAction::Quarantine, quarantine.as_str())];
+            if !deletable.is_empty() {
+                options.push((CleanAction::Delete, delete.as_str()));
+            }
+            options.push((CleanAction::Leave, "Leave them"));
+            let labels: Vec<&str> = options.iter().map(|(_, label)| *label).collect();
+            options[utils::ui::prompt_select("What should happen to them?", &labels)?].0
+        }
+    };
+    
+    match action {
+        CleanAction::Quarantine => {
+            for project in &broken {
+                let target = utils::project::quarantine_project(project)?;
+                println!("  Moved {} to {}", project.path.display(), target.display());
+            }
+            utils::ui::print_success(&format!("Quarantined {} project directories", broken.len()));
+        }
+        CleanAction::Delete if deletable.is_empty() => {
+            utils::ui::print_info("None of them are in the projects directory, so nothing was deleted");
+        }
+        CleanAction::Delete => {
+            if !utils::ui::prompt_yes_no("Permanently delete these directories?", false)? {
+                utils::ui::print_info("Nothing was deleted");
+                return Ok(());
+            }
+            for project in &deletable {
+                std::fs::remove_dir_all(&project.path)?;
+                println!("  Deleted {}", project.path.display());
+            }
+            utils::ui::print_success(&format!("Deleted {} project directories", deletable.len()));
+        }
+        CleanAction::Leave => utils::ui::print_info("Nothing was changed"),
+    }
+    
+    Ok(())
+}
+
+/// Use the project given with `--project`, or ask for one
+async fn resolve_project(project: Option<String>) -> Result<String> {
+    match project {
+        Some(project) => Ok(project),
+        None => utils::project::pick_project().await,
+    }
 }
 
 /// Handle the show command to display project status
@@ -166,15 +1221,223 @@ async fn handle_show_command(project_id: &str) -> Result<()> {
     utils::project::show_status(project_id)
 }
 
+/// Print a stage's output, rendering its Markdown when asked and writing to a terminal
+fn handle_show_stage_command(project_id: &str, stage: u8, render: bool, format: OutputFormat) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let project = utils::project::load_project(project_id)?;
+    let stage_data = project.get_stage(stage).ok_or(ToolkitError::StageNotFound(stage))?;
+    
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(stage_data)?);
+        return Ok(());
+    }
+    
+    match &stage_data.content {
+        Some(content) if render && std::io::stdout().is_terminal() => utils::ui::print_markdown(content),
+        Some(content) => println!("{}", content),
+        None => utils::ui::print_info(&format!("Stage {} ({}) has no output yet.", stage, stage_data.name)),
+    }
+    
+    Ok(())
+}
+
+/// Handle the regen command and print the new section
+async fn handle_regen_command(project_id: &str, stage: u8, section: &str) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let new_section = stages::regen::regenerate_section(project_id, stage, section).await?;
+    
+    println!("{}", new_section.trim_end());
+    utils::ui::print_success(&format!("Regenerated section '{}' of stage {}.", section, stage));
+    Ok(())
+}
+
+/// Handle the watch command by rerunning a stage each time the idea file is saved
+///
+/// The stage is reset before each run so it doesn't ask whether to run again,
+/// and its fresh output is printed once it completes. A failed run is reported
+/// and watching carries on; Ctrl-C stops it.
+async fn handle_watch_command(project_id: &str, stage: u8) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let project = utils::project::load_project_async(project_id).await?;
+    stages::get_stage(stage).ok_or(ToolkitError::StageNotFound(stage))?;
+    
+    let idea_file = project.path.join("idea.md");
+    let mut watcher = utils::watch::FileWatcher::new(&idea_file)?;
+    println!(
+        "{} {} {} {}",
+        "Watching".green(),
+        idea_file.display().to_string().yellow(),
+        format!("to rerun stage {}.", stage).green(),
+        "Press Ctrl-C to stop.".dimmed()
+    );
+    
+    while watcher.changed(utils::watch::DEBOUNCE).await.is_some() {
+        utils::ui::print_info(&format!("{} changed, rerunning stage {}", idea_file.display(), stage));
+        stages::reset_stage_async(project_id, stage, false).await?;
+        
+        match handle_run_stage_command(stage, project_id, false, stages::StageContext::new()).await {
+            Err(ToolkitError::Cancelled) => return Err(ToolkitError::Cancelled),
+            Err(e) => utils::ui::print_error(&format!("Stage {} failed: {}", stage, e)),
+            Ok(()) => {
+                let completed = utils::project::load_project_async(project_id).await?
+                    .get_stage(stage)
+                    .is_some_and(|s| s.status == models::StageStatus::Completed);
+                if completed {
+                    handle_show_stage_command(project_id, stage, false, OutputFormat::Text)?;
+                }
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Handle the resume command by running the next incomplete stage
+async fn handle_resume_command(project_id: &str) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let project = utils::project::load_project_async(project_id).await?;
+    
+    match stages::next_stage(&project) {
+        Some(stage) => {
+            println!("{} {} {}", "Resuming with stage".green(), stage.number().to_string().yellow(), stage.name().cyan());
+            handle_run_stage_command(stage.number(), project_id, std::io::stdout().is_terminal(), stages::StageContext::new()).await
+        }
+        None if project.stages.iter().all(|s| s.status == models::StageStatus::Completed) => {
+            utils::ui::print_success(&format!("All stages of project {} are complete.", project_id));
+            Ok(())
+        }
+        None => {
+            utils::ui::print_warning("No remaining stage has its dependencies satisfied. Check the project status.");
+            Ok(())
+        }
+    }
+}
+
+/// Handle the chat command
+///
+/// The conversation is seeded with the output of the most recently completed
+/// stage, so the user can ask follow-up questions and refine it.
+async fn handle_chat_command(project_id: &str) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    let project = utils::project::load_project_async(project_id).await?;
+    
+    let mut system = format!(
+        "You are helping refine the software project \"{}\": {}",
+        project.name, project.description
+    );
+    
+    let latest = project
+        .stages
+        .iter()
+        .filter(|s| s.status == models::StageStatus::Completed && s.content.is_some())
+        .max_by_key(|s| s.completed_at);
+    match latest {
+        Some(stage) => {
+            system.push_str(&format!(
+                "\n\nThe latest output, from stage {} ({}), is:\n\n{}",
+                stage.number, stage.name, stage.content.as_deref().unwrap_or_default()
+            ));
+            utils::ui::print_info(&format!("Discussing stage {}: {}", stage.number, stage.name));
+        }
+        None => utils::ui::print_info("No stage has been completed yet; discussing the project idea."),
+    }
+    println!("Type your message and press Enter. An empty line or \"exit\" ends the chat.");
+    
+    let client = ai::get_cached_client().await?;
+    let mut session = ai::ChatSession::new(client).with_system(system);
+    
+    loop {
+        let input = utils::ui::prompt(&"you>".cyan().to_string())?;
+        if input.is_empty() || input == "exit" || input == "quit" {
+            break;
+        }
+        
+        match session.send(&input).await {
+            Ok(reply) => println!("\n{}\n", reply),
+            Err(e) => utils::ui::print_error(&format!("Failed to get a reply: {}", e)),
+        }
+    }
+    
+    Ok(())
+}
+
+/// Get the prompt for `ask` from the argument, a file or stdin, in that order
+fn read_ask_prompt(prompt: Option<String>, file: Option<&std::path::Path>) -> Result<String> {
+    let prompt = match (prompt, file) {
+        (Some(prompt), _) => prompt,
+        (None, Some(file)) => std::fs::read_to_string(file)
+            .map_err(|e| ToolkitError::File(format!("Failed to read {}: {}", file.display(), e)))?,
+        (None, None) if !std::io::stdin().is_terminal() => {
+            let _waiting = utils::cancel::waiting_for_input();
+            let mut prompt = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut prompt)?;
+            prompt
+        }
+        (None, None) => {
+            return Err(ToolkitError::InvalidInput(
+                "Give a prompt, a file with --file, or pipe the prompt on stdin".to_string(),
+            ))
+        }
+    };
+    
+    if prompt.trim().is_empty() {
+        return Err(ToolkitError::InvalidInput("The prompt is empty".to_string()));
+    }
+    Ok(prompt)
+}
+
+/// Handle the ask command by sending the prompt as it is and printing the response
+///
+/// The request goes through the same client as the stages, so responses are
+/// cached and rate limited in the same way.
+async fn handle_ask_command(prompt: &str, stream: bool, seed: Option<u64>, format: OutputFormat) -> Result<()> {
+    let client = ai::get_cached_client().await?;
+    let options = ai::RequestOptions { seed, ..Default::default() };
+    ai::check_context_window(client.model_version(), prompt, &options)?;
+    
+    if format == OutputFormat::Json {
+        let response = client.generate_with_usage(prompt, options).await?;
+        let output = serde_json::json!({
+            "model": client.model_version(),
+            "response": response.text,
+            "usage": response.usage,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    
+    if stream {
+        let mut chunks = client.generate_streaming_with_options(prompt, options).await?;
+        let mut stdout = std::io::stdout();
+        while let Some(chunk) = futures::StreamExt::next(&mut chunks).await {
+            print!("{}", chunk?);
+            std::io::Write::flush(&mut stdout)?;
+        }
+        println!();
+    } else {
+        println!("{}", client.generate_with_options(prompt, options).await?);
+    }
+    
+    Ok(())
+}
+
 /// Handle the run stage command
-async fn handle_run_stage_command(stage: u8, project_id: &str) -> Result<()> {
+async fn handle_run_stage_command(
+    stage: u8,
+    project_id: &str,
+    stream: bool,
+    mut context: stages::StageContext,
+) -> Result<()> {
     debug!("Running stage {} for project {}", stage, project_id);
     
     // Validate the project ID
     utils::project::validate_project_id(project_id)?;
     
     // Load the project to make sure it exists
-    let _project = utils::project::load_project(project_id)?;
+    let project = utils::project::load_project_async(project_id).await?;
+    if context.get(stages::APPEND_KEY).is_some() && stages::previous_output(&project, stage, &context).is_none() {
+        utils::ui::print_warning(&format!("Stage {} has no output to refine yet; it will be written from scratch.", stage));
+    }
     
     // Get the stage implementation
     let stage_impl = stages::get_stage(stage).ok_or_else(|| {
@@ -183,11 +1446,19 @@ async fn handle_run_stage_command(stage: u8, project_id: &str) -> Result<()> {
     })?;
     
     // Execute the stage
-    let context = stages::StageContext::new();
+    let output_dir = context.get(stages::OUTPUT_DIR_KEY).map(std::path::PathBuf::from);
+    if stream {
+        context.set(stages::STREAM_OUTPUT_KEY, "true");
+    }
     let result = stage_impl.execute(project_id, context).await?;
     
     if result.is_success() {
         utils::ui::print_success(&format!("Stage {} completed successfully!", stage));
+        if let Some(dir) = output_dir {
+            for (stage, path) in stages::write_stage_outputs(project_id, &[stage], &dir).await? {
+                println!("Wrote stage {} to {}", stage, path.display());
+            }
+        }
     } else if result.is_skipped() {
         utils::ui::print_warning(&format!("Stage {} was skipped: {}", stage, 
             result.message.unwrap_or_else(|| "No reason provided".to_string())));
@@ -199,6 +1470,142 @@ async fn handle_run_stage_command(stage: u8, project_id: &str) -> Result<()> {
     Ok(())
 }
 
+/// Build the context for a stage run from the command-line overrides
+fn run_context(
+    instructions: Option<String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    seed: Option<u64>,
+    style: Option<stages::OutputStyle>,
+    output_dir: Option<std::path::PathBuf>,
+    append: bool,
+) -> stages::StageContext {
+    let mut context = stages::StageContext::new();
+    if let Some(instructions) = instructions {
+        context.set(stages::EXTRA_INSTRUCTIONS_KEY, instructions);
+    }
+    if let Some(max_tokens) = max_tokens {
+        context.set(stages::MAX_TOKENS_KEY, max_tokens.to_string());
+    }
+    if let Some(temperature) = temperature {
+        context.set(stages::TEMPERATURE_KEY, temperature.to_string());
+    }
+    if let Some(seed) = seed {
+        context.set(stages::SEED_KEY, seed.to_string());
+    }
+    if let Some(style) = style {
+        context.set(stages::STYLE_KEY, style.to_string());
+    }
+    if let Some(output_dir) = output_dir {
+        context.set(stages::OUTPUT_DIR_KEY, output_dir.to_string_lossy());
+    }
+    if append {
+        context.set(stages::APPEND_KEY, "true");
+    }
+    context
+}
+
+/// Handle `run-stage --stage all`
+///
+/// Runs every stage whose dependencies are met, concurrently where the
+/// dependency graph allows. Output isn't streamed since several stages may be
+/// generating at once. The estimated cost is shown first and, in a terminal,
+/// confirmed unless `--yes` is set.
+async fn handle_run_all_command(
+    project_id: &str,
+    context: stages::StageContext,
+) -> Result<()> {
+    utils::project::validate_project_id(project_id)?;
+    utils::project::load_project_async(project_id).await?;
+    
+    let model = ai::get_cached_client().await?.model_version().to_string();
+    let estimates = stages::estimate_pending_stages_async(project_id, &context, &model).await?;
+    if !estimates.is_empty() {
+        print_cost_estimate(&estimates, &model);
+        if !utils::ui::prompt_yes_no("Run these stages?", true)? {
+            utils::ui::print_info("No stages were run.");
+            return Ok(());
+        }
+    }
+    
+    stages::run_available_stages(project_id, context).await?;
+    
+    utils::ui::print_success(&format!("Finished running the stages of project {}", project_id));
+    Ok(())
+}
+
+/// Print the estimated tokens of each stage and the cost of the whole run
+fn print_cost_estimate(estimates: &[stages::StageEstimate], model: &str) {
+    println!("{}", format!("Estimated usage with {}:", model).green());
+    for estimate in estimates {
+        println!(
+            "  Stage {} ({}): ~{} prompt + ~{} completion tokens",
+            estimate.number, estimate.name, estimate.prompt_tokens, estimate.completion_tokens
+        );
+    }
+    
+    let prompt_tokens: usize = estimates.iter().map(|e| e.prompt_tokens).sum();
+    let completion_tokens: usize = estimates.iter().map(|e| e.completion_tokens).sum();
+    match ai::pricing::pricing_for(model) {
+        Some(pricing) => println!(
+            "Estimated cost: {}",
+            format!("${:.2}", pricing.cost(prompt_tokens, completion_tokens)).yellow()
+        ),
+        None => utils::ui::print_info(&format!(
+            "No pricing known for {}; about {} tokens in total.",
+            model, prompt_tokens + completion_tokens
+        )),
+    }
+}
+
+/// Print the prompt a stage would send, without calling the AI
+fn handle_dry_run_command(
+    stage: u8,
+    project_id: &str,
+    instructions: Option<String>,
+    style: Option<stages::OutputStyle>,
+    append: bool,
+) -> Result<()> {
+    debug!("Previewing stage {} for project {}", stage, project_id);
+    
+    utils::project::validate_project_id(project_id)?;
+    let project = utils::project::load_project(project_id)?;
+    
+    let stage_impl = stages::get_stage(stage).ok_or_else(|| {
+        error!("Invalid stage number: {}", stage);
+        ToolkitError::StageNotFound(stage)
+    })?;
+    
+    // Report skips the same way a real run would decide them
+    if !stage_impl.can_execute(&project) {
+        utils::ui::print_warning(&format!(
+            "Stage {} would be skipped: complete stages {:?} first.",
+            stage, stage_impl.dependencies()
+        ));
+        return Ok(());
+    }
+    
+    let completed = project
+        .get_stage(stage)
+        .map(|s| s.status == models::StageStatus::Completed)
+        .unwrap_or(false);
+    if completed {
+        utils::ui::print_info(&format!(
+            "Stage {} has already been completed; running it would ask before replacing the result.",
+            stage
+        ));
+    }
+    
+    // Fill in the context the same way a real run would
+    let mut context = run_context(instructions, None, None, None, style, None, append);
+    context.merge_project(&project, stage);
+    
+    let prompt = stage_impl.build_prompt(&project, &context)?;
+    println!("{}", prompt);
+    
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::rate_limiter;
@@ -229,4 +1636,16 @@ mod tests {
         // Still over the limit though
         assert!(!rate_limiter::can_make_request("test_provider"));
     }
+    
+    #[test]
+    fn test_read_ask_prompt() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("prompt.txt");
+        std::fs::write(&file, "Name three sorting algorithms\n").unwrap();
+        
+        assert_eq!(super::read_ask_prompt(Some("Hi".to_string()), None).unwrap(), "Hi");
+        assert_eq!(super::read_ask_prompt(None, Some(&file)).unwrap(), "Name three sorting algorithms\n");
+        assert!(matches!(super::read_ask_prompt(Some("  ".to_string()), None), Err(crate::ToolkitError::InvalidInput(_))));
+        assert!(matches!(super::read_ask_prompt(None, Some(&dir.path().join("missing.txt"))), Err(crate::ToolkitError::File(_))));
+    }
 }
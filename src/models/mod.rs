@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +11,31 @@ pub struct Project {
     pub updated_at: chrono::DateTime<chrono::Utc>,
     pub stages: Vec<Stage>,
     pub path: PathBuf,
+    /// Archived projects are hidden from the project list by default
+    #[serde(default)]
+    pub archived: bool,
+    /// Labels for grouping projects, e.g. from the scaffold it was created with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Data passed between stages, kept so stages run one at a time see it too
+    #[serde(default)]
+    pub context: HashMap<String, String>,
+    /// Template variables available to every stage, e.g. `team_size`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
+    /// Embedding of the project's idea, kept so `related` doesn't recompute it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<ProjectEmbedding>,
+}
+
+/// An embedding vector along with what it was computed from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProjectEmbedding {
+    /// Provider that computed the vector; vectors from different providers can't be compared
+    pub provider: String,
+    /// Hash of the embedded text, to notice when it changes
+    pub source_hash: u64,
+    pub vector: Vec<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +47,29 @@ pub struct Stage {
     pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
     pub content: Option<String>,
     pub artifacts: Vec<Artifact>,
+    /// Tokens consumed by the most recent run of this stage
+    #[serde(default)]
+    pub tokens_used: Option<TokenUsage>,
+    /// Wall-clock duration of the most recent AI call in milliseconds
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// Shorter version of `content` sent to later stages, with `summarize_context`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// Token counts reported by an AI provider for a single request
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Total tokens consumed by the request
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -39,6 +88,96 @@ pub struct Artifact {
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// The stages every project is created with, none of them started yet
+fn default_stages() -> Vec<Stage> {
+    vec![
+        Stage {
+            number: 1,
+            name: "Initial Plan Creation".to_string(),
+            description: "Develop a comprehensive plan based on the initial idea".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+        Stage {
+            number: 2,
+            name: "Critical Evaluation".to_string(),
+            description: "Analyze and identify overly complex or impractical elements".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+        Stage {
+            number: 3,
+            name: "Realistic Alternative".to_string(),
+            description: "Propose a more practical, achievable alternative approach".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+        Stage {
+            number: 4,
+            name: "Technical Approach Refinement".to_string(),
+            description: "Compare different technical implementation options".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+        Stage {
+            number: 5,
+            name: "AI Implementation Enhancement".to_string(),
+            description: "Restructure the plan for AI-assisted development".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+        Stage {
+            number: 6,
+            name: "Code Review and Optimization".to_string(),
+            description: "Review and optimize code using Claude Code".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+        Stage {
+            number: 7,
+            name: "User Story Extraction".to_string(),
+            description: "Extract structured user stories that can be imported into an issue tracker".to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        },
+    ]
+}
+
 impl Project {
     pub fn new(id: String, name: String, description: String, path: PathBuf) -> Self {
         let now = chrono::Utc::now();
@@ -49,64 +188,51 @@ impl Project {
             description,
             created_at: now,
             updated_at: now,
-            stages: vec![
-                Stage {
-                    number: 1,
-                    name: "Initial Plan Creation".to_string(),
-                    description: "Develop a comprehensive plan based on the initial idea".to_string(),
-                    status: StageStatus::NotStarted,
-                    completed_at: None,
-                    content: None,
-                    artifacts: vec![],
-                },
-                Stage {
-                    number: 2,
-                    name: "Critical Evaluation".to_string(),
-                    description: "Analyze and identify overly complex or impractical elements".to_string(),
-                    status: StageStatus::NotStarted,
-                    completed_at: None,
-                    content: None,
-                    artifacts: vec![],
-                },
-                Stage {
-                    number: 3,
-                    name: "Realistic Alternative".to_string(),
-                    description: "Propose a more practical, achievable alternative approach".to_string(),
-                    status: StageStatus::NotStarted,
-                    completed_at: None,
-                    content: None,
-                    artifacts: vec![],
-                },
-                Stage {
-                    number: 4,
-                    name: "Technical Approach Refinement".to_string(),
-                    description: "Compare different technical implementation options".to_string(),
-                    status: StageStatus::NotStarted,
-                    completed_at: None,
-                    content: None,
-                    artifacts: vec![],
-                },
-                Stage {
-                    number: 5,
-                    name: "AI Implementation Enhancement".to_string(),
-                    description: "Restructure the plan for AI-assisted development".to_string(),
-                    status: StageStatus::NotStarted,
-                    completed_at: None,
-                    content: None,
-                    artifacts: vec![],
-                },
-                Stage {
-                    number: 6,
-                    name: "Code Review and Optimization".to_string(),
-                    description: "Review and optimize code using Claude Code".to_string(),
-                    status: StageStatus::NotStarted,
-                    completed_at: None,
-                    content: None,
-                    artifacts: vec![],
-                },
-            ],
+            stages: default_stages(),
             path,
+            archived: false,
+            tags: Vec::new(),
+            context: HashMap::new(),
+            variables: HashMap::new(),
+            embedding: None,
+        }
+    }
+    
+    /// Append any default stages missing from the project
+    ///
+    /// Projects created before a stage was introduced don't have an entry for
+    /// it, so this is applied when loading a project from disk.
+    pub fn ensure_default_stages(&mut self) {
+        for stage in default_stages() {
+            if self.get_stage(stage.number).is_none() {
+                self.stages.push(stage);
+            }
         }
+        self.stages.sort_by_key(|s| s.number);
+    }
+    
+    /// Add an empty entry for a stage the project doesn't know about yet
+    ///
+    /// Used for user-defined stages. Returns `true` if an entry was added.
+    pub fn ensure_stage(&mut self, number: u8, name: &str, description: &str) -> bool {
+        if self.get_stage(number).is_some() {
+            return false;
+        }
+        
+        self.stages.push(Stage {
+            number,
+            name: name.to_string(),
+            description: description.to_string(),
+            status: StageStatus::NotStarted,
+            completed_at: None,
+            content: None,
+            artifacts: vec![],
+            tokens_used: None,
+            duration_ms: None,
+            summary: None,
+        });
+        self.stages.sort_by_key(|s| s.number);
+        true
     }
     
     pub fn get_stage(&self, stage_number: u8) -> Option<&Stage> {
@@ -120,6 +246,7 @@ impl Project {
     pub fn update_stage(&mut self, stage_number: u8, content: String, status: StageStatus) -> bool {
         if let Some(stage) = self.get_stage_mut(stage_number) {
             stage.content = Some(content);
+            stage.summary = None;
             
             // Check if it will be completed before setting the status
             let is_completed = status == StageStatus::Completed;
@@ -139,6 +266,59 @@ impl Project {
         false
     }
     
+    /// Save a summary of a stage's current output
+    pub fn set_stage_summary(&mut self, stage_number: u8, summary: String) -> bool {
+        if let Some(stage) = self.get_stage_mut(stage_number) {
+            stage.summary = Some(summary);
+            return true;
+        }
+        
+        false
+    }
+    
+    pub fn record_stage_metrics(&mut self, stage_number: u8, usage: Option<TokenUsage>, duration_ms: u64) -> bool {
+        if let Some(stage) = self.get_stage_mut(stage_number) {
+            stage.tokens_used = usage;
+            stage.duration_ms = Some(duration_ms);
+            return true;
+        }
+        
+        false
+    }
+    
+    /// Sum the tokens used across all stages
+    pub fn total_tokens(&self) -> u64 {
+        self.stages
+            .iter()
+            .filter_map(|s| s.tokens_used)
+            .map(|usage| usage.total())
+            .sum()
+    }
+    
+    /// Put a stage back to `NotStarted`, dropping its output, artifacts and metrics
+    ///
+    /// Context values holding the stage's output are dropped too, so later
+    /// stages don't build on the old result.
+    pub fn reset_stage(&mut self, stage_number: u8) -> bool {
+        if let Some(content) = self.get_stage(stage_number).and_then(|s| s.content.clone()) {
+            self.context.retain(|_, value| *value != content);
+        }
+        
+        if let Some(stage) = self.get_stage_mut(stage_number) {
+            stage.status = StageStatus::NotStarted;
+            stage.completed_at = None;
+            stage.content = None;
+            stage.summary = None;
+            stage.artifacts.clear();
+            stage.tokens_used = None;
+            stage.duration_ms = None;
+            self.updated_at = chrono::Utc::now();
+            return true;
+        }
+        
+        false
+    }
+    
     pub fn add_artifact(&mut self, stage_number: u8, artifact: Artifact) -> bool {
         if let Some(stage) = self.get_stage_mut(stage_number) {
             stage.artifacts.push(artifact);
@@ -149,3 +329,84 @@ impl Project {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_project() -> Project {
+        Project::new(
+            "proj_1".to_string(),
+            "Test".to_string(),
+            "A test project".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        )
+    }
+
+    #[test]
+    fn test_total_tokens_sums_stages() {
+        let mut project = test_project();
+        project.record_stage_metrics(1, Some(TokenUsage { input_tokens: 100, output_tokens: 50 }), 1200);
+        project.record_stage_metrics(2, Some(TokenUsage { input_tokens: 10, output_tokens: 5 }), 800);
+        project.record_stage_metrics(3, None, 300);
+
+        assert_eq!(project.total_tokens(), 165);
+        assert_eq!(project.get_stage(3).unwrap().duration_ms, Some(300));
+    }
+
+    #[test]
+    fn test_stage_without_metrics_deserializes() {
+        let json = r#"{
+            "number": 1,
+            "name": "Initial Plan Creation",
+            "description": "Plan",
+            "status": "Completed",
+            "completed_at": null,
+            "content": "The plan",
+            "artifacts": []
+        }"#;
+
+        let stage: Stage = serde_json::from_str(json).unwrap();
+        assert!(stage.tokens_used.is_none());
+        assert!(stage.duration_ms.is_none());
+    }
+
+    #[test]
+    fn test_ensure_default_stages_adds_missing() {
+        let mut project = test_project();
+        project.stages.retain(|s| s.number <= 6);
+        project.update_stage(6, "code".to_string(), StageStatus::Completed);
+
+        project.ensure_default_stages();
+
+        assert_eq!(project.stages.len(), 7);
+        assert_eq!(project.get_stage(7).unwrap().status, StageStatus::NotStarted);
+        assert_eq!(project.get_stage(6).unwrap().content.as_deref(), Some("code"));
+    }
+
+    #[test]
+    fn test_reset_stage_clears_output() {
+        let mut project = test_project();
+        project.update_stage(2, "design".to_string(), StageStatus::Completed);
+        project.record_stage_metrics(2, Some(TokenUsage { input_tokens: 10, output_tokens: 5 }), 800);
+        project.context.insert("architecture_design".to_string(), "design".to_string());
+        project.context.insert("current_status".to_string(), "in progress".to_string());
+
+        assert!(project.reset_stage(2));
+        assert!(!project.reset_stage(42));
+
+        let stage = project.get_stage(2).unwrap();
+        assert_eq!(stage.status, StageStatus::NotStarted);
+        assert!(stage.content.is_none() && stage.completed_at.is_none() && stage.tokens_used.is_none());
+        assert_eq!(project.context.keys().collect::<Vec<_>>(), vec!["current_status"]);
+    }
+
+    #[test]
+    fn test_project_without_archived_flag_deserializes() {
+        let mut value = serde_json::to_value(test_project()).unwrap();
+        value.as_object_mut().unwrap().remove("archived");
+
+        let project: Project = serde_json::from_value(value).unwrap();
+        assert!(!project.archived);
+    }
+}
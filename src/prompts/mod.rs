@@ -46,17 +46,58 @@
 //! - `{{variable_name}}` - Inserts the value of a variable
 //! - `{{#if condition}}...{{else}}...{{/if}}` - Conditional blocks
 //! - `{{#each items}}...{{/each}}` - Iteration over arrays
+//! - `{{> _footer}}` - Includes the partial stored in `_footer.hbs`
 //!
 //! See the Handlebars documentation for more details on the template syntax.
 
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use handlebars::Handlebars;
+use handlebars::{handlebars_helper, Handlebars};
+use handlebars::template::{Parameter, Template, TemplateElement};
 use serde_json::Value;
-use log::{debug, error};
+use log::{debug, error, warn};
 use crate::error::{Result, ToolkitError};
 
+/// Prefix marking a template file as a partial that other templates can include
+pub const PARTIAL_PREFIX: &str = "_";
+
+/// Subdirectory of a project directory holding its template overrides
+pub const PROJECT_TEMPLATES_DIR: &str = "templates";
+
+/// Helpers registered on every template engine, with their usage
+const HELPERS: &[(&str, &str)] = &[
+    ("truncate", "{{truncate text 4000}} - cut text to at most N characters on a word boundary, adding \"...\""),
+    ("word_count", "{{word_count text}} - number of whitespace-separated words in text"),
+];
+
+/// Cut `text` to at most `max_chars` characters without splitting a word.
+///
+/// Text that already fits is returned unchanged; otherwise the result ends
+/// with `...`. A single word longer than `max_chars` is cut mid-word.
+fn truncate_words(text: &str, max_chars: usize) -> String {
+    let cut = match text.char_indices().nth(max_chars) {
+        Some((index, _)) => index,
+        None => return text.to_string(),
+    };
+
+    // Back up to the last whitespace unless the cut already falls between words
+    let head = &text[..cut];
+    let head = if text[cut..].starts_with(char::is_whitespace) {
+        head
+    } else {
+        match head.rfind(char::is_whitespace) {
+            Some(index) => &head[..index],
+            None => head,
+        }
+    };
+
+    format!("{}...", head.trim_end())
+}
+
+handlebars_helper!(truncate: |text: str, max_chars: u64| truncate_words(text, max_chars as usize));
+handlebars_helper!(word_count: |text: str| text.split_whitespace().count());
+
 /// A prompt template manager that handles loading and rendering templates.
 ///
 /// This struct provides methods for loading templates from a directory,
@@ -111,8 +152,9 @@ impl PromptManager {
     ///
     /// # Errors
     ///
-    /// Returns an error if the template directory cannot be created or if
-    /// templates cannot be loaded.
+    /// Returns an error if templates cannot be loaded. A template directory
+    /// that can't be created, e.g. on a read-only file system, is only logged,
+    /// and the built-in templates are used.
     ///
     /// # Examples
     ///
@@ -127,12 +169,16 @@ impl PromptManager {
         
         // Ensure the template directory exists
         if !template_dir.exists() {
-            fs::create_dir_all(&template_dir)?;
+            if let Err(e) = fs::create_dir_all(&template_dir) {
+                warn!("Cannot create template directory {:?}, using built-in templates: {}", template_dir, e);
+            }
         }
         
         let mut handlebars = Handlebars::new();
         // Don't escape HTML entities in the templates
         handlebars.set_strict_mode(false);
+        handlebars.register_helper("truncate", Box::new(truncate));
+        handlebars.register_helper("word_count", Box::new(word_count));
         
         // Load all templates from the template directory
         Self::load_templates(&mut handlebars, &template_dir)?;
@@ -181,14 +227,39 @@ impl PromptManager {
                     .and_then(|s| s.to_str())
                     .ok_or_else(|| ToolkitError::InvalidInput(format!("Invalid template name: {:?}", path)))?;
                 
-                debug!("Loading template: {}", template_name);
-                handlebars.register_template_file(template_name, &path)?;
+                if template_name.starts_with(PARTIAL_PREFIX) {
+                    debug!("Loading partial: {}", template_name);
+                    handlebars.register_partial(template_name, fs::read_to_string(&path)?)?;
+                } else {
+                    debug!("Loading template: {}", template_name);
+                    handlebars.register_template_file(template_name, &path)?;
+                }
             }
         }
         
         Ok(())
     }
     
+    /// Register templates from another directory on top of the loaded ones.
+    ///
+    /// Templates and partials in `dir` shadow those of the same name, so a
+    /// project can carry its own `stage2.hbs` while every other template falls
+    /// back to the global or built-in one. New templates are still saved to the
+    /// global template directory.
+    ///
+    /// # Parameters
+    ///
+    /// * `dir` - The directory with the overriding `.hbs` files. Nothing
+    ///   happens if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be read or a template in it
+    /// fails to parse.
+    pub fn with_overlay(&mut self, dir: &Path) -> Result<()> {
+        Self::load_templates(&mut self.handlebars, dir)
+    }
+    
     /// Register default templates as fallbacks.
     ///
     /// This method registers the built-in default templates that are used as
@@ -257,10 +328,127 @@ impl PromptManager {
         }
     }
     
+    /// Get the variables a template requires to render meaningfully.
+    ///
+    /// This method walks the parsed template and collects every variable that is
+    /// referenced unconditionally, either directly (`{{name}}`) or as a helper
+    /// parameter. Variables that only appear inside block helpers such as
+    /// `{{#if name}}...{{/if}}` are treated as optional and are not returned.
+    ///
+    /// # Parameters
+    ///
+    /// * `template_name` - The name of the template to inspect.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the required variable names, in order of first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template is not registered.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use crate::prompts::PromptManager;
+    /// use std::path::Path;
+    ///
+    /// let manager = PromptManager::new(Path::new("./templates")).unwrap();
+    /// let vars = manager.required_vars("stage2").unwrap();
+    /// assert!(vars.contains(&"initial_plan".to_string()));
+    /// ```
+    pub fn required_vars(&self, template_name: &str) -> Result<Vec<String>> {
+        let template = self.handlebars.get_template(template_name).ok_or_else(|| {
+            ToolkitError::TemplateError(format!("Template '{}' not found", template_name))
+        })?;
+        
+        let mut vars = Vec::new();
+        Self::collect_required_vars(template, &mut vars);
+        Ok(vars)
+    }
+    
+    /// Collect unconditionally referenced variables from a parsed template.
+    fn collect_required_vars(template: &Template, vars: &mut Vec<String>) {
+        for element in &template.elements {
+            let helper = match element {
+                TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => helper,
+                // Block helpers, partials and decorators render conditionally
+                _ => continue,
+            };
+            
+            // A bare `{{name}}` is a variable; otherwise `name` is a helper and its
+            // path parameters are the variables
+            let names: Vec<&str> = if helper.params.is_empty() && helper.hash.is_empty() {
+                helper.name.as_name().into_iter().collect()
+            } else {
+                helper
+                    .params
+                    .iter()
+                    .chain(helper.hash.values())
+                    .filter(|param| matches!(param, Parameter::Path(_)))
+                    .filter_map(|param| param.as_name())
+                    .collect()
+            };
+            
+            for name in names {
+                // Only the root of a path needs to be supplied, and `this`/`@index`
+                // style references come from the render context itself
+                let root = name.split('.').next().unwrap_or(name);
+                if root.is_empty() || root == "this" || root.starts_with('@') || root.starts_with("..") {
+                    continue;
+                }
+                
+                if !vars.iter().any(|v| v == root) {
+                    vars.push(root.to_string());
+                }
+            }
+        }
+    }
+    
+    /// Render a template after checking that all required variables are supplied.
+    ///
+    /// This behaves like [`render`](Self::render), but first compares the
+    /// template's [`required_vars`](Self::required_vars) against the keys in
+    /// `data`. Because strict mode is off, a missing variable would otherwise
+    /// render as an empty string and silently produce a malformed prompt.
+    ///
+    /// # Parameters
+    ///
+    /// * `template_name` - The name of the template to render.
+    /// * `data` - The data to use for variable substitution.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the rendered template as a string if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TemplateError` listing the missing variables if any required
+    /// variable is absent, or an error if rendering fails.
+    pub fn render_checked(&self, template_name: &str, data: &Value) -> Result<String> {
+        let missing: Vec<String> = self
+            .required_vars(template_name)?
+            .into_iter()
+            .filter(|var| data.get(var).filter(|value| !value.is_null()).is_none())
+            .collect();
+        
+        if !missing.is_empty() {
+            error!("Template {} is missing variables: {:?}", template_name, missing);
+            return Err(ToolkitError::TemplateError(format!(
+                "Template '{}' is missing required variables: {}",
+                template_name,
+                missing.join(", ")
+            )));
+        }
+        
+        self.render(template_name, data)
+    }
+    
     /// Add a new template or update an existing one.
     ///
     /// This method registers a new template with the Handlebars engine and
-    /// saves it to the template directory.
+    /// saves it to the template directory. When it can't be saved, it stays
+    /// registered for the life of this manager.
     ///
     /// # Parameters
     ///
@@ -273,7 +461,8 @@ impl PromptManager {
     ///
     /// # Errors
     ///
-    /// Returns an error if the template cannot be registered or saved.
+    /// Returns a `TemplateError` if the template cannot be registered, and
+    /// `TemplateNotPersisted` if it was registered but cannot be saved.
     ///
     /// # Examples
     ///
@@ -290,8 +479,10 @@ impl PromptManager {
             Ok(_) => {
                 // Save the template to disk
                 let template_path = self.template_dir.join(format!("{}.hbs", name));
-                fs::write(template_path, content)?;
-                Ok(())
+                fs::write(&template_path, content).map_err(|e| {
+                    warn!("Template {} is registered but could not be saved to {:?}: {}", name, template_path, e);
+                    ToolkitError::TemplateNotPersisted { name: name.to_string(), reason: e.to_string() }
+                })
             },
             Err(e) => {
                 error!("Failed to register template {}: {}", name, e);
@@ -303,6 +494,53 @@ impl PromptManager {
         }
     }
     
+    /// Add or update a partial that templates can include with `{{> name}}`.
+    ///
+    /// Partials are stored alongside templates, and their names must start
+    /// with `_` so they can be told apart when the directory is loaded.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` - The name of the partial, e.g. `_footer`.
+    /// * `content` - The partial content.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name doesn't start with `_`, or if the partial
+    /// cannot be registered or saved.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use crate::prompts::PromptManager;
+    /// use std::path::Path;
+    ///
+    /// let mut manager = PromptManager::new(Path::new("./templates")).unwrap();
+    /// manager.add_partial("_footer", "Format your answer in Markdown.").unwrap();
+    /// manager.add_template("custom", "Plan {{project_name}}.\n\n{{> _footer}}").unwrap();
+    /// ```
+    pub fn add_partial(&mut self, name: &str, content: &str) -> Result<()> {
+        if !name.starts_with(PARTIAL_PREFIX) {
+            return Err(ToolkitError::InvalidInput(format!(
+                "Partial names must start with '{}': {}",
+                PARTIAL_PREFIX, name
+            )));
+        }
+        
+        debug!("Adding/updating partial: {}", name);
+        self.handlebars.register_partial(name, content).map_err(|e| {
+            error!("Failed to register partial {}: {}", name, e);
+            ToolkitError::TemplateError(format!("Failed to register partial '{}': {}", name, e))
+        })?;
+        
+        fs::write(self.template_path(name), content)?;
+        Ok(())
+    }
+    
     /// Check if a template exists.
     ///
     /// # Parameters
@@ -330,6 +568,8 @@ impl PromptManager {
     
     /// Get all registered template names.
     ///
+    /// Partials are not included; see [`get_partial_names`](Self::get_partial_names).
+    ///
     /// # Returns
     ///
     /// A vector of template names.
@@ -347,7 +587,150 @@ impl PromptManager {
     /// }
     /// ```
     pub fn get_template_names(&self) -> Vec<String> {
-        self.handlebars.get_templates().keys().cloned().collect()
+        self.handlebars
+            .get_templates()
+            .keys()
+            .filter(|name| !name.starts_with(PARTIAL_PREFIX))
+            .cloned()
+            .collect()
+    }
+    
+    /// List the custom helpers available to templates.
+    ///
+    /// Handlebars' built-in helpers (`if`, `each`, `eq`, ...) are always
+    /// available as well and aren't included here.
+    ///
+    /// # Returns
+    ///
+    /// Pairs of helper name and a one-line usage description.
+    pub fn list_helpers() -> &'static [(&'static str, &'static str)] {
+        HELPERS
+    }
+    
+    /// Get all registered partial names.
+    ///
+    /// # Returns
+    ///
+    /// A vector of partial names, each starting with `_`.
+    pub fn get_partial_names(&self) -> Vec<String> {
+        self.handlebars
+            .get_templates()
+            .keys()
+            .filter(|name| name.starts_with(PARTIAL_PREFIX))
+            .cloned()
+            .collect()
+    }
+    
+    /// Get the path where a template is stored on disk.
+    ///
+    /// The file may not exist yet if the template is only registered from the
+    /// built-in defaults.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` - The name of the template.
+    ///
+    /// # Returns
+    ///
+    /// The path of the template's `.hbs` file in the template directory.
+    pub fn template_path(&self, name: &str) -> PathBuf {
+        self.template_dir.join(format!("{}.hbs", name))
+    }
+    
+    /// Get the raw source of a template.
+    ///
+    /// The file in the template directory takes precedence; built-in defaults
+    /// are used when no file exists.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` - The name of the template.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the unrendered template content.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template file cannot be read or if no template
+    /// with the given name exists.
+    pub fn template_source(&self, name: &str) -> Result<String> {
+        let path = self.template_path(name);
+        if path.exists() {
+            return Ok(fs::read_to_string(path)?);
+        }
+        
+        templates::DEFAULT_TEMPLATES
+            .get(name)
+            .map(|content| content.to_string())
+            .ok_or_else(|| ToolkitError::TemplateError(format!("Template '{}' not found", name)))
+    }
+    
+    /// Re-register a template from its file in the template directory.
+    ///
+    /// This is used after a template file has been modified outside of the
+    /// prompt manager, e.g. in an editor.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` - The name of the template to reload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the template fails to parse.
+    pub fn reload_template(&mut self, name: &str) -> Result<()> {
+        debug!("Reloading template: {}", name);
+        let content = fs::read_to_string(self.template_path(name))?;
+        self.add_template(name, &content)
+    }
+    
+    /// Restore a template to its built-in default.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` - The name of the template to reset.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no built-in default with the given name or
+    /// if the template cannot be saved.
+    pub fn reset_template(&mut self, name: &str) -> Result<()> {
+        let content = templates::DEFAULT_TEMPLATES.get(name).ok_or_else(|| {
+            ToolkitError::InvalidInput(format!("There is no default template named '{}'", name))
+        })?;
+        
+        debug!("Resetting template to default: {}", name);
+        self.add_template(name, content)
+    }
+    
+    /// Restore all built-in templates to their defaults.
+    ///
+    /// Custom templates without a built-in counterpart are left untouched.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the names of the templates that were reset.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any template cannot be saved.
+    pub fn reset_all_templates(&mut self) -> Result<Vec<String>> {
+        let mut names: Vec<&str> = templates::DEFAULT_TEMPLATES.keys().copied().collect();
+        names.sort_unstable();
+        
+        for name in &names {
+            self.reset_template(name)?;
+        }
+        
+        Ok(names.into_iter().map(String::from).collect())
     }
     
     /// Convert a HashMap of variables into a serde_json::Value for template rendering.
@@ -390,8 +773,7 @@ impl PromptManager {
     ///
     /// # Errors
     ///
-    /// Returns an error if the template directory cannot be created or if
-    /// templates cannot be loaded.
+    /// Returns an error if templates cannot be loaded.
     ///
     /// # Examples
     ///
@@ -401,8 +783,7 @@ impl PromptManager {
     /// let manager = PromptManager::global().unwrap();
     /// ```
     pub fn global() -> Result<Self> {
-        let home_dir = dirs::home_dir().expect("Failed to find home directory");
-        let templates_dir = home_dir.join(".rust-ai-toolkit").join("templates");
+        let templates_dir = crate::config::get_config_dir()?.join("templates");
         Self::new(&templates_dir)
     }
 }
@@ -428,6 +809,7 @@ pub mod templates {
         /// - `stage3`: Implementation Strategy
         /// - `stage4`: Progress Assessment
         /// - `stage5`: User Experience Design
+        /// - `export_default`: Layout of a project exported with `--template default`
         pub static ref DEFAULT_TEMPLATES: HashMap<&'static str, &'static str> = {
             let mut m = HashMap::new();
             
@@ -449,7 +831,24 @@ Include the following:
 4. Timeline and milestones
 5. Potential challenges and solutions
 
+{{#if (eq style "concise")}}
+Keep the plan brief: a short sketch of each point, focused on what matters for a first version.
+{{else}}
 Make the plan thorough and ambitious, capturing the full vision of what this project could be.
+{{/if}}
+{{#if previous_output}}
+
+## Previous Version
+{{previous_output}}
+
+Refine and extend the previous version above rather than starting over: keep what works, fix what is weak and add what is missing. Respond with the complete revised version, not just the changes.
+{{/if}}
+{{#if extra_instructions}}
+
+## Additional Instructions
+{{extra_instructions}}
+{{/if}}
+
 Format your response in Markdown with clear sections and structure.
 "#);
             
@@ -472,7 +871,24 @@ Based on the initial plan, create a comprehensive software architecture design:
 5. Diagrams or visual representations (describe in text)
 6. Performance, security, and scalability considerations
 
+{{#if (eq style "concise")}}
+Keep it brief: name each component and its role in a sentence or two.
+{{else}}
 Provide extensive detail on each component and how they work together.
+{{/if}}
+{{#if previous_output}}
+
+## Previous Version
+{{previous_output}}
+
+Refine and extend the previous version above rather than starting over: keep what works, fix what is weak and add what is missing. Respond with the complete revised version, not just the changes.
+{{/if}}
+{{#if extra_instructions}}
+
+## Additional Instructions
+{{extra_instructions}}
+{{/if}}
+
 Format your response in Markdown with clear sections and structure.
 "#);
             
@@ -495,7 +911,24 @@ Develop a detailed implementation strategy that includes:
 5. Testing strategy and quality assurance approach
 6. Deployment considerations
 
+{{#if (eq style "concise")}}
+Keep it brief: list the main tasks in order with a line on how to approach each.
+{{else}}
 Break down complex components into manageable tasks and explain the approach for implementing each one.
+{{/if}}
+{{#if previous_output}}
+
+## Previous Version
+{{previous_output}}
+
+Refine and extend the previous version above rather than starting over: keep what works, fix what is weak and add what is missing. Respond with the complete revised version, not just the changes.
+{{/if}}
+{{#if extra_instructions}}
+
+## Additional Instructions
+{{extra_instructions}}
+{{/if}}
+
 Format your response in Markdown with clear sections and structure.
 "#);
 
@@ -521,6 +954,22 @@ Provide a comprehensive progress assessment:
 5. Provide technical guidance for overcoming any obstacles
 
 Be honest and constructive in your assessment. Focus on actionable advice.
+{{#if (eq style "concise")}}
+Keep it brief: a few bullet points per section.
+{{/if}}
+{{#if previous_output}}
+
+## Previous Version
+{{previous_output}}
+
+Refine and extend the previous version above rather than starting over: keep what works, fix what is weak and add what is missing. Respond with the complete revised version, not just the changes.
+{{/if}}
+{{#if extra_instructions}}
+
+## Additional Instructions
+{{extra_instructions}}
+{{/if}}
+
 Format your response in Markdown with clear sections and structure.
 "#);
 
@@ -544,7 +993,103 @@ Create a user experience design strategy that includes:
 6. User testing approach
 
 Focus on creating an intuitive, engaging, and accessible user experience.
+{{#if (eq style "concise")}}
+Keep it brief: a few bullet points per section.
+{{/if}}
+{{#if previous_output}}
+
+## Previous Version
+{{previous_output}}
+
+Refine and extend the previous version above rather than starting over: keep what works, fix what is weak and add what is missing. Respond with the complete revised version, not just the changes.
+{{/if}}
+{{#if extra_instructions}}
+
+## Additional Instructions
+{{extra_instructions}}
+{{/if}}
+
 Format your response in Markdown with clear sections and structure.
+"#);
+
+            // Stage 7: User Story Extraction
+            m.insert("stage7", r#"# User Story Extraction
+
+## Project Overview
+{{project_description}}
+
+## Initial Plan
+{{initial_plan}}
+
+## Implementation Strategy
+{{implementation_strategy}}
+
+## Task
+Break the project down into user stories that a development team can import into an issue tracker.
+
+1. Cover every major feature in the plan with at least one story
+2. Keep each story small enough to complete in a single sprint
+3. Write acceptance criteria that are specific and testable
+4. Number the stories sequentially using ids like "US-1", "US-2", ...
+{{#if (eq style "concise")}}
+5. Keep to the handful of stories that cover the core features
+{{/if}}
+{{#if previous_output}}
+
+## Previous Version
+{{previous_output}}
+
+Refine and extend the previous version above rather than starting over: keep what works, fix what is weak and add what is missing. Respond with the complete revised version, not just the changes.
+{{/if}}
+{{#if extra_instructions}}
+
+## Additional Instructions
+{{extra_instructions}}
+{{/if}}
+
+Respond with only a JSON object, without code fences or commentary, holding the stories in a `user_stories` array and matching this schema:
+
+```json
+{
+  "type": "object",
+  "required": ["user_stories"],
+  "additionalProperties": false,
+  "properties": {
+    "user_stories": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["id", "title", "as_a", "i_want", "so_that", "acceptance_criteria"],
+        "additionalProperties": false,
+        "properties": {
+          "id": { "type": "string" },
+          "title": { "type": "string" },
+          "as_a": { "type": "string" },
+          "i_want": { "type": "string" },
+          "so_that": { "type": "string" },
+          "acceptance_criteria": { "type": "array", "items": { "type": "string" } }
+        }
+      }
+    }
+  }
+}
+```
+"#);
+
+            // Export layout, rendered with the whole project rather than stage variables
+            m.insert("export_default", r#"# {{name}}
+
+{{{description}}}
+{{#each stages}}
+
+## Stage {{number}}: {{name}}
+
+{{#if (eq status "Completed")}}
+{{{content}}}
+{{else}}
+_(not run)_
+{{/if}}
+{{/each}}
 "#);
 
             m
@@ -553,4 +1098,157 @@ Format your response in Markdown with clear sections and structure.
 }
 
 // Re-export key items for easier access
-pub use templates::DEFAULT_TEMPLATES; 
\ No newline at end of file
+pub use templates::DEFAULT_TEMPLATES; 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_required_vars_skips_conditional_sections() {
+        let dir = tempdir().unwrap();
+        let manager = PromptManager::new(dir.path()).unwrap();
+
+        let vars = manager.required_vars("stage2").unwrap();
+        assert_eq!(vars, vec!["project_description".to_string(), "initial_plan".to_string()]);
+    }
+
+    #[test]
+    fn test_render_checked_reports_missing_vars() {
+        let dir = tempdir().unwrap();
+        let manager = PromptManager::new(dir.path()).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("project_description".to_string(), "A todo app".to_string());
+
+        let err = manager.render_checked("stage3", &PromptManager::vars_to_json(vars.clone())).unwrap_err();
+        match err {
+            ToolkitError::TemplateError(msg) => assert!(msg.contains("architecture_design")),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        vars.insert("architecture_design".to_string(), "Monolith".to_string());
+        assert!(manager.render_checked("stage3", &PromptManager::vars_to_json(vars)).is_ok());
+    }
+
+    #[test]
+    fn test_partials_are_loaded_and_hidden_from_templates() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("_footer.hbs"), "Format in Markdown.").unwrap();
+        fs::write(dir.path().join("review.hbs"), "Review {{project_name}}.\n{{> _footer}}").unwrap();
+
+        let mut manager = PromptManager::new(dir.path()).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("project_name".to_string(), "Taskly".to_string());
+        let rendered = manager.render("review", &PromptManager::vars_to_json(vars)).unwrap();
+        assert_eq!(rendered, "Review Taskly.\nFormat in Markdown.");
+
+        assert!(manager.get_template_names().contains(&"review".to_string()));
+        assert!(!manager.get_template_names().contains(&"_footer".to_string()));
+        assert_eq!(manager.get_partial_names(), vec!["_footer".to_string()]);
+
+        assert!(manager.add_partial("footer", "no prefix").is_err());
+        manager.add_partial("_header", "Project context").unwrap();
+        assert!(dir.path().join("_header.hbs").exists());
+    }
+
+    #[test]
+    fn test_truncate_and_word_count_helpers() {
+        let dir = tempdir().unwrap();
+        let mut manager = PromptManager::new(dir.path()).unwrap();
+        manager
+            .add_template("summary", "{{truncate plan 12}}|{{word_count plan}}")
+            .unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("plan".to_string(), "Build the backend first, then the UI".to_string());
+        let rendered = manager.render("summary", &PromptManager::vars_to_json(vars)).unwrap();
+        assert_eq!(rendered, "Build the...|7");
+
+        assert_eq!(truncate_words("short", 10), "short");
+        assert_eq!(truncate_words("one two", 3), "one...");
+        assert_eq!(truncate_words("unbreakable", 5), "unbre...");
+    }
+
+    #[test]
+    fn test_overlay_shadows_global_templates() {
+        let global = tempdir().unwrap();
+        let project = tempdir().unwrap();
+        fs::write(project.path().join("stage1.hbs"), "Project-specific plan for {{project_name}}").unwrap();
+
+        let mut manager = PromptManager::new(global.path()).unwrap();
+        manager.with_overlay(&project.path().join("missing")).unwrap();
+        manager.with_overlay(project.path()).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("project_name".to_string(), "Taskly".to_string());
+        let rendered = manager.render("stage1", &PromptManager::vars_to_json(vars)).unwrap();
+        assert_eq!(rendered, "Project-specific plan for Taskly");
+        assert!(manager.template_path("stage1").starts_with(global.path()));
+    }
+
+    #[test]
+    fn test_unwritable_template_dir_keeps_templates_in_memory() {
+        // A directory can't be created under a regular file, even as root
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("file"), "").unwrap();
+        let mut manager = PromptManager::new(dir.path().join("file").join("templates")).unwrap();
+        assert!(manager.has_template("stage1"));
+
+        let error = manager.add_template("custom", "Plan {{project_name}}").unwrap_err();
+        assert!(matches!(error, ToolkitError::TemplateNotPersisted { ref name, .. } if name == "custom"));
+
+        let mut vars = HashMap::new();
+        vars.insert("project_name".to_string(), "Taskly".to_string());
+        assert_eq!(manager.render("custom", &PromptManager::vars_to_json(vars)).unwrap(), "Plan Taskly");
+    }
+
+    #[test]
+    fn test_reset_template_restores_default() {
+        let dir = tempdir().unwrap();
+        let mut manager = PromptManager::new(dir.path()).unwrap();
+
+        manager.add_template("stage1", "Custom {{project_idea}}").unwrap();
+        assert_eq!(manager.template_source("stage1").unwrap(), "Custom {{project_idea}}");
+
+        manager.reset_template("stage1").unwrap();
+        assert_eq!(manager.template_source("stage1").unwrap(), DEFAULT_TEMPLATES["stage1"]);
+
+        assert!(manager.reset_template("no_such_template").is_err());
+    }
+
+    #[test]
+    fn test_extra_instructions_section_is_conditional() {
+        let dir = tempdir().unwrap();
+        let manager = PromptManager::new(dir.path()).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("project_description".to_string(), "A todo app".to_string());
+        vars.insert("initial_plan".to_string(), "Build it".to_string());
+
+        let rendered = manager.render("stage2", &PromptManager::vars_to_json(vars.clone())).unwrap();
+        assert!(!rendered.contains("## Additional Instructions"));
+
+        vars.insert("extra_instructions".to_string(), "Focus on mobile".to_string());
+        let rendered = manager.render("stage2", &PromptManager::vars_to_json(vars)).unwrap();
+        assert!(rendered.contains("## Additional Instructions\nFocus on mobile"));
+    }
+
+    #[test]
+    fn test_style_selects_template_branch() {
+        let dir = tempdir().unwrap();
+        let manager = PromptManager::new(dir.path()).unwrap();
+
+        let mut vars = HashMap::new();
+        vars.insert("project_idea".to_string(), "A todo app".to_string());
+
+        let rendered = manager.render("stage1", &PromptManager::vars_to_json(vars.clone())).unwrap();
+        assert!(rendered.contains("thorough and ambitious"));
+
+        vars.insert("style".to_string(), "concise".to_string());
+        let rendered = manager.render("stage1", &PromptManager::vars_to_json(vars)).unwrap();
+        assert!(rendered.contains("Keep the plan brief"));
+        assert!(!rendered.contains("thorough and ambitious"));
+    }
+}
@@ -0,0 +1,384 @@
+use crate::ai::{self, AiClient};
+use crate::config::{self, CustomStageConfig};
+use crate::error::{Result, ToolkitError};
+use crate::models::{Artifact, Project, StageStatus, TokenUsage};
+use crate::utils::{file, json, project, ui};
+use crate::stages::{generate_stage_output, Stage, StageContext, StageResult};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// A user-defined stage declared in the `[[custom_stages]]` config array
+///
+/// It renders the configured template with the outputs of every completed
+/// stage available as `stage<N>_output`, and stores the AI's response like a
+/// built-in stage does. A stage with a `schema` must answer with JSON matching
+/// it; the JSON and a validation report are attached as artifacts.
+pub struct CustomStage {
+    config: CustomStageConfig,
+}
+
+impl CustomStage {
+    pub fn new(config: CustomStageConfig) -> Self {
+        Self { config }
+    }
+
+    /// Load the stage's JSON schema, if it declares one
+    async fn load_schema(&self) -> Result<Option<(PathBuf, Value)>> {
+        let Some(path) = &self.config.schema else {
+            return Ok(None);
+        };
+        let path = config::get_config_dir()?.join(path);
+        let content = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| ToolkitError::File(format!("Failed to read the schema of stage {} at {}: {}", self.config.number, path.display(), e)))?;
+        let schema = serde_json::from_str(&content)
+            .map_err(|e| ToolkitError::Parse(format!("Invalid JSON schema {}: {}", path.display(), e)))?;
+        Ok(Some((path, schema)))
+    }
+
+    /// Ask the AI for the stage's output, asking again while it misses the schema
+    ///
+    /// The JSON and a validation report are attached to the project. Only the
+    /// first attempt resumes saved partial output; retries start clean.
+    async fn generate_output(
+        &self,
+        ai_client: &dyn AiClient,
+        project: &mut Project,
+        base_prompt: &str,
+        schema: Option<&(PathBuf, Value)>,
+        context: &StageContext,
+    ) -> Result<Generated> {
+        let number = self.config.number;
+        let mut prompt = base_prompt.to_string();
+        let mut attempt_context = context.clone();
+        let mut usage = None;
+        let mut rejected: Vec<String> = Vec::new();
+        loop {
+            let generated = match generate_stage_output(ai_client, &prompt, self.request_options(context), &attempt_context).await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    error!("AI service error: {}", e);
+                    return Err(e);
+                }
+            };
+            usage = add_usage(usage, generated.usage);
+            let response = generated.text;
+
+            if let Err(reason) = self.validate_output(&response) {
+                return Ok(Generated::Rejected { response, reason });
+            }
+
+            let Some((schema_path, schema)) = schema else {
+                return Ok(Generated::Accepted { response, usage });
+            };
+            let validated = validate_against_schema(&response, schema);
+            let report = serde_json::json!({
+                "schema": schema_path,
+                "valid": validated.is_ok(),
+                "attempts": rejected.len() + 1,
+                "errors": rejected.iter().cloned().chain(validated.as_ref().err().map(|e| e.to_string())).collect::<Vec<_>>(),
+            });
+            self.attach_json(project, format!("stage{}-validation_report.json", number), &report).await?;
+
+            match validated {
+                Ok(value) => {
+                    self.attach_json(project, format!("stage{}-output.json", number), &value).await?;
+                    return Ok(Generated::Accepted { response, usage });
+                }
+                Err(e) if rejected.len() < self.config.schema_retries as usize => {
+                    warn!("Output of stage {} doesn't match its schema, asking again: {}", number, e);
+                    ui::print_warning(&format!("The response doesn't match the schema; asking again ({}/{}).", rejected.len() + 1, self.config.schema_retries));
+                    prompt = schema_retry_prompt(base_prompt, &e);
+                    attempt_context = super::retry_context(context).await?;
+                    rejected.push(e.to_string());
+                }
+                Err(reason) => return Ok(Generated::Rejected { response, reason }),
+            }
+        }
+    }
+
+    /// Write a JSON artifact to the project directory, replacing one of the same name
+    async fn attach_json(&self, project: &mut Project, name: String, value: &Value) -> Result<()> {
+        let path = project.path.join(&name);
+        file::write_file_async(&path, &serde_json::to_string_pretty(value)?).await?;
+        if let Some(stage) = project.get_stage_mut(self.config.number) {
+            stage.artifacts.retain(|a| a.name != name);
+        }
+        project.add_artifact(self.config.number, Artifact {
+            name,
+            file_type: "json".to_string(),
+            path,
+            created_at: chrono::Utc::now(),
+        });
+        Ok(())
+    }
+}
+
+/// Extract the JSON from a response and check it against a schema
+///
+/// Fails with a `Parse` error naming each schema violation.
+pub fn validate_against_schema(content: &str, schema: &Value) -> Result<Value> {
+    let value = json::extract_json(content)
+        .map_err(|e| ToolkitError::Parse(format!("The response has no JSON to check against the schema: {}", e)))?;
+    ai::validate_json_schema(&value, schema)?;
+    Ok(value)
+}
+
+/// The prompt asking again after a response failed the schema
+pub fn schema_retry_prompt(prompt: &str, error: &ToolkitError) -> String {
+    format!(
+        "{}\n\n## Fix Your Previous Answer\nYour previous response was rejected: {}\nRespond again with JSON that matches the required schema.",
+        prompt, error
+    )
+}
+
+/// What asking for a custom stage's output came to
+enum Generated {
+    /// The output passed the checks
+    Accepted { response: String, usage: Option<TokenUsage> },
+    /// The last output failed the checks for `reason`
+    Rejected { response: String, reason: ToolkitError },
+}
+
+/// Add up the usage of several requests, if any of them reported it
+fn add_usage(total: Option<TokenUsage>, usage: Option<TokenUsage>) -> Option<TokenUsage> {
+    match (total, usage) {
+        (Some(a), Some(b)) => Some(TokenUsage {
+            input_tokens: a.input_tokens + b.input_tokens,
+            output_tokens: a.output_tokens + b.output_tokens,
+        }),
+        (a, b) => a.or(b),
+    }
+}
+
+#[async_trait]
+impl Stage for CustomStage {
+    fn number(&self) -> u8 {
+        self.config.number
+    }
+
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn description(&self) -> &str {
+        &self.config.description
+    }
+
+    fn dependencies(&self) -> Vec<u8> {
+        self.config.depends_on.clone()
+    }
+
+    fn template_name(&self) -> String {
+        self.config.template.clone()
+    }
+
+    fn prepare_template_vars(&self, project: &Project, context: &StageContext) -> HashMap<String, String> {
+        let mut vars = super::template_vars(project, self.number(), context);
+
+        // Expose the output of every completed stage, unless the context overrides it
+        for stage in &project.stages {
+            let key = format!("stage{}_output", stage.number);
+            if let (StageStatus::Completed, Some(content), false) = (&stage.status, &stage.content, context.has(&key)) {
+                vars.insert(key, content.clone());
+            }
+        }
+
+        vars
+    }
+
+    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        let number = self.number();
+        info!("Starting custom stage {} ({}) for project: {}", number, self.name(), project_id);
+
+        // Load the project and make sure it has an entry for this stage
+        let mut project = self.load_project(project_id).await?;
+        project.ensure_stage(number, self.name(), self.description());
+
+        // Check if this stage should be skipped
+        if self.should_skip_run(&project, &context)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+
+        ui::print_stage_header(number, self.name());
+
+        // Build the prompt from the project and earlier stages
+        let base_prompt = self.build_prompt(&project, &context)?;
+        let schema = self.load_schema().await?;
+
+        // Initialize AI client
+        debug!("Initializing AI client");
+        let ai_client = ai::get_cached_client().await?;
+
+        // Send the prompt to the AI, asking again while the output misses the schema
+        info!("Sending prompt to AI service");
+        let started = Instant::now();
+        let (response, usage) = match self.generate_output(ai_client.as_ref(), &mut project, &base_prompt, schema.as_ref(), &context).await? {
+            Generated::Accepted { response, usage } => (response, usage),
+            Generated::Rejected { response, reason } => {
+                return self.reject_output(&mut project, response, reason, context).await;
+            }
+        };
+        let duration_ms = started.elapsed().as_millis() as u64;
+
+        // Update the project with the AI's response
+        info!("Updating project with AI response");
+        project.update_stage(number, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(number, usage, duration_ms);
+
+        // Save the updated project
+        debug!("Saving updated project");
+        if let Err(e) = project::save_stage_async(&project, number).await {
+            error!("Failed to save project {}: {}", project_id, e);
+            return Err(e);
+        }
+
+        // Make the output available to later stages in this run
+        context.set(self.output_key(), response);
+
+        ui::print_success(&format!("Stage {} completed successfully!", number));
+
+        Ok(StageResult::success(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn security_review() -> CustomStage {
+        CustomStage::new(CustomStageConfig {
+            number: 8,
+            name: "Security Review".to_string(),
+            description: "Review the plan for security issues".to_string(),
+            template: "security_review".to_string(),
+            depends_on: vec![2, 3],
+            schema: None,
+            schema_retries: 0,
+        })
+    }
+
+    #[test]
+    fn test_custom_stage_uses_configured_dependencies() {
+        let stage = security_review();
+        let mut project = Project::new(
+            "proj_1".to_string(),
+            "Test".to_string(),
+            "A test project".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        );
+        project.ensure_stage(8, stage.name(), stage.description());
+
+        assert_eq!(stage.dependencies(), vec![2, 3]);
+        assert_eq!(stage.template_name(), "security_review");
+        assert!(!stage.can_execute(&project));
+
+        project.update_stage(2, "evaluation".to_string(), StageStatus::Completed);
+        project.update_stage(3, "alternative".to_string(), StageStatus::Completed);
+        assert!(stage.can_execute(&project));
+
+        let vars = stage.prepare_template_vars(&project, &StageContext::new());
+        assert_eq!(vars.get("stage2_output").map(String::as_str), Some("evaluation"));
+        assert!(!vars.contains_key("stage1_output"));
+    }
+
+    #[test]
+    fn test_project_variables_have_lowest_precedence() {
+        let stage = security_review();
+        let mut project = Project::new(
+            "proj_1".to_string(),
+            "Test".to_string(),
+            "A test project".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        );
+        project.variables.insert("team_size".to_string(), "4".to_string());
+        project.variables.insert("deadline".to_string(), "June".to_string());
+        project.variables.insert("project_name".to_string(), "Shadowed".to_string());
+
+        let mut context = StageContext::new();
+        context.set("deadline", "May");
+        let vars = stage.prepare_template_vars(&project, &context);
+
+        assert_eq!(vars["team_size"], "4");
+        assert_eq!(vars["deadline"], "May");
+        assert_eq!(vars["project_name"], "Test");
+    }
+
+    #[tokio::test]
+    async fn test_schema_retry_of_resumed_stage_starts_clean() {
+        use crate::ai::testing::{MockAiClient, MockReply};
+        use crate::stages::{PARTIAL_FILE_KEY, RESUME_PARTIAL_KEY, STREAM_OUTPUT_KEY};
+
+        let dir = tempfile::tempdir().unwrap();
+        let stage = CustomStage::new(CustomStageConfig { schema_retries: 1, ..security_review().config });
+        let mut project = Project::new(
+            "proj_1".to_string(),
+            "Test".to_string(),
+            "A test project".to_string(),
+            dir.path().to_path_buf(),
+        );
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "risks": { "type": "array", "items": { "type": "string" } } },
+            "required": ["risks"]
+        });
+        let schema = (PathBuf::from("risks.json"), schema);
+        let answer = serde_json::json!({
+            "risks": [
+                "Cross-site scripting in the comment form",
+                "SQL injection in the search endpoint",
+                "Session tokens stored in local storage",
+                "No rate limiting on the login endpoint",
+                "Secrets committed to the repository"
+            ]
+        })
+        .to_string();
+        let rejected = " nothing stands out as a risk in this plan. ".repeat(5);
+        let mock = MockAiClient::new()
+            .once("Continue Your Previous Answer", MockReply::Chunks(vec![rejected]))
+            .stream("Fix Your Previous Answer", &[answer.as_str()]);
+
+        let partial = dir.path().join("stage8.partial");
+        std::fs::write(&partial, "Reviewing the plan for risks:").unwrap();
+        let mut context = StageContext::new();
+        context.set(STREAM_OUTPUT_KEY, "true");
+        context.set(PARTIAL_FILE_KEY, partial.to_string_lossy());
+        context.set(RESUME_PARTIAL_KEY, "true");
+
+        let generated = stage.generate_output(&mock, &mut project, "Review it", Some(&schema), &context).await.unwrap();
+
+        // The retry neither continues nor keeps the rejected answer
+        match generated {
+            Generated::Accepted { response, .. } => assert_eq!(response, answer),
+            Generated::Rejected { reason, .. } => panic!("expected the retry to be accepted, got {}", reason),
+        }
+        let prompts = mock.prompts();
+        assert!(prompts[0].contains("Reviewing the plan for risks:"));
+        assert!(!prompts[1].contains("Continue Your Previous Answer"));
+        assert!(!prompts[1].contains("Reviewing the plan for risks:"));
+        assert_eq!(std::fs::read_to_string(&partial).unwrap(), answer);
+    }
+
+    #[test]
+    fn test_validate_against_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "risks": { "type": "array", "items": { "type": "string" } } },
+            "required": ["risks"]
+        });
+
+        let value = validate_against_schema("Here you go:\n```json\n{\"risks\": [\"XSS\"]}\n```", &schema).unwrap();
+        assert_eq!(value["risks"][0], "XSS");
+
+        let error = validate_against_schema("{\"risks\": [42]}", &schema).unwrap_err();
+        assert!(matches!(&error, ToolkitError::Parse(message) if message.contains("/risks/0")));
+        assert!(schema_retry_prompt("Review it", &error).starts_with("Review it\n\n## Fix Your Previous Answer\n"));
+
+        assert!(matches!(validate_against_schema("No risks found", &schema), Err(ToolkitError::Parse(_))));
+    }
+}
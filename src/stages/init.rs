@@ -1,12 +1,97 @@
-use crate::error::Result;
-use crate::models::Project;
+use crate::error::{Result, ToolkitError};
+use crate::models::{Project, StageStatus};
 use crate::utils::project;
+use crate::utils::scaffold::Scaffold;
 use colored::Colorize;
 use crate::config::ColorizeExt;
 use nanoid::nanoid;
 use std::env;
+use std::io::Read;
+use std::path::Path;
 
-pub async fn run_init(name: &str, description: &str) -> Result<()> {
+/// Read a project idea from a file, or from stdin when `path` is `-`
+///
+/// The file must exist and be valid UTF-8.
+pub fn read_idea_file(path: &Path) -> Result<String> {
+    read_text_input(path, "Idea")
+}
+
+/// Read a Markdown plan to import, or read it from stdin when `path` is `-`
+pub fn read_plan_file(path: &Path) -> Result<String> {
+    read_text_input(path, "Plan")
+}
+
+/// Read non-empty UTF-8 text from a file or stdin; `what` names it in errors
+fn read_text_input(path: &Path, what: &str) -> Result<String> {
+    let (bytes, source) = if path == Path::new("-") {
+        let _waiting = crate::utils::cancel::waiting_for_input();
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| ToolkitError::File(format!("Failed to read the {} from stdin: {}", what.to_lowercase(), e)))?;
+        (bytes, "stdin".to_string())
+    } else {
+        if !path.is_file() {
+            return Err(ToolkitError::File(format!("{} file not found: {}", what, path.display())));
+        }
+        let bytes = std::fs::read(path)
+            .map_err(|e| ToolkitError::File(format!("Failed to read {} file {}: {}", what.to_lowercase(), path.display(), e)))?;
+        (bytes, path.display().to_string())
+    };
+
+    let text = String::from_utf8(bytes)
+        .map_err(|_| ToolkitError::File(format!("{} from {} is not valid UTF-8", what, source)))?;
+
+    if text.trim().is_empty() {
+        return Err(ToolkitError::File(format!("{} from {} is empty", what, source)));
+    }
+
+    Ok(text.trim().to_string())
+}
+
+/// Create a new project in the current directory
+///
+/// With a scaffold, the project starts with its tags and context values.
+pub async fn run_init(name: &str, description: &str, scaffold: Option<&Scaffold>) -> Result<()> {
+    let project = create_project(name, description, scaffold)?;
+    
+    println!("{} {} {}", "Project".green(), name.yellow(), "initialized successfully.".green());
+    print_project_location(&project);
+    println!();
+    println!("{}", "Use the following commands to manage your project:".green());
+    println!("  {} {} - {}", "run-stage".yellow(), "1".bright_blue(), "Run the first stage (Initial Plan Creation)");
+    println!("  {} {} {} - {}", "status".yellow(), "-p".bright_blue(), project.id.bright_blue(), "Check project status");
+    
+    Ok(())
+}
+
+/// Create a project from an externally written plan
+///
+/// The project is created as `init` would, then the plan becomes the content
+/// of `stage_number`, which is marked completed so later stages build on it.
+pub fn run_import(name: &str, file: &Path, stage_number: u8) -> Result<Project> {
+    let stage = super::get_stage(stage_number).ok_or(ToolkitError::StageNotFound(stage_number))?;
+    let plan = read_plan_file(file)?;
+    
+    let description = if file == Path::new("-") {
+        "Imported from stdin".to_string()
+    } else {
+        format!("Imported from {}", file.display())
+    };
+    let mut project = create_project(name, &description, None)?;
+    
+    project.ensure_stage(stage_number, stage.name(), stage.description());
+    project.update_stage(stage_number, plan, StageStatus::Completed);
+    project::save_stage(&project, stage_number)?;
+    
+    println!("{} {} {} {}", "Imported".green(), name.yellow(), "into stage".green(), stage_number.to_string().yellow());
+    print_project_location(&project);
+    
+    Ok(project)
+}
+
+/// Create the directories and files of a new project in the current directory
+fn create_project(name: &str, description: &str, scaffold: Option<&Scaffold>) -> Result<Project> {
     // Get the current directory
     let current_dir = env::current_dir()?;
     
@@ -26,12 +111,16 @@ pub async fn run_init(name: &str, description: &str) -> Result<()> {
     std::fs::create_dir_all(&artifacts_dir)?;
     
     // Create a new project
-    let project = Project::new(
-        id.clone(),
+    let mut project = Project::new(
+        id,
         name.to_string(),
         description.to_string(),
         project_dir.clone(),
     );
+    if let Some(scaffold) = scaffold {
+        project.tags = scaffold.tags.clone();
+        project.context = scaffold.context.clone();
+    }
     
     // Save the project
     project::save_project(&project)?;
@@ -43,13 +132,47 @@ pub async fn run_init(name: &str, description: &str) -> Result<()> {
         format!("# {}\n\n{}\n\nCreated at: {}", name, description, project.created_at),
     )?;
     
-    println!("{} {} {}", "Project".green(), name.yellow(), "initialized successfully.".green());
-    println!("{} {}", "Project ID:".green(), id.yellow());
-    println!("{} {}", "Project directory:".green(), project_dir.display().to_string().yellow());
-    println!();
-    println!("{}", "Use the following commands to manage your project:".green());
-    println!("  {} {} - {}", "run-stage".yellow(), "1".bright_blue(), "Run the first stage (Initial Plan Creation)");
-    println!("  {} {} {} - {}", "status".yellow(), "-p".bright_blue(), id.bright_blue(), "Check project status");
-    
-    Ok(())
+    Ok(project)
+}
+
+fn print_project_location(project: &Project) {
+    println!("{} {}", "Project ID:".green(), project.id.yellow());
+    println!("{} {}", "Project directory:".green(), project.path.display().to_string().yellow());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_idea_file() {
+        let dir = tempdir().unwrap();
+
+        let idea = dir.path().join("idea.md");
+        std::fs::write(&idea, "A CLI for planning.\n\nWith several paragraphs.\n").unwrap();
+        assert_eq!(read_idea_file(&idea).unwrap(), "A CLI for planning.\n\nWith several paragraphs.");
+
+        let binary = dir.path().join("idea.bin");
+        std::fs::write(&binary, [0xff, 0xfe, 0x00]).unwrap();
+        assert!(matches!(read_idea_file(&binary), Err(ToolkitError::File(_))));
+
+        assert!(matches!(read_idea_file(&dir.path().join("missing.md")), Err(ToolkitError::File(_))));
+    }
+
+    #[test]
+    fn test_read_plan_file() {
+        let dir = tempdir().unwrap();
+
+        let plan = dir.path().join("plan.md");
+        std::fs::write(&plan, "# Plan\n\n1. Build it\n").unwrap();
+        assert_eq!(read_plan_file(&plan).unwrap(), "# Plan\n\n1. Build it");
+
+        let empty = dir.path().join("empty.md");
+        std::fs::write(&empty, "\n  \n").unwrap();
+        match read_plan_file(&empty) {
+            Err(ToolkitError::File(message)) => assert!(message.starts_with("Plan from")),
+            other => panic!("expected a file error, got {:?}", other),
+        }
+    }
 }
@@ -1,20 +1,34 @@
+pub mod custom;
 pub mod init;
+pub mod postprocess;
+pub mod regen;
 pub mod stage1;
 pub mod stage2;
 pub mod stage3;
 pub mod stage4;
 pub mod stage5;
 pub mod stage6;
+pub mod stage7;
+pub mod summarize;
 
+use crate::ai::{self, AiClient, GenerateResponse, JsonResponse, RequestOptions};
 use crate::error::Result;
-use crate::models::{Project, StageStatus};
-use crate::utils::{project, ui};
+use crate::models::{Artifact, Project, StageStatus};
+use crate::prompts::{PromptManager, PROJECT_TEMPLATES_DIR};
+use crate::utils::{file, notify, project, tokens, ui};
 use async_trait::async_trait;
+use futures::StreamExt;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
 use log::{debug, error, info, warn};
+use once_cell::sync::OnceCell;
 use serde_json::Value;
 use anyhow::anyhow;
+use colored::Colorize;
 use crate::error::ToolkitError;
 
 /// The status of a stage execution
@@ -39,6 +53,157 @@ impl Display for StageExecutionStatus {
     }
 }
 
+/// Number of built-in stages in the pipeline
+///
+/// This must match the built-in stages returned by [`get_stage`]. Custom stages
+/// from the configuration use numbers above it.
+pub const STAGE_COUNT: u8 = 7;
+
+/// Context key holding free-form instructions supplied for a single run
+pub const EXTRA_INSTRUCTIONS_KEY: &str = "extra_instructions";
+
+/// Context key set to `"true"` to stream the AI response to the terminal
+pub const STREAM_OUTPUT_KEY: &str = "stream_output";
+
+/// Context key overriding the stage's default maximum response length
+pub const MAX_TOKENS_KEY: &str = "max_tokens";
+
+/// Context key overriding the stage's default sampling temperature
+pub const TEMPERATURE_KEY: &str = "temperature";
+
+/// Context key holding the sampling seed for a single run
+pub const SEED_KEY: &str = "seed";
+
+/// Context key holding the [`OutputStyle`] templates branch on
+pub const STYLE_KEY: &str = "style";
+
+/// Context key naming the directory completed stages are written to as Markdown files
+pub const OUTPUT_DIR_KEY: &str = "output_dir";
+
+/// Context key set to `"true"` to refine the stage's existing output instead of replacing it
+pub const APPEND_KEY: &str = "append";
+
+/// Context key set to `"true"` to keep running other stages after one fails
+pub const CONTINUE_ON_ERROR_KEY: &str = "continue_on_error";
+
+/// Context key naming the file a streamed response is saved to as it arrives
+pub const PARTIAL_FILE_KEY: &str = "partial_file";
+
+/// Context key set to `"true"` to continue the response saved in the [`PARTIAL_FILE_KEY`] file
+pub const RESUME_PARTIAL_KEY: &str = "resume_partial";
+
+/// Context key set to `"true"` once this run has decided the stage won't be skipped
+pub const SKIP_CHECKED_KEY: &str = "skip_checked";
+
+/// Template variable holding the stage's existing output when [`APPEND_KEY`] is set
+pub const PREVIOUS_OUTPUT_VAR: &str = "previous_output";
+
+/// Template variable naming the language stage output is written in
+pub const LANGUAGE_VAR: &str = "language";
+
+/// Language given with `--lang`, used instead of the configured one
+static LANGUAGE_OVERRIDE: OnceCell<String> = OnceCell::new();
+
+/// Context keys that only apply to the run that sets them and aren't saved with the project
+const RUN_ONLY_KEYS: [&str; 12] = [
+    EXTRA_INSTRUCTIONS_KEY,
+    STREAM_OUTPUT_KEY,
+    MAX_TOKENS_KEY,
+    TEMPERATURE_KEY,
+    SEED_KEY,
+    STYLE_KEY,
+    OUTPUT_DIR_KEY,
+    APPEND_KEY,
+    CONTINUE_ON_ERROR_KEY,
+    PARTIAL_FILE_KEY,
+    RESUME_PARTIAL_KEY,
+    SKIP_CHECKED_KEY,
+];
+
+/// How much detail the stage templates ask the AI for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputStyle {
+    /// A quick sketch of each point
+    Concise,
+    /// The full, thorough treatment
+    #[default]
+    Detailed,
+}
+
+impl std::str::FromStr for OutputStyle {
+    type Err = ToolkitError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "concise" => Ok(Self::Concise),
+            "detailed" => Ok(Self::Detailed),
+            other => Err(ToolkitError::InvalidInput(format!(
+                "Unsupported style: {}. Supported styles: concise, detailed",
+                other
+            ))),
+        }
+    }
+}
+
+impl Display for OutputStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Concise => write!(f, "concise"),
+            Self::Detailed => write!(f, "detailed"),
+        }
+    }
+}
+
+/// Shortest output, in characters, a stage accepts from the AI
+///
+/// Read from the `min_response_chars` setting; 0 turns the check off.
+pub fn min_response_chars() -> usize {
+    crate::config::get_config()
+        .map(|config| config.min_response_chars)
+        .unwrap_or_else(|_| crate::config::default_min_response_chars())
+}
+
+/// Reject output shorter than `min_chars` characters, besides surrounding whitespace
+///
+/// Short answers are usually empty responses, refusals or responses cut off
+/// by a low `max_tokens`.
+pub fn check_output_length(content: &str, min_chars: usize) -> Result<()> {
+    let length = content.trim().chars().count();
+    if length < min_chars {
+        return Err(ToolkitError::Parse(format!(
+            "The AI returned {} characters of output, expected at least {}. If the response was cut off, raise --max-tokens.",
+            length, min_chars
+        )));
+    }
+    
+    Ok(())
+}
+
+/// Put the configured prefix and suffix around a stage prompt
+///
+/// Each is separated from the prompt by a blank line; blank values are ignored.
+pub fn wrap_prompt(prompt: String, prefix: Option<&str>, suffix: Option<&str>) -> String {
+    let prefix = prefix.map(str::trim).filter(|text| !text.is_empty());
+    let suffix = suffix.map(str::trim).filter(|text| !text.is_empty());
+    if prefix.is_none() && suffix.is_none() {
+        return prompt;
+    }
+    
+    let parts: Vec<&str> = prefix.into_iter().chain(Some(prompt.as_str())).chain(suffix).collect();
+    parts.join("\n\n")
+}
+
+/// Check that a sampling temperature is within the range providers accept
+pub fn validate_temperature(temperature: f32) -> Result<()> {
+    if !(0.0..=2.0).contains(&temperature) {
+        return Err(ToolkitError::InvalidInput(format!(
+            "Temperature must be between 0.0 and 2.0, got {}",
+            temperature
+        )));
+    }
+    Ok(())
+}
+
 /// Context data passed between stages
 #[derive(Debug, Clone, Default)]
 pub struct StageContext {
@@ -73,6 +238,40 @@ impl StageContext {
     pub fn to_json(&self) -> Value {
         serde_json::to_value(&self.data).unwrap_or_default()
     }
+    
+    /// Fill in values saved with the project by earlier runs
+    ///
+    /// Values already in the context win, so this run's own data and
+    /// command-line overrides aren't replaced by older ones.
+    pub fn merge_saved(&mut self, saved: &HashMap<String, String>) {
+        for (key, value) in saved {
+            self.data.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    
+    /// Fill in what earlier runs left in `project` for running `stage`
+    ///
+    /// The current output of the completed stages before `stage` comes first,
+    /// so a copy saved in the project context before the stage was
+    /// regenerated, imported or edited can't override it. The rest of the
+    /// saved context fills the remaining gaps.
+    pub fn merge_project(&mut self, project: &Project, stage: u8) {
+        self.merge_saved(&earlier_stage_outputs(project, stage));
+        self.merge_saved(&project.context);
+    }
+    
+    /// Values that differ from `saved` and should be kept with the project
+    ///
+    /// Per-run settings such as extra instructions and sampling overrides
+    /// are left out.
+    pub fn changes_from(&self, saved: &HashMap<String, String>) -> HashMap<String, String> {
+        self.data
+            .iter()
+            .filter(|(key, _)| !RUN_ONLY_KEYS.contains(&key.as_str()))
+            .filter(|(key, value)| saved.get(*key) != Some(*value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
 }
 
 /// Result of a stage execution
@@ -158,29 +357,102 @@ pub trait Stage: Send + Sync {
         }
     }
     
+    /// Get the dependencies this stage runs with
+    ///
+    /// An entry for this stage in the `stage_overrides` config table replaces
+    /// [`Stage::dependencies`].
+    fn configured_dependencies(&self) -> Vec<u8> {
+        dependency_override(self.number()).unwrap_or_else(|| self.dependencies())
+    }
+    
+    /// Whether this stage asks the user for input while it runs
+    ///
+    /// Such stages don't run concurrently with others, so their prompt has
+    /// the terminal to itself.
+    fn is_interactive(&self) -> bool {
+        false
+    }
+    
+    /// Get the context key later stages find this stage's output under
+    fn output_key(&self) -> String {
+        format!("stage{}_output", self.number())
+    }
+    
     /// Get the template name for this stage
     fn template_name(&self) -> String {
         format!("stage{}", self.number())
     }
     
-    /// Prepare the template variables for this stage
-    fn prepare_template_vars(&self, project: &Project, context: &StageContext) -> HashMap<String, String> {
-        let mut vars = HashMap::new();
-        
-        // Add project description
-        vars.insert("project_description".to_string(), project.description.clone());
+    /// Get the system prompt for this stage, if any
+    fn system_prompt(&self) -> Option<&str> {
+        None
+    }
+    
+    /// Get the default sampling options for this stage
+    ///
+    /// Stages that benefit from more creative or more deterministic output
+    /// override this. Values given on the command line take precedence.
+    fn default_options(&self) -> RequestOptions {
+        RequestOptions::default()
+    }
+    
+    /// Build the AI request options for this stage
+    ///
+    /// Starts from [`Stage::default_options`], adds the system prompt, asks
+    /// for the output language when it isn't English, opts in to
+    /// provider-side prompt caching and applies any `max_tokens`,
+    /// `temperature` or `seed` overrides from the context.
+    fn request_options(&self, context: &StageContext) -> RequestOptions {
+        let mut options = self.default_options();
+        options.system = with_language(self.system_prompt(), &stage_language());
+        // Every run of a stage repeats its system prompt, and a rerun the whole prompt
+        options.provider_cache = true;
         
-        // Add all context variables
-        for (key, value) in &context.data {
-            vars.insert(key.clone(), value.clone());
+        if let Some(max_tokens) = context.get(MAX_TOKENS_KEY).and_then(|v| v.parse().ok()) {
+            options.max_tokens = Some(max_tokens);
         }
+        if let Some(temperature) = context.get(TEMPERATURE_KEY).and_then(|v| v.parse().ok()) {
+            options.temperature = Some(temperature);
+        }
+        if let Some(seed) = context.get(SEED_KEY).and_then(|v| v.parse().ok()) {
+            options.seed = Some(seed);
+        }
+        
+        options
+    }
+    
+    /// Prepare the template variables for this stage
+    fn prepare_template_vars(&self, project: &Project, context: &StageContext) -> HashMap<String, String> {
+        template_vars(project, self.number(), context)
+    }
+    
+    /// Build the prompt this stage sends to the AI
+    ///
+    /// Stages that need more than the common template variables override this
+    /// to gather them from the project before rendering.
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
+        let template_vars = self.prepare_template_vars(project, context);
+        self.render_prompt(project, template_vars)
+    }
+    
+    /// Render this stage's template with the given variables
+    ///
+    /// Templates in the project's `templates/` directory take precedence over
+    /// the global ones. The configured `prompt_prefix` and `prompt_suffix`
+    /// wrap the result.
+    fn render_prompt(&self, project: &Project, template_vars: HashMap<String, String>) -> Result<String> {
+        let mut prompt_manager = PromptManager::global()?;
+        prompt_manager.with_overlay(&project.path.join(PROJECT_TEMPLATES_DIR))?;
+        let variables = PromptManager::vars_to_json(template_vars);
+        let prompt = prompt_manager.render_checked(&self.template_name(), &variables)?;
         
-        vars
+        let config = crate::config::get_config()?;
+        Ok(wrap_prompt(prompt, config.prompt_prefix.as_deref(), config.prompt_suffix.as_deref()))
     }
     
     /// Check if this stage can be executed based on dependencies
     fn can_execute(&self, project: &Project) -> bool {
-        let dependencies = self.dependencies();
+        let dependencies = self.configured_dependencies();
         
         // If there are no dependencies, we can always execute
         if dependencies.is_empty() {
@@ -201,13 +473,42 @@ pub trait Stage: Send + Sync {
         true
     }
     
+    /// Check the AI's output before the stage is marked completed
+    ///
+    /// By default the output must be at least [`min_response_chars`] long,
+    /// which catches empty answers, refusals and truncated responses. Stages
+    /// expecting a particular format override this to check it.
+    fn validate_output(&self, content: &str) -> Result<()> {
+        check_output_length(content, min_response_chars())
+    }
+    
+    /// Mark this stage failed because its output didn't pass [`Stage::validate_output`]
+    ///
+    /// The rejected output is saved with the stage so it can be inspected, but
+    /// stages depending on this one won't run until it completes.
+    async fn reject_output(&self, project: &mut Project, content: String, reason: ToolkitError, context: StageContext) -> Result<StageResult> {
+        let number = self.number();
+        warn!("Rejected output of Stage {}: {}", number, reason);
+        
+        project.update_stage(number, content, StageStatus::Failed);
+        if let Err(e) = project::save_stage_async(project, number).await {
+            error!("Failed to save project {}: {}", project.id, e);
+            return Err(e);
+        }
+        
+        Ok(StageResult::failure(
+            format!("{} Run the stage again, or adjust its prompt with --instructions.", reason),
+            context,
+        ))
+    }
+    
     /// Execute this stage, returning the result
     async fn execute(&self, project_id: &str, context: StageContext) -> Result<StageResult>;
     
     /// Common implementation for loading a project
-    fn load_project(&self, project_id: &str) -> Result<Project> {
+    async fn load_project(&self, project_id: &str) -> Result<Project> {
         debug!("Loading project data for {}", project_id);
-        project::load_project(project_id).map_err(|e| {
+        project::load_project_async(project_id).await.map_err(|e| {
             error!("Failed to load project {}: {}", project_id, e);
             e
         })
@@ -221,18 +522,22 @@ pub trait Stage: Send + Sync {
                 warn!("Stage {} has already been completed", self.number());
                 ui::print_warning(&format!("Stage {} has already been completed.", self.number()));
                 
-                if !ui::prompt_yes_no("Do you want to run it again?", false)? {
+                // With `--yes` or without a terminal there's no one to ask, and
+                // running the stage was asked for, so it runs again
+                if ui::assume_yes() || !ui::is_interactive() {
+                    info!("Running Stage {} again without asking", self.number());
+                } else if !ui::prompt_yes_no("Do you want to run it again?", false)? {
                     info!("User chose not to run Stage {} again", self.number());
                     return Ok(true);
+                } else {
+                    info!("User chose to run Stage {} again", self.number());
                 }
-                
-                info!("User chose to run Stage {} again", self.number());
             }
         }
         
         // Check dependencies
         if !self.can_execute(project) {
-            let deps = self.dependencies();
+            let deps = self.configured_dependencies();
             warn!("Dependencies for Stage {} are not met: {:?}", self.number(), deps);
             ui::print_warning(&format!(
                 "Dependencies for Stage {} are not met. Please complete stages {:?} first.",
@@ -243,6 +548,250 @@ pub trait Stage: Send + Sync {
         
         Ok(false)
     }
+    
+    /// Check if a stage should be skipped, unless this run already decided it won't be
+    fn should_skip_run(&self, project: &Project, context: &StageContext) -> Result<bool> {
+        if context.get(SKIP_CHECKED_KEY).map(String::as_str) == Some("true") {
+            return Ok(false);
+        }
+        self.should_skip(project)
+    }
+}
+
+/// The template variables every stage gets
+///
+/// Project variables, the project's name and description, and the run's
+/// context, plus the style, language, extra instructions and, when
+/// refining, the stage's previous output.
+pub fn template_vars(project: &Project, stage_number: u8, context: &StageContext) -> HashMap<String, String> {
+    // Project variables come first so everything else can override them
+    let mut vars = project.variables.clone();
+    
+    // Add project name and description
+    vars.insert("project_name".to_string(), project.name.clone());
+    vars.insert("project_description".to_string(), project.description.clone());
+    
+    // Add all context variables
+    for (key, value) in &context.data {
+        vars.insert(key.clone(), value.clone());
+    }
+    
+    // Templates branch on the style, so it always has a value
+    vars.entry(STYLE_KEY.to_string()).or_insert_with(|| OutputStyle::default().to_string());
+    vars.insert(LANGUAGE_VAR.to_string(), stage_language());
+    
+    // Only surface extra instructions when they actually say something
+    match context.get(EXTRA_INSTRUCTIONS_KEY).map(|s| s.trim()) {
+        Some(instructions) if !instructions.is_empty() => {
+            vars.insert(EXTRA_INSTRUCTIONS_KEY.to_string(), instructions.to_string());
+        }
+        _ => {
+            vars.remove(EXTRA_INSTRUCTIONS_KEY);
+        }
+    }
+    
+    vars.remove(PREVIOUS_OUTPUT_VAR);
+    if let Some(previous) = previous_output(project, stage_number, context) {
+        vars.insert(PREVIOUS_OUTPUT_VAR.to_string(), previous.to_string());
+    }
+    
+    vars
+}
+
+/// Write stage output in a different language than the configured one for the rest of the process
+///
+/// Set from the `--lang` flag. Only the first call has an effect.
+pub fn set_language_override(language: &str) {
+    if LANGUAGE_OVERRIDE.set(language.trim().to_string()).is_err() {
+        warn!("Language was already overridden; ignoring the new value");
+    }
+}
+
+/// The language stages write in: `--lang` if given, else the configured `language`
+pub fn stage_language() -> String {
+    LANGUAGE_OVERRIDE
+        .get()
+        .cloned()
+        .or_else(|| crate::config::get_config().ok().map(|config| config.language))
+        .unwrap_or_else(|| "English".to_string())
+}
+
+/// Add an instruction to answer in `language` to a system prompt
+///
+/// English is what the templates are written in, so it needs no instruction
+/// and leaves the system prompt, and the responses cached under it, unchanged.
+pub fn with_language(system: Option<&str>, language: &str) -> Option<String> {
+    let language = language.trim();
+    if language.is_empty() || language.eq_ignore_ascii_case("english") {
+        return system.map(str::to_string);
+    }
+
+    let instruction = format!("Respond in {}.", language);
+    Some(match system {
+        Some(system) => format!("{} {}", system, instruction),
+        None => instruction,
+    })
+}
+
+/// The stage's existing output, when the run asks to refine it with [`APPEND_KEY`]
+pub fn previous_output<'a>(project: &'a Project, stage: u8, context: &StageContext) -> Option<&'a str> {
+    if context.get(APPEND_KEY).map(String::as_str) != Some("true") {
+        return None;
+    }
+    project
+        .get_stage(stage)
+        .and_then(|s| s.content.as_deref())
+        .filter(|content| !content.trim().is_empty())
+}
+
+/// File a stage's streamed response is saved to while it arrives, e.g. `stage2.partial`
+pub fn partial_file(project: &Project, stage: u8) -> PathBuf {
+    project.path.join(format!("stage{}.partial", stage))
+}
+
+/// Text saved by an interrupted run of a stage that hasn't completed since
+pub async fn pending_partial(project: &Project, stage: u8) -> Option<String> {
+    let completed = project.get_stage(stage).is_some_and(|s| s.status == StageStatus::Completed);
+    if completed {
+        return None;
+    }
+    tokio::fs::read_to_string(partial_file(project, stage))
+        .await
+        .ok()
+        .filter(|partial| !partial.trim().is_empty())
+}
+
+/// Check whether running a stage asks the user something on the terminal
+///
+/// Besides stages that always ask, a stage with saved partial output asks
+/// whether to resume it.
+async fn asks_for_input(project: &Project, stage: &StageEnum) -> bool {
+    stage.is_interactive() || pending_partial(project, stage.number()).await.is_some()
+}
+
+/// Ask the AI to carry on from a response that was cut off
+fn continuation_prompt(prompt: &str, partial: &str) -> String {
+    format!(
+        "{}\n\n## Continue Your Previous Answer\n\nYour previous answer was cut off. This is what you wrote so far:\n\n{}\n\nContinue exactly where it stops, without repeating any of it.",
+        prompt, partial
+    )
+}
+
+/// Context for asking again after an answer was rejected
+///
+/// Only the first attempt continues saved output. A retry starts over, so it
+/// doesn't resume and the partial file, which may hold the rejected answer,
+/// is emptied.
+pub async fn retry_context(context: &StageContext) -> Result<StageContext> {
+    let mut context = context.clone();
+    context.data.remove(RESUME_PARTIAL_KEY);
+    if let Some(path) = context.get(PARTIAL_FILE_KEY).map(PathBuf::from) {
+        if path.exists() {
+            tokio::fs::write(&path, "").await?;
+        }
+    }
+    Ok(context)
+}
+
+/// Send a stage prompt to the AI client
+///
+/// When the context asks for streaming, the response is printed to the terminal
+/// as it arrives and accumulated into the returned text. With a
+/// [`PARTIAL_FILE_KEY`] in the context, each chunk is also appended to that
+/// file, so a crash doesn't lose what has arrived. With [`RESUME_PARTIAL_KEY`]
+/// set, the AI continues the text already in the file instead of starting over.
+/// Streamed responses don't report token usage. A prompt too long for the
+/// model's context window, counting any resumed text, is rejected before
+/// anything is sent.
+pub async fn generate_stage_output(
+    ai_client: &dyn AiClient,
+    prompt: &str,
+    options: RequestOptions,
+    context: &StageContext,
+) -> Result<GenerateResponse> {
+    let partial_path = context.get(PARTIAL_FILE_KEY).map(PathBuf::from);
+    let resumed = match &partial_path {
+        Some(path) if context.get(RESUME_PARTIAL_KEY).map(String::as_str) == Some("true") => {
+            tokio::fs::read_to_string(path).await.unwrap_or_default()
+        }
+        _ => String::new(),
+    };
+    let prompt = if resumed.is_empty() {
+        prompt.to_string()
+    } else {
+        info!("Continuing {} characters of saved output", resumed.chars().count());
+        continuation_prompt(prompt, &resumed)
+    };
+    ai::check_context_window(ai_client.model_version(), &prompt, &options)?;
+    
+    if context.get(STREAM_OUTPUT_KEY).map(String::as_str) != Some("true") {
+        let mut response = ai_client.generate_with_usage(&prompt, options).await?;
+        response.text.insert_str(0, &resumed);
+        return Ok(response);
+    }
+    
+    let mut stream = ai_client.generate_streaming_with_options(&prompt, options).await?;
+    let mut partial = match &partial_path {
+        Some(path) => match open_partial_file(path, !resumed.is_empty()).await {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("Couldn't save the response to {} as it arrives: {}", path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut text = resumed;
+    let mut stdout = std::io::stdout();
+    print!("{}", text);
+    
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        print!("{}", chunk);
+        stdout.flush()?;
+        if let Some(file) = partial.as_mut() {
+            if let Err(e) = async { file.write_all(chunk.as_bytes()).await?; file.flush().await }.await {
+                warn!("Stopped saving the response as it arrives: {}", e);
+                partial = None;
+            }
+        }
+        text.push_str(&chunk);
+    }
+    println!();
+    
+    Ok(GenerateResponse { text, usage: None })
+}
+
+/// Send a stage prompt whose response must match a JSON schema
+///
+/// Like [`generate_stage_output`], a prompt too long for the model's context
+/// window is rejected before anything is sent. The structured response only
+/// means something once it is whole, so it is never streamed or saved to the
+/// [`PARTIAL_FILE_KEY`] file.
+pub async fn generate_stage_json(
+    ai_client: &dyn AiClient,
+    prompt: &str,
+    schema: Value,
+    options: RequestOptions,
+    context: &StageContext,
+) -> Result<JsonResponse> {
+    ai::check_context_window(ai_client.model_version(), prompt, &options)?;
+    if context.get(STREAM_OUTPUT_KEY).map(String::as_str) == Some("true") {
+        ui::print_warning("This stage's structured output can't be streamed; it will be shown once it is complete.");
+    }
+    
+    ai_client.generate_json_schema_with_usage(prompt, schema, options).await
+}
+
+/// Open the file a streamed response is saved to, keeping its text when resuming
+async fn open_partial_file(path: &Path, resume: bool) -> std::io::Result<tokio::fs::File> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resume)
+        .truncate(!resume)
+        .open(path)
+        .await
 }
 
 /// An enum that wraps all possible stage implementations
@@ -254,6 +803,8 @@ pub enum StageEnum {
     Stage4(stage4::Stage4),
     Stage5(stage5::Stage5),
     Stage6(stage6::Stage6),
+    Stage7(stage7::Stage7),
+    Custom(custom::CustomStage),
 }
 
 impl StageEnum {
@@ -266,6 +817,8 @@ impl StageEnum {
             StageEnum::Stage4(s) => s.number(),
             StageEnum::Stage5(s) => s.number(),
             StageEnum::Stage6(s) => s.number(),
+            StageEnum::Stage7(s) => s.number(),
+            StageEnum::Custom(s) => s.number(),
         }
     }
     
@@ -278,11 +831,41 @@ impl StageEnum {
             StageEnum::Stage4(s) => s.name(),
             StageEnum::Stage5(s) => s.name(),
             StageEnum::Stage6(s) => s.name(),
+            StageEnum::Stage7(s) => s.name(),
+            StageEnum::Custom(s) => s.name(),
+        }
+    }
+    
+    /// Get the description of this stage
+    pub fn description(&self) -> &str {
+        match self {
+            StageEnum::Stage1(s) => s.description(),
+            StageEnum::Stage2(s) => s.description(),
+            StageEnum::Stage3(s) => s.description(),
+            StageEnum::Stage4(s) => s.description(),
+            StageEnum::Stage5(s) => s.description(),
+            StageEnum::Stage6(s) => s.description(),
+            StageEnum::Stage7(s) => s.description(),
+            StageEnum::Custom(s) => s.description(),
         }
     }
     
-    /// Get the dependencies of this stage
+    /// Get the dependencies of this stage, including any `stage_overrides` from the config
     pub fn dependencies(&self) -> Vec<u8> {
+        match self {
+            StageEnum::Stage1(s) => s.configured_dependencies(),
+            StageEnum::Stage2(s) => s.configured_dependencies(),
+            StageEnum::Stage3(s) => s.configured_dependencies(),
+            StageEnum::Stage4(s) => s.configured_dependencies(),
+            StageEnum::Stage5(s) => s.configured_dependencies(),
+            StageEnum::Stage6(s) => s.configured_dependencies(),
+            StageEnum::Stage7(s) => s.configured_dependencies(),
+            StageEnum::Custom(s) => s.configured_dependencies(),
+        }
+    }
+    
+    /// Get the dependencies this stage declares, ignoring `stage_overrides`
+    fn default_dependencies(&self) -> Vec<u8> {
         match self {
             StageEnum::Stage1(s) => s.dependencies(),
             StageEnum::Stage2(s) => s.dependencies(),
@@ -290,11 +873,172 @@ impl StageEnum {
             StageEnum::Stage4(s) => s.dependencies(),
             StageEnum::Stage5(s) => s.dependencies(),
             StageEnum::Stage6(s) => s.dependencies(),
+            StageEnum::Stage7(s) => s.dependencies(),
+            StageEnum::Custom(s) => s.dependencies(),
+        }
+    }
+    
+    /// Build the AI request options this stage would use
+    pub fn request_options(&self, context: &StageContext) -> RequestOptions {
+        match self {
+            StageEnum::Stage1(s) => s.request_options(context),
+            StageEnum::Stage2(s) => s.request_options(context),
+            StageEnum::Stage3(s) => s.request_options(context),
+            StageEnum::Stage4(s) => s.request_options(context),
+            StageEnum::Stage5(s) => s.request_options(context),
+            StageEnum::Stage6(s) => s.request_options(context),
+            StageEnum::Stage7(s) => s.request_options(context),
+            StageEnum::Custom(s) => s.request_options(context),
+        }
+    }
+    
+    /// Whether this stage asks the user for input while it runs
+    pub fn is_interactive(&self) -> bool {
+        match self {
+            StageEnum::Stage1(s) => s.is_interactive(),
+            StageEnum::Stage2(s) => s.is_interactive(),
+            StageEnum::Stage3(s) => s.is_interactive(),
+            StageEnum::Stage4(s) => s.is_interactive(),
+            StageEnum::Stage5(s) => s.is_interactive(),
+            StageEnum::Stage6(s) => s.is_interactive(),
+            StageEnum::Stage7(s) => s.is_interactive(),
+            StageEnum::Custom(s) => s.is_interactive(),
+        }
+    }
+    
+    /// Get the context key later stages find this stage's output under
+    pub fn output_key(&self) -> String {
+        match self {
+            StageEnum::Stage1(s) => s.output_key(),
+            StageEnum::Stage2(s) => s.output_key(),
+            StageEnum::Stage3(s) => s.output_key(),
+            StageEnum::Stage4(s) => s.output_key(),
+            StageEnum::Stage5(s) => s.output_key(),
+            StageEnum::Stage6(s) => s.output_key(),
+            StageEnum::Stage7(s) => s.output_key(),
+            StageEnum::Custom(s) => s.output_key(),
+        }
+    }
+    
+    /// Check if this stage can be executed based on dependencies
+    pub fn can_execute(&self, project: &Project) -> bool {
+        match self {
+            StageEnum::Stage1(s) => s.can_execute(project),
+            StageEnum::Stage2(s) => s.can_execute(project),
+            StageEnum::Stage3(s) => s.can_execute(project),
+            StageEnum::Stage4(s) => s.can_execute(project),
+            StageEnum::Stage5(s) => s.can_execute(project),
+            StageEnum::Stage6(s) => s.can_execute(project),
+            StageEnum::Stage7(s) => s.can_execute(project),
+            StageEnum::Custom(s) => s.can_execute(project),
+        }
+    }
+    
+    /// Check if this stage should be skipped, asking whether to run a completed one again
+    pub fn should_skip(&self, project: &Project) -> Result<bool> {
+        match self {
+            StageEnum::Stage1(s) => s.should_skip(project),
+            StageEnum::Stage2(s) => s.should_skip(project),
+            StageEnum::Stage3(s) => s.should_skip(project),
+            StageEnum::Stage4(s) => s.should_skip(project),
+            StageEnum::Stage5(s) => s.should_skip(project),
+            StageEnum::Stage6(s) => s.should_skip(project),
+            StageEnum::Stage7(s) => s.should_skip(project),
+            StageEnum::Custom(s) => s.should_skip(project),
+        }
+    }
+    
+    /// Build the prompt this stage would send to the AI
+    pub fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
+        match self {
+            StageEnum::Stage1(s) => s.build_prompt(project, context),
+            StageEnum::Stage2(s) => s.build_prompt(project, context),
+            StageEnum::Stage3(s) => s.build_prompt(project, context),
+            StageEnum::Stage4(s) => s.build_prompt(project, context),
+            StageEnum::Stage5(s) => s.build_prompt(project, context),
+            StageEnum::Stage6(s) => s.build_prompt(project, context),
+            StageEnum::Stage7(s) => s.build_prompt(project, context),
+            StageEnum::Custom(s) => s.build_prompt(project, context),
         }
     }
     
     /// Execute this stage
+    ///
+    /// The context saved with the project fills in anything `context` doesn't
+    /// have, and what a completed stage adds to it is saved for later runs.
+    /// Completed stages go through the configured post-processors, and runs
+    /// that complete or fail are reported to the configured webhook.
     pub async fn execute(&self, project_id: &str, context: StageContext) -> Result<StageResult> {
+        let started = Instant::now();
+        let result = self.execute_with_saved_context(project_id, context).await;
+        
+        let (status, message) = match &result {
+            Ok(result) if result.is_success() => ("completed", None),
+            Ok(result) if result.is_failure() => ("failed", result.message.clone()),
+            Ok(_) | Err(ToolkitError::Cancelled) => return result,
+            Err(e) => ("failed", Some(e.to_string())),
+        };
+        notify::notify_stage_finished(&notify::StageNotification {
+            project: project_id.to_string(),
+            stage: self.number(),
+            status,
+            message,
+            duration_ms: started.elapsed().as_millis() as u64,
+        })
+        .await;
+        
+        result
+    }
+    
+    async fn execute_with_saved_context(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        let project = project::load_project_async(project_id).await?;
+        context.merge_project(&project, self.number());
+        
+        // Decided here so that a skipped stage doesn't summarize earlier ones
+        if self.should_skip(&project)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+        context.set(SKIP_CHECKED_KEY, "true");
+        
+        let originals = summarize::summarize_earlier_stages(&project, self.number(), &mut context).await;
+        let partial = partial_file(&project, self.number());
+        if let Some(saved) = pending_partial(&project, self.number()).await {
+            ui::print_warning(&format!(
+                "Stage {} was interrupted with {} characters of output saved.",
+                self.number(), saved.chars().count()
+            ));
+            // Without a terminal, or with `--yes`, the saved output is kept and continued
+            if ui::prompt_yes_no("Resume from it? Answering no discards it.", true)? {
+                context.set(RESUME_PARTIAL_KEY, "true");
+            } else {
+                tokio::fs::remove_file(&partial).await?;
+            }
+        }
+        context.set(PARTIAL_FILE_KEY, partial.to_string_lossy());
+        
+        let mut result = self.execute_stage(project_id, context).await?;
+        // The response is saved with the stage now, even when it was rejected
+        if !result.is_skipped() && partial.exists() {
+            if let Err(e) = tokio::fs::remove_file(&partial).await {
+                warn!("Failed to remove {}: {}", partial.display(), e);
+            }
+        }
+        // Later stages and the saved context keep the full text
+        for (key, content) in originals {
+            result.context.set(key, content);
+        }
+        if result.is_success() {
+            let changes = result.context.changes_from(&project.context);
+            if !changes.is_empty() {
+                project::save_context_async(&project, &changes).await?;
+            }
+            postprocess::run_configured(project_id, self.number()).await;
+        }
+        
+        Ok(result)
+    }
+    
+    async fn execute_stage(&self, project_id: &str, context: StageContext) -> Result<StageResult> {
         match self {
             StageEnum::Stage1(s) => s.execute(project_id, context).await,
             StageEnum::Stage2(s) => s.execute(project_id, context).await,
@@ -302,12 +1046,38 @@ impl StageEnum {
             StageEnum::Stage4(s) => s.execute(project_id, context).await,
             StageEnum::Stage5(s) => s.execute(project_id, context).await,
             StageEnum::Stage6(s) => s.execute(project_id, context).await,
+            StageEnum::Stage7(s) => s.execute(project_id, context).await,
+            StageEnum::Custom(s) => s.execute(project_id, context).await,
         }
     }
 }
 
+/// Collect the output of completed stages numbered below `stage`, by their context keys
+///
+/// This rebuilds the context a full run would have passed along, for stages
+/// run on their own.
+pub fn earlier_stage_outputs(project: &Project, stage: u8) -> HashMap<String, String> {
+    project
+        .stages
+        .iter()
+        .filter(|s| s.number < stage && s.status == StageStatus::Completed)
+        .filter_map(|s| {
+            let content = s.content.clone()?;
+            Some((get_stage(s.number)?.output_key(), content))
+        })
+        .collect()
+}
+
 /// Get a stage by its number
+///
+/// Built-in stages take precedence; numbers beyond them are looked up in the
+/// `custom_stages` declared in the configuration.
 pub fn get_stage(stage_number: u8) -> Option<StageEnum> {
+    builtin_stage(stage_number).or_else(|| custom_stage(stage_number))
+}
+
+/// Get a built-in stage by its number
+fn builtin_stage(stage_number: u8) -> Option<StageEnum> {
     match stage_number {
         1 => Some(StageEnum::Stage1(stage1::Stage1::new())),
         2 => Some(StageEnum::Stage2(stage2::Stage2::new())),
@@ -315,17 +1085,141 @@ pub fn get_stage(stage_number: u8) -> Option<StageEnum> {
         4 => Some(StageEnum::Stage4(stage4::Stage4::new())),
         5 => Some(StageEnum::Stage5(stage5::Stage5::new())),
         6 => Some(StageEnum::Stage6(stage6::Stage6::new())),
+        7 => Some(StageEnum::Stage7(stage7::Stage7::new())),
         _ => None,
     }
 }
 
+/// Get a user-defined stage from the configuration by its number
+fn custom_stage(stage_number: u8) -> Option<StageEnum> {
+    if stage_number <= STAGE_COUNT {
+        return None;
+    }
+    
+    let config = crate::config::get_config().ok()?;
+    config
+        .custom_stages
+        .into_iter()
+        .find(|stage| stage.number == stage_number)
+        .map(|stage| StageEnum::Custom(custom::CustomStage::new(stage)))
+}
+
+/// Get the dependencies configured for a stage in `stage_overrides`, if any
+fn dependency_override(stage_number: u8) -> Option<Vec<u8>> {
+    let config = crate::config::get_config().ok()?;
+    config
+        .stage_overrides
+        .into_iter()
+        .find(|(key, _)| key.trim().parse() == Ok(stage_number))
+        .map(|(_, deps)| deps)
+}
+
+/// Check the `stage_overrides` table of a config, returning what's wrong
+///
+/// Every key must be the number of a built-in or custom stage and every
+/// dependency must be a stage too. With the overrides applied, the stages'
+/// dependencies must not form a cycle.
+pub fn dependency_override_problems(config: &crate::config::Config) -> Vec<String> {
+    if config.stage_overrides.is_empty() {
+        return Vec::new();
+    }
+    
+    let mut graph: Vec<(u8, Vec<u8>)> = (1..=STAGE_COUNT)
+        .filter_map(builtin_stage)
+        .map(|stage| (stage.number(), stage.default_dependencies()))
+        .collect();
+    for stage in config.custom_stages.iter().filter(|stage| stage.number > STAGE_COUNT) {
+        graph.push((stage.number, stage.depends_on.clone()));
+    }
+    let known: HashSet<u8> = graph.iter().map(|(number, _)| *number).collect();
+    
+    let mut overrides: Vec<(&String, &Vec<u8>)> = config.stage_overrides.iter().collect();
+    overrides.sort();
+    let mut problems = Vec::new();
+    for (key, deps) in overrides {
+        let stage = match key.trim().parse::<u8>() {
+            Ok(stage) if known.contains(&stage) => stage,
+            Ok(stage) => {
+                problems.push(format!("stage_overrides refers to stage {}, which doesn't exist", stage));
+                continue;
+            }
+            Err(_) => {
+                problems.push(format!("stage_overrides key '{}' isn't a stage number", key));
+                continue;
+            }
+        };
+        for dep in deps.iter().filter(|dep| !known.contains(dep)) {
+            problems.push(format!("stage_overrides makes stage {} depend on stage {}, which doesn't exist", stage, dep));
+        }
+        if let Some((_, existing)) = graph.iter_mut().find(|(number, _)| *number == stage) {
+            *existing = deps.clone();
+        }
+    }
+    
+    if problems.is_empty() {
+        match topological_levels(&graph) {
+            Ok(_) => {}
+            Err(ToolkitError::Config(message)) => problems.push(message),
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    
+    problems
+}
+
+/// Get the numbers of all stages in order, built-in stages first
+///
+/// Built-in stage numbers are contiguous from 1, so this walks the built-in
+/// stages until it runs out, then appends any configured custom stages.
+pub fn all_stage_numbers() -> Vec<u8> {
+    let mut numbers: Vec<u8> = (1..=u8::MAX)
+        .map_while(builtin_stage)
+        .map(|stage| stage.number())
+        .collect();
+    
+    if let Ok(config) = crate::config::get_config() {
+        for stage in &config.custom_stages {
+            if stage.number <= STAGE_COUNT {
+                warn!("Ignoring custom stage {} ({}): numbers up to {} are reserved for built-in stages",
+                    stage.number, stage.name, STAGE_COUNT);
+            } else if !numbers.contains(&stage.number) {
+                numbers.push(stage.number);
+            }
+        }
+    }
+    
+    numbers.sort_unstable();
+    numbers
+}
+
+/// Find the next stage to run for a project
+///
+/// Returns the lowest-numbered stage that isn't completed and whose
+/// dependencies are satisfied, or `None` if there is nothing to run.
+pub fn next_stage(project: &Project) -> Option<StageEnum> {
+    let mut pending: Vec<u8> = project
+        .stages
+        .iter()
+        .filter(|s| s.status != StageStatus::Completed)
+        .map(|s| s.number)
+        .collect();
+    pending.sort_unstable();
+    
+    pending
+        .into_iter()
+        .filter_map(get_stage)
+        .find(|stage| stage.can_execute(project))
+}
+
 /// Run a sequence of stages for a project
 pub async fn run_stages(project_id: &str, stages: &[u8]) -> Result<StageContext> {
     let mut context = StageContext::new();
+    let mut progress = ui::StageProgress::new(stages.len());
     
     for &stage_number in stages {
         if let Some(stage) = get_stage(stage_number) {
             println!("Running stage {}: {}", stage_number, stage.name());
+            progress.start(&[stage.name()]);
             let result = stage.execute(project_id, context.clone()).await?;
             
             if result.is_failure() {
@@ -365,66 +1259,811 @@ pub async fn run_stages(project_id: &str, stages: &[u8]) -> Result<StageContext>
 
 /// Run all stages for a project in sequence
 pub async fn run_all_stages(project_id: &str) -> Result<StageContext> {
-    run_stages(project_id, &[1, 2, 3, 4, 5, 6]).await
+    run_stages(project_id, &all_stage_numbers()).await
+}
+
+/// Group stages into levels that can run concurrently
+///
+/// Each entry is a stage number with its dependencies. Every stage lands in the
+/// level after its latest dependency, so stages within a level never depend on
+/// each other. Dependencies outside the given set are ignored here and checked
+/// when the stage runs. Returns an error if the dependencies form a cycle.
+pub fn topological_levels(graph: &[(u8, Vec<u8>)]) -> Result<Vec<Vec<u8>>> {
+    let known: HashSet<u8> = graph.iter().map(|(number, _)| *number).collect();
+    let mut placed: HashMap<u8, usize> = HashMap::new();
+    let mut levels: Vec<Vec<u8>> = Vec::new();
+
+    while placed.len() < graph.len() {
+        let level: Vec<u8> = graph
+            .iter()
+            .filter(|(number, deps)| {
+                !placed.contains_key(number)
+                    && deps.iter().all(|dep| !known.contains(dep) || placed.contains_key(dep))
+            })
+            .map(|(number, _)| *number)
+            .collect();
+
+        if level.is_empty() {
+            let mut remaining: Vec<u8> = graph
+                .iter()
+                .map(|(number, _)| *number)
+                .filter(|number| !placed.contains_key(number))
+                .collect();
+            remaining.sort_unstable();
+            return Err(ToolkitError::Config(format!(
+                "Stage dependencies form a cycle between stages {:?}",
+                remaining
+            )));
+        }
+
+        for &number in &level {
+            placed.insert(number, levels.len());
+        }
+        levels.push(level);
+    }
+
+    Ok(levels)
+}
+
+/// Find every stage that depends on `stage`, directly or transitively
+///
+/// Each entry of `graph` is a stage number with its dependencies. The result
+/// is sorted and doesn't include `stage` itself.
+pub fn dependent_stages(graph: &[(u8, Vec<u8>)], stage: u8) -> Vec<u8> {
+    let mut dependents: HashSet<u8> = HashSet::new();
+    let mut frontier = vec![stage];
+
+    while let Some(current) = frontier.pop() {
+        for (number, deps) in graph {
+            if deps.contains(&current) && *number != stage && dependents.insert(*number) {
+                frontier.push(*number);
+            }
+        }
+    }
+
+    let mut dependents: Vec<u8> = dependents.into_iter().collect();
+    dependents.sort_unstable();
+    dependents
+}
+
+/// Reset a stage so it runs fresh
+///
+/// The stage goes back to `NotStarted` and loses its content, artifacts and
+/// metrics. With `cascade`, every stage that depends on it is reset as well,
+/// since their output was built on the old result. Returns the numbers of the
+/// stages that were reset.
+pub fn reset_stage(project_id: &str, stage: u8, cascade: bool) -> Result<Vec<u8>> {
+    get_stage(stage).ok_or(ToolkitError::StageNotFound(stage))?;
+    let project = project::load_project(project_id)?;
+
+    let mut stages = vec![stage];
+    if cascade {
+        let graph: Vec<(u8, Vec<u8>)> = all_stage_numbers()
+            .into_iter()
+            .filter_map(get_stage)
+            .map(|s| (s.number(), s.dependencies()))
+            .collect();
+        stages.extend(dependent_stages(&graph, stage));
+    }
+
+    let mut reset = Vec::new();
+    project::update_project_file(&project, |merged| {
+        reset = stages.into_iter().filter(|&number| merged.reset_stage(number)).collect();
+    })?;
+    if reset.is_empty() {
+        return Err(ToolkitError::StageNotFound(stage));
+    }
+
+    info!("Reset stages {:?} of project {}", reset, project_id);
+
+    Ok(reset)
+}
+
+/// Async version of [`reset_stage`] for async contexts
+pub async fn reset_stage_async(project_id: &str, stage: u8, cascade: bool) -> Result<Vec<u8>> {
+    let project_id = project_id.to_string();
+    project::spawn_io(move || reset_stage(&project_id, stage, cascade)).await
+}
+
+/// File name a stage is written to with `--output-dir`, e.g. `stage1-initial-plan-creation.md`
+pub fn stage_file_name(number: u8, name: &str) -> String {
+    let slug = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("stage{}-{}.md", number, slug)
+}
+
+/// Write a completed stage's content to `dir` and record the file as an artifact
+///
+/// An artifact of the same name from an earlier run is replaced. Returns the
+/// path written, or `None` if the stage has no completed output.
+pub fn write_stage_output(project_id: &str, stage: u8, dir: &Path) -> Result<Option<PathBuf>> {
+    let mut project = project::load_project(project_id)?;
+    let (name, content) = match project.get_stage(stage) {
+        Some(s) if s.status == StageStatus::Completed => match &s.content {
+            Some(content) => (stage_file_name(stage, &s.name), content.clone()),
+            None => return Ok(None),
+        },
+        Some(_) => return Ok(None),
+        None => return Err(ToolkitError::StageNotFound(stage)),
+    };
+
+    let path = dir.join(&name);
+    file::write_file(&path, &content)?;
+    debug!("Wrote stage {} of project {} to {}", stage, project_id, path.display());
+
+    if let Some(s) = project.get_stage_mut(stage) {
+        s.artifacts.retain(|a| a.name != name);
+    }
+    project.add_artifact(stage, Artifact {
+        name,
+        file_type: "md".to_string(),
+        path: path.clone(),
+        created_at: chrono::Utc::now(),
+    });
+    project::save_stage(&project, stage)?;
+
+    Ok(Some(path))
+}
+
+/// Write the output of several stages to `dir` at once
+///
+/// Each stage is written by [`write_stage_output`] on the blocking thread
+/// pool, so a level of stages finishing together is written concurrently.
+/// Returns the paths written, in the order of `stages`.
+pub async fn write_stage_outputs(project_id: &str, stages: &[u8], dir: &Path) -> Result<Vec<(u8, PathBuf)>> {
+    let writes = stages.iter().map(|&stage| {
+        let project_id = project_id.to_string();
+        let dir = dir.to_path_buf();
+        tokio::task::spawn_blocking(move || write_stage_output(&project_id, stage, &dir))
+    });
+
+    let mut written = Vec::new();
+    for (&stage, result) in stages.iter().zip(futures::future::join_all(writes).await) {
+        let result = result.map_err(|e| ToolkitError::Unknown(format!("Writing stage {} failed: {}", stage, e)))?;
+        if let Some(path) = result? {
+            written.push((stage, path));
+        }
+    }
+    Ok(written)
+}
+
+/// Report the outcome of one stage run, returning an error if it failed
+fn report_stage_result(stage_num: u8, result: &StageResult) -> Result<()> {
+    if result.is_failure() {
+        error!("Stage {} failed: {:?}", stage_num, result.message);
+        if let Some(msg) = &result.message {
+            ui::print_error(&format!("Stage {} failed: {}", stage_num, msg));
+        } else {
+            ui::print_error(&format!("Stage {} failed", stage_num));
+        }
+        return Err(ToolkitError::Unknown(format!("Stage {} failed", stage_num)));
+    }
+
+    if result.is_skipped() {
+        info!("Stage {} was skipped", stage_num);
+        if let Some(msg) = &result.message {
+            ui::print_info(&format!("Stage {} skipped: {}", stage_num, msg));
+        } else {
+            ui::print_info(&format!("Stage {} was skipped", stage_num));
+        }
+        return Ok(());
+    }
+
+    ui::print_success(&format!("Stage {} completed successfully", stage_num));
+    info!("Stage {} completed successfully", stage_num);
+    Ok(())
+}
+
+/// What happened to a stage in a run that continues past failures
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StageOutcome {
+    Completed,
+    Skipped(String),
+    Failed(String),
+    /// Not run because this dependency failed or wasn't run either
+    Blocked(u8),
+}
+
+impl Display for StageOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Completed => write!(f, "completed"),
+            Self::Skipped(reason) => write!(f, "skipped: {}", reason),
+            Self::Failed(reason) => write!(f, "failed: {}", reason),
+            Self::Blocked(dependency) => write!(f, "not run: stage {} did not complete", dependency),
+        }
+    }
+}
+
+/// The first dependency that failed or was blocked, if any
+fn failed_dependency(deps: &[u8], failed: &HashSet<u8>) -> Option<u8> {
+    deps.iter().copied().find(|dep| failed.contains(dep))
+}
+
+/// Print the outcome of every stage a run tried, one row per stage
+fn print_outcomes(outcomes: &[(u8, String, StageOutcome)]) {
+    println!("\nStage summary:");
+    for (number, name, outcome) in outcomes {
+        let row = format!("  {:>3}  {:<32} {}", number, name, outcome);
+        // Failures go to stdout too, so the table stays in one piece
+        match outcome {
+            StageOutcome::Completed => println!("{}", row.green()),
+            StageOutcome::Skipped(_) => println!("{}", row.blue()),
+            StageOutcome::Failed(_) => println!("{}", row.red()),
+            StageOutcome::Blocked(_) => println!("{}", row.yellow()),
+        }
+    }
+}
+
+/// Check whether every dependency of a stage has completed
+fn dependencies_met(project: &Project, deps: &[u8]) -> bool {
+    deps.iter().all(|&dep| {
+        project
+            .get_stage(dep)
+            .map(|stage| stage.status == StageStatus::Completed)
+            .unwrap_or(false)
+    })
+}
+
+/// Completion tokens expected from a stage that doesn't limit `max_tokens`
+pub const EXPECTED_COMPLETION_TOKENS: usize = 2048;
+
+/// Estimated token use of a stage that hasn't run yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageEstimate {
+    pub number: u8,
+    pub name: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+/// Estimate the tokens of every stage [`run_available_stages`] would run
+///
+/// Prompts are rendered as in a dry run. A stage waiting on others can't see
+/// their output yet, so the expected completion of its pending dependencies is
+/// added to its prompt.
+pub fn estimate_pending_stages(project_id: &str, context: &StageContext, model: &str) -> Result<Vec<StageEstimate>> {
+    let project = project::load_project(project_id)?;
+    let mut estimates: Vec<StageEstimate> = Vec::new();
+
+    for stage in all_stage_numbers().into_iter().filter_map(get_stage) {
+        let completed = project
+            .get_stage(stage.number())
+            .is_some_and(|s| s.status == StageStatus::Completed);
+        if completed {
+            continue;
+        }
+
+        let mut stage_context = context.clone();
+        stage_context.merge_project(&project, stage.number());
+
+        // The run reports a prompt that can't be built; the estimate just leaves it out
+        let prompt_tokens = match stage.build_prompt(&project, &stage_context) {
+            Ok(prompt) => tokens::estimate_tokens(&prompt, model),
+            Err(e) => {
+                warn!("Can't estimate the prompt of stage {}: {}", stage.number(), e);
+                0
+            }
+        };
+        let pending_inputs: usize = estimates
+            .iter()
+            .filter(|e| stage.dependencies().contains(&e.number))
+            .map(|e| e.completion_tokens)
+            .sum();
+        let completion_tokens = stage
+            .request_options(&stage_context)
+            .max_tokens
+            .map_or(EXPECTED_COMPLETION_TOKENS, |max| max as usize);
+
+        estimates.push(StageEstimate {
+            number: stage.number(),
+            name: stage.name().to_string(),
+            prompt_tokens: prompt_tokens + pending_inputs,
+            completion_tokens,
+        });
+    }
+
+    Ok(estimates)
+}
+
+/// Async version of [`estimate_pending_stages`] for async contexts
+pub async fn estimate_pending_stages_async(project_id: &str, context: &StageContext, model: &str) -> Result<Vec<StageEstimate>> {
+    let project_id = project_id.to_string();
+    let context = context.clone();
+    let model = model.to_string();
+    project::spawn_io(move || estimate_pending_stages(&project_id, &context, &model)).await
 }
 
 /// Run all available stages for a project based on dependencies
-pub async fn run_available_stages(project_id: &str) -> Result<StageContext> {
-    let mut context = StageContext::new();
-    
-    for stage_num in 1..=6 {
-        if let Some(stage) = get_stage(stage_num) {
-            // Check dependencies
-            let deps = stage.dependencies();
-            
-            // Skip if dependencies aren't met
-            let mut can_run = true;
-            for &dep in &deps {
-                let project = project::load_project(project_id)?;
-                if let Some(dep_stage) = project.get_stage(dep) {
-                    if dep_stage.status != StageStatus::Completed {
-                        can_run = false;
-                        break;
-                    }
-                } else {
-                    can_run = false;
-                    break;
-                }
+///
+/// Stages are grouped into dependency levels and the stages within a level run
+/// concurrently, unless one of them asks the user for input. Stages that are
+/// already completed are left alone. Requests still go through the shared rate
+/// limiter, and each stage saves only its own entry so concurrent saves don't
+/// clobber each other. If the dependencies
+/// form a cycle the error is reported and the stages run one at a time.
+///
+/// `context` is handed to every stage, e.g. to pass extra instructions.
+///
+/// The first failure stops the run, unless [`CONTINUE_ON_ERROR_KEY`] is set.
+/// Then every stage's outcome is recorded, stages depending on a failed one
+/// are not run, and a summary of all of them is printed at the end. The run
+/// still fails if any stage did.
+pub async fn run_available_stages(project_id: &str, mut context: StageContext) -> Result<StageContext> {
+    let continue_on_error = context.get(CONTINUE_ON_ERROR_KEY).is_some_and(|value| value == "true");
+    let mut outcomes: Vec<(u8, String, StageOutcome)> = Vec::new();
+    let mut failed: HashSet<u8> = HashSet::new();
+
+    let stages: Vec<StageEnum> = all_stage_numbers().into_iter().filter_map(get_stage).collect();
+    let graph: Vec<(u8, Vec<u8>)> = stages.iter().map(|s| (s.number(), s.dependencies())).collect();
+
+    let levels = match topological_levels(&graph) {
+        Ok(levels) => levels,
+        Err(e) => {
+            warn!("Falling back to sequential stage execution: {}", e);
+            ui::print_warning(&format!("{}. Running stages one at a time.", e));
+            graph.iter().map(|(number, _)| vec![*number]).collect()
+        }
+    };
+
+    let pending = project::load_project_async(project_id)
+        .await?
+        .stages
+        .iter()
+        .filter(|s| s.status != StageStatus::Completed)
+        .count();
+    let mut progress = ui::StageProgress::new(pending);
+
+    for level in levels {
+        // Reload after each level to see what the previous level completed
+        let project = project::load_project_async(project_id).await?;
+
+        for stage in stages.iter().filter(|s| level.contains(&s.number())) {
+            let completed = project.get_stage(stage.number()).is_some_and(|s| s.status == StageStatus::Completed);
+            if completed {
+                continue;
             }
-            
-            if can_run {
-                println!("Running stage {}: {}", stage_num, stage.name());
-                let result = stage.execute(project_id, context.clone()).await?;
-                
-                if result.is_failure() {
-                    error!("Stage {} failed: {:?}", stage_num, result.message);
-                    if let Some(msg) = &result.message {
-                        ui::print_error(&format!("Stage {} failed: {}", stage_num, msg));
-                    } else {
-                        ui::print_error(&format!("Stage {} failed", stage_num));
-                    }
-                    return Err(ToolkitError::Unknown(format!("Stage {} failed", stage_num)));
-                }
-                
-                if result.is_skipped() {
-                    info!("Stage {} was skipped", stage_num);
-                    if let Some(msg) = &result.message {
-                        ui::print_info(&format!("Stage {} skipped: {}", stage_num, msg));
-                    } else {
-                        ui::print_info(&format!("Stage {} was skipped", stage_num));
-                    }
+            if let Some(dependency) = failed_dependency(&stage.dependencies(), &failed) {
+                outcomes.push((stage.number(), stage.name().to_string(), StageOutcome::Blocked(dependency)));
+                failed.insert(stage.number());
+            }
+        }
+
+        let runnable: Vec<&StageEnum> = stages
+            .iter()
+            .filter(|s| level.contains(&s.number()))
+            .filter(|s| {
+                let completed = project
+                    .get_stage(s.number())
+                    .map(|stage| stage.status == StageStatus::Completed)
+                    .unwrap_or(false);
+                !completed && dependencies_met(&project, &s.dependencies())
+            })
+            .collect();
+
+        if runnable.is_empty() {
+            continue;
+        }
+
+        for stage in &runnable {
+            println!("Running stage {}: {}", stage.number(), stage.name());
+        }
+        progress.start(&runnable.iter().map(|s| s.name()).collect::<Vec<_>>());
+
+        let mut one_at_a_time = false;
+        for stage in &runnable {
+            one_at_a_time = one_at_a_time || asks_for_input(&project, stage).await;
+        }
+
+        let results = if one_at_a_time {
+            // A prompt can't share the terminal with stages running alongside it
+            let mut results = Vec::with_capacity(runnable.len());
+            for stage in &runnable {
+                results.push(stage.execute(project_id, context.clone()).await);
+            }
+            results
+        } else {
+            futures::future::join_all(
+                runnable.iter().map(|stage| stage.execute(project_id, context.clone())),
+            )
+            .await
+        };
+
+        let mut succeeded = Vec::new();
+        for (stage, result) in runnable.iter().zip(results) {
+            let result = match result {
+                Err(e) if continue_on_error && !matches!(e, ToolkitError::Cancelled) => {
+                    ui::print_error(&format!("Stage {} failed: {}", stage.number(), e));
+                    outcomes.push((stage.number(), stage.name().to_string(), StageOutcome::Failed(e.to_string())));
+                    failed.insert(stage.number());
                     continue;
                 }
-                
-                // Update context for the next stage
-                context = result.context;
-                
-                // Mark stage as completed in project
-                ui::print_success(&format!("Stage {} completed successfully", stage_num));
-                info!("Stage {} completed successfully", stage_num);
+                result => result?,
+            };
+            if let Err(e) = report_stage_result(stage.number(), &result) {
+                if !continue_on_error {
+                    return Err(e);
+                }
+                let reason = result.message.clone().unwrap_or_else(|| "unknown error".to_string());
+                outcomes.push((stage.number(), stage.name().to_string(), StageOutcome::Failed(reason)));
+                failed.insert(stage.number());
+                continue;
+            }
+            let outcome = if result.is_skipped() {
+                StageOutcome::Skipped(result.message.clone().unwrap_or_default())
+            } else {
+                StageOutcome::Completed
+            };
+            outcomes.push((stage.number(), stage.name().to_string(), outcome));
+
+            if result.is_success() {
+                succeeded.push(stage.number());
+            }
+
+            // Merge what this stage learned into the context for later levels
+            if !result.is_skipped() {
+                context.data.extend(result.context.data);
+            }
+        }
+
+        if let Some(dir) = context.get(OUTPUT_DIR_KEY) {
+            for (stage, path) in write_stage_outputs(project_id, &succeeded, Path::new(dir)).await? {
+                println!("Wrote stage {} to {}", stage, path.display());
             }
         }
     }
-    
+
+    if continue_on_error {
+        outcomes.sort_by_key(|(number, _, _)| *number);
+        print_outcomes(&outcomes);
+        let failures: Vec<String> = outcomes
+            .iter()
+            .filter(|(_, _, outcome)| matches!(outcome, StageOutcome::Failed(_)))
+            .map(|(number, _, _)| number.to_string())
+            .collect();
+        if !failures.is_empty() {
+            return Err(ToolkitError::Unknown(format!("Stage(s) {} failed", failures.join(", "))));
+        }
+    }
+
     Ok(context)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_project() -> Project {
+        Project::new(
+            "proj_1".to_string(),
+            "Test".to_string(),
+            "A test project".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        )
+    }
+
+    #[test]
+    fn test_stage_file_name() {
+        assert_eq!(stage_file_name(1, "Initial Plan Creation"), "stage1-initial-plan-creation.md");
+        assert_eq!(stage_file_name(12, "API / Security Review"), "stage12-api-security-review.md");
+    }
+
+    #[test]
+    fn test_dependent_stages_is_transitive() {
+        let graph = vec![
+            (1, vec![]),
+            (2, vec![1]),
+            (3, vec![2]),
+            (4, vec![1]),
+            (5, vec![3, 4]),
+            (6, vec![]),
+        ];
+
+        assert_eq!(dependent_stages(&graph, 2), vec![3, 5]);
+        assert_eq!(dependent_stages(&graph, 1), vec![2, 3, 4, 5]);
+        assert!(dependent_stages(&graph, 6).is_empty());
+    }
+
+    #[test]
+    fn test_saved_context_fills_gaps_and_skips_run_settings() {
+        let saved: HashMap<String, String> = [
+            ("initial_plan".to_string(), "old plan".to_string()),
+            ("architecture_design".to_string(), "design".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        
+        let mut context = StageContext::new();
+        context.set("initial_plan", "new plan");
+        context.set(EXTRA_INSTRUCTIONS_KEY, "focus on mobile");
+        context.set(SEED_KEY, "42");
+        context.merge_saved(&saved);
+        assert_eq!(context.get("initial_plan").unwrap(), "new plan");
+        assert_eq!(context.get("architecture_design").unwrap(), "design");
+        
+        let changes = context.changes_from(&saved);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes["initial_plan"], "new plan");
+    }
+
+    #[test]
+    fn test_current_stage_output_wins_over_saved_copy() {
+        let mut project = test_project();
+        project.update_stage(1, "regenerated plan".to_string(), StageStatus::Completed);
+        project.context.insert("initial_plan".to_string(), "old plan".to_string());
+        project.context.insert("target_users".to_string(), "students".to_string());
+
+        let mut context = StageContext::new();
+        context.merge_project(&project, 2);
+        assert_eq!(context.get("initial_plan").unwrap(), "regenerated plan");
+        assert_eq!(context.get("target_users").unwrap(), "students");
+
+        // This run's own values still come first
+        let mut context = StageContext::new();
+        context.set("initial_plan", "plan from this run");
+        context.merge_project(&project, 2);
+        assert_eq!(context.get("initial_plan").unwrap(), "plan from this run");
+    }
+
+    #[test]
+    fn test_failed_dependency_blocks_downstream_stages() {
+        let failed: HashSet<u8> = [2].into_iter().collect();
+        assert_eq!(failed_dependency(&[1, 2], &failed), Some(2));
+        assert_eq!(failed_dependency(&[1, 3], &failed), None);
+        assert_eq!(failed_dependency(&[], &failed), None);
+
+        assert_eq!(StageOutcome::Blocked(2).to_string(), "not run: stage 2 did not complete");
+        assert_eq!(StageOutcome::Failed("timeout".to_string()).to_string(), "failed: timeout");
+    }
+
+    #[test]
+    fn test_earlier_stage_outputs_use_context_keys() {
+        let mut project = test_project();
+        project.update_stage(1, "plan".to_string(), StageStatus::Completed);
+        project.update_stage(2, "design".to_string(), StageStatus::Completed);
+        project.update_stage(3, "draft".to_string(), StageStatus::Failed);
+        
+        let outputs = earlier_stage_outputs(&project, 7);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs["initial_plan"], "plan");
+        assert_eq!(outputs["architecture_design"], "design");
+        
+        assert_eq!(earlier_stage_outputs(&project, 2).keys().collect::<Vec<_>>(), vec!["initial_plan"]);
+    }
+
+    #[test]
+    fn test_wrap_prompt() {
+        let prompt = "# Initial Plan".to_string();
+        assert_eq!(wrap_prompt(prompt.clone(), None, Some("  ")), "# Initial Plan");
+        assert_eq!(
+            wrap_prompt(prompt.clone(), Some("Internal use only."), None),
+            "Internal use only.\n\n# Initial Plan"
+        );
+        assert_eq!(
+            wrap_prompt(prompt, Some("Internal use only."), Some("Cite sources.")),
+            "Internal use only.\n\n# Initial Plan\n\nCite sources."
+        );
+    }
+
+    #[test]
+    fn test_check_output_length_rejects_short_answers() {
+        assert!(check_output_length("", 50).is_err());
+        assert!(check_output_length("  I can't help with that.\n", 50).is_err());
+        assert!(check_output_length(&"A detailed plan. ".repeat(5), 50).is_ok());
+        assert!(check_output_length("", 0).is_ok());
+        
+        match check_output_length("# Plan\n\n1. Set up", 200) {
+            Err(ToolkitError::Parse(message)) => assert!(message.starts_with("The AI returned 17 characters")),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_next_stage_picks_lowest_incomplete() {
+        let mut project = test_project();
+        assert_eq!(next_stage(&project).map(|s| s.number()), Some(1));
+
+        project.update_stage(1, "plan".to_string(), StageStatus::Completed);
+        project.update_stage(2, "design".to_string(), StageStatus::Completed);
+        assert_eq!(next_stage(&project).map(|s| s.number()), Some(3));
+    }
+
+    #[test]
+    fn test_next_stage_none_when_all_complete() {
+        let mut project = test_project();
+        for number in all_stage_numbers() {
+            project.update_stage(number, "done".to_string(), StageStatus::Completed);
+        }
+        assert!(next_stage(&project).is_none());
+    }
+
+    #[test]
+    fn test_stage_count_matches_stages() {
+        let numbers: Vec<u8> = (1..=u8::MAX)
+            .map_while(builtin_stage)
+            .map(|stage| stage.number())
+            .collect();
+        assert_eq!(numbers.len(), STAGE_COUNT as usize);
+        assert_eq!(numbers, (1..=STAGE_COUNT).collect::<Vec<u8>>());
+
+        // New projects start with an entry for every stage
+        let project_numbers: Vec<u8> = test_project().stages.iter().map(|s| s.number).collect();
+        assert_eq!(project_numbers, numbers);
+    }
+
+    #[test]
+    fn test_topological_levels_groups_independent_stages() {
+        let graph: Vec<(u8, Vec<u8>)> = (1..=u8::MAX)
+            .map_while(builtin_stage)
+            .map(|stage| (stage.number(), stage.dependencies()))
+            .collect();
+        let levels = topological_levels(&graph).unwrap();
+
+        assert_eq!(levels[0], vec![1]);
+        // User stories need the implementation strategy, so they run alongside stage 4
+        assert_eq!(levels[3], vec![4, 7]);
+        assert_eq!(levels.concat().len(), graph.len());
+    }
+
+    #[test]
+    fn test_only_progress_assessment_is_interactive() {
+        let interactive: Vec<u8> = (1..=u8::MAX)
+            .map_while(builtin_stage)
+            .filter(|stage| stage.is_interactive())
+            .map(|stage| stage.number())
+            .collect();
+        // Stage 4 asks for the current status, so its level runs one stage at a time
+        assert_eq!(interactive, vec![4]);
+    }
+
+    #[test]
+    fn test_topological_levels_ignores_unknown_dependencies() {
+        let graph = vec![(8, vec![2]), (9, vec![8]), (10, vec![2])];
+        assert_eq!(topological_levels(&graph).unwrap(), vec![vec![8, 10], vec![9]]);
+    }
+
+    #[test]
+    fn test_topological_levels_detects_cycles() {
+        let graph = vec![(1, vec![]), (8, vec![9]), (9, vec![8])];
+        let err = topological_levels(&graph).unwrap_err();
+        assert!(matches!(err, ToolkitError::Config(_)));
+        assert!(err.to_string().contains("[8, 9]"));
+    }
+
+    #[test]
+    fn test_dependency_override_problems() {
+        let overrides = |entries: &[(&str, &[u8])]| crate::config::Config {
+            stage_overrides: entries.iter().map(|(key, deps)| (key.to_string(), deps.to_vec())).collect(),
+            ..Default::default()
+        };
+
+        assert!(dependency_override_problems(&overrides(&[])).is_empty());
+        assert!(dependency_override_problems(&overrides(&[("5", &[2]), ("3", &[1])])).is_empty());
+
+        let problems = dependency_override_problems(&overrides(&[("9", &[1]), ("5", &[12]), ("five", &[2])]));
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].contains("stage 5 depend on stage 12"));
+        assert!(problems[1].contains("stage 9, which doesn't exist"));
+        assert!(problems[2].contains("'five'"));
+
+        // Stage 2 already depends on stage 1
+        let problems = dependency_override_problems(&overrides(&[("1", &[2])]));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("cycle"));
+    }
+
+    #[test]
+    fn test_build_prompt_uses_earlier_stage_output() {
+        let mut project = test_project();
+        project.update_stage(1, "Build a todo list with tags".to_string(), StageStatus::Completed);
+
+        let mut context = StageContext::new();
+        context.set(EXTRA_INSTRUCTIONS_KEY, "focus on mobile");
+
+        let stage = get_stage(2).unwrap();
+        let prompt = stage.build_prompt(&project, &context).unwrap();
+        assert!(prompt.contains("Build a todo list with tags"));
+        assert!(prompt.contains("focus on mobile"));
+    }
+
+    #[test]
+    fn test_append_refines_previous_output() {
+        let mut project = test_project();
+        project.update_stage(1, "Build a todo list with tags".to_string(), StageStatus::Completed);
+        project.update_stage(2, "Use a single SQLite database".to_string(), StageStatus::Completed);
+
+        let stage = get_stage(2).unwrap();
+        let mut context = StageContext::new();
+        assert!(!stage.build_prompt(&project, &context).unwrap().contains("Use a single SQLite database"));
+
+        context.set(APPEND_KEY, "true");
+        let prompt = stage.build_prompt(&project, &context).unwrap();
+        assert!(prompt.contains("## Previous Version\nUse a single SQLite database"));
+        assert!(previous_output(&project, 3, &context).is_none());
+    }
+
+    #[test]
+    fn test_with_language() {
+        assert_eq!(with_language(Some("You are a planner."), "English"), Some("You are a planner.".to_string()));
+        assert_eq!(with_language(None, "english"), None);
+        assert_eq!(
+            with_language(Some("You are a planner."), "French"),
+            Some("You are a planner. Respond in French.".to_string())
+        );
+        assert_eq!(with_language(None, " German "), Some("Respond in German.".to_string()));
+    }
+
+    #[test]
+    fn test_request_options_overrides_defaults() {
+        let stage = stage1::Stage1::new();
+        let defaults = stage.request_options(&StageContext::new());
+        assert_eq!(defaults.temperature, stage.default_options().temperature);
+        assert!(defaults.system.is_some());
+
+        let mut context = StageContext::new();
+        context.set(MAX_TOKENS_KEY, "2048");
+        context.set(TEMPERATURE_KEY, "0.1");
+        context.set(SEED_KEY, "7");
+        let options = stage.request_options(&context);
+        assert_eq!(options.max_tokens, Some(2048));
+        assert_eq!(options.temperature, Some(0.1));
+        assert_eq!(options.seed, Some(7));
+    }
+
+    #[test]
+    fn test_validate_temperature() {
+        assert!(validate_temperature(0.0).is_ok());
+        assert!(validate_temperature(2.0).is_ok());
+        assert!(matches!(validate_temperature(2.5), Err(ToolkitError::InvalidInput(_))));
+        assert!(validate_temperature(-0.1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_output_is_saved_and_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = test_project();
+        project.path = dir.path().to_path_buf();
+        let path = partial_file(&project, 2);
+        let mock = crate::ai::testing::MockAiClient::new()
+            .stream("Continue Your Previous Answer", &["ld"])
+            .stream("Greet", &["Hello ", "wor"]);
+
+        let mut context = StageContext::new();
+        context.set(STREAM_OUTPUT_KEY, "true");
+        context.set(PARTIAL_FILE_KEY, path.to_string_lossy());
+        let response = generate_stage_output(&mock, "Greet me", RequestOptions::default(), &context).await.unwrap();
+        assert_eq!(response.text, "Hello wor");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hello wor");
+        assert_eq!(pending_partial(&project, 2).await.as_deref(), Some("Hello wor"));
+
+        context.set(RESUME_PARTIAL_KEY, "true");
+        let response = generate_stage_output(&mock, "Greet me", RequestOptions::default(), &context).await.unwrap();
+        assert_eq!(response.text, "Hello world");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hello world");
+        assert!(mock.prompts()[1].contains("Hello wor"));
+
+        // A completed stage has nothing to resume
+        project.update_stage(2, response.text, StageStatus::Completed);
+        assert!(pending_partial(&project, 2).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stage_with_partial_output_asks_for_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = test_project();
+        project.path = dir.path().to_path_buf();
+        let stage = builtin_stage(2).unwrap();
+
+        assert!(!asks_for_input(&project, &stage).await);
+        assert!(asks_for_input(&project, &builtin_stage(4).unwrap()).await);
+
+        // Asking whether to resume can't share the terminal with other stages
+        std::fs::write(partial_file(&project, 2), "Hello wor").unwrap();
+        assert!(asks_for_input(&project, &stage).await);
+    }
+}
@@ -0,0 +1,196 @@
+//! Post-processors that save parts of a completed stage's output as files.
+//!
+//! Stage output is a single Markdown document, which buries anything meant to
+//! be used on its own, like an architecture diagram. After a stage completes,
+//! each post-processor named in the `post_processors` setting extracts such
+//! parts. They are written to the project's `artifacts/stage<N>` directory and
+//! recorded as the stage's artifacts.
+
+use crate::config;
+use crate::error::{Result, ToolkitError};
+use crate::models::{Artifact, Project, StageStatus};
+use crate::utils::{file, markdown, project, ui};
+use log::{debug, info, warn};
+use std::path::PathBuf;
+
+/// A file a post-processor extracted from a stage's output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    /// File name, e.g. `diagram-1.mmd`
+    pub name: String,
+    /// File type recorded with the artifact, e.g. `mmd`
+    pub file_type: String,
+    pub content: String,
+}
+
+/// Extracts files from a completed stage's output
+pub trait PostProcessor: Send + Sync {
+    /// Name used in the `post_processors` setting
+    fn name(&self) -> &'static str;
+
+    /// Files to save from a stage's output; usually none
+    fn extract(&self, content: &str) -> Vec<ExtractedFile>;
+}
+
+/// Saves fenced Mermaid and PlantUML diagrams, as `diagram-1.mmd`, `diagram-2.puml` and so on
+pub struct DiagramExtractor;
+
+impl PostProcessor for DiagramExtractor {
+    fn name(&self) -> &'static str {
+        "diagrams"
+    }
+
+    fn extract(&self, content: &str) -> Vec<ExtractedFile> {
+        markdown::code_blocks(content)
+            .into_iter()
+            .filter_map(|block| {
+                let extension = match block.language.as_str() {
+                    "mermaid" => "mmd",
+                    "plantuml" | "puml" => "puml",
+                    _ => return None,
+                };
+                Some((extension, block.content))
+            })
+            .enumerate()
+            .map(|(i, (extension, content))| ExtractedFile {
+                name: format!("diagram-{}.{}", i + 1, extension),
+                file_type: extension.to_string(),
+                content,
+            })
+            .collect()
+    }
+}
+
+/// Names accepted in the `post_processors` setting
+pub const POST_PROCESSORS: &[&str] = &["diagrams"];
+
+/// Look up a post-processor by its name in the `post_processors` setting
+pub fn post_processor(name: &str) -> Option<Box<dyn PostProcessor>> {
+    match name {
+        "diagrams" => Some(Box::new(DiagramExtractor)),
+        _ => None,
+    }
+}
+
+/// Directory a stage's extracted files are written to
+pub fn artifacts_dir(project: &Project, stage: u8) -> PathBuf {
+    project.path.join("artifacts").join(format!("stage{}", stage))
+}
+
+/// Run post-processors on a completed stage and record the files they extract
+///
+/// Files extracted by an earlier run of the stage are removed first, so the
+/// artifacts always match the current output. Returns the paths written.
+///
+/// # Errors
+///
+/// Returns an error if the project can't be loaded or saved, the stage
+/// doesn't exist, or a file can't be written.
+pub fn apply_post_processors(project_id: &str, stage: u8, processors: &[Box<dyn PostProcessor>]) -> Result<Vec<PathBuf>> {
+    let mut project = project::load_project(project_id)?;
+    let content = match project.get_stage(stage) {
+        Some(s) if s.status == StageStatus::Completed => s.content.clone().unwrap_or_default(),
+        Some(_) => return Ok(Vec::new()),
+        None => return Err(ToolkitError::StageNotFound(stage)),
+    };
+
+    let dir = artifacts_dir(&project, stage);
+    let mut files = Vec::new();
+    for processor in processors {
+        let extracted = processor.extract(&content);
+        debug!("Post-processor {} found {} files in stage {}", processor.name(), extracted.len(), stage);
+        files.extend(extracted);
+    }
+
+    let had_artifacts = project
+        .get_stage(stage)
+        .is_some_and(|s| s.artifacts.iter().any(|a| a.path.starts_with(&dir)));
+    if files.is_empty() && !had_artifacts {
+        return Ok(Vec::new());
+    }
+
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| ToolkitError::Io(format!("Failed to clear {}: {}", dir.display(), e)))?;
+    }
+    if let Some(s) = project.get_stage_mut(stage) {
+        s.artifacts.retain(|a| !a.path.starts_with(&dir));
+    }
+
+    let mut written = Vec::new();
+    for extracted in files {
+        let path = dir.join(&extracted.name);
+        file::write_file(&path, &extracted.content)?;
+        debug!("Extracted {} from stage {} of project {}", extracted.name, stage, project_id);
+        project.add_artifact(stage, Artifact {
+            name: extracted.name,
+            file_type: extracted.file_type,
+            path: path.clone(),
+            created_at: chrono::Utc::now(),
+        });
+        written.push(path);
+    }
+
+    project::save_stage(&project, stage)?;
+    Ok(written)
+}
+
+/// Run the post-processors named in the config on a stage that just completed
+///
+/// The stage's output is already saved, so a failure here is only reported
+/// as a warning.
+pub async fn run_configured(project_id: &str, stage: u8) {
+    let names = config::get_config()
+        .map(|config| config.post_processors)
+        .unwrap_or_else(|_| config::default_post_processors());
+    let processors: Vec<Box<dyn PostProcessor>> = names
+        .iter()
+        .filter_map(|name| {
+            let processor = post_processor(name);
+            if processor.is_none() {
+                warn!("Unknown post-processor {} in the config; skipping it", name);
+            }
+            processor
+        })
+        .collect();
+    if processors.is_empty() {
+        return;
+    }
+
+    let id = project_id.to_string();
+    let result = tokio::task::spawn_blocking(move || apply_post_processors(&id, stage, &processors))
+        .await
+        .map_err(|e| ToolkitError::Unknown(e.to_string()))
+        .and_then(|result| result);
+    match result {
+        Ok(written) if !written.is_empty() => {
+            info!("Saved {} artifacts extracted from stage {} of project {}", written.len(), stage, project_id);
+        }
+        Ok(_) => {}
+        Err(e) => ui::print_warning(&format!("Couldn't save the files extracted from stage {}: {}", stage, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagram_extractor_saves_only_diagrams() {
+        let content = "## Architecture\n\n```mermaid\ngraph TD\n  A-->B\n```\n\n```rust\nfn main() {}\n```\n\n```plantuml\n@startuml\nA -> B\n@enduml\n```\n";
+
+        let files = DiagramExtractor.extract(content);
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "diagram-1.mmd");
+        assert_eq!(files[0].content, "graph TD\n  A-->B\n");
+        assert_eq!(files[1].name, "diagram-2.puml");
+        assert_eq!(files[1].file_type, "puml");
+        assert!(DiagramExtractor.extract("No diagrams here").is_empty());
+
+        for name in POST_PROCESSORS {
+            assert_eq!(post_processor(name).unwrap().name(), *name);
+        }
+        assert!(post_processor("tables").is_none());
+    }
+}
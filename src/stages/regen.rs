@@ -0,0 +1,108 @@
+//! Regenerating a single section of a stage's output.
+//!
+//! A stage's output is often mostly right with one weak section. Rather than
+//! rerunning the whole stage, [`regenerate_section`] sends the document to the
+//! AI, asks for a new version of just that `##` section and splices it back
+//! in, leaving every other byte of the output as it was.
+
+use super::{generate_stage_output, get_stage, StageContext};
+use crate::ai;
+use crate::error::{Result, ToolkitError};
+use crate::utils::markdown::{self, Section};
+use crate::utils::project;
+use log::info;
+use std::collections::HashMap;
+
+/// Build the prompt asking for a new version of one section
+fn section_prompt(project_name: &str, stage_name: &str, content: &str, section: &Section) -> String {
+    let heading = content[section.range.clone()].lines().next().unwrap_or_default();
+    format!(
+        "Below is the {} document for the project \"{}\".\n\n<document>\n{}\n</document>\n\n\
+         Rewrite only the \"{}\" section so it is more specific, complete and consistent with the rest of the document. \
+         Reply with the rewritten section alone in Markdown, starting with the heading line `{}`, and nothing else.",
+        stage_name, project_name, content, section.title, heading
+    )
+}
+
+/// Fit the AI's reply in place of `original`
+///
+/// Only the first `##` section of the reply is kept, the original heading is
+/// added if the reply left it out, and the blank lines that followed the
+/// original section are kept so the document's spacing doesn't change.
+fn fit_section(reply: &str, original: &str) -> String {
+    let reply = reply.trim();
+    let body = match markdown::sections(reply).first() {
+        Some(section) if section.range.start == 0 => reply[section.range.clone()].trim_end().to_string(),
+        _ => {
+            let heading = original.lines().next().unwrap_or_default();
+            format!("{}\n\n{}", heading, reply)
+        }
+    };
+
+    let trailing = &original[original.trim_end().len()..];
+    format!("{}{}", body, trailing)
+}
+
+/// Regenerate the section of a stage's output with the given heading
+///
+/// The stage keeps its status; only its content changes, saved as a stage
+/// run would save it, along with the copy under the stage's output key in
+/// the project context. Returns the new section.
+///
+/// # Errors
+///
+/// Returns `InvalidInput` if the stage has no output yet or no section with
+/// that heading, listing the sections it does have.
+pub async fn regenerate_section(project_id: &str, stage_number: u8, title: &str) -> Result<String> {
+    let stage = get_stage(stage_number).ok_or(ToolkitError::StageNotFound(stage_number))?;
+    let mut project = project::load_project_async(project_id).await?;
+    let stage_data = project.get_stage(stage_number).ok_or(ToolkitError::StageNotFound(stage_number))?;
+    let status = stage_data.status.clone();
+    let content = stage_data
+        .content
+        .clone()
+        .filter(|content| !content.trim().is_empty())
+        .ok_or_else(|| ToolkitError::InvalidInput(format!("Stage {} of project {} has no output yet", stage_number, project_id)))?;
+
+    let section = markdown::find_section(&content, title).ok_or_else(|| {
+        let titles: Vec<String> = markdown::sections(&content).into_iter().map(|s| s.title).collect();
+        ToolkitError::InvalidInput(if titles.is_empty() {
+            format!("Stage {} has no ## sections", stage_number)
+        } else {
+            format!("Stage {} has no section '{}'. Its sections are: {}", stage_number, title, titles.join(", "))
+        })
+    })?;
+
+    info!("Regenerating section '{}' of stage {} for project {}", section.title, stage_number, project_id);
+    let prompt = section_prompt(&project.name, stage.name(), &content, &section);
+    let context = StageContext::new();
+    let client = ai::get_cached_client().await?;
+    let generated = generate_stage_output(client.as_ref(), &prompt, stage.request_options(&context), &context).await?;
+
+    let new_section = fit_section(&generated.text, &content[section.range.clone()]);
+    let updated = markdown::replace_section(&content, &section, &new_section);
+
+    project.update_stage(stage_number, updated.clone(), status);
+    project::save_stage_async(&project, stage_number).await?;
+    // Later stages and resets read the output from the saved context
+    let output = HashMap::from([(stage.output_key(), updated)]);
+    project::save_context_async(&project, &output).await?;
+
+    Ok(new_section)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_section() {
+        let original = "## Timeline\n\nTwo weeks.\n\n";
+
+        let reply = "## Timeline\n\n- Week 1: design\n- Week 2: build\n\n## Risks\nExtra section";
+        assert_eq!(fit_section(reply, original), "## Timeline\n\n- Week 1: design\n- Week 2: build\n\n");
+
+        assert_eq!(fit_section("Three weeks, with a buffer.", original), "## Timeline\n\nThree weeks, with a buffer.\n\n");
+        assert_eq!(fit_section("## Timeline\nSix weeks.\n", "## Timeline\nTwo weeks."), "## Timeline\nSix weeks.");
+    }
+}
@@ -1,11 +1,11 @@
-use crate::ai;
+use crate::ai::{self, RequestOptions};
 use crate::error::Result;
-use crate::models::StageStatus;
+use crate::models::{Project, StageStatus};
 use crate::utils::{project, ui};
-use crate::prompts::PromptManager;
-use crate::stages::{Stage, StageContext, StageResult};
+use crate::stages::{generate_stage_output, Stage, StageContext, StageResult};
 use async_trait::async_trait;
 use log::{debug, error, info};
+use std::time::Instant;
 
 pub struct Stage1 {
     name: String,
@@ -35,71 +35,95 @@ impl Stage for Stage1 {
         &self.description
     }
     
+    fn output_key(&self) -> String {
+        "initial_plan".to_string()
+    }
+    
+    fn system_prompt(&self) -> Option<&str> {
+        Some("You are an experienced software project planner. Turn rough project ideas into clear, actionable plans with well-defined goals, scope, milestones and risks.")
+    }
+    
+    fn default_options(&self) -> RequestOptions {
+        // Planning benefits from more varied ideas
+        RequestOptions {
+            temperature: Some(0.9),
+            ..Default::default()
+        }
+    }
+    
     fn dependencies(&self) -> Vec<u8> {
         // Stage 1 has no dependencies
         vec![]
     }
     
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
+        // Get the project idea for the prompt
+        let project_idea = match project::get_project_idea(&project.id) {
+            Ok(idea) => idea,
+            Err(e) => {
+                error!("Failed to get project idea for {}: {}", project.id, e);
+                return Err(e);
+            }
+        };
+        
+        // Add project idea to the template variables
+        let mut template_vars = self.prepare_template_vars(project, context);
+        template_vars.insert("project_idea".to_string(), project_idea);
+        
+        self.render_prompt(project, template_vars)
+    }
+    
     async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
         info!("Starting Stage 1 for project: {}", project_id);
         
         // Load the project
-        let mut project = self.load_project(project_id)?;
+        let mut project = self.load_project(project_id).await?;
         
         // Check if this stage should be skipped
-        if self.should_skip(&project)? {
+        if self.should_skip_run(&project, &context)? {
             return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
         }
         
         ui::print_stage_header(1, &self.name);
         
-        // Get the project idea for the prompt
-        let project_idea = match project::get_project_idea(project_id) {
-            Ok(idea) => idea,
-            Err(e) => {
-                error!("Failed to get project idea for {}: {}", project_id, e);
-                return Err(e);
-            }
-        };
-        
-        // Add project idea to the template variables
-        let mut template_vars = self.prepare_template_vars(&project, &context);
-        template_vars.insert("project_idea".to_string(), project_idea);
+        // Build the prompt from the project and earlier stages
+        let prompt = self.build_prompt(&project, &context)?;
         
         // Initialize AI client
         debug!("Initializing AI client");
-        let ai_client = ai::get_client().await?;
-        
-        // Create a prompt manager
-        let prompt_manager = PromptManager::global()?;
-        
-        // Render the template
-        let variables = PromptManager::vars_to_json(template_vars);
-        let prompt = prompt_manager.render(&self.template_name(), &variables)?;
+        let ai_client = ai::get_cached_client().await?;
         
         // Send the prompt to the AI
         info!("Sending prompt to AI service");
-        let response = match ai_client.generate(&prompt).await {
+        let started = Instant::now();
+        let generated = match generate_stage_output(ai_client.as_ref(), &prompt, self.request_options(&context), &context).await {
             Ok(resp) => resp,
             Err(e) => {
                 error!("AI service error: {}", e);
                 return Err(e);
             }
         };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let response = generated.text;
+
+        if let Err(e) = self.validate_output(&response) {
+            return self.reject_output(&mut project, response, e, context).await;
+        }
         
         // Update the project with the AI's response
         info!("Updating project with AI response");
         project.update_stage(1, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(1, generated.usage, duration_ms);
         
         // Save the updated project
         debug!("Saving updated project");
-        if let Err(e) = project::save_project(&project) {
+        if let Err(e) = project::save_stage_async(&project, 1).await {
             error!("Failed to save project {}: {}", project_id, e);
             return Err(e);
         }
         
         // Update the context with the initial plan
-        context.set("initial_plan", response);
+        context.set(self.output_key(), response);
         
         ui::print_success("Stage 1 completed successfully!");
         
@@ -1,11 +1,11 @@
 use crate::ai;
 use crate::error::{Result, ToolkitError};
-use crate::models::StageStatus;
+use crate::models::{Project, StageStatus};
 use crate::utils::{project, ui};
-use crate::prompts::PromptManager;
-use crate::stages::{Stage, StageContext, StageResult};
+use crate::stages::{generate_stage_output, Stage, StageContext, StageResult};
 use async_trait::async_trait;
 use log::{debug, error, info};
+use std::time::Instant;
 
 pub struct Stage2 {
     name: String,
@@ -35,32 +35,28 @@ impl Stage for Stage2 {
         &self.description
     }
     
+    fn output_key(&self) -> String {
+        "architecture_design".to_string()
+    }
+    
+    fn system_prompt(&self) -> Option<&str> {
+        Some("You are a senior software architect. Design pragmatic, maintainable system architectures and explain the trade-offs behind each decision.")
+    }
+    
     fn dependencies(&self) -> Vec<u8> {
         vec![1] // Depends on stage 1
     }
     
-    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
-        info!("Starting Stage 2 for project: {}", project_id);
-        
-        // Load the project
-        let mut project = self.load_project(project_id)?;
-        
-        // Check if this stage should be skipped
-        if self.should_skip(&project)? {
-            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
-        }
-        
-        ui::print_stage_header(2, &self.name);
-        
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
         // Check if we have the initial plan in the context
-        let initial_plan = if let Some(plan) = context.get("initial_plan") {
-            plan.clone()
+        let initial_plan = if let Some(value) = context.get("initial_plan") {
+            value.clone()
         } else {
             // Try to get it from the project
             if let Some(stage1) = project.get_stage(1) {
                 stage1.content.clone().unwrap_or_else(|| "No initial plan available".to_string())
             } else {
-                error!("Stage 1 output not found for project {}", project_id);
+                error!("Stage 1 output not found for project {}", project.id);
                 return Err(ToolkitError::InvalidInput(
                     "Stage 1 must be completed before running Stage 2".to_string()
                 ));
@@ -68,43 +64,63 @@ impl Stage for Stage2 {
         };
         
         // Prepare template variables
-        let mut template_vars = self.prepare_template_vars(&project, &context);
+        let mut template_vars = self.prepare_template_vars(project, context);
         template_vars.insert("initial_plan".to_string(), initial_plan);
         
-        // Initialize AI client
-        debug!("Initializing AI client");
-        let ai_client = ai::get_client().await?;
+        self.render_prompt(project, template_vars)
+    }
+    
+    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        info!("Starting Stage 2 for project: {}", project_id);
+        
+        // Load the project
+        let mut project = self.load_project(project_id).await?;
+        
+        // Check if this stage should be skipped
+        if self.should_skip_run(&project, &context)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+        
+        ui::print_stage_header(2, &self.name);
         
-        // Create a prompt manager
-        let prompt_manager = PromptManager::global()?;
+        // Build the prompt from the project and earlier stages
+        let prompt = self.build_prompt(&project, &context)?;
         
-        // Render the template
-        let variables = PromptManager::vars_to_json(template_vars);
-        let prompt = prompt_manager.render(&self.template_name(), &variables)?;
+        // Initialize AI client
+        debug!("Initializing AI client");
+        let ai_client = ai::get_cached_client().await?;
         
         // Send the prompt to the AI
         info!("Sending prompt to AI service");
-        let response = match ai_client.generate(&prompt).await {
+        let started = Instant::now();
+        let generated = match generate_stage_output(ai_client.as_ref(), &prompt, self.request_options(&context), &context).await {
             Ok(resp) => resp,
             Err(e) => {
                 error!("AI service error: {}", e);
                 return Err(e);
             }
         };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let response = generated.text;
+
+        if let Err(e) = self.validate_output(&response) {
+            return self.reject_output(&mut project, response, e, context).await;
+        }
         
         // Update the project with the AI's response
         info!("Updating project with AI response");
         project.update_stage(2, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(2, generated.usage, duration_ms);
         
         // Save the updated project
         debug!("Saving updated project");
-        if let Err(e) = project::save_project(&project) {
+        if let Err(e) = project::save_stage_async(&project, 2).await {
             error!("Failed to save project {}: {}", project_id, e);
             return Err(e);
         }
         
         // Update the context with the architecture design
-        context.set("architecture_design", response);
+        context.set(self.output_key(), response);
         
         ui::print_success("Stage 2 completed successfully!");
         
@@ -1,11 +1,11 @@
-use crate::ai;
+use crate::ai::{self, RequestOptions};
 use crate::error::{Result, ToolkitError};
-use crate::models::StageStatus;
+use crate::models::{Project, StageStatus};
 use crate::utils::{project, ui};
-use crate::prompts::PromptManager;
-use crate::stages::{Stage, StageContext, StageResult};
+use crate::stages::{generate_stage_output, Stage, StageContext, StageResult};
 use async_trait::async_trait;
 use log::{debug, error, info};
+use std::time::Instant;
 
 pub struct Stage4 {
     name: String,
@@ -35,77 +35,112 @@ impl Stage for Stage4 {
         &self.description
     }
     
-    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
-        info!("Starting Stage 4 for project: {}", project_id);
-        
-        // Load the project
-        let mut project = self.load_project(project_id)?;
-        
-        // Check if this stage should be skipped
-        if self.should_skip(&project)? {
-            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+    fn is_interactive(&self) -> bool {
+        true
+    }
+    
+    fn output_key(&self) -> String {
+        "progress_assessment".to_string()
+    }
+    
+    fn system_prompt(&self) -> Option<&str> {
+        Some("You are a pragmatic engineering manager. Assess project progress honestly, identify blockers and recommend focused next steps.")
+    }
+    
+    fn default_options(&self) -> RequestOptions {
+        // Refinement should be consistent between runs
+        RequestOptions {
+            temperature: Some(0.2),
+            ..Default::default()
         }
-        
-        ui::print_stage_header(4, &self.name);
-        
+    }
+    
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
         // Check if we have the implementation strategy in the context
-        let implementation_strategy = if let Some(strategy) = context.get("implementation_strategy") {
-            strategy.clone()
+        let implementation_strategy = if let Some(value) = context.get("implementation_strategy") {
+            value.clone()
         } else {
             // Try to get it from the project
             if let Some(stage3) = project.get_stage(3) {
                 stage3.content.clone().unwrap_or_else(|| "No implementation strategy available".to_string())
             } else {
-                error!("Stage 3 output not found for project {}", project_id);
+                error!("Stage 3 output not found for project {}", project.id);
                 return Err(ToolkitError::InvalidInput(
                     "Stage 3 must be completed before running Stage 4".to_string()
                 ));
             }
         };
         
-        // Get the current status from the user
-        ui::print_info("Please provide a brief summary of the current project status:");
-        let current_status = ui::prompt("Current status: ")?;
+        // The current status is asked for when the stage runs
+        let current_status = context
+            .get("current_status")
+            .cloned()
+            .unwrap_or_else(|| "(asked for when the stage runs)".to_string());
         
         // Prepare template variables
-        let mut template_vars = self.prepare_template_vars(&project, &context);
+        let mut template_vars = self.prepare_template_vars(project, context);
         template_vars.insert("implementation_strategy".to_string(), implementation_strategy);
         template_vars.insert("current_status".to_string(), current_status);
         
-        // Initialize AI client
-        debug!("Initializing AI client");
-        let ai_client = ai::get_client().await?;
+        self.render_prompt(project, template_vars)
+    }
+    
+    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        info!("Starting Stage 4 for project: {}", project_id);
         
-        // Create a prompt manager
-        let prompt_manager = PromptManager::global()?;
+        // Load the project
+        let mut project = self.load_project(project_id).await?;
         
-        // Render the template
-        let variables = PromptManager::vars_to_json(template_vars);
-        let prompt = prompt_manager.render(&self.template_name(), &variables)?;
+        // Check if this stage should be skipped
+        if self.should_skip_run(&project, &context)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+        
+        ui::print_stage_header(4, &self.name);
+        
+        // Get the current status from the user
+        ui::print_info("Please provide a brief summary of the current project status:");
+        let current_status = ui::prompt("Current status: ")?;
+        context.set("current_status", current_status);
+        
+        // Build the prompt from the project and earlier stages
+        let prompt = self.build_prompt(&project, &context)?;
+        
+        // Initialize AI client
+        debug!("Initializing AI client");
+        let ai_client = ai::get_cached_client().await?;
         
         // Send the prompt to the AI
         info!("Sending prompt to AI service");
-        let response = match ai_client.generate(&prompt).await {
+        let started = Instant::now();
+        let generated = match generate_stage_output(ai_client.as_ref(), &prompt, self.request_options(&context), &context).await {
             Ok(resp) => resp,
             Err(e) => {
                 error!("AI service error: {}", e);
                 return Err(e);
             }
         };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let response = generated.text;
+
+        if let Err(e) = self.validate_output(&response) {
+            return self.reject_output(&mut project, response, e, context).await;
+        }
         
         // Update the project with the AI's response
         info!("Updating project with AI response");
         project.update_stage(4, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(4, generated.usage, duration_ms);
         
         // Save the updated project
         debug!("Saving updated project");
-        if let Err(e) = project::save_project(&project) {
+        if let Err(e) = project::save_stage_async(&project, 4).await {
             error!("Failed to save project {}: {}", project_id, e);
             return Err(e);
         }
         
         // Update the context with the progress assessment
-        context.set("progress_assessment", response);
+        context.set(self.output_key(), response);
         
         ui::print_success("Stage 4 completed successfully!");
         
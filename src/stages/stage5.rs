@@ -1,11 +1,11 @@
 use crate::ai;
 use crate::error::{Result, ToolkitError};
-use crate::models::StageStatus;
+use crate::models::{Project, StageStatus};
 use crate::utils::{project, ui};
-use crate::prompts::PromptManager;
-use crate::stages::{Stage, StageContext, StageResult};
+use crate::stages::{generate_stage_output, Stage, StageContext, StageResult};
 use async_trait::async_trait;
 use log::{debug, error, info};
+use std::time::Instant;
 
 pub struct Stage5 {
     name: String,
@@ -35,28 +35,24 @@ impl Stage for Stage5 {
         &self.description
     }
     
-    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
-        info!("Starting Stage 5 for project: {}", project_id);
-        
-        // Load the project
-        let mut project = self.load_project(project_id)?;
-        
-        // Check if this stage should be skipped
-        if self.should_skip(&project)? {
-            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
-        }
-        
-        ui::print_stage_header(5, &self.name);
-        
+    fn output_key(&self) -> String {
+        "ux_design".to_string()
+    }
+    
+    fn system_prompt(&self) -> Option<&str> {
+        Some("You are an expert UX designer. Produce user-centered interface designs with clear user flows, layouts and accessibility considerations.")
+    }
+    
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
         // Check if we have the architecture design in the context
-        let architecture_design = if let Some(design) = context.get("architecture_design") {
-            design.clone()
+        let architecture_design = if let Some(value) = context.get("architecture_design") {
+            value.clone()
         } else {
             // Try to get it from the project
             if let Some(stage2) = project.get_stage(2) {
                 stage2.content.clone().unwrap_or_else(|| "No architecture design available".to_string())
             } else {
-                error!("Stage 2 output not found for project {}", project_id);
+                error!("Stage 2 output not found for project {}", project.id);
                 return Err(ToolkitError::InvalidInput(
                     "Stage 2 must be completed before running Stage 5".to_string()
                 ));
@@ -64,43 +60,63 @@ impl Stage for Stage5 {
         };
         
         // Prepare template variables
-        let mut template_vars = self.prepare_template_vars(&project, &context);
+        let mut template_vars = self.prepare_template_vars(project, context);
         template_vars.insert("architecture_design".to_string(), architecture_design);
         
-        // Initialize AI client
-        debug!("Initializing AI client");
-        let ai_client = ai::get_client().await?;
+        self.render_prompt(project, template_vars)
+    }
+    
+    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        info!("Starting Stage 5 for project: {}", project_id);
+        
+        // Load the project
+        let mut project = self.load_project(project_id).await?;
+        
+        // Check if this stage should be skipped
+        if self.should_skip_run(&project, &context)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+        
+        ui::print_stage_header(5, &self.name);
         
-        // Create a prompt manager
-        let prompt_manager = PromptManager::global()?;
+        // Build the prompt from the project and earlier stages
+        let prompt = self.build_prompt(&project, &context)?;
         
-        // Render the template
-        let variables = PromptManager::vars_to_json(template_vars);
-        let prompt = prompt_manager.render(&self.template_name(), &variables)?;
+        // Initialize AI client
+        debug!("Initializing AI client");
+        let ai_client = ai::get_cached_client().await?;
         
         // Send the prompt to the AI
         info!("Sending prompt to AI service");
-        let response = match ai_client.generate(&prompt).await {
+        let started = Instant::now();
+        let generated = match generate_stage_output(ai_client.as_ref(), &prompt, self.request_options(&context), &context).await {
             Ok(resp) => resp,
             Err(e) => {
                 error!("AI service error: {}", e);
                 return Err(e);
             }
         };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let response = generated.text;
+
+        if let Err(e) = self.validate_output(&response) {
+            return self.reject_output(&mut project, response, e, context).await;
+        }
         
         // Update the project with the AI's response
         info!("Updating project with AI response");
         project.update_stage(5, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(5, generated.usage, duration_ms);
         
         // Save the updated project
         debug!("Saving updated project");
-        if let Err(e) = project::save_project(&project) {
+        if let Err(e) = project::save_stage_async(&project, 5).await {
             error!("Failed to save project {}: {}", project_id, e);
             return Err(e);
         }
         
         // Update the context with the UX design
-        context.set("ux_design", response);
+        context.set(self.output_key(), response);
         
         ui::print_success("Stage 5 completed successfully!");
         
@@ -1,11 +1,11 @@
-use crate::ai;
+use crate::ai::{self, RequestOptions};
 use crate::error::Result;
-use crate::models::StageStatus;
+use crate::models::{Project, StageStatus};
 use crate::utils::{project, ui};
-use crate::prompts::PromptManager;
-use crate::stages::{Stage, StageContext, StageResult};
+use crate::stages::{generate_stage_output, Stage, StageContext, StageResult};
 use async_trait::async_trait;
 use log::{debug, error, info};
+use std::time::Instant;
 
 pub struct Stage6 {
     name: String,
@@ -35,21 +35,25 @@ impl Stage for Stage6 {
         &self.description
     }
     
-    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
-        info!("Starting Stage 6 for project: {}", project_id);
-        
-        // Load the project
-        let mut project = self.load_project(project_id)?;
-        
-        // Check if this stage should be skipped
-        if self.should_skip(&project)? {
-            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+    fn output_key(&self) -> String {
+        "code_generation".to_string()
+    }
+    
+    fn system_prompt(&self) -> Option<&str> {
+        Some("You are an expert software engineer. Write clean, idiomatic, well-documented code that follows the agreed architecture and implementation strategy.")
+    }
+    
+    fn default_options(&self) -> RequestOptions {
+        // Code should follow the design closely rather than improvise
+        RequestOptions {
+            temperature: Some(0.2),
+            ..Default::default()
         }
-        
-        ui::print_stage_header(6, &self.name);
-        
+    }
+    
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
         // Gather required context from previous stages if not already in context
-        let mut template_vars = self.prepare_template_vars(&project, &context);
+        let mut template_vars = self.prepare_template_vars(project, context);
         
         // Architecture design
         if !context.has("architecture_design") {
@@ -72,40 +76,60 @@ impl Stage for Stage6 {
             }
         }
         
-        // Initialize AI client
-        debug!("Initializing AI client");
-        let ai_client = ai::get_client().await?;
+        self.render_prompt(project, template_vars)
+    }
+    
+    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        info!("Starting Stage 6 for project: {}", project_id);
         
-        // Create a prompt manager
-        let prompt_manager = PromptManager::global()?;
+        // Load the project
+        let mut project = self.load_project(project_id).await?;
         
-        // Render the template
-        let variables = PromptManager::vars_to_json(template_vars);
-        let prompt = prompt_manager.render(&self.template_name(), &variables)?;
+        // Check if this stage should be skipped
+        if self.should_skip_run(&project, &context)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+        
+        ui::print_stage_header(6, &self.name);
+        
+        // Build the prompt from the project and earlier stages
+        let prompt = self.build_prompt(&project, &context)?;
+        
+        // Initialize AI client
+        debug!("Initializing AI client");
+        let ai_client = ai::get_cached_client().await?;
         
         // Send the prompt to the AI
         info!("Sending prompt to AI service");
-        let response = match ai_client.generate(&prompt).await {
+        let started = Instant::now();
+        let generated = match generate_stage_output(ai_client.as_ref(), &prompt, self.request_options(&context), &context).await {
             Ok(resp) => resp,
             Err(e) => {
                 error!("AI service error: {}", e);
                 return Err(e);
             }
         };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let response = generated.text;
+
+        if let Err(e) = self.validate_output(&response) {
+            return self.reject_output(&mut project, response, e, context).await;
+        }
         
         // Update the project with the AI's response
         info!("Updating project with AI response");
         project.update_stage(6, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(6, generated.usage, duration_ms);
         
         // Save the updated project
         debug!("Saving updated project");
-        if let Err(e) = project::save_project(&project) {
+        if let Err(e) = project::save_stage_async(&project, 6).await {
             error!("Failed to save project {}: {}", project_id, e);
             return Err(e);
         }
         
         // Update the context with the code generation and review
-        context.set("code_generation", response);
+        context.set(self.output_key(), response);
         
         ui::print_success("Stage 6 completed successfully!");
         
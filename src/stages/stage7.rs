@@ -0,0 +1,322 @@
+use crate::ai::{self, RequestOptions};
+use crate::error::{Result, ToolkitError};
+use crate::models::{Artifact, Project, StageStatus};
+use crate::utils::{file, json, project, ui};
+use crate::stages::{generate_stage_json, Stage, StageContext, StageResult};
+use async_trait::async_trait;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+
+/// File name of the user stories artifact written to the project directory
+pub const USER_STORIES_FILE: &str = "user_stories.json";
+
+/// A single user story in a shape that issue trackers can import
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserStory {
+    pub id: String,
+    pub title: String,
+    pub as_a: String,
+    pub i_want: String,
+    pub so_that: String,
+    pub acceptance_criteria: Vec<String>,
+}
+
+/// JSON schema for the stage's response: an object wrapping the user stories
+///
+/// Every field is required and no extra fields are allowed, so providers can
+/// enforce the schema strictly.
+pub fn user_stories_schema() -> Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "user_stories": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string" },
+                        "title": { "type": "string" },
+                        "as_a": { "type": "string" },
+                        "i_want": { "type": "string" },
+                        "so_that": { "type": "string" },
+                        "acceptance_criteria": {
+                            "type": "array",
+                            "items": { "type": "string" }
+                        }
+                    },
+                    "required": ["id", "title", "as_a", "i_want", "so_that", "acceptance_criteria"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["user_stories"],
+        "additionalProperties": false
+    })
+}
+
+/// Validate the AI's JSON response and turn it into user stories
+///
+/// The response must be an array of stories, or an object wrapping that array
+/// in a `user_stories` field. Every story needs a non-empty id and title.
+pub fn parse_user_stories(value: Value) -> Result<Vec<UserStory>> {
+    let value = match value {
+        Value::Object(mut map) => map.remove("user_stories").ok_or_else(|| {
+            ToolkitError::Parse("Expected a JSON array of user stories".to_string())
+        })?,
+        other => other,
+    };
+
+    let stories: Vec<UserStory> = serde_json::from_value(value)
+        .map_err(|e| ToolkitError::Parse(format!("Invalid user story format: {}", e)))?;
+
+    if stories.is_empty() {
+        return Err(ToolkitError::Parse("The AI returned no user stories".to_string()));
+    }
+
+    if let Some(story) = stories.iter().find(|s| s.id.trim().is_empty() || s.title.trim().is_empty()) {
+        return Err(ToolkitError::Parse(format!(
+            "User story is missing an id or title: {:?}",
+            story
+        )));
+    }
+
+    Ok(stories)
+}
+
+pub struct Stage7 {
+    name: String,
+    description: String,
+}
+
+impl Stage7 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for Stage7 {
+    fn default() -> Self {
+        Self {
+            name: "User Story Extraction".to_string(),
+            description: "Extract structured user stories that can be imported into an issue tracker".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Stage for Stage7 {
+    fn number(&self) -> u8 {
+        7
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn output_key(&self) -> String {
+        "user_stories".to_string()
+    }
+
+    fn system_prompt(&self) -> Option<&str> {
+        Some("You are an experienced product owner. Break project plans down into small, testable user stories and respond with valid JSON only.")
+    }
+
+    fn default_options(&self) -> RequestOptions {
+        // Structured output needs to stay close to the schema
+        RequestOptions {
+            temperature: Some(0.2),
+            ..Default::default()
+        }
+    }
+
+    fn dependencies(&self) -> Vec<u8> {
+        // User stories are drawn from the plan and the implementation strategy
+        vec![1, 3]
+    }
+
+    fn build_prompt(&self, project: &Project, context: &StageContext) -> Result<String> {
+        // Gather context from previous stages if not already in context
+        let mut template_vars = self.prepare_template_vars(project, context);
+
+        // Initial plan
+        if !context.has("initial_plan") {
+            if let Some(stage1) = project.get_stage(1) {
+                template_vars.insert("initial_plan".to_string(), stage1.content.clone().unwrap_or_else(|| "No initial plan available".to_string()));
+            }
+        }
+
+        // Implementation strategy
+        if !context.has("implementation_strategy") {
+            let strategy = project.get_stage(3).and_then(|s| s.content.clone());
+            template_vars.insert("implementation_strategy".to_string(), strategy.unwrap_or_else(|| "No implementation strategy available".to_string()));
+        }
+
+        self.render_prompt(project, template_vars)
+    }
+
+    /// The output must be JSON holding at least one valid user story
+    fn validate_output(&self, content: &str) -> Result<()> {
+        // A truncated response is reported as too short rather than as bad JSON
+        let value = json::extract_json(content).map_err(|e| {
+            super::check_output_length(content, super::min_response_chars())
+                .err()
+                .unwrap_or_else(|| ToolkitError::Parse(format!("The AI's user stories are not valid JSON: {}", e)))
+        })?;
+        parse_user_stories(value).map(|_| ())
+    }
+
+    async fn execute(&self, project_id: &str, mut context: StageContext) -> Result<StageResult> {
+        info!("Starting Stage 7 for project: {}", project_id);
+
+        // Load the project
+        let mut project = self.load_project(project_id).await?;
+
+        // Check if this stage should be skipped
+        if self.should_skip_run(&project, &context)? {
+            return Ok(StageResult::skipped("Stage already completed or dependencies not met", context));
+        }
+
+        ui::print_stage_header(7, &self.name);
+
+        // Build the prompt from the project and earlier stages
+        let prompt = self.build_prompt(&project, &context)?;
+
+        // Initialize AI client
+        debug!("Initializing AI client");
+        let ai_client = ai::get_cached_client().await?;
+
+        // Send the prompt to the AI and validate the structured response
+        info!("Sending prompt to AI service");
+        let started = Instant::now();
+        let generated = match generate_stage_json(
+            ai_client.as_ref(),
+            &prompt,
+            user_stories_schema(),
+            self.request_options(&context),
+            &context,
+        )
+        .await
+        {
+            Ok(generated) => generated,
+            Err(e) => {
+                error!("AI service error: {}", e);
+                return Err(e);
+            }
+        };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let response = serde_json::to_string_pretty(&generated.value)?;
+        if let Err(e) = self.validate_output(&response) {
+            return self.reject_output(&mut project, response, e, context).await;
+        }
+        let stories = parse_user_stories(generated.value)?;
+        let response = serde_json::to_string_pretty(&stories)?;
+
+        // Write the stories as a standalone artifact for importing elsewhere
+        let artifact_path = project.path.join(USER_STORIES_FILE);
+        file::write_file_async(&artifact_path, &response).await?;
+
+        // Update the project with the AI's response
+        info!("Updating project with {} user stories", stories.len());
+        project.update_stage(7, response.clone(), StageStatus::Completed);
+        project.record_stage_metrics(7, generated.usage, duration_ms);
+
+        // Replace the artifact from any previous run
+        if let Some(stage) = project.get_stage_mut(7) {
+            stage.artifacts.retain(|a| a.name != USER_STORIES_FILE);
+        }
+        project.add_artifact(7, Artifact {
+            name: USER_STORIES_FILE.to_string(),
+            file_type: "json".to_string(),
+            path: artifact_path,
+            created_at: chrono::Utc::now(),
+        });
+
+        // Save the updated project
+        debug!("Saving updated project");
+        if let Err(e) = project::save_stage_async(&project, 7).await {
+            error!("Failed to save project {}: {}", project_id, e);
+            return Err(e);
+        }
+
+        // Update the context with the user stories
+        context.set(self.output_key(), response);
+
+        ui::print_success("Stage 7 completed successfully!");
+
+        Ok(StageResult::success(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn story(id: &str) -> Value {
+        json!({
+            "id": id,
+            "title": "Add a task",
+            "as_a": "user",
+            "i_want": "to add tasks",
+            "so_that": "I remember them",
+            "acceptance_criteria": ["Task appears in the list"]
+        })
+    }
+
+    #[test]
+    fn test_parse_user_stories_array() {
+        let stories = parse_user_stories(json!([story("US-1"), story("US-2")])).unwrap();
+        assert_eq!(stories.len(), 2);
+        assert_eq!(stories[0].id, "US-1");
+        assert_eq!(stories[0].acceptance_criteria, vec!["Task appears in the list".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_user_stories_wrapped() {
+        let stories = parse_user_stories(json!({ "user_stories": [story("US-1")] })).unwrap();
+        assert_eq!(stories.len(), 1);
+    }
+
+    #[test]
+    fn test_validate_output_requires_user_stories() {
+        let stage = Stage7::new();
+        assert!(stage.validate_output(&json!({ "user_stories": [story("US-1")] }).to_string()).is_ok());
+        assert!(stage.validate_output("Sorry, I can't help with that.").is_err());
+        assert!(stage.validate_output("[]").is_err());
+    }
+
+    #[test]
+    fn test_parse_user_stories_rejects_bad_shape() {
+        assert!(parse_user_stories(json!([])).is_err());
+        assert!(parse_user_stories(json!([{ "id": "US-1", "title": "Missing fields" }])).is_err());
+        assert!(parse_user_stories(json!([story("")])).is_err());
+        assert!(parse_user_stories(json!({ "stories": [] })).is_err());
+    }
+
+    #[test]
+    fn test_default_template_asks_for_the_schema() {
+        let template = crate::prompts::DEFAULT_TEMPLATES["stage7"];
+        assert_eq!(json::extract_json(template).unwrap(), user_stories_schema());
+
+        let shipped = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/stage7.hbs")).unwrap();
+        assert_eq!(json::extract_json(&shipped).unwrap(), user_stories_schema());
+    }
+
+    #[test]
+    fn test_user_stories_schema() {
+        let schema = user_stories_schema();
+        assert!(ai::validate_json_schema(&json!({ "user_stories": [story("US-1")] }), &schema).is_ok());
+
+        let missing = json!({ "user_stories": [{ "id": "US-1", "title": "Missing fields" }] });
+        match ai::validate_json_schema(&missing, &schema) {
+            Err(ToolkitError::Parse(msg)) => assert!(msg.contains("as_a"), "{}", msg),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Summaries of earlier stages' output, sent to later stages instead of the full text.
+//!
+//! Every stage sees the output of the stages before it, so by stage 4 each
+//! prompt resends the plan, the architecture and the strategy in full. With
+//! the `summarize_context` setting, an earlier output longer than
+//! `summary_threshold_tokens` is replaced by a summary from the
+//! `summary_model`. Summaries are saved with their stage and reused until the
+//! stage runs again.
+
+use crate::ai::{self, AiClient, RequestOptions};
+use crate::config;
+use crate::models::{Project, StageStatus};
+use crate::stages::{get_stage, StageContext};
+use crate::utils::{project, tokens, ui};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+
+/// Build the prompt asking for a summary of a stage's output
+fn summary_prompt(stage_name: &str, content: &str) -> String {
+    format!(
+        "Summarize the following {} for a colleague who will build on it. Keep every decision, \
+         requirement, name and number; drop explanations, examples and repetition. Answer with \
+         the summary only, in Markdown.\n\n---\n\n{}",
+        stage_name, content
+    )
+}
+
+/// Summarize the long outputs of completed stages before `stage`
+///
+/// Only context values that are still a stage's saved output are replaced,
+/// so values set by hand are left alone. A stage's saved summary is used if
+/// it has one; otherwise `client` writes it. Returns the full text of each
+/// replaced key, and the new summaries by stage number.
+async fn summarize_with(
+    client: &dyn AiClient,
+    project: &Project,
+    stage: u8,
+    context: &mut StageContext,
+    threshold: usize,
+    model: &str,
+) -> (HashMap<String, String>, Vec<(u8, String)>) {
+    let mut originals = HashMap::new();
+    let mut summaries = Vec::new();
+
+    for earlier in project.stages.iter().filter(|s| s.number < stage && s.status == StageStatus::Completed) {
+        let (Some(content), Some(key)) = (&earlier.content, get_stage(earlier.number).map(|s| s.output_key())) else {
+            continue;
+        };
+        if context.get(&key) != Some(content) || tokens::estimate_tokens(content, model) <= threshold {
+            continue;
+        }
+
+        let summary = match &earlier.summary {
+            Some(summary) => summary.clone(),
+            None => {
+                let options = RequestOptions { temperature: Some(0.2), ..Default::default() };
+                match client.generate_with_options(&summary_prompt(&earlier.name, content), options).await {
+                    Ok(summary) => {
+                        summaries.push((earlier.number, summary.clone()));
+                        summary
+                    }
+                    Err(e) => {
+                        ui::print_warning(&format!("Couldn't summarize stage {}; sending it in full: {}", earlier.number, e));
+                        continue;
+                    }
+                }
+            }
+        };
+
+        debug!("Sending the summary of stage {} to stage {}", earlier.number, stage);
+        originals.insert(key.clone(), content.clone());
+        context.set(key, summary);
+    }
+
+    (originals, summaries)
+}
+
+/// Replace long earlier outputs in `context` with summaries, if `summarize_context` is on
+///
+/// New summaries are saved with their stages. Returns the full text of each
+/// replaced key, so it can be put back once the stage has run. Failures are
+/// reported as warnings and leave the full text in place.
+pub async fn summarize_earlier_stages(project: &Project, stage: u8, context: &mut StageContext) -> HashMap<String, String> {
+    let config = match config::get_config() {
+        Ok(config) if config.summarize_context => config,
+        _ => return HashMap::new(),
+    };
+    let summary_model = config.summary_model.clone().unwrap_or_else(|| config.model.clone());
+    let client = match ai::get_cached_client_for_model(&summary_model).await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Couldn't create a client for summaries with {}: {}", summary_model, e);
+            return HashMap::new();
+        }
+    };
+
+    let (originals, summaries) =
+        summarize_with(client.as_ref(), project, stage, context, config.summary_threshold_tokens, &config.model).await;
+
+    let mut project = project.clone();
+    for (number, summary) in summaries {
+        project.set_stage_summary(number, summary);
+        match project::save_stage_async(&project, number).await {
+            Ok(()) => info!("Saved a summary of stage {} of project {}", number, project.id),
+            Err(e) => warn!("Couldn't save the summary of stage {}: {}", number, e),
+        }
+    }
+
+    originals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::testing::{MockAiClient, MockReply};
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_only_long_outputs_are_summarized() {
+        let mut project = Project::new(
+            "summaries".to_string(),
+            "Summaries".to_string(),
+            "A project with long stages".to_string(),
+            PathBuf::from("/tmp/summaries"),
+        );
+        let long_plan = "The plan goes into great detail. ".repeat(100);
+        project.update_stage(1, long_plan.clone(), StageStatus::Completed);
+        project.update_stage(2, "A short architecture".to_string(), StageStatus::Completed);
+        project.update_stage(3, "Strategy ".repeat(200), StageStatus::Completed);
+        project.set_stage_summary(3, "saved summary".to_string());
+
+        let mut context = StageContext::new();
+        context.set("initial_plan", long_plan.clone());
+        context.set("architecture_design", "A short architecture");
+        context.set("implementation_strategy", "Edited by hand ".repeat(200));
+        let client = MockAiClient::new().with_default(MockReply::Text("summary".to_string()));
+
+        let (originals, summaries) = summarize_with(&client, &project, 4, &mut context, 100, "gpt-4o").await;
+
+        assert_eq!(context.get("initial_plan").unwrap(), "summary");
+        assert_eq!(context.get("architecture_design").unwrap(), "A short architecture");
+        assert!(context.get("implementation_strategy").unwrap().starts_with("Edited by hand"));
+        assert_eq!(originals.get("initial_plan"), Some(&long_plan));
+        assert_eq!(summaries, vec![(1, "summary".to_string())]);
+        assert_eq!(client.prompts().len(), 1);
+
+        // A saved summary is reused without asking again
+        context.set("implementation_strategy", "Strategy ".repeat(200));
+        let (_, summaries) = summarize_with(&client, &project, 4, &mut context, 100, "gpt-4o").await;
+        assert_eq!(context.get("implementation_strategy").unwrap(), "saved summary");
+        assert!(summaries.is_empty());
+    }
+}
@@ -44,6 +44,7 @@ use crate::error::Result;
 use log::debug;
 use crate::config;
 use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
 
 /// The maximum time a project should be kept in cache before being refreshed.
 ///
@@ -54,14 +55,20 @@ const PROJECT_CACHE_TTL: Duration = Duration::from_secs(300); // 5 minutes
 /// The maximum number of projects to keep in the cache
 const MAX_CACHE_SIZE: usize = 100;
 
+/// The `project_cache_ttl` setting, recorded by [`configure`]
+static CONFIGURED_TTL: OnceCell<Duration> = OnceCell::new();
+
 // Initialize the cache with the TTL from config
 lazy_static! {
     /// Global project cache to avoid repeated disk access
-    pub static ref PROJECT_CACHE: Mutex<ProjectCache> = {
-        let config = config::get_config().unwrap_or_default();
-        let ttl = Duration::from_secs(config.project_cache_ttl);
-        Mutex::new(ProjectCache::new_with_ttl(ttl))
-    };
+    pub static ref PROJECT_CACHE: Mutex<ProjectCache> = Mutex::new(ProjectCache::new_with_ttl(ProjectCache::get_ttl()));
+}
+
+/// Set the project cache TTL from the config loaded at startup
+///
+/// Must be called before the cache is first used; later calls have no effect.
+pub fn configure(config: &config::Config) {
+    let _ = CONFIGURED_TTL.set(Duration::from_secs(config.project_cache_ttl));
 }
 
 /// Struct for caching project metadata to avoid repeated file operations.
@@ -410,13 +417,9 @@ impl ProjectCache {
         self.projects.len()
     }
 
-    /// Get the current TTL value
+    /// Get the TTL set by [`configure`], or the default before that
     pub fn get_ttl() -> Duration {
-        // Get TTL from config or use default
-        match config::get_config() {
-            Ok(config) => Duration::from_secs(config.project_cache_ttl),
-            Err(_) => PROJECT_CACHE_TTL,
-        }
+        CONFIGURED_TTL.get().copied().unwrap_or(PROJECT_CACHE_TTL)
     }
 }
 
@@ -0,0 +1,131 @@
+//! Cancellation of in-flight work when the user presses Ctrl-C.
+//!
+//! [`install_ctrl_c_handler`] flips a process-wide flag on interrupt. Work
+//! wrapped in [`until_cancelled`] is dropped as soon as the flag is set, which
+//! also drops any HTTP request it was waiting on.
+//!
+//! Reading the terminal blocks a runtime thread, so a future waiting on the
+//! user can't be dropped. While a [`waiting_for_input`] guard is alive, Ctrl-C
+//! exits the process instead, as it would without the handler.
+
+use crate::error::{Result, ToolkitError};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::watch;
+
+lazy_static! {
+    static ref CANCELLED: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// How many [`InputGuard`]s are alive
+static WAITING_FOR_INPUT: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks the terminal as waiting for the user's answer until dropped
+pub struct InputGuard(());
+
+impl Drop for InputGuard {
+    fn drop(&mut self) {
+        WAITING_FOR_INPUT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Make Ctrl-C exit the process while the returned guard is alive
+///
+/// Hold it around anything that reads the terminal synchronously.
+pub fn waiting_for_input() -> InputGuard {
+    WAITING_FOR_INPUT.fetch_add(1, Ordering::SeqCst);
+    InputGuard(())
+}
+
+/// Whether something is waiting for the user's input
+fn is_waiting_for_input() -> bool {
+    WAITING_FOR_INPUT.load(Ordering::SeqCst) > 0
+}
+
+/// Cancel all work wrapped in [`until_cancelled`]
+pub fn cancel() {
+    CANCELLED.send_replace(true);
+}
+
+/// Wait until the user cancels
+pub async fn cancelled() {
+    let mut receiver = CANCELLED.subscribe();
+    // The sender lives in a static, so the channel never closes
+    let _ = receiver.wait_for(|cancelled| *cancelled).await;
+}
+
+/// Run a future, giving up with [`ToolkitError::Cancelled`] if the user cancels first
+pub async fn until_cancelled<T, F>(future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    race(cancelled(), future).await
+}
+
+/// Run a future until `signal` completes
+async fn race<T, S, F>(signal: S, future: F) -> Result<T>
+where
+    S: Future<Output = ()>,
+    F: Future<Output = Result<T>>,
+{
+    tokio::select! {
+        biased;
+        _ = signal => Err(ToolkitError::Cancelled),
+        result = future => result,
+    }
+}
+
+/// Cancel in-flight work when the user presses Ctrl-C
+///
+/// While the terminal is waiting for input, Ctrl-C exits with status 130
+/// instead. Must be called from within a Tokio runtime.
+pub fn install_ctrl_c_handler() {
+    tokio::spawn(async {
+        loop {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                warn!("Failed to listen for Ctrl-C: {}", e);
+                return;
+            }
+            if is_waiting_for_input() {
+                println!();
+                std::process::exit(130);
+            }
+            info!("Received Ctrl-C, cancelling");
+            cancel();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Cancelling the process-wide flag would fail every other test making
+    // requests, so these drive the race with a local signal instead
+    #[tokio::test]
+    async fn test_race_drops_pending_work() {
+        let finished = race(std::future::pending(), async { Ok(1) }).await;
+        assert_eq!(finished.unwrap(), 1);
+
+        let pending = race(tokio::time::sleep(Duration::from_millis(10)), async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        assert!(matches!(pending.await, Err(ToolkitError::Cancelled)));
+    }
+
+    #[test]
+    fn test_input_guard_marks_waiting() {
+        let outer = waiting_for_input();
+        let inner = waiting_for_input();
+        assert!(is_waiting_for_input());
+
+        drop(inner);
+        assert!(is_waiting_for_input());
+        drop(outer);
+        assert!(!is_waiting_for_input());
+    }
+}
@@ -0,0 +1,299 @@
+use crate::error::{Result, ToolkitError};
+use crate::models::{Project, StageStatus};
+use crate::prompts::PromptManager;
+use crate::utils::file;
+use log::{debug, info};
+use pulldown_cmark::{html, Options, Parser};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Output formats supported by project export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Pdf,
+}
+
+impl ExportFormat {
+    /// Whether the format produces binary output that can't be printed to a terminal
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Pdf)
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ToolkitError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" | "htm" => Ok(Self::Html),
+            "pdf" => Ok(Self::Pdf),
+            other => Err(ToolkitError::InvalidInput(format!(
+                "Unsupported export format: {}. Supported formats: markdown, html, pdf",
+                other
+            ))),
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Markdown => write!(f, "markdown"),
+            Self::Html => write!(f, "html"),
+            Self::Pdf => write!(f, "pdf"),
+        }
+    }
+}
+
+/// Minimal stylesheet for HTML and PDF exports
+const HTML_STYLE: &str = r#"body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; line-height: 1.6; color: #24292f; max-width: 860px; margin: 2rem auto; padding: 0 1.5rem; }
+header { border-bottom: 2px solid #d0d7de; margin-bottom: 2rem; }
+header h1 { margin-bottom: 0.25rem; }
+header .generated { color: #57606a; font-size: 0.9rem; margin-top: 0; }
+h2 { border-bottom: 1px solid #d0d7de; padding-bottom: 0.3rem; margin-top: 2.5rem; }
+pre { background: #f6f8fa; padding: 1rem; overflow-x: auto; border-radius: 6px; }
+code { font-family: "SFMono-Regular", Consolas, monospace; font-size: 0.9em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 0.4rem 0.8rem; }
+blockquote { color: #57606a; border-left: 4px solid #d0d7de; margin: 0; padding: 0 1rem; }"#;
+
+/// Assemble the body of a project document: the description followed by each stage
+fn markdown_body(project: &Project) -> String {
+    let mut doc = format!("{}\n", project.description.trim());
+
+    for stage in &project.stages {
+        doc.push_str(&format!("\n## Stage {}: {}\n\n", stage.number, stage.name));
+
+        match (&stage.status, &stage.content) {
+            (StageStatus::Completed, Some(content)) => {
+                doc.push_str(content.trim());
+                doc.push('\n');
+            }
+            _ => doc.push_str("_(not run)_\n"),
+        }
+    }
+
+    doc
+}
+
+/// Assemble a project into a single Markdown document
+///
+/// The project description forms the preamble, followed by each stage under a
+/// `## Stage N: <name>` heading. Stages that haven't completed are noted as not run.
+pub fn to_markdown(project: &Project) -> String {
+    format!("# {}\n\n{}", project.name, markdown_body(project))
+}
+
+/// Render a project into a standalone, styled HTML document
+///
+/// The header shows the project name and when the document was generated.
+pub fn to_html(project: &Project) -> String {
+    let title = escape_html(&project.name);
+    let generated = chrono::Utc::now().format("%Y-%m-%d %H:%M UTC");
+    let header = format!("<header>\n<h1>{}</h1>\n<p class=\"generated\">Generated {}</p>\n</header>\n", title, generated);
+
+    html_page(&project.name, &header, &markdown_body(project))
+}
+
+/// Wrap Markdown, converted to HTML, in a styled page
+fn html_page(title: &str, header: &str, markdown: &str) -> String {
+    let mut body = String::new();
+    let parser = Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS);
+    html::push_html(&mut body, parser);
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{style}\n</style>\n</head>\n<body>\n{header}<main>\n{body}</main>\n</body>\n</html>\n",
+        title = escape_html(title),
+        style = HTML_STYLE,
+        header = header,
+        body = body,
+    )
+}
+
+/// Name an export template is registered under, e.g. `export_summary` for `summary`
+pub fn export_template_name(name: &str) -> String {
+    format!("export_{}", name.strip_prefix("export_").unwrap_or(name))
+}
+
+/// Lay a project out with an export template
+///
+/// The template receives the whole project, stages included, and should
+/// produce Markdown, which HTML and PDF exports convert like the built-in
+/// layout. `name` may be given with or without the `export_` prefix.
+///
+/// # Errors
+///
+/// Returns a `TemplateError` if no such export template exists or it fails to
+/// render.
+pub fn export_project_with_template(
+    project: &Project,
+    format: ExportFormat,
+    prompt_manager: &PromptManager,
+    name: &str,
+) -> Result<Vec<u8>> {
+    let template = export_template_name(name);
+    if !prompt_manager.has_template(&template) {
+        return Err(ToolkitError::TemplateError(format!(
+            "Export template '{}' not found. Add {}.hbs to the templates directory.",
+            name, template
+        )));
+    }
+    debug!("Exporting project {} as {} with template {}", project.id, format, template);
+
+    let markdown = prompt_manager.render(&template, &serde_json::to_value(project)?)?;
+    match format {
+        ExportFormat::Markdown => Ok(markdown.into_bytes()),
+        ExportFormat::Html => Ok(html_page(&project.name, "", &markdown).into_bytes()),
+        ExportFormat::Pdf => html_to_pdf(&html_page(&project.name, "", &markdown)),
+    }
+}
+
+/// Escape text for safe inclusion in HTML
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render HTML to PDF using a headless Chrome/Chromium instance
+#[cfg(feature = "pdf")]
+fn html_to_pdf(html: &str) -> Result<Vec<u8>> {
+    use headless_chrome::Browser;
+
+    // Chrome needs a URL to load, so stage the document in a temporary file
+    let html_path = std::env::temp_dir().join(format!("rust-ai-toolkit-export-{}.html", nanoid::nanoid!(8)));
+    std::fs::write(&html_path, html)?;
+
+    let result = (|| -> std::result::Result<Vec<u8>, anyhow::Error> {
+        let browser = Browser::default()?;
+        let tab = browser.new_tab()?;
+        tab.navigate_to(&format!("file://{}", html_path.display()))?;
+        tab.wait_until_navigated()?;
+        tab.print_to_pdf(None)
+    })();
+
+    std::fs::remove_file(&html_path).ok();
+
+    result.map_err(|e| ToolkitError::Unknown(format!("Failed to render PDF: {}", e)))
+}
+
+/// PDF rendering is unavailable without the `pdf` feature
+#[cfg(not(feature = "pdf"))]
+fn html_to_pdf(_html: &str) -> Result<Vec<u8>> {
+    Err(ToolkitError::Config(
+        "PDF export is not available in this build. Reinstall with `--features pdf` to enable it.".to_string(),
+    ))
+}
+
+/// Render a project in the given export format
+pub fn export_project(project: &Project, format: ExportFormat) -> Result<Vec<u8>> {
+    debug!("Exporting project {} as {}", project.id, format);
+
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(project).into_bytes()),
+        ExportFormat::Html => Ok(to_html(project).into_bytes()),
+        ExportFormat::Pdf => html_to_pdf(&to_html(project)),
+    }
+}
+
+/// Write exported content to a file, or to stdout when no path is given
+pub fn write_export(content: &[u8], output: Option<&Path>) -> Result<()> {
+    match output {
+        Some(path) => {
+            if let Some(parent) = path.parent() {
+                file::ensure_dir_exists(parent)?;
+            }
+            std::fs::write(path, content)
+                .map_err(|e| ToolkitError::Io(format!("Failed to write {}: {}", path.display(), e)))?;
+            info!("Exported project to {}", path.display());
+            Ok(())
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(content)?;
+            stdout.flush()?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_markdown_includes_completed_stages_only() {
+        let mut project = Project::new(
+            "proj_1".to_string(),
+            "Todo App".to_string(),
+            "A simple todo app".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        );
+        project.update_stage(1, "The plan".to_string(), StageStatus::Completed);
+        project.update_stage(2, "Half done".to_string(), StageStatus::InProgress);
+
+        let doc = to_markdown(&project);
+
+        assert!(doc.starts_with("# Todo App\n\nA simple todo app\n"));
+        assert!(doc.contains("## Stage 1: Initial Plan Creation\n\nThe plan\n"));
+        assert!(doc.contains(&format!("## Stage 2: {}\n\n_(not run)_\n", project.stages[1].name)));
+        assert!(!doc.contains("Half done"));
+    }
+
+    #[test]
+    fn test_html_has_header_and_rendered_stages() {
+        let mut project = Project::new(
+            "proj_1".to_string(),
+            "Todo <App>".to_string(),
+            "A simple todo app".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        );
+        project.update_stage(1, "- first\n- second".to_string(), StageStatus::Completed);
+
+        let html = to_html(&project);
+
+        assert!(html.contains("<h1>Todo &lt;App&gt;</h1>"));
+        assert!(html.contains("<p class=\"generated\">Generated "));
+        assert!(html.contains("<h2>Stage 1: Initial Plan Creation</h2>"));
+        assert!(html.contains("<li>first</li>"));
+    }
+
+    #[test]
+    fn test_parse_export_format() {
+        assert_eq!("markdown".parse::<ExportFormat>().unwrap(), ExportFormat::Markdown);
+        assert_eq!("MD".parse::<ExportFormat>().unwrap(), ExportFormat::Markdown);
+        assert_eq!("html".parse::<ExportFormat>().unwrap(), ExportFormat::Html);
+        assert_eq!("pdf".parse::<ExportFormat>().unwrap(), ExportFormat::Pdf);
+        assert!("docx".parse::<ExportFormat>().is_err());
+    }
+
+    #[test]
+    fn test_export_template_receives_the_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = PromptManager::new(dir.path()).unwrap();
+        let mut project = Project::new(
+            "proj_1".to_string(),
+            "Todo App".to_string(),
+            "A simple todo app".to_string(),
+            PathBuf::from("/tmp/proj_1"),
+        );
+        project.update_stage(1, "The \"plan\"".to_string(), StageStatus::Completed);
+
+        let doc = export_project_with_template(&project, ExportFormat::Markdown, &manager, "default").unwrap();
+        assert_eq!(doc, to_markdown(&project).into_bytes());
+
+        manager.add_template("export_summary", "{{name}}: {{stages.0.name}}").unwrap();
+        let doc = export_project_with_template(&project, ExportFormat::Markdown, &manager, "export_summary").unwrap();
+        assert_eq!(String::from_utf8(doc).unwrap(), "Todo App: Initial Plan Creation");
+
+        let error = export_project_with_template(&project, ExportFormat::Html, &manager, "missing").unwrap_err();
+        assert!(matches!(error, ToolkitError::TemplateError(_)));
+    }
+}
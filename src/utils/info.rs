@@ -0,0 +1,119 @@
+//! Installation and configuration details, for the `info` command.
+
+use crate::ai;
+use crate::config::{self, Config};
+use crate::error::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Everything a bug report needs to know about this installation
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolkitInfo {
+    pub version: &'static str,
+    /// Optional cargo features compiled into this build
+    pub features: Vec<&'static str>,
+    pub config_dir: PathBuf,
+    pub config_file: PathBuf,
+    pub templates_dir: PathBuf,
+    /// Settings in effect, or `None` when the config can't be loaded
+    pub config: Option<ConfigInfo>,
+    /// Why the config couldn't be loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_error: Option<String>,
+}
+
+/// The settings in effect, after environment variables and `--model`
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigInfo {
+    pub provider: String,
+    pub model: String,
+    /// URL requests are sent to; `None` when no client could be built to ask
+    pub base_url: Option<String>,
+    /// The API key with all but its ends hidden, or `None` when it isn't set
+    pub api_key: Option<String>,
+    pub projects_dir: PathBuf,
+    pub response_cache_max_mb: u32,
+    pub response_cache_ttl_secs: u64,
+    pub project_cache_ttl_secs: u64,
+}
+
+impl ConfigInfo {
+    /// Describe a config, using the model and URL of the client built from it if any
+    pub fn new(config: &Config, client: Option<&dyn ai::AiClient>) -> Self {
+        Self {
+            provider: config.provider.clone(),
+            model: client.map_or_else(|| config.model.clone(), |client| client.model_version().to_string()),
+            base_url: client.map(|client| client.base_url().to_string()).or_else(|| config.base_url.clone()),
+            api_key: (!config.api_key.is_empty()).then(|| config::mask_api_key(&config.api_key)),
+            projects_dir: config.projects_dir.clone(),
+            response_cache_max_mb: config.max_cache_size_mb,
+            response_cache_ttl_secs: config.response_cache_ttl,
+            project_cache_ttl_secs: config.project_cache_ttl,
+        }
+    }
+}
+
+/// Optional cargo features this build was compiled with
+pub fn compiled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "pdf") {
+        features.push("pdf");
+    }
+    if cfg!(feature = "test-utils") {
+        features.push("test-utils");
+    }
+    features
+}
+
+/// Gather the details of this installation
+///
+/// A config that can't be loaded is reported rather than returned as an
+/// error, since that is often what the user needs help with.
+///
+/// # Errors
+///
+/// Returns an error only if the config directory can't be found or created.
+pub async fn collect() -> Result<ToolkitInfo> {
+    let config_dir = config::get_config_dir()?;
+
+    let (config, config_error) = match config::get_config() {
+        Ok(config) => {
+            // Building a client needs a key, but sends nothing
+            let client = ai::get_client().await.ok();
+            (Some(ConfigInfo::new(&config, client.as_deref())), None)
+        }
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    Ok(ToolkitInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: compiled_features(),
+        config_file: config_dir.join("config.toml"),
+        templates_dir: config_dir.join("templates"),
+        config_dir,
+        config,
+        config_error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_info_masks_the_key() {
+        let config = Config {
+            api_key: "sk-ant-1234567890abcdef".to_string(),
+            ..Config::default()
+        };
+        let info = ConfigInfo::new(&config, None);
+        assert_eq!(info.api_key.as_deref(), Some("sk-a...cdef"));
+        assert_eq!(info.model, config.model);
+        assert_eq!(info.base_url, config.base_url);
+
+        assert!(!serde_json::to_string(&info).unwrap().contains("1234567890"));
+
+        let info = ConfigInfo::new(&Config::default(), None);
+        assert_eq!(info.api_key, None);
+    }
+}
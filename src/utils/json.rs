@@ -0,0 +1,85 @@
+//! Pulling JSON out of AI responses.
+//!
+//! Models asked for JSON often wrap it in a markdown code fence, introduce it
+//! with a sentence or follow it with an explanation. [`extract_json`] finds the
+//! first complete JSON object or array in such a response, so the structured
+//! part is still usable when the text around it isn't.
+
+use crate::error::{Result, ToolkitError};
+use serde_json::Value;
+
+/// The body of the first markdown code fence in `text`, if it has one
+fn fenced_block(text: &str) -> Option<&str> {
+    let start = text.find("```")?;
+    let after = &text[start + 3..];
+    // Skip the language tag, e.g. ```json
+    let body = &after[after.find('\n')? + 1..];
+    let end = body.find("```").unwrap_or(body.len());
+    Some(&body[..end])
+}
+
+/// Parse the first complete JSON object or array starting anywhere in `text`
+///
+/// Trailing content after the balanced value is ignored. Openers that don't
+/// start valid JSON, like braces in prose, are skipped.
+fn first_json_value(text: &str) -> Option<Value> {
+    text.match_indices(['{', '['])
+        .find_map(|(start, _)| {
+            serde_json::Deserializer::from_str(&text[start..])
+                .into_iter::<Value>()
+                .next()
+                .and_then(|value| value.ok())
+        })
+}
+
+/// Extract the JSON value from an AI response
+///
+/// The whole response is parsed first. Failing that, the first markdown code
+/// fence is searched, then the whole text, for the first balanced `{...}` or
+/// `[...]` block.
+///
+/// # Errors
+///
+/// Returns a `Parse` error, with the error from parsing the whole response,
+/// if no JSON object or array can be found.
+pub fn extract_json(text: &str) -> Result<Value> {
+    let error = match serde_json::from_str(text.trim()) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    fenced_block(text)
+        .and_then(first_json_value)
+        .or_else(|| first_json_value(text))
+        .ok_or_else(|| ToolkitError::Parse(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_json_from_fenced_block() {
+        let text = "Here are the stories:\n```json\n{\"stories\": [1, 2]}\n```\nLet me know if you need more.";
+        assert_eq!(extract_json(text).unwrap(), json!({"stories": [1, 2]}));
+
+        let bare_fence = "```\n[\"a\", \"b\"]\n```";
+        assert_eq!(extract_json(bare_fence).unwrap(), json!(["a", "b"]));
+    }
+
+    #[test]
+    fn test_extract_json_from_prose() {
+        let text = "Sure! Using {placeholders} as asked: {\"name\": \"a } in a string\", \"n\": 1} Hope this helps [1].";
+        assert_eq!(extract_json(text).unwrap(), json!({"name": "a } in a string", "n": 1}));
+
+        assert_eq!(extract_json("  {\"ok\": true}\n").unwrap(), json!({"ok": true}));
+        assert_eq!(extract_json("42").unwrap(), json!(42));
+    }
+
+    #[test]
+    fn test_extract_json_without_json_fails() {
+        assert!(matches!(extract_json("No JSON here"), Err(ToolkitError::Parse(_))));
+        assert!(matches!(extract_json("Cut off: {\"stories\": [1,"), Err(ToolkitError::Parse(_))));
+    }
+}
@@ -0,0 +1,181 @@
+//! Splitting Markdown documents into `##` sections and fenced code blocks.
+//!
+//! Stage outputs are Markdown documents with a `##` heading per topic.
+//! [`sections`] finds where each one starts and ends, so [`replace_section`]
+//! can swap a single section while the rest of the document stays
+//! byte-for-byte the same. [`code_blocks`] finds the fenced blocks, such as
+//! diagrams, that are worth saving on their own.
+
+use std::ops::Range;
+
+/// A `##` section of a Markdown document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Heading text without the `##` marker
+    pub title: String,
+    /// Byte range of the section, from its heading up to the next `#` or `##` heading
+    pub range: Range<usize>,
+}
+
+/// A fenced code block of a Markdown document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// Language named after the opening fence, lowercased, e.g. `mermaid`; empty if none
+    pub language: String,
+    /// Lines between the fences
+    pub content: String,
+}
+
+/// Level and text of an ATX heading line such as `## Timeline`
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[level..];
+    if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        Some((level, rest.trim().trim_end_matches('#').trim_end()))
+    } else {
+        None
+    }
+}
+
+/// Find the `##` sections of a document, in order
+///
+/// A section runs until the next heading of level one or two, so `###`
+/// subsections belong to the section above them. Text before the first `##`
+/// heading is in no section, and headings inside fenced code blocks are
+/// ignored.
+pub fn sections(text: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut open: Option<(String, usize)> = None;
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        if let Some((level @ 1..=2, title)) = heading(line.trim_end()) {
+            if let Some((title, section_start)) = open.take() {
+                sections.push(Section { title, range: section_start..start });
+            }
+            if level == 2 {
+                open = Some((title.to_string(), start));
+            }
+        }
+    }
+
+    if let Some((title, start)) = open {
+        sections.push(Section { title, range: start..text.len() });
+    }
+    sections
+}
+
+/// Compare headings ignoring case and a leading number such as `3.`
+fn normalize_title(title: &str) -> String {
+    title
+        .trim()
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches(['.', ')'])
+        .trim()
+        .to_lowercase()
+}
+
+/// Find the section with the given heading
+///
+/// Headings match ignoring case, so `timeline` finds `## 5. Timeline`.
+pub fn find_section(text: &str, title: &str) -> Option<Section> {
+    let wanted = normalize_title(title.trim_start_matches('#'));
+    sections(text)
+        .into_iter()
+        .find(|section| section.title.eq_ignore_ascii_case(title.trim()) || normalize_title(&section.title) == wanted)
+}
+
+/// Replace a section of `text` with `replacement`, keeping everything around it
+pub fn replace_section(text: &str, section: &Section, replacement: &str) -> String {
+    let mut result = String::with_capacity(text.len() + replacement.len());
+    result.push_str(&text[..section.range.start]);
+    result.push_str(replacement);
+    result.push_str(&text[section.range.end..]);
+    result
+}
+
+/// Find the fenced code blocks of a document, in order
+///
+/// Only backtick fences are recognized. A block left open runs to the end
+/// of the document.
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut open: Option<CodeBlock> = None;
+
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut open, fence) {
+            (None, Some(info)) => {
+                let language = info.split_whitespace().next().unwrap_or_default().to_lowercase();
+                open = Some(CodeBlock { language, content: String::new() });
+            }
+            (Some(_), Some(_)) => blocks.extend(open.take()),
+            (Some(block), None) => {
+                block.content.push_str(line);
+                block.content.push('\n');
+            }
+            (None, None) => {}
+        }
+    }
+
+    blocks.extend(open);
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = "# Plan\n\nIntro text.\n\n## 1. Overview\n\nA todo app.\n\n### Goals\n\n- Fast\n\n## Timeline\n\n```markdown\n## Not a heading\n```\nTwo weeks.\n\n## Risks\nNone.";
+
+    #[test]
+    fn test_sections() {
+        let sections = sections(PLAN);
+        let titles: Vec<&str> = sections.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, vec!["1. Overview", "Timeline", "Risks"]);
+
+        assert_eq!(&PLAN[sections[0].range.clone()], "## 1. Overview\n\nA todo app.\n\n### Goals\n\n- Fast\n\n");
+        assert_eq!(&PLAN[sections[1].range.clone()], "## Timeline\n\n```markdown\n## Not a heading\n```\nTwo weeks.\n\n");
+        assert_eq!(&PLAN[sections[2].range.clone()], "## Risks\nNone.");
+
+        assert!(heading("##Timeline").is_none());
+        assert_eq!(heading("## Timeline ##"), Some((2, "Timeline")));
+    }
+
+    #[test]
+    fn test_replace_section_keeps_the_rest() {
+        let section = find_section(PLAN, "overview").unwrap();
+        assert_eq!(section.title, "1. Overview");
+
+        let replaced = replace_section(PLAN, &section, "## 1. Overview\n\nA better todo app.\n\n");
+        assert_eq!(replaced, PLAN.replace("A todo app.\n\n### Goals\n\n- Fast\n\n", "A better todo app.\n\n"));
+
+        let risks = find_section(PLAN, "## RISKS").unwrap();
+        assert!(replace_section(PLAN, &risks, "## Risks\nSome.").ends_with("Two weeks.\n\n## Risks\nSome."));
+
+        assert!(find_section(PLAN, "Budget").is_none());
+    }
+
+    #[test]
+    fn test_code_blocks() {
+        let blocks = code_blocks(PLAN);
+        assert_eq!(blocks, vec![CodeBlock { language: "markdown".to_string(), content: "## Not a heading\n".to_string() }]);
+
+        let blocks = code_blocks("```Mermaid title\ngraph TD\n  A-->B\n```\ntext\n```\nplain");
+        assert_eq!(blocks[0].language, "mermaid");
+        assert_eq!(blocks[0].content, "graph TD\n  A-->B\n");
+        assert_eq!(blocks[1], CodeBlock { language: String::new(), content: "plain\n".to_string() });
+    }
+}
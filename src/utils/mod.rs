@@ -1,8 +1,19 @@
 pub mod project;
+pub mod related;
 pub mod rate_limiter;
+pub mod scaffold;
 pub mod cache;
+pub mod cancel;
 pub mod file;
+pub mod json;
+pub mod markdown;
+pub mod export;
+pub mod info;
+pub mod notify;
 pub mod ui;
+pub mod stats;
+pub mod tokens;
+pub mod watch;
 
 /// Logging utilities for consistent output formatting
 pub mod logging {
@@ -0,0 +1,98 @@
+//! Webhook notifications when a stage finishes.
+//!
+//! When `webhook_url` is set in the config, a small JSON payload is POSTed to
+//! it after each stage run. The payload is the same for every endpoint, so it
+//! works with anything that accepts JSON: a Slack or Discord relay, a CI
+//! hook, or a script of your own.
+
+use crate::config;
+use log::{debug, warn};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long to wait for the webhook before giving up
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+static NOTIFICATIONS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn webhook notifications on or off for this process (`--no-notify`)
+pub fn set_notifications_enabled(enabled: bool) {
+    NOTIFICATIONS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// The JSON body sent to the webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct StageNotification {
+    /// Project ID
+    pub project: String,
+    pub stage: u8,
+    /// `completed` or `failed`
+    pub status: &'static str,
+    /// Why the stage failed, if it did
+    pub message: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Post a stage notification to the configured webhook
+///
+/// Does nothing when no webhook is configured or notifications are turned
+/// off. Failures are logged as warnings and never fail the run.
+pub async fn notify_stage_finished(notification: &StageNotification) {
+    if !NOTIFICATIONS_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let url = match config::get_config() {
+        Ok(config) => match config.webhook_url.filter(|url| !url.is_empty()) {
+            Some(url) => url,
+            None => return,
+        },
+        Err(e) => {
+            warn!("Not sending stage notification, failed to load config: {}", e);
+            return;
+        }
+    };
+
+    let client = match crate::ai::build_http_client(WEBHOOK_TIMEOUT) {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Not sending stage notification: {}", e);
+            return;
+        }
+    };
+
+    debug!("Notifying {} that stage {} {}", url, notification.stage, notification.status);
+    match client.post(&url).json(notification).send().await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("Stage notification to {} was rejected: {}", url, response.status()),
+        Err(e) => warn!("Failed to send stage notification to {}: {}", url, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_payload() {
+        let notification = StageNotification {
+            project: "proj_1".to_string(),
+            stage: 2,
+            status: "failed",
+            message: Some("timed out".to_string()),
+            duration_ms: 1500,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "project": "proj_1",
+                "stage": 2,
+                "status": "failed",
+                "message": "timed out",
+                "duration_ms": 1500,
+            })
+        );
+    }
+}
@@ -1,19 +1,22 @@
 // Remove unused import
 use crate::error::{Result, ToolkitError};
-use crate::models::Project;
+use crate::models::{Project, ProjectEmbedding};
 use crate::utils::cache;
 use colored::Colorize;
 use crate::config::ColorizeExt;
 use std::fs;
+use std::io::IsTerminal;
 use std::env;
 use std::path::Path;
 use log::{debug, error, info, warn};
 use tokio::fs as tokio_fs;
-use futures::future::{self};
-use futures::stream::{StreamExt};
+use futures::stream::{self, StreamExt};
 use std::time::{Instant, Duration, SystemTime};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use fs2::FileExt;
+use serde::Serialize;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 
 /// Validates a project ID to prevent injection attacks
 pub fn validate_project_id(project_id: &str) -> Result<()> {
@@ -28,7 +31,77 @@ pub fn validate_project_id(project_id: &str) -> Result<()> {
     Ok(())
 }
 
+/// Name of the lock file that serializes writes to a project
+pub const PROJECT_LOCK_FILE: &str = "project.json.lock";
+
+/// Name of the file a project is written to before it replaces `project.json`
+///
+/// Renaming a file within a directory is atomic, so a save interrupted part
+/// way leaves the previous `project.json` intact instead of truncated. Only
+/// written while the project is locked.
+pub const PROJECT_TEMP_FILE: &str = "project.json.tmp";
+
+/// How long a save waits for another process to release a project
+const PROJECT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An exclusive advisory lock on a project directory
+///
+/// Held while a project is written so two processes saving the same project
+/// can't clobber each other. Reading a project doesn't take the lock. The lock
+/// is released when the guard is dropped.
+pub struct ProjectLock {
+    file: fs::File,
+}
+
+impl ProjectLock {
+    /// Lock the project in `project_dir`, waiting up to the default timeout
+    pub fn acquire(project_dir: &Path) -> Result<Self> {
+        Self::acquire_with_timeout(project_dir, PROJECT_LOCK_TIMEOUT)
+    }
+
+    fn acquire_with_timeout(project_dir: &Path, timeout: Duration) -> Result<Self> {
+        fs::create_dir_all(project_dir)
+            .map_err(|e| ToolkitError::Io(format!("Failed to create project directory: {}", e)))?;
+
+        let lock_path = project_dir.join(PROJECT_LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+
+        let started = Instant::now();
+        while file.try_lock_exclusive().is_err() {
+            if started.elapsed() >= timeout {
+                return Err(ToolkitError::ProjectLocked(format!(
+                    "could not lock {} within {} seconds",
+                    lock_path.display(),
+                    timeout.as_secs()
+                )));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        debug!("Locked project at {}", project_dir.display());
+        Ok(Self { file })
+    }
+}
+
+impl Drop for ProjectLock {
+    fn drop(&mut self) {
+        if let Err(e) = FileExt::unlock(&self.file) {
+            warn!("Failed to release project lock: {}", e);
+        }
+    }
+}
+
 pub fn save_project(project: &Project) -> Result<()> {
+    let _lock = ProjectLock::acquire(&project.path)?;
+    write_project_file(project)
+}
+
+/// Write `project.json`; the caller must hold the project's lock
+fn write_project_file(project: &Project) -> Result<()> {
     // Serialize the project to JSON
     let json = serde_json::to_string_pretty(project)
         .map_err(|e| ToolkitError::Serialization(e.to_string()))?;
@@ -39,8 +112,10 @@ pub fn save_project(project: &Project) -> Result<()> {
     
     // Write the project file
     let project_file = project.path.join("project.json");
+    let temp_file = project.path.join(PROJECT_TEMP_FILE);
     debug!("Saving project file to: {}", project_file.display());
-    fs::write(project_file, json)?;
+    fs::write(&temp_file, json)?;
+    fs::rename(&temp_file, &project_file)?;
     
     // No need to manually update the cache, the cache module handles this
     
@@ -48,6 +123,106 @@ pub fn save_project(project: &Project) -> Result<()> {
     Ok(())
 }
 
+/// Save a single stage of a project without clobbering the others
+///
+/// The project file is re-read from disk and only the given stage is replaced
+/// before writing it back, so stages running concurrently don't overwrite each
+/// other's results.
+pub fn save_stage(project: &Project, stage_number: u8) -> Result<()> {
+    let stage = project
+        .get_stage(stage_number)
+        .cloned()
+        .ok_or(ToolkitError::StageNotFound(stage_number))?;
+
+    update_project_file(project, |merged| {
+        match merged.stages.iter_mut().find(|s| s.number == stage_number) {
+            Some(existing) => *existing = stage,
+            None => {
+                merged.stages.push(stage);
+                merged.stages.sort_by_key(|s| s.number);
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Merge values into a project's saved stage context
+///
+/// Like [`save_stage`], the project file is re-read before writing, so values
+/// saved by stages running concurrently are kept. An empty value removes the
+/// key.
+pub fn save_context(project: &Project, values: &HashMap<String, String>) -> Result<()> {
+    update_project_file(project, |merged| {
+        for (key, value) in values {
+            if value.is_empty() {
+                merged.context.remove(key);
+            } else {
+                merged.context.insert(key.clone(), value.clone());
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Save the embedding cached for a project
+pub fn save_embedding(project: &Project, embedding: ProjectEmbedding) -> Result<()> {
+    update_project_file(project, |merged| merged.embedding = Some(embedding))?;
+    Ok(())
+}
+
+/// Re-read a project's file, apply `update` and write it back
+///
+/// Writes are serialized behind the project's file lock, held across the read
+/// so nobody can save in between. The cache is refreshed with the merged
+/// project, which is returned.
+pub(crate) fn update_project_file(project: &Project, update: impl FnOnce(&mut Project)) -> Result<Project> {
+    let _lock = ProjectLock::acquire(&project.path)?;
+
+    let project_file = project.path.join("project.json");
+    let mut merged = if project_file.exists() {
+        let json = fs::read_to_string(&project_file)?;
+        let mut on_disk: Project = serde_json::from_str(&json)
+            .map_err(|e| ToolkitError::Serialization(e.to_string()))?;
+        on_disk.path = project.path.clone();
+        on_disk
+    } else {
+        project.clone()
+    };
+
+    update(&mut merged);
+    merged.updated_at = project.updated_at.max(merged.updated_at);
+
+    write_project_file(&merged)?;
+    // The cache mutex is only held for the insert, never while waiting on the lock
+    cache::PROJECT_CACHE.lock().unwrap().insert_project(merged.clone());
+
+    Ok(merged)
+}
+
+/// Run blocking project I/O on tokio's blocking thread pool
+///
+/// Reads and writes of project files take the project cache mutex and the
+/// project's file lock, either of which can wait, so async code runs them
+/// here rather than on the runtime's worker threads.
+pub(crate) async fn spawn_io<T: Send + 'static>(io: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(io)
+        .await
+        .map_err(|e| ToolkitError::Unknown(format!("Project I/O task failed: {}", e)))?
+}
+
+/// Async version of [`save_stage`] for async contexts
+pub async fn save_stage_async(project: &Project, stage_number: u8) -> Result<()> {
+    let project = project.clone();
+    spawn_io(move || save_stage(&project, stage_number)).await
+}
+
+/// Async version of [`save_context`] for async contexts
+pub async fn save_context_async(project: &Project, values: &HashMap<String, String>) -> Result<()> {
+    let project = project.clone();
+    let values = values.clone();
+    spawn_io(move || save_context(&project, &values)).await
+}
+
 /// Async version of save_project for async contexts
 pub async fn save_project_async(project: &Project) -> Result<()> {
     // Validate project ID
@@ -59,16 +234,22 @@ pub async fn save_project_async(project: &Project) -> Result<()> {
         tokio_fs::create_dir_all(&project.path).await?;
     }
     
+    // Waiting for the lock blocks, so keep it off the async runtime
+    let project_dir = project.path.clone();
+    let _lock = spawn_io(move || ProjectLock::acquire(&project_dir)).await?;
+    
     // Convert the project to JSON
     let json = serde_json::to_string_pretty(project).map_err(|e| {
         error!("Failed to serialize project to JSON: {}", e);
         ToolkitError::Serialization(e.to_string())
     })?;
     
-    // Save the project file
+    // Save the project file, through a temporary file like `write_project_file`
     let project_file = project.path.join("project.json");
+    let temp_file = project.path.join(PROJECT_TEMP_FILE);
     debug!("Saving project file to: {}", project_file.display());
-    tokio_fs::write(project_file, json).await?;
+    tokio_fs::write(&temp_file, json).await?;
+    tokio_fs::rename(&temp_file, &project_file).await?;
     
     // Update the cache
     {
@@ -93,11 +274,9 @@ pub async fn load_project_async(project_id: &str) -> Result<Project> {
     // Validate project ID
     validate_project_id(project_id)?;
     
-    // Use tokio spawn_blocking to avoid blocking the async runtime with synchronous file I/O
+    // A cache miss reads from disk, so keep it off the async runtime
     let project_id = project_id.to_string(); // Clone the string to move into the closure
-    tokio::task::spawn_blocking(move || {
-        crate::utils::cache::get_cached_project(&project_id)
-    }).await.map_err(|e| ToolkitError::Unknown(e.to_string()))?
+    spawn_io(move || crate::utils::cache::get_cached_project(&project_id)).await
 }
 
 /// Internal function to load a project directly from disk
@@ -185,6 +364,16 @@ pub(crate) fn load_project_internal(project_id: &str) -> Result<Project> {
     // Ensure the path is set correctly
     project.path = project_dir;
     
+    // Projects created by older versions may lack newer stages
+    project.ensure_default_stages();
+    
+    // Give user-defined stages an entry so they show up in status and resume
+    if let Ok(config) = crate::config::get_config() {
+        for stage in &config.custom_stages {
+            project.ensure_stage(stage.number, &stage.name, &stage.description);
+        }
+    }
+    
     info!("Project loaded successfully: {}", project.id);
     Ok(project)
 }
@@ -273,38 +462,36 @@ fn search_for_project_in_directory(dir: &Path, project_id: &str) -> Result<Optio
 
 /// Async version of search_for_project_in_directory
 async fn search_for_project_in_directory_async(dir: &Path, project_id: &str) -> Result<Option<std::path::PathBuf>> {
-    // Check the cache first
-    {
-        let cache = cache::PROJECT_CACHE.lock().unwrap();
-        if let Some(project_ids) = cache.get_projects_in_dir(dir) {
-            if project_ids.contains(&project_id.to_string()) {
-                // We know the ID exists in this dir, now find the actual path
-                let mut entries = tokio_fs::read_dir(dir).await?;
-                while let Some(entry) = entries.next_entry().await? {
-                    let path = entry.path();
+    // Check the cache first, releasing the lock before reading anything
+    let cached_ids = cache::PROJECT_CACHE.lock().unwrap().get_projects_in_dir(dir).cloned();
+    if let Some(project_ids) = cached_ids {
+        if project_ids.contains(&project_id.to_string()) {
+            // We know the ID exists in this dir, now find the actual path
+            let mut entries = tokio_fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                
+                if tokio_fs::metadata(&path).await?.is_dir() {
+                    let potential_project_file = path.join("project.json");
                     
-                    if tokio_fs::metadata(&path).await?.is_dir() {
-                        let potential_project_file = path.join("project.json");
-                        
-                        if tokio_fs::try_exists(&potential_project_file).await? {
-                            debug!("Found potential project file: {}", potential_project_file.display());
-                            // Read the project file to check the ID
-                            if let Ok(json) = tokio_fs::read_to_string(&potential_project_file).await {
-                                if let Ok(project) = serde_json::from_str::<Project>(&json) {
-                                    if project.id == project_id {
-                                        debug!("Project ID matches: {}", project_id);
-                                        return Ok(Some(path));
-                                    }
+                    if tokio_fs::try_exists(&potential_project_file).await? {
+                        debug!("Found potential project file: {}", potential_project_file.display());
+                        // Read the project file to check the ID
+                        if let Ok(json) = tokio_fs::read_to_string(&potential_project_file).await {
+                            if let Ok(project) = serde_json::from_str::<Project>(&json) {
+                                if project.id == project_id {
+                                    debug!("Project ID matches: {}", project_id);
+                                    return Ok(Some(path));
                                 }
                             }
                         }
                     }
                 }
-            } else {
-                // Project ID not in cache for this directory
-                debug!("Project ID not found in directory cache: {}", project_id);
-                return Ok(None);
             }
+        } else {
+            // Project ID not in cache for this directory
+            debug!("Project ID not found in directory cache: {}", project_id);
+            return Ok(None);
         }
     }
     
@@ -359,45 +546,256 @@ async fn search_for_project_in_directory_async(dir: &Path, project_id: &str) ->
     Ok(result_path)
 }
 
-pub fn list_projects() -> Result<()> {
-    let projects = get_all_projects()?;
+/// Which projects `list` shows
+#[derive(Debug, Clone, Default)]
+pub struct ProjectFilter {
+    /// Show archived projects as well
+    pub include_archived: bool,
+    /// Only projects created at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only projects created at or before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl ProjectFilter {
+    /// Check whether a project was created within the filter's dates
+    pub fn in_range(&self, project: &Project) -> bool {
+        self.since.is_none_or(|since| project.created_at >= since)
+            && self.until.is_none_or(|until| project.created_at <= until)
+    }
+}
+
+/// Parse a date given on the command line
+///
+/// Accepts an RFC 3339 timestamp or a `YYYY-MM-DD` date in UTC. A plain date
+/// means the start of that day, or its end when `end_of_day` is set, so that
+/// `--until 2024-05-01` includes projects created on the 1st.
+pub fn parse_date(input: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&Utc));
+    }
     
-    println!("{:-^50}", " Projects ".green());
-    println!("{:<15} | {:<30}", "ID".cyan(), "Name".cyan());
-    println!("{:-<50}", "".dimmed());
+    let date = NaiveDate::parse_from_str(input, "%Y-%m-%d").map_err(|_| {
+        ToolkitError::InvalidInput(format!(
+            "Invalid date '{}': use YYYY-MM-DD or an RFC 3339 timestamp such as 2024-05-01T12:00:00Z",
+            input
+        ))
+    })?;
     
-    if projects.is_empty() {
-        println!("{}", "No projects found.".yellow());
+    let start = date.and_time(NaiveTime::MIN).and_utc();
+    if end_of_day {
+        Ok(start + chrono::Duration::days(1) - chrono::Duration::nanoseconds(1))
     } else {
-        for project in projects {
-            println!("{:<15} | {:<30}", project.id.yellow(), project.name);
-        }
+        Ok(start)
     }
-    
-    println!("{:-<50}", "".dimmed());
-    
+}
+
+pub fn list_projects(filter: &ProjectFilter) -> Result<()> {
+    let projects = get_all_projects()?;
+    print_project_list(&projects, filter);
     Ok(())
 }
 
 /// Async version of list_projects
-pub async fn list_projects_async() -> Result<()> {
+pub async fn list_projects_async(filter: &ProjectFilter) -> Result<()> {
     let projects = get_all_projects_async().await?;
-    
+    print_project_list(&projects, filter);
+    Ok(())
+}
+
+/// Print the project table, dimming archived projects or leaving them out
+fn print_project_list(projects: &[Project], filter: &ProjectFilter) {
     println!("{:-^50}", " Projects ".green());
     println!("{:<15} | {:<30}", "ID".cyan(), "Name".cyan());
     println!("{:-<50}", "".dimmed());
     
-    if projects.is_empty() {
+    let in_range: Vec<&Project> = projects.iter().filter(|project| filter.in_range(project)).collect();
+    let visible: Vec<&Project> = in_range
+        .iter()
+        .copied()
+        .filter(|project| filter.include_archived || !project.archived)
+        .collect();
+    let hidden = in_range.len() - visible.len();
+    
+    if visible.is_empty() {
         println!("{}", "No projects found.".yellow());
     } else {
-        for project in projects {
-            println!("{:<15} | {:<30}", project.id.yellow(), project.name);
+        for project in visible {
+            if project.archived {
+                println!("{}", format!("{:<15} | {:<30} (archived)", project.id, project.name).dimmed());
+            } else {
+                println!("{:<15} | {:<30}", project.id.yellow(), project.name);
+            }
         }
     }
     
+    if hidden > 0 {
+        println!("{}", format!("{} archived project(s) hidden. Use --all to show them.", hidden).dimmed());
+    }
+    
     println!("{:-<50}", "".dimmed());
+}
+
+/// The projects offered by [`pick_project`]: unarchived, each once, most recently updated first
+pub fn picker_choices(projects: &[Project]) -> Vec<&Project> {
+    let mut seen = std::collections::HashSet::new();
+    let mut choices: Vec<&Project> = projects
+        .iter()
+        .filter(|project| !project.archived && seen.insert(project.id.as_str()))
+        .collect();
+    choices.sort_by_key(|project| std::cmp::Reverse(project.updated_at));
+    choices
+}
+
+/// Ask the user to choose a project, for commands run without `--project`
+///
+/// Only offered in an interactive terminal; elsewhere `--project` is required.
+pub async fn pick_project() -> Result<String> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(ToolkitError::InvalidInput(
+            "No project given. Pass --project when not running in an interactive terminal.".to_string(),
+        ));
+    }
     
-    Ok(())
+    let projects = get_all_projects_async().await?;
+    let choices = picker_choices(&projects);
+    if choices.is_empty() {
+        return Err(ToolkitError::InvalidInput(
+            "There are no projects to choose from. Create one with `init` first.".to_string(),
+        ));
+    }
+    
+    let labels: Vec<String> = choices.iter().map(|project| format!("{} ({})", project.name, project.id)).collect();
+    let _waiting = super::cancel::waiting_for_input();
+    let index = dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
+        .with_prompt("Select a project")
+        .default(0)
+        .items(&labels)
+        .interact()
+        .map_err(|e| ToolkitError::InvalidInput(format!("Selection error: {}", e)))?;
+    
+    Ok(choices[index].id.clone())
+}
+
+/// Archive or unarchive a project
+///
+/// The project is saved and the cache updated so the change shows up straight
+/// away in listings.
+pub fn set_archived(project_id: &str, archived: bool) -> Result<Project> {
+    let project = load_project(project_id)?;
+    
+    if project.archived == archived {
+        return Ok(project);
+    }
+    
+    let project = update_project_file(&project, |merged| {
+        merged.archived = archived;
+        merged.updated_at = chrono::Utc::now();
+    })?;
+    info!("Project {} {}", project_id, if archived { "archived" } else { "unarchived" });
+    
+    Ok(project)
+}
+
+/// Set and remove a project's template variables
+///
+/// Values in `set` are applied before the keys in `unset` are removed. The
+/// project is saved and the cache updated.
+pub fn update_variables(project_id: &str, set: Vec<(String, String)>, unset: &[String]) -> Result<Project> {
+    let project = load_project(project_id)?;
+    
+    if set.is_empty() && unset.is_empty() {
+        return Ok(project);
+    }
+    
+    let project = update_project_file(&project, |merged| {
+        merged.variables.extend(set);
+        for key in unset {
+            merged.variables.remove(key);
+        }
+        merged.updated_at = chrono::Utc::now();
+    })?;
+    info!("Updated the variables of project {}", project_id);
+    
+    Ok(project)
+}
+
+/// Rename a project, optionally giving it a new ID as well
+///
+/// Changing the ID also renames the project directory when it is named after
+/// the old ID, and drops the old ID and directory scans from the cache so the
+/// project isn't found under its old ID any more.
+///
+/// # Parameters
+///
+/// * `project_id` - The current ID of the project.
+/// * `new_name` - The new display name.
+/// * `new_id` - The new ID, if it should change.
+///
+/// # Returns
+///
+/// The renamed project.
+pub fn rename_project(project_id: &str, new_name: &str, new_id: Option<&str>) -> Result<Project> {
+    let new_name = new_name.trim();
+    if new_name.is_empty() {
+        return Err(ToolkitError::InvalidInput("Project name cannot be empty".to_string()));
+    }
+    
+    let mut project = load_project(project_id)?;
+    let old_path = project.path.clone();
+    
+    if let Some(new_id) = new_id.filter(|id| *id != project.id) {
+        validate_project_id(new_id)?;
+        if get_all_projects()?.iter().any(|other| other.id == new_id) {
+            return Err(ToolkitError::InvalidInput(format!(
+                "A project with ID '{}' already exists",
+                new_id
+            )));
+        }
+        
+        // Directories named after the ID follow it
+        if project.path.file_name().is_some_and(|name| name == project.id.as_str()) {
+            let new_path = project.path.with_file_name(new_id);
+            if new_path.exists() {
+                return Err(ToolkitError::InvalidInput(format!(
+                    "Cannot move the project to {}: it already exists",
+                    new_path.display()
+                )));
+            }
+            
+            fs::rename(&project.path, &new_path)
+                .map_err(|e| ToolkitError::Io(format!("Failed to rename project directory: {}", e)))?;
+            project.path = new_path;
+        }
+        
+        {
+            let mut cache = cache::PROJECT_CACHE.lock().unwrap();
+            cache.invalidate_project(&project.id);
+            cache.invalidate_dir_scans();
+        }
+        
+        info!("Changing project ID from {} to {}", project.id, new_id);
+        project.id = new_id.to_string();
+    }
+    
+    let project = update_project_file(&project, |merged| {
+        // Keep artifact paths pointing inside the moved directory
+        if merged.path != old_path {
+            for stage in &mut merged.stages {
+                for artifact in &mut stage.artifacts {
+                    if let Ok(relative) = artifact.path.strip_prefix(&old_path) {
+                        artifact.path = merged.path.join(relative);
+                    }
+                }
+            }
+        }
+        merged.id = project.id.clone();
+        merged.name = new_name.to_string();
+        merged.updated_at = chrono::Utc::now();
+    })?;
+    
+    info!("Project {} renamed to {}", project.id, project.name);
+    Ok(project)
 }
 
 /// Get all projects from both current directory and configured projects directory
@@ -452,6 +850,92 @@ pub async fn get_all_projects_async() -> Result<Vec<Project>> {
     Ok(projects)
 }
 
+/// Characters of context shown on each side of a search match
+const SEARCH_CONTEXT_CHARS: usize = 40;
+
+/// A project description or stage output that matches a search
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchMatch {
+    pub project_id: String,
+    pub project_name: String,
+    /// The stage whose output matched, or `None` for the project description
+    pub stage: Option<u8>,
+    /// Number of matches in the text
+    pub matches: usize,
+    /// Text leading up to the first match
+    pub before: String,
+    /// The first match
+    pub matched: String,
+    /// Text following the first match
+    pub after: String,
+}
+
+/// Search project descriptions and stage output
+///
+/// Matching is case-insensitive. `query` is a literal string unless `regex`
+/// is set. With `stage`, only that stage's output is searched. Projects found
+/// twice are searched once.
+pub fn search_projects(projects: &[Project], query: &str, regex: bool, stage: Option<u8>) -> Result<Vec<SearchMatch>> {
+    let pattern = if regex { query.to_string() } else { regex::escape(query) };
+    let pattern = regex::RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| ToolkitError::InvalidInput(format!("Invalid search pattern: {}", e)))?;
+    
+    let mut seen = std::collections::HashSet::new();
+    let mut results = Vec::new();
+    
+    for project in projects.iter().filter(|p| seen.insert(p.id.as_str())) {
+        let description = Some((None, project.description.as_str())).filter(|_| stage.is_none());
+        let stages = project
+            .stages
+            .iter()
+            .filter(|s| stage.is_none_or(|number| s.number == number))
+            .filter_map(|s| s.content.as_deref().map(|content| (Some(s.number), content)));
+        
+        for (stage, text) in description.into_iter().chain(stages) {
+            let mut found = pattern.find_iter(text).filter(|m| !m.is_empty());
+            let Some(first) = found.next() else { continue };
+            
+            let before = &text[..first.start()];
+            let skip = before.chars().count().saturating_sub(SEARCH_CONTEXT_CHARS);
+            let after = &text[first.end()..];
+            let truncated = after.chars().count() > SEARCH_CONTEXT_CHARS;
+            
+            results.push(SearchMatch {
+                project_id: project.id.clone(),
+                project_name: project.name.clone(),
+                stage,
+                matches: 1 + found.count(),
+                before: format!("{}{}", if skip > 0 { "..." } else { "" }, flatten(before.chars().skip(skip))),
+                matched: flatten(first.as_str().chars()),
+                after: format!("{}{}", flatten(after.chars().take(SEARCH_CONTEXT_CHARS)), if truncated { "..." } else { "" }),
+            });
+        }
+    }
+    
+    Ok(results)
+}
+
+/// Search every project, see [`search_projects`]
+pub async fn search_all_projects(query: &str, regex: bool, stage: Option<u8>) -> Result<Vec<SearchMatch>> {
+    let projects = get_all_projects_async().await?;
+    search_projects(&projects, query, regex, stage)
+}
+
+/// Collapse runs of whitespace, including newlines, into single spaces
+fn flatten(text: impl Iterator<Item = char>) -> String {
+    let mut flat = String::new();
+    for c in text {
+        if !c.is_whitespace() {
+            flat.push(c);
+        } else if !flat.ends_with(' ') {
+            flat.push(' ');
+        }
+    }
+    flat
+}
+
 // Helper function to collect projects from a directory
 fn collect_projects_from_directory(dir: &Path) -> Result<Vec<Project>> {
     // Check the cache first
@@ -491,41 +975,14 @@ fn collect_projects_from_directory(dir: &Path) -> Result<Vec<Project>> {
         }
     }
     
-    let entries = fs::read_dir(dir)?;
-    let mut projects = Vec::new();
-    let mut project_ids = Vec::new();
-    
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_dir() {
-            let project_file = path.join("project.json");
-            
-            if project_file.exists() {
-                debug!("Found project file: {}", project_file.display());
-                match fs::read_to_string(&project_file) {
-                    Ok(json) => {
-                        match serde_json::from_str::<Project>(&json) {
-                            Ok(mut project) => {
-                                // Ensure the path is set correctly
-                                project.path = path;
-                                project_ids.push(project.id.clone());
-                                projects.push(project);
-                            }
-                            Err(e) => {
-                                warn!("Failed to parse project file {}: {}", project_file.display(), e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Failed to read project file {}: {}", project_file.display(), e);
-                    }
-                }
-            }
-        }
+    let scan = scan_directory(dir, is_projects_dir(dir))?;
+    for broken in &scan.broken {
+        warn!("Skipping project directory {}: {}", broken.path.display(), broken.reason);
     }
     
+    let projects = scan.projects;
+    let project_ids: Vec<String> = projects.iter().map(|project| project.id.clone()).collect();
+    
     // Update the cache with all found project IDs and projects
     {
         let mut cache = cache::PROJECT_CACHE.lock().unwrap();
@@ -540,51 +997,208 @@ fn collect_projects_from_directory(dir: &Path) -> Result<Vec<Project>> {
     Ok(projects)
 }
 
+/// Name of the folder that broken project directories are moved into
+pub const QUARANTINE_DIR: &str = ".corrupt";
+
+/// A directory that looks like a project but can't be loaded
+#[derive(Debug, Clone)]
+pub struct BrokenProject {
+    /// The project directory
+    pub path: PathBuf,
+    /// Why the project couldn't be loaded
+    pub reason: String,
+    /// Whether `clean` may delete the directory
+    ///
+    /// A directory outside the projects directory that lost its `project.json`
+    /// may not be a project at all, so it is only ever moved aside.
+    pub deletable: bool,
+}
+
+/// The projects in a directory, along with the project directories that failed to load
+#[derive(Debug, Default)]
+struct DirectoryScan {
+    projects: Vec<Project>,
+    broken: Vec<BrokenProject>,
+}
+
+/// Read every project directory directly inside `dir`
+///
+/// A subdirectory counts as a project if it has a `project.json`, or if it
+/// lost its `project.json` but still has the lock or temporary file the
+/// toolkit writes next to it. In the configured projects directory, where
+/// every subdirectory is a project, the `idea.md` or `stages` folder that
+/// `init` creates is enough too. Projects whose file can't be read or parsed
+/// are reported as broken instead of being dropped.
+fn scan_directory(dir: &Path, in_projects_dir: bool) -> Result<DirectoryScan> {
+    let mut scan = DirectoryScan::default();
+    
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        
+        if !path.is_dir() || entry.file_name() == QUARANTINE_DIR {
+            continue;
+        }
+        
+        let project_file = path.join("project.json");
+        if !project_file.exists() {
+            let toolkit_files = path.join(PROJECT_LOCK_FILE).exists() || path.join(PROJECT_TEMP_FILE).exists();
+            let init_files = path.join("idea.md").exists() || path.join("stages").is_dir();
+            if toolkit_files || (in_projects_dir && init_files) {
+                scan.broken.push(BrokenProject {
+                    path,
+                    reason: "project.json is missing".to_string(),
+                    deletable: in_projects_dir,
+                });
+            }
+            continue;
+        }
+        
+        debug!("Found project file: {}", project_file.display());
+        let parsed = fs::read_to_string(&project_file)
+            .map_err(|e| format!("failed to read project.json: {}", e))
+            .and_then(|json| {
+                serde_json::from_str::<Project>(&json).map_err(|e| format!("failed to parse project.json: {}", e))
+            });
+        
+        match parsed {
+            Ok(mut project) => {
+                // Ensure the path is set correctly
+                project.path = path;
+                scan.projects.push(project);
+            }
+            Err(reason) => scan.broken.push(BrokenProject { path, reason, deletable: true }),
+        }
+    }
+    
+    Ok(scan)
+}
+
+/// Find project directories that can't be loaded
+///
+/// Scans the current directory and the configured projects directory, the same
+/// places `list` looks, and reports directories with a missing or unparseable
+/// `project.json`.
+pub fn find_broken_projects() -> Result<Vec<BrokenProject>> {
+    let current_dir = env::current_dir()?;
+    let config = crate::config::get_config()?;
+    let in_projects_dir = same_directory(&current_dir, &config.projects_dir);
+    let mut dirs = vec![(current_dir, in_projects_dir)];
+    if config.projects_dir.exists() && !in_projects_dir {
+        dirs.push((config.projects_dir, true));
+    }
+    
+    let mut broken = Vec::new();
+    for (dir, in_projects_dir) in dirs {
+        debug!("Scanning for broken projects in: {}", dir.display());
+        broken.append(&mut scan_directory(&dir, in_projects_dir)?.broken);
+    }
+    
+    Ok(broken)
+}
+
+/// Check whether `dir` is the configured projects directory
+fn is_projects_dir(dir: &Path) -> bool {
+    crate::config::get_config().is_ok_and(|config| same_directory(dir, &config.projects_dir))
+}
+
+/// Check whether two paths point at the same directory
+fn same_directory(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Move a broken project directory into the `.corrupt` folder next to it
+///
+/// The directory keeps its name unless the quarantine folder already holds one
+/// with that name, in which case a timestamp is appended.
+///
+/// # Returns
+///
+/// The new location of the directory.
+pub fn quarantine_project(broken: &BrokenProject) -> Result<PathBuf> {
+    let parent = broken.path.parent().ok_or_else(|| {
+        ToolkitError::InvalidInput(format!("Cannot quarantine {}: it has no parent directory", broken.path.display()))
+    })?;
+    let name = broken.path.file_name().ok_or_else(|| {
+        ToolkitError::InvalidInput(format!("Cannot quarantine {}: it has no directory name", broken.path.display()))
+    })?;
+    
+    let quarantine_dir = parent.join(QUARANTINE_DIR);
+    fs::create_dir_all(&quarantine_dir)
+        .map_err(|e| ToolkitError::Io(format!("Failed to create {}: {}", quarantine_dir.display(), e)))?;
+    
+    let mut target = quarantine_dir.join(name);
+    if target.exists() {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        target = quarantine_dir.join(format!("{}-{}", name.to_string_lossy(), timestamp));
+    }
+    
+    fs::rename(&broken.path, &target)
+        .map_err(|e| ToolkitError::Io(format!("Failed to move {}: {}", broken.path.display(), e)))?;
+    
+    info!("Quarantined {} to {}", broken.path.display(), target.display());
+    Ok(target)
+}
+
+/// Number of project files read at the same time, from `max_concurrent_io`
+fn max_concurrent_io() -> usize {
+    crate::config::get_config()
+        .map(|config| config.max_concurrent_io)
+        .unwrap_or(16)
+        .max(1)
+}
+
 /// Async version of collect_projects_from_directory
 async fn collect_projects_from_directory_async(dir: &Path) -> Result<Vec<Project>> {
-    // Check the cache first
-    {
-        let cache = cache::PROJECT_CACHE.lock().unwrap();
-        if let Some(project_ids) = cache.get_projects_in_dir(dir) {
-            debug!("Using cached project list for directory: {}", dir.display());
-            
-            // Collect futures for loading projects
-            let futures: Vec<_> = project_ids.iter().map(|project_id| {
-                // For each project ID, check if it's in the cache and valid
-                let project_id = project_id.clone();
-                async move {
-                    // Try to get from cache first
-                    {
-                        let mut cache = cache::PROJECT_CACHE.lock().unwrap();
-                        if let Some(cached_project) = cache.get_project(&project_id) {
-                            if cached_project.is_valid() {
-                                return Ok(cached_project.project.clone());
-                            }
+    let limit = max_concurrent_io();
+    
+    // Check the cache first, releasing the lock before loading anything
+    let cached_ids = cache::PROJECT_CACHE.lock().unwrap().get_projects_in_dir(dir).cloned();
+    if let Some(project_ids) = cached_ids {
+        debug!("Using cached project list for directory: {}", dir.display());
+        
+        // Collect futures for loading projects
+        let futures: Vec<_> = project_ids.iter().map(|project_id| {
+            // For each project ID, check if it's in the cache and valid
+            let project_id = project_id.clone();
+            async move {
+                // Try to get from cache first
+                {
+                    let mut cache = cache::PROJECT_CACHE.lock().unwrap();
+                    if let Some(cached_project) = cache.get_project(&project_id) {
+                        if cached_project.is_valid() {
+                            return Ok(cached_project.project.clone());
                         }
                     }
-                    
-                    // If not in cache or invalid, load from disk
-                    load_project_async(&project_id).await
                 }
-            }).collect();
-            
-            // Execute all futures concurrently
-            let results = future::join_all(futures).await;
-            
-            // Collect successful results
-            let projects: Vec<_> = results
-                .into_iter()
-                .filter_map(|result| match result {
-                    Ok(project) => Some(project),
-                    Err(e) => {
-                        warn!("Failed to load project: {}", e);
-                        None
-                    }
-                })
-                .collect();
-            
-            return Ok(projects);
-        }
+                
+                // If not in cache or invalid, load from disk
+                load_project_async(&project_id).await
+            }
+        }).collect();
+        
+        // Load the projects, at most `limit` at a time
+        let results: Vec<_> = stream::iter(futures).buffer_unordered(limit).collect().await;
+        
+        // Collect successful results
+        let projects: Vec<_> = results
+            .into_iter()
+            .filter_map(|result| match result {
+                Ok(project) => Some(project),
+                Err(e) => {
+                    warn!("Failed to load project: {}", e);
+                    None
+                }
+            })
+            .collect();
+        
+        return Ok(projects);
     }
     
     let mut entries = tokio_fs::read_dir(dir).await?;
@@ -631,8 +1245,8 @@ async fn collect_projects_from_directory_async(dir: &Path) -> Result<Vec<Project
         }
     }
     
-    // Execute all project loading tasks concurrently
-    let results = future::join_all(project_loading_tasks).await;
+    // Execute the project loading tasks, at most `limit` at a time
+    let results: Vec<_> = stream::iter(project_loading_tasks).buffer_unordered(limit).collect().await;
     
     // Process results
     for result in results {
@@ -662,8 +1276,19 @@ pub fn show_status(project_id: &str) -> Result<()> {
     
     info!("Displaying status for project: {} ({})", project.name, project.id);
     println!("{:-^80}", format!(" Project: {} ", project.name).green());
+    if project.archived {
+        println!("{}", " ARCHIVED ".on_bright_black().white().bold());
+    }
     println!("ID: {}", project.id.yellow());
     println!("Description: {}", project.description);
+    if !project.tags.is_empty() {
+        println!("Tags: {}", project.tags.join(", "));
+    }
+    if !project.variables.is_empty() {
+        let mut variables: Vec<String> = project.variables.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        variables.sort();
+        println!("Variables: {}", variables.join(", "));
+    }
     println!("Created: {}", project.created_at);
     println!("Updated: {}", project.updated_at);
     println!("Directory: {}", project.path.display().to_string().yellow());
@@ -686,6 +1311,14 @@ pub fn show_status(project_id: &str) -> Result<()> {
             println!("  Completed: {}", completed_at);
         }
         
+        if let Some(usage) = &stage.tokens_used {
+            println!("  Tokens: {} (input: {}, output: {})", usage.total(), usage.input_tokens, usage.output_tokens);
+        }
+        
+        if let Some(duration_ms) = stage.duration_ms {
+            println!("  Duration: {:.1}s", duration_ms as f64 / 1000.0);
+        }
+        
         if !stage.artifacts.is_empty() {
             println!("  Artifacts:");
             for artifact in &stage.artifacts {
@@ -696,6 +1329,11 @@ pub fn show_status(project_id: &str) -> Result<()> {
         println!();
     }
     
+    let total_tokens = project.total_tokens();
+    if total_tokens > 0 {
+        println!("Total tokens used: {}", total_tokens.to_string().yellow());
+    }
+    
     Ok(())
 }
 
@@ -839,3 +1477,257 @@ impl ProjectCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2024-05-01", false).unwrap().to_rfc3339(), "2024-05-01T00:00:00+00:00");
+        assert_eq!(parse_date("2024-05-01", true).unwrap().format("%H:%M:%S").to_string(), "23:59:59");
+        assert_eq!(parse_date("2024-05-01T12:00:00+02:00", false).unwrap().to_rfc3339(), "2024-05-01T10:00:00+00:00");
+        assert!(matches!(parse_date("May 1st", false), Err(ToolkitError::InvalidInput(_))));
+        assert!(matches!(parse_date("2024-13-01", false), Err(ToolkitError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_picker_choices_skip_archived_and_duplicates() {
+        let project = |id: &str, updated: &str| {
+            let mut project = Project::new(id.to_string(), id.to_string(), "Test".to_string(), PathBuf::from("/tmp").join(id));
+            project.updated_at = parse_date(updated, false).unwrap();
+            project
+        };
+        let mut archived = project("proj_old", "2024-05-03");
+        archived.archived = true;
+        let projects = vec![
+            project("proj_a", "2024-05-01"),
+            project("proj_b", "2024-05-02"),
+            archived,
+            project("proj_a", "2024-05-01"),
+        ];
+
+        let ids: Vec<&str> = picker_choices(&projects).iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["proj_b", "proj_a"]);
+    }
+
+    #[test]
+    fn test_project_filter_dates() {
+        let mut project = Project::new("proj_1".to_string(), "Shop".to_string(), "Shop".to_string(), PathBuf::from("/tmp/proj_1"));
+        project.created_at = parse_date("2024-05-01T09:30:00Z", false).unwrap();
+
+        let filter = ProjectFilter {
+            since: Some(parse_date("2024-05-01", false).unwrap()),
+            until: Some(parse_date("2024-05-01", true).unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.in_range(&project));
+
+        let later = ProjectFilter { since: Some(parse_date("2024-05-02", false).unwrap()), ..Default::default() };
+        assert!(!later.in_range(&project));
+    }
+
+    #[test]
+    fn test_search_projects() {
+        let mut project = Project::new("proj_1".to_string(), "Shop".to_string(), "An online shop".to_string(), PathBuf::from("/tmp/proj_1"));
+        project.update_stage(1, "Plan\n\nUse a GraphQL API for the catalog. graphql again.".to_string(), crate::models::StageStatus::Completed);
+        let projects = vec![project.clone(), project];
+
+        let results = search_projects(&projects, "graphql", false, None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].stage, Some(1));
+        assert_eq!(results[0].matches, 2);
+        assert_eq!((results[0].before.as_str(), results[0].matched.as_str()), ("Plan Use a ", "GraphQL"));
+
+        assert_eq!(search_projects(&projects, "online", false, None).unwrap()[0].stage, None);
+        assert!(search_projects(&projects, "online", false, Some(1)).unwrap().is_empty());
+        assert_eq!(search_projects(&projects, r"graph\w+ api", true, Some(1)).unwrap().len(), 1);
+        assert!(search_projects(&projects, "(", true, None).is_err());
+    }
+
+    #[test]
+    fn test_project_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let lock = ProjectLock::acquire(dir.path()).unwrap();
+        let err = ProjectLock::acquire_with_timeout(dir.path(), Duration::from_millis(100)).err();
+        assert!(matches!(err, Some(ToolkitError::ProjectLocked(_))));
+
+        drop(lock);
+        assert!(ProjectLock::acquire_with_timeout(dir.path(), Duration::from_millis(100)).is_ok());
+    }
+
+    #[test]
+    fn test_update_waiting_for_lock_leaves_cache_free() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = Project::new(
+            "waiting_update".to_string(),
+            "Waiting".to_string(),
+            "Updated while another process holds the lock".to_string(),
+            dir.path().join("waiting_update"),
+        );
+        save_project(&project).unwrap();
+
+        let lock = ProjectLock::acquire(&project.path).unwrap();
+        let updater = std::thread::spawn({
+            let project = project.clone();
+            move || update_project_file(&project, |merged| merged.archived = true)
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        // Other projects can still be looked up while the update waits
+        let started = Instant::now();
+        drop(cache::PROJECT_CACHE.lock().unwrap());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        drop(lock);
+        assert!(updater.join().unwrap().unwrap().archived);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_save_leaves_project_file_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut project = Project::new(
+            "atomic_save".to_string(),
+            "Original".to_string(),
+            "Saved before the interruption".to_string(),
+            dir.path().join("atomic_save"),
+        );
+        save_project(&project).unwrap();
+        let project_file = project.path.join("project.json");
+        let temp_file = project.path.join(PROJECT_TEMP_FILE);
+        let read_name = || {
+            let json = fs::read_to_string(&project_file).unwrap();
+            serde_json::from_str::<Project>(&json).unwrap().name
+        };
+
+        // A save killed part way leaves a truncated temp file behind
+        fs::write(&temp_file, "{\"id\": \"atomic_sa").unwrap();
+        assert_eq!(read_name(), "Original");
+
+        // A save that fails while writing never touches the real file
+        fs::remove_file(&temp_file).unwrap();
+        fs::create_dir(&temp_file).unwrap();
+        project.name = "Lost".to_string();
+        assert!(save_project(&project).is_err());
+        assert!(save_project_async(&project).await.is_err());
+        assert_eq!(read_name(), "Original");
+
+        // The next save replaces the leftover temp file
+        fs::remove_dir(&temp_file).unwrap();
+        fs::write(&temp_file, "{\"id\": \"atomic_sa").unwrap();
+        project.name = "Renamed".to_string();
+        save_project(&project).unwrap();
+        assert_eq!(read_name(), "Renamed");
+        project.name = "Renamed again".to_string();
+        save_project_async(&project).await.unwrap();
+        assert_eq!(read_name(), "Renamed again");
+        assert!(!temp_file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_async_loads_leave_the_runtime_free() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let project = Project::new(
+            "concurrent_loads".to_string(),
+            "Concurrent".to_string(),
+            "Loaded many times at once".to_string(),
+            dir.path().join("concurrent_loads"),
+        );
+        cache::save_cached_project(&project).unwrap();
+
+        // Hold the cache for a while so the loads have to wait on it
+        let (locked, is_locked) = std::sync::mpsc::channel();
+        let holder = std::thread::spawn(move || {
+            let _cache = cache::PROJECT_CACHE.lock().unwrap();
+            locked.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+        });
+        is_locked.recv().unwrap();
+
+        // The test runtime has one thread, so this only ticks while the loads wait
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let loads = futures::future::join_all((0..64).map(|_| load_project_async("concurrent_loads"))).await;
+        ticker.abort();
+        holder.join().unwrap();
+
+        assert_eq!(loads.len(), 64);
+        assert!(loads.iter().all(|loaded| loaded.as_ref().is_ok_and(|p| p.id == "concurrent_loads")));
+        assert!(ticks.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_scan_directory_reports_broken_projects() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let project = Project::new(
+            "good".to_string(),
+            "Good".to_string(),
+            "Loads fine".to_string(),
+            dir.path().join("good"),
+        );
+        save_project(&project).unwrap();
+
+        fs::create_dir_all(dir.path().join("corrupt")).unwrap();
+        fs::write(dir.path().join("corrupt").join("project.json"), "{ not json").unwrap();
+
+        fs::create_dir_all(dir.path().join("orphan").join("stages")).unwrap();
+        fs::create_dir_all(dir.path().join("unrelated")).unwrap();
+
+        let scan = scan_directory(dir.path(), true).unwrap();
+        assert_eq!(scan.projects.len(), 1);
+        assert_eq!(scan.projects[0].id, "good");
+
+        let mut broken: Vec<(String, &str)> = scan
+            .broken
+            .iter()
+            .map(|b| (b.path.file_name().unwrap().to_string_lossy().into_owned(), b.reason.as_str()))
+            .collect();
+        broken.sort();
+        assert_eq!(broken.len(), 2);
+        assert_eq!(broken[0].0, "corrupt");
+        assert!(broken[0].1.starts_with("failed to parse project.json"));
+        assert_eq!(broken[1], ("orphan".to_string(), "project.json is missing"));
+
+        let moved = quarantine_project(&scan.broken[0]).unwrap();
+        assert!(moved.starts_with(dir.path().join(QUARANTINE_DIR)));
+        assert_eq!(scan_directory(dir.path(), true).unwrap().broken.len(), 1);
+    }
+
+    #[test]
+    fn test_scan_outside_projects_dir_ignores_other_directories() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A source tree next to the project, like `src` in a repository
+        fs::create_dir_all(dir.path().join("src").join("stages")).unwrap();
+        fs::write(dir.path().join("docs.md"), "").unwrap();
+        fs::create_dir_all(dir.path().join("notes")).unwrap();
+        fs::write(dir.path().join("notes").join("idea.md"), "An idea").unwrap();
+
+        // A project that lost its file mid-save
+        fs::create_dir_all(dir.path().join("interrupted")).unwrap();
+        fs::write(dir.path().join("interrupted").join(PROJECT_TEMP_FILE), "{").unwrap();
+
+        let scan = scan_directory(dir.path(), false).unwrap();
+        assert_eq!(scan.broken.len(), 1);
+        assert!(scan.broken[0].path.ends_with("interrupted"));
+        assert!(!scan.broken[0].deletable);
+
+        let scan = scan_directory(dir.path(), true).unwrap();
+        assert_eq!(scan.broken.len(), 3);
+        assert!(scan.broken.iter().all(|b| b.deletable));
+    }
+}
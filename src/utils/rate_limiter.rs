@@ -3,9 +3,18 @@
 //! This module provides rate limiting capabilities to prevent exceeding API provider
 //! rate limits. It implements a sliding window approach to track requests over time
 //! and includes exponential backoff for handling failures and rate limit responses.
+//! Alongside the request count, an optional tokens-per-minute budget tracks the
+//! rolling sum of tokens spent over the same window.
+//!
+//! A circuit breaker stops hammering a provider that keeps failing: after
+//! [`CIRCUIT_FAILURE_THRESHOLD`] consecutive failures the circuit opens and
+//! requests are refused for [`CIRCUIT_COOLDOWN`]. The circuit then half-opens
+//! and lets one trial request through; its success closes the circuit and its
+//! failure opens it again.
 //!
 //! The main components are:
 //! - [`Provider`]: Enum representing different API providers
+//! - [`CircuitState`]: Whether requests to a provider are currently refused
 //! - Public functions for checking and recording requests
 //! - Internal rate limiting implementation with provider-specific limits
 //!
@@ -34,6 +43,13 @@
 //! }
 //! ```
 //!
+//! # Persistence
+//!
+//! The request history of the global rate limiter is stored in
+//! `~/.rust-ai-toolkit/rate_state.json` so that consecutive invocations of the
+//! CLI share the same sliding window. Entries older than the window are
+//! discarded when the state is loaded.
+//!
 //! # Thread Safety
 //!
 //! The rate limiter uses a mutex to protect its internal state, making it safe
@@ -41,11 +57,15 @@
 //! rate limiter will allow requests to proceed to avoid blocking the application.
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use once_cell::sync::Lazy;
-use log::{warn, info};
+use log::{debug, warn, info};
 use colored::Colorize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 /// Trait for clock abstraction to make testing easier.
 ///
@@ -77,11 +97,18 @@ impl Clock for RealClock {
     }
 }
 
-// Singleton rate limiter instance with configurable clock
+// Singleton rate limiter instance with configurable clock, seeded from the persisted state
 static RATE_LIMITER: Lazy<Arc<Mutex<RateLimiter<RealClock>>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(RateLimiter::new(RealClock::default())))
+    let mut limiter = RateLimiter::new(RealClock::default());
+    if let Some(path) = state_path() {
+        limiter.load_state(&path);
+    }
+    Arc::new(Mutex::new(limiter))
 });
 
+// Length of the sliding window used for rate limiting
+const WINDOW: Duration = Duration::from_secs(60);
+
 // Default rate limits (requests per minute)
 const DEFAULT_RPM_LIMIT: u32 = 30;
 const WARN_THRESHOLD_PERCENT: f32 = 0.8; // Warn at 80% of limit
@@ -91,6 +118,31 @@ const INITIAL_RETRY_DELAY_MS: u64 = 1000; // 1 second
 const MAX_RETRY_DELAY_MS: u64 = 60000;    // 1 minute
 const BACKOFF_FACTOR: f32 = 2.0;
 
+/// Pick a random delay between half of `delay` and `delay`
+///
+/// Requests that fail together would otherwise all retry after the same
+/// delay and collide again, even on their first retry.
+fn jitter(delay: u64) -> u64 {
+    rand::thread_rng().gen_range(delay / 2..=delay)
+}
+
+/// Consecutive failures after which a provider's circuit opens
+pub const CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long an open circuit refuses requests before letting a trial request through
+pub const CIRCUIT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// The state of a provider's circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through as normal
+    Closed,
+    /// The provider kept failing; requests are refused until the cooldown ends
+    Open,
+    /// The cooldown has ended; one trial request decides whether to close or reopen
+    HalfOpen,
+}
+
 /// Represents an API provider for rate limiting.
 ///
 /// This enum defines the supported API providers, each with their own
@@ -111,6 +163,8 @@ pub enum Provider {
     Anthropic,
     /// OpenAI API provider (GPT models)
     OpenAI,
+    /// Azure OpenAI Service (GPT models behind Azure deployments)
+    AzureOpenAI,
     /// Custom or unknown API provider
     Custom,
 }
@@ -132,11 +186,55 @@ impl From<&str> for Provider {
         match provider {
             "anthropic" | "anthropic_enhanced" => Provider::Anthropic,
             "openai" => Provider::OpenAI,
+            "azure_openai" => Provider::AzureOpenAI,
             _ => Provider::Custom,
         }
     }
 }
 
+impl Provider {
+    /// Get the key used for this provider in the persisted state.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Anthropic => "anthropic",
+            Provider::OpenAI => "openai",
+            Provider::AzureOpenAI => "azure_openai",
+            Provider::Custom => "custom",
+        }
+    }
+}
+
+/// Request history as stored on disk.
+///
+/// Timestamps are milliseconds since the Unix epoch, since `Instant` values
+/// are only meaningful within a single process.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedState {
+    requests: HashMap<String, Vec<u64>>,
+}
+
+/// Get the location of the persisted rate limiter state.
+#[cfg(not(test))]
+fn state_path() -> Option<PathBuf> {
+    crate::config::get_config_dir()
+        .map(|dir| dir.join("rate_state.json"))
+        .ok()
+}
+
+/// Tests never read or write the user's real rate limiter state.
+#[cfg(test)]
+fn state_path() -> Option<PathBuf> {
+    None
+}
+
+/// Get the current wall-clock time in milliseconds since the Unix epoch.
+fn epoch_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 /// Tracks rate limiting information for a provider.
 ///
 /// This struct maintains the state needed for rate limiting a specific provider,
@@ -145,8 +243,14 @@ impl From<&str> for Provider {
 struct ProviderRateLimit {
     requests: Vec<Instant>,
     rpm_limit: u32,
+    tokens: Vec<(Instant, u32)>,
+    tpm_limit: Option<u32>,
     consecutive_failures: u32,
     last_backoff_delay_ms: u64,
+    /// When the circuit last opened, if it is open or half-open
+    circuit_opened_at: Option<Instant>,
+    /// When the half-open circuit let its trial request through
+    trial_started_at: Option<Instant>,
 }
 
 impl ProviderRateLimit {
@@ -163,8 +267,73 @@ impl ProviderRateLimit {
         Self {
             requests: Vec::new(),
             rpm_limit,
+            tokens: Vec::new(),
+            tpm_limit: None,
             consecutive_failures: 0,
             last_backoff_delay_ms: INITIAL_RETRY_DELAY_MS,
+            circuit_opened_at: None,
+            trial_started_at: None,
+        }
+    }
+
+    /// Get the state of the circuit breaker.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    fn circuit_state<C: Clock>(&self, clock: &C) -> CircuitState {
+        match self.circuit_opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if clock.now().saturating_duration_since(opened_at) < CIRCUIT_COOLDOWN => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Check whether the circuit breaker lets a request through.
+    ///
+    /// A half-open circuit lets one trial request through. Should its outcome
+    /// never be recorded, another is allowed once a cooldown has passed.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the request may be made.
+    fn circuit_allows<C: Clock>(&mut self, clock: &C) -> bool {
+        match self.circuit_state(clock) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                let now = clock.now();
+                let trial_pending = self
+                    .trial_started_at
+                    .is_some_and(|started| now.saturating_duration_since(started) < CIRCUIT_COOLDOWN);
+                if !trial_pending {
+                    self.trial_started_at = Some(now);
+                }
+                !trial_pending
+            }
+        }
+    }
+
+    /// Open the circuit if the failure threshold is reached or the trial request failed.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    fn trip_circuit<C: Clock>(&mut self, clock: &C) {
+        let trial_failed = self.circuit_state(clock) == CircuitState::HalfOpen;
+        if trial_failed || self.consecutive_failures >= CIRCUIT_FAILURE_THRESHOLD {
+            if self.circuit_state(clock) != CircuitState::Open {
+                warn!(
+                    "Opening circuit after {} consecutive failures; refusing requests for {}s",
+                    self.consecutive_failures, CIRCUIT_COOLDOWN.as_secs()
+                );
+            }
+            self.circuit_opened_at = Some(clock.now());
+            self.trial_started_at = None;
         }
     }
 
@@ -177,7 +346,7 @@ impl ProviderRateLimit {
     ///
     /// * `clock` - The clock implementation to use for time calculations.
     fn cleanup_old_requests<C: Clock>(&mut self, clock: &C) {
-        let one_minute_ago = clock.now() - Duration::from_secs(60);
+        let one_minute_ago = clock.now() - WINDOW;
         self.requests.retain(|time| *time > one_minute_ago);
     }
 
@@ -218,20 +387,98 @@ impl ProviderRateLimit {
         self.requests.push(clock.now());
     }
 
-    /// Record a successful request, resetting the failure count.
+    /// Get the number of tokens spent in the last minute.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    ///
+    /// # Returns
+    ///
+    /// The sum of tokens recorded within the sliding window.
+    fn get_current_tpm<C: Clock>(&mut self, clock: &C) -> u32 {
+        let one_minute_ago = clock.now() - WINDOW;
+        self.tokens.retain(|(time, _)| *time > one_minute_ago);
+        self.tokens.iter().map(|(_, count)| *count).sum()
+    }
+
+    /// Check if the estimated tokens can be spent without exceeding the token limit.
+    ///
+    /// A request is always allowed when nothing has been spent in the current
+    /// window, so a single prompt larger than the limit is not blocked forever.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    /// * `estimated` - The estimated number of tokens the request will use.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the tokens can be spent, `false` if the token limit would be exceeded.
+    fn can_spend_tokens<C: Clock>(&mut self, clock: &C, estimated: u32) -> bool {
+        let limit = match self.tpm_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        let current = self.get_current_tpm(clock);
+        current == 0 || current.saturating_add(estimated) <= limit
+    }
+
+    /// Record tokens spent in the history.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    /// * `count` - The number of tokens spent.
+    fn record_tokens<C: Clock>(&mut self, clock: &C, count: u32) {
+        self.tokens.push((clock.now(), count));
+    }
+
+    /// Get how long until the oldest tokens in the window expire.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    ///
+    /// # Returns
+    ///
+    /// The time until some of the budget frees up, or zero if nothing has been
+    /// spent in the current window.
+    fn token_wait<C: Clock>(&mut self, clock: &C) -> Duration {
+        self.get_current_tpm(clock);
+        let now = clock.now();
+        self.tokens
+            .first()
+            .map(|(time, _)| (*time + WINDOW).saturating_duration_since(now))
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Record a successful request, resetting the failure count and closing the circuit.
     fn record_success(&mut self) {
         // Reset failure count on success
         self.consecutive_failures = 0;
         self.last_backoff_delay_ms = INITIAL_RETRY_DELAY_MS;
+        self.circuit_opened_at = None;
+        self.trial_started_at = None;
     }
 
     /// Record a failed request and calculate the backoff delay.
     ///
+    /// The delay doubles with each consecutive failure up to
+    /// `MAX_RETRY_DELAY_MS`, and a random delay between half of that and
+    /// all of it is returned.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    ///
     /// # Returns
     ///
     /// The backoff delay in milliseconds before the next retry.
-    fn record_failure(&mut self) -> u64 {
+    fn record_failure<C: Clock>(&mut self, clock: &C) -> u64 {
         self.consecutive_failures += 1;
+        self.trip_circuit(clock);
         
         // Calculate exponential backoff
         if self.consecutive_failures > 1 {
@@ -241,16 +488,21 @@ impl ProviderRateLimit {
             }
         }
         
-        self.last_backoff_delay_ms
+        jitter(self.last_backoff_delay_ms)
     }
 
     /// Records a rate limit response from the API.
     ///
     /// This is used when we receive a 429 Too Many Requests response.
     /// It increases the backoff delay more aggressively than a normal failure.
-    fn record_rate_limit(&mut self) {
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - The clock implementation to use for time calculations.
+    fn record_rate_limit<C: Clock>(&mut self, clock: &C) {
         // Increase the consecutive failures counter
         self.consecutive_failures += 1;
+        self.trip_circuit(clock);
         
         // Calculate the appropriate backoff delay
         self.last_backoff_delay_ms = (INITIAL_RETRY_DELAY_MS as f32 * 
@@ -288,6 +540,7 @@ impl<C: Clock> RateLimiter<C> {
         let mut providers = HashMap::new();
         providers.insert(Provider::Anthropic, ProviderRateLimit::new(30)); // 30 RPM for Anthropic
         providers.insert(Provider::OpenAI, ProviderRateLimit::new(60));    // 60 RPM for OpenAI
+        providers.insert(Provider::AzureOpenAI, ProviderRateLimit::new(60)); // 60 RPM for Azure OpenAI
         providers.insert(Provider::Custom, ProviderRateLimit::new(DEFAULT_RPM_LIMIT));
         
         Self { providers, clock }
@@ -295,7 +548,8 @@ impl<C: Clock> RateLimiter<C> {
     
     /// Checks if a request can be made to the specified provider.
     ///
-    /// This method also logs warnings if the rate limit is being approached.
+    /// Requests are refused while the provider's circuit is open. This method
+    /// also logs warnings if the rate limit is being approached.
     ///
     /// # Parameters
     ///
@@ -309,6 +563,11 @@ impl<C: Clock> RateLimiter<C> {
             .entry(provider)
             .or_insert_with(|| ProviderRateLimit::new(DEFAULT_RPM_LIMIT));
             
+        if !rate_limit.circuit_allows(&self.clock) {
+            warn!("Circuit open for {:?}: refusing request after repeated failures", provider);
+            return false;
+        }
+            
         // Check if we're approaching the limit and warn if so
         let current_rpm = rate_limit.get_current_rpm(&self.clock);
         let limit = rate_limit.rpm_limit;
@@ -328,6 +587,44 @@ impl<C: Clock> RateLimiter<C> {
         
         rate_limit.can_make_request(&self.clock)
     }
+
+    /// Checks if the estimated tokens can be spent with the specified provider.
+    ///
+    /// # Parameters
+    ///
+    /// * `provider` - The provider to check.
+    /// * `estimated` - The estimated number of tokens the request will use.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the tokens can be spent, `false` if the token limit would be exceeded.
+    fn check_token_limit(&mut self, provider: Provider, estimated: u32) -> bool {
+        let rate_limit = self.providers
+            .entry(provider)
+            .or_insert_with(|| ProviderRateLimit::new(DEFAULT_RPM_LIMIT));
+
+        let allowed = rate_limit.can_spend_tokens(&self.clock, estimated);
+        if !allowed {
+            warn!(
+                "Token limit reached for {:?}: {} tokens used, {} more requested (limit {:?})",
+                provider, rate_limit.get_current_tpm(&self.clock), estimated, rate_limit.tpm_limit
+            );
+        }
+
+        allowed
+    }
+    
+    /// Gets how long until some of the provider's token budget frees up.
+    ///
+    /// # Parameters
+    ///
+    /// * `provider` - The provider to check.
+    fn token_wait(&mut self, provider: Provider) -> Duration {
+        match self.providers.get_mut(&provider) {
+            Some(rate_limit) => rate_limit.token_wait(&self.clock),
+            None => Duration::ZERO,
+        }
+    }
     
     /// Records a successful request.
     ///
@@ -351,7 +648,7 @@ impl<C: Clock> RateLimiter<C> {
     /// The backoff delay in milliseconds before the next retry.
     fn record_failure(&mut self, provider: Provider) -> u64 {
         if let Some(rate_limit) = self.providers.get_mut(&provider) {
-            rate_limit.record_failure()
+            rate_limit.record_failure(&self.clock)
         } else {
             INITIAL_RETRY_DELAY_MS
         }
@@ -369,12 +666,113 @@ impl<C: Clock> RateLimiter<C> {
                 let rpm = match provider {
                     Provider::Anthropic => 10, // Anthropic has lower limits
                     Provider::OpenAI => DEFAULT_RPM_LIMIT,
+                    Provider::AzureOpenAI => DEFAULT_RPM_LIMIT,
                     Provider::Custom => DEFAULT_RPM_LIMIT,
                 };
                 ProviderRateLimit::new(rpm)
             });
             
-        provider_limits.record_rate_limit();
+        provider_limits.record_rate_limit(&self.clock);
+    }
+
+    /// Get the state of the specified provider's circuit breaker.
+    ///
+    /// # Parameters
+    ///
+    /// * `provider` - The provider to check.
+    fn circuit_state(&self, provider: Provider) -> CircuitState {
+        self.providers
+            .get(&provider)
+            .map(|rate_limit| rate_limit.circuit_state(&self.clock))
+            .unwrap_or(CircuitState::Closed)
+    }
+
+    /// Capture the request history as wall-clock timestamps.
+    ///
+    /// # Parameters
+    ///
+    /// * `now_ms` - The current wall-clock time in milliseconds since the Unix epoch.
+    fn snapshot(&self, now_ms: u64) -> PersistedState {
+        let now = self.clock.now();
+        let requests = self.providers
+            .iter()
+            .map(|(provider, rate_limit)| {
+                let timestamps = rate_limit.requests
+                    .iter()
+                    .map(|time| now_ms.saturating_sub(now.saturating_duration_since(*time).as_millis() as u64))
+                    .collect();
+                (provider.as_str().to_string(), timestamps)
+            })
+            .collect();
+
+        PersistedState { requests }
+    }
+
+    /// Restore request history from wall-clock timestamps.
+    ///
+    /// Timestamps that fall outside the sliding window are discarded.
+    ///
+    /// # Parameters
+    ///
+    /// * `state` - The persisted request history.
+    /// * `now_ms` - The current wall-clock time in milliseconds since the Unix epoch.
+    fn restore(&mut self, state: PersistedState, now_ms: u64) {
+        let now = self.clock.now();
+        let window_ms = WINDOW.as_millis() as u64;
+
+        for (name, timestamps) in state.requests {
+            let provider = Provider::from(name.as_str());
+            let rate_limit = self.providers
+                .entry(provider)
+                .or_insert_with(|| ProviderRateLimit::new(DEFAULT_RPM_LIMIT));
+
+            rate_limit.requests = timestamps
+                .into_iter()
+                .filter(|ts| *ts <= now_ms && now_ms - ts < window_ms)
+                .filter_map(|ts| now.checked_sub(Duration::from_millis(now_ms - ts)))
+                .collect();
+            rate_limit.requests.sort();
+        }
+    }
+
+    /// Load persisted request history from a file.
+    ///
+    /// A missing or unreadable file leaves the limiter empty.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - The path of the state file.
+    fn load_state(&mut self, path: &Path) {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return,
+        };
+
+        match serde_json::from_str::<PersistedState>(&content) {
+            Ok(state) => self.restore(state, epoch_millis()),
+            Err(e) => warn!("Ignoring invalid rate limiter state in {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Write a snapshot of the request history to a file.
+///
+/// The snapshot goes to a temporary file of its own and is then renamed over
+/// the state file, so a process reading it, or another write at the same time,
+/// never sees a half-written file. Failures are logged and otherwise ignored,
+/// since losing the history only makes the limiter more permissive.
+fn write_state(path: &Path, state: &PersistedState) {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let write = WRITES.fetch_add(1, Ordering::Relaxed);
+    let temp_path = path.with_extension(format!("json.{}-{}.tmp", std::process::id(), write));
+    let result = serde_json::to_string(state)
+        .map_err(std::io::Error::other)
+        .and_then(|json| std::fs::write(&temp_path, json))
+        .and_then(|()| std::fs::rename(&temp_path, path));
+
+    if let Err(e) = result {
+        debug!("Failed to save rate limiter state to {}: {}", path.display(), e);
+        std::fs::remove_file(&temp_path).ok();
     }
 }
 
@@ -441,15 +839,28 @@ pub fn can_make_request(provider_str: &str) -> bool {
 ///
 /// This function is thread-safe. If the mutex cannot be acquired, the
 /// request will not be recorded, but this will not affect the application.
+///
+/// The updated request history is flushed to disk so later runs can see it.
 pub fn record_request(provider_str: &str) {
     let provider = Provider::from(provider_str);
-    if let Ok(mut limiter) = RATE_LIMITER.lock() {
-        // Get the current time before borrowing the rate limit
-        let now = limiter.clock.now();
-        
-        if let Some(rate_limit) = limiter.providers.get_mut(&provider) {
-            rate_limit.requests.push(now);
+    let path = state_path();
+    let snapshot = match RATE_LIMITER.lock() {
+        Ok(mut limiter) => {
+            // Get the current time before borrowing the rate limit
+            let now = limiter.clock.now();
+            
+            if let Some(rate_limit) = limiter.providers.get_mut(&provider) {
+                rate_limit.requests.push(now);
+            }
+
+            path.as_ref().map(|_| limiter.snapshot(epoch_millis()))
         }
+        Err(_) => None,
+    };
+
+    // Other requests needn't wait on the disk, so write once the lock is released
+    if let (Some(path), Some(snapshot)) = (path, snapshot) {
+        write_state(&path, &snapshot);
     }
 }
 
@@ -521,6 +932,94 @@ pub fn record_failure(provider_str: &str) -> u64 {
     }
 }
 
+/// Check if the estimated tokens can be spent with the specified provider.
+///
+/// # Parameters
+///
+/// * `provider_str` - The provider name as a string.
+/// * `estimated` - The estimated number of tokens the request will use.
+///
+/// # Returns
+///
+/// `true` if the tokens can be spent, `false` if the tokens-per-minute limit
+/// would be exceeded. Providers without a token limit always return `true`.
+///
+/// # Thread Safety
+///
+/// This function is thread-safe. If the mutex cannot be acquired, it will
+/// allow the request to proceed to avoid blocking the application.
+pub fn can_spend_tokens(provider_str: &str, estimated: u32) -> bool {
+    let provider = Provider::from(provider_str);
+    if let Ok(mut limiter) = RATE_LIMITER.lock() {
+        limiter.check_token_limit(provider, estimated)
+    } else {
+        true
+    }
+}
+
+/// Records tokens spent with the specified provider.
+///
+/// # Parameters
+///
+/// * `provider_str` - The provider name as a string.
+/// * `count` - The number of tokens spent.
+pub fn record_tokens(provider_str: &str, count: u32) {
+    let provider = Provider::from(provider_str);
+    if let Ok(mut guard) = RATE_LIMITER.lock() {
+        let limiter = &mut *guard;
+
+        if let Some(rate_limit) = limiter.providers.get_mut(&provider) {
+            rate_limit.record_tokens(&limiter.clock, count);
+        }
+    }
+}
+
+/// Get the delay before tokens spent with the specified provider start to expire.
+///
+/// Used to wait out a request held back by the tokens-per-minute budget
+/// instead of backing off as if the provider had failed.
+///
+/// # Parameters
+///
+/// * `provider_str` - The provider name as a string.
+///
+/// # Returns
+///
+/// The delay in milliseconds, at least the initial retry delay.
+///
+/// # Thread Safety
+///
+/// This function is thread-safe. If the mutex cannot be acquired, the
+/// initial retry delay is returned.
+pub fn token_wait_ms(provider_str: &str) -> u64 {
+    let provider = Provider::from(provider_str);
+    let wait = match RATE_LIMITER.lock() {
+        Ok(mut limiter) => limiter.token_wait(provider).as_millis() as u64,
+        Err(_) => 0,
+    };
+    wait.max(INITIAL_RETRY_DELAY_MS)
+}
+
+/// Sets the token limit for a provider (tokens per minute). A limit of 0 disables it.
+pub fn set_token_limit(provider_str: &str, tpm: u32) {
+    let provider = Provider::from(provider_str);
+    if let Ok(mut limiter) = RATE_LIMITER.lock() {
+        if let Some(rate_limit) = limiter.providers.get_mut(&provider) {
+            rate_limit.tpm_limit = if tpm == 0 { None } else { Some(tpm) };
+            info!("Token limit for {:?} set to {} tokens per minute", provider, tpm);
+        }
+    }
+}
+
+/// Apply the requests-per-minute limits from the config loaded at startup
+pub fn configure(config: &crate::config::Config) {
+    let limits = &config.rate_limits;
+    set_rate_limit("anthropic", limits.anthropic);
+    set_rate_limit("openai", limits.openai);
+    set_rate_limit("azure_openai", limits.azure_openai);
+    set_rate_limit("custom", limits.custom);
+}
+
 /// Sets the rate limit for a provider (requests per minute)
 pub fn set_rate_limit(provider_str: &str, rpm: u32) {
     let provider = Provider::from(provider_str);
@@ -541,6 +1040,28 @@ pub fn record_rate_limit(provider_str: &str) {
     limiter.record_rate_limit(provider);
 }
 
+/// Get the state of a provider's circuit breaker.
+///
+/// While the circuit is [`CircuitState::Open`], [`can_make_request`] returns
+/// `false` so callers fail fast instead of retrying into an outage.
+///
+/// # Parameters
+///
+/// * `provider_str` - The provider name as a string.
+///
+/// # Thread Safety
+///
+/// This function is thread-safe. If the mutex cannot be acquired, the circuit
+/// is reported as closed.
+pub fn circuit_state(provider_str: &str) -> CircuitState {
+    let provider = Provider::from(provider_str);
+    if let Ok(limiter) = RATE_LIMITER.lock() {
+        limiter.circuit_state(provider)
+    } else {
+        CircuitState::Closed
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -628,18 +1149,74 @@ mod tests {
         let mut limiter = RateLimiter::new(clock);
         let provider = Provider::Custom;
 
-        // Initial backoff should be 1 second
-        assert_eq!(limiter.record_failure(provider), INITIAL_RETRY_DELAY_MS);
+        // Initial backoff should be up to 1 second
+        let delay = limiter.record_failure(provider);
+        assert!((INITIAL_RETRY_DELAY_MS / 2..=INITIAL_RETRY_DELAY_MS).contains(&delay));
 
-        // Second failure should double the backoff
-        assert_eq!(limiter.record_failure(provider), INITIAL_RETRY_DELAY_MS * 2);
+        // Each failure doubles the upper bound of the jittered delay
+        let delay = limiter.record_failure(provider);
+        assert!((INITIAL_RETRY_DELAY_MS..=INITIAL_RETRY_DELAY_MS * 2).contains(&delay));
+        let delay = limiter.record_failure(provider);
+        assert!((INITIAL_RETRY_DELAY_MS * 2..=INITIAL_RETRY_DELAY_MS * 4).contains(&delay));
 
-        // Third failure should double again
-        assert_eq!(limiter.record_failure(provider), INITIAL_RETRY_DELAY_MS * 4);
+        // The upper bound stops at the maximum
+        for _ in 0..10 {
+            let delay = limiter.record_failure(provider);
+            assert!(delay <= MAX_RETRY_DELAY_MS);
+        }
+        assert_eq!(limiter.providers[&provider].last_backoff_delay_ms, MAX_RETRY_DELAY_MS);
 
         // Success should reset the backoff
         limiter.record_success(provider);
-        assert_eq!(limiter.record_failure(provider), INITIAL_RETRY_DELAY_MS);
+        assert!(limiter.record_failure(provider) <= INITIAL_RETRY_DELAY_MS);
+    }
+
+    #[test]
+    fn test_backoff_jitter_spreads_retries() {
+        let delays: std::collections::HashSet<u64> = (0..20).map(|_| jitter(MAX_RETRY_DELAY_MS)).collect();
+        assert!(delays.iter().all(|delay| (MAX_RETRY_DELAY_MS / 2..=MAX_RETRY_DELAY_MS).contains(delay)));
+        assert!(delays.len() > 1);
+
+        // The first retry is spread out too
+        let delays: std::collections::HashSet<u64> = (0..20).map(|_| jitter(INITIAL_RETRY_DELAY_MS)).collect();
+        assert!(delays.iter().all(|delay| (INITIAL_RETRY_DELAY_MS / 2..=INITIAL_RETRY_DELAY_MS).contains(delay)));
+        assert!(delays.len() > 1);
+        assert_eq!(jitter(0), 0);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(clock.clone());
+        let provider = Provider::OpenAI;
+
+        for _ in 1..CIRCUIT_FAILURE_THRESHOLD {
+            limiter.record_failure(provider);
+        }
+        assert_eq!(limiter.circuit_state(provider), CircuitState::Closed);
+        assert!(limiter.check_rate_limit(provider));
+
+        // Reaching the threshold opens the circuit
+        limiter.record_failure(provider);
+        assert_eq!(limiter.circuit_state(provider), CircuitState::Open);
+        assert!(!limiter.check_rate_limit(provider));
+
+        // After the cooldown a single trial request is let through
+        clock.advance(CIRCUIT_COOLDOWN);
+        assert_eq!(limiter.circuit_state(provider), CircuitState::HalfOpen);
+        assert!(limiter.check_rate_limit(provider));
+        assert!(!limiter.check_rate_limit(provider));
+
+        // A failed trial reopens the circuit
+        limiter.record_failure(provider);
+        assert_eq!(limiter.circuit_state(provider), CircuitState::Open);
+
+        // A successful trial closes it
+        clock.advance(CIRCUIT_COOLDOWN);
+        assert!(limiter.check_rate_limit(provider));
+        limiter.record_success(provider);
+        assert_eq!(limiter.circuit_state(provider), CircuitState::Closed);
+        assert!(limiter.check_rate_limit(provider));
     }
 
     #[test]
@@ -652,6 +1229,9 @@ mod tests {
         
         // Test OpenAI limit (60 RPM)
         assert_eq!(limiter.providers[&Provider::OpenAI].rpm_limit, 60);
+        
+        // Test Azure OpenAI limit (60 RPM)
+        assert_eq!(limiter.providers[&Provider::AzureOpenAI].rpm_limit, 60);
 
         // Test custom provider (default limit)
         assert_eq!(limiter.providers[&Provider::Custom].rpm_limit, DEFAULT_RPM_LIMIT);
@@ -696,4 +1276,95 @@ mod tests {
         assert!(total_requests > 0);
         assert!(total_requests <= 1000); // Should not exceed the rate limit
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_state_round_trip_discards_stale_requests() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(clock.clone());
+        let provider = Provider::OpenAI;
+
+        for _ in 0..3 {
+            limiter.providers.get_mut(&provider).unwrap().record_request(&clock);
+        }
+
+        let now_ms = 1_700_000_000_000;
+        let mut state = limiter.snapshot(now_ms);
+        assert_eq!(state.requests["openai"].len(), 3);
+
+        // Add a request from two minutes ago, which is outside the window
+        state.requests.get_mut("openai").unwrap().push(now_ms - 120_000);
+
+        let mut restored = RateLimiter::new(MockClock::new());
+        restored.restore(state, now_ms);
+        assert_eq!(restored.providers[&provider].requests.len(), 3);
+        assert!(restored.providers[&Provider::Anthropic].requests.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let path = std::env::temp_dir().join(format!("rate_state_test_{}.json", std::process::id()));
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(clock.clone());
+        limiter.providers.get_mut(&Provider::Anthropic).unwrap().record_request(&clock);
+        write_state(&path, &limiter.snapshot(epoch_millis()));
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&format!("{}.", file_name)))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        let mut loaded = RateLimiter::new(MockClock::new());
+        loaded.load_state(&path);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.providers[&Provider::Anthropic].requests.len(), 1);
+        assert!(loaded.providers[&Provider::OpenAI].requests.is_empty());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(clock.clone());
+        let provider = Provider::Anthropic;
+
+        // Without a token limit everything is allowed
+        assert!(limiter.check_token_limit(provider, 1_000_000));
+
+        limiter.providers.get_mut(&provider).unwrap().tpm_limit = Some(1000);
+
+        // An oversized request is allowed when nothing has been spent yet
+        assert!(limiter.check_token_limit(provider, 5000));
+
+        limiter.providers.get_mut(&provider).unwrap().record_tokens(&clock, 600);
+        assert!(limiter.check_token_limit(provider, 400));
+        assert!(!limiter.check_token_limit(provider, 401));
+
+        // Tokens expire with the sliding window
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(limiter.providers.get_mut(&provider).unwrap().get_current_tpm(&clock), 0);
+        assert!(limiter.check_token_limit(provider, 900));
+    }
+
+    #[test]
+    fn test_token_wait() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(clock.clone());
+        let provider = Provider::OpenAI;
+
+        assert_eq!(limiter.token_wait(provider), Duration::ZERO);
+
+        limiter.providers.get_mut(&provider).unwrap().record_tokens(&clock, 600);
+        clock.advance(Duration::from_secs(20));
+        limiter.providers.get_mut(&provider).unwrap().record_tokens(&clock, 300);
+
+        // The budget starts to free up when the oldest tokens leave the window
+        let wait = limiter.token_wait(provider);
+        assert!(wait > Duration::from_secs(39) && wait <= Duration::from_secs(40));
+
+        clock.advance(Duration::from_secs(41));
+        let wait = limiter.token_wait(provider);
+        assert!(wait > Duration::from_secs(18) && wait <= Duration::from_secs(19));
+    }
+}
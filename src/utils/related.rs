@@ -0,0 +1,170 @@
+//! Finding projects similar to one another, for the `related` command.
+//!
+//! Each project's idea (its description and initial plan) is embedded with the
+//! active provider and projects are ranked by the cosine similarity of their
+//! vectors. Embeddings are cached in the project file and only recomputed when
+//! the idea or the provider changes.
+
+use crate::ai::{self, AiClient};
+use crate::config;
+use crate::error::{Result, ToolkitError};
+use crate::models::{Project, ProjectEmbedding, StageStatus};
+use crate::utils::project;
+use log::{debug, warn};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+/// A project similar to the one asked about
+#[derive(Debug, Clone, Serialize)]
+pub struct RelatedProject {
+    pub id: String,
+    pub name: String,
+    /// Cosine similarity, from -1 to 1; higher is more similar
+    pub score: f32,
+}
+
+/// The text embedded for a project: its description and, once written, its initial plan
+pub fn embedding_text(project: &Project) -> String {
+    match project.get_stage(1).filter(|s| s.status == StageStatus::Completed).and_then(|s| s.content.as_ref()) {
+        Some(plan) => format!("{}\n\n{}", project.description, plan),
+        None => project.description.clone(),
+    }
+}
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Cosine similarity of two vectors
+///
+/// Vectors of different lengths or with no magnitude have nothing in common,
+/// so they score 0.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Rank candidates by similarity to `target`, most similar first, keeping the top `top`
+pub fn rank_related(target: &[f32], candidates: &[(&Project, Vec<f32>)], top: usize) -> Vec<RelatedProject> {
+    let mut related: Vec<RelatedProject> = candidates
+        .iter()
+        .map(|(project, vector)| RelatedProject {
+            id: project.id.clone(),
+            name: project.name.clone(),
+            score: cosine_similarity(target, vector),
+        })
+        .collect();
+
+    related.sort_by(|a, b| b.score.total_cmp(&a.score));
+    related.truncate(top);
+    related
+}
+
+/// Get a project's embedding, from its cache when still current
+async fn project_embedding(client: &dyn AiClient, provider: &str, project: &Project) -> Result<Vec<f32>> {
+    let text = embedding_text(project);
+    let source_hash = text_hash(&text);
+
+    if let Some(cached) = &project.embedding {
+        if cached.provider == provider && cached.source_hash == source_hash {
+            return Ok(cached.vector.clone());
+        }
+    }
+
+    debug!("Embedding project {}", project.id);
+    let vector = client.embed(&text).await?;
+    let embedding = ProjectEmbedding {
+        provider: provider.to_string(),
+        source_hash,
+        vector: vector.clone(),
+    };
+    if let Err(e) = project::save_embedding(project, embedding) {
+        warn!("Failed to cache the embedding of project {}: {}", project.id, e);
+    }
+
+    Ok(vector)
+}
+
+/// Find the `top` projects most similar to `project_id`
+pub async fn find_related(project_id: &str, top: usize) -> Result<Vec<RelatedProject>> {
+    let target = project::load_project_async(project_id).await?;
+    let provider = config::get_config()?.provider;
+    let client = ai::get_cached_client().await?;
+
+    let target_vector = project_embedding(client.as_ref(), &provider, &target).await?;
+
+    // A project can be found twice when the current directory is the projects directory
+    let mut seen = HashSet::from([target.id.clone()]);
+    let others: Vec<Project> = project::get_all_projects_async()
+        .await?
+        .into_iter()
+        .filter(|p| seen.insert(p.id.clone()))
+        .collect();
+    if others.is_empty() {
+        return Err(ToolkitError::InvalidInput(
+            "There are no other projects to compare with".to_string(),
+        ));
+    }
+
+    let mut candidates = Vec::with_capacity(others.len());
+    for other in &others {
+        candidates.push((other, project_embedding(client.as_ref(), &provider, other).await?));
+    }
+
+    Ok(rank_related(&target_vector, &candidates, top))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn project(id: &str) -> Project {
+        Project::new(id.to_string(), id.to_string(), "Test".to_string(), PathBuf::from("/tmp").join(id))
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_rank_related_orders_by_score() {
+        let (near, far, opposite) = (project("near"), project("far"), project("opposite"));
+        let candidates = vec![
+            (&far, vec![0.0, 1.0]),
+            (&opposite, vec![-1.0, 0.0]),
+            (&near, vec![0.9, 0.1]),
+        ];
+
+        let related = rank_related(&[1.0, 0.0], &candidates, 2);
+        let ids: Vec<&str> = related.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["near", "far"]);
+    }
+
+    #[test]
+    fn test_embedding_text_includes_completed_plan() {
+        let mut project = project("demo");
+        assert_eq!(embedding_text(&project), "Test");
+
+        project.update_stage(1, "The plan".to_string(), StageStatus::Completed);
+        assert_eq!(embedding_text(&project), "Test\n\nThe plan");
+    }
+}
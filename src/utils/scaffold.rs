@@ -0,0 +1,130 @@
+//! Scaffolds: starting points for recurring kinds of project.
+//!
+//! A scaffold is a TOML file in the `scaffolds/` folder of the config
+//! directory with a starter description, tags and context values saved with
+//! the new project. `init --template <name>` reads `<name>.toml` from there,
+//! falling back to the built-in [`DEFAULT_SCAFFOLDS`].
+
+use crate::config;
+use crate::error::{Result, ToolkitError};
+use lazy_static::lazy_static;
+use log::debug;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+/// Folder of the config directory holding user scaffolds
+pub const SCAFFOLDS_DIR: &str = "scaffolds";
+
+/// What a new project starts with when created from a scaffold
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Scaffold {
+    /// Description used when the project is created without one
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Context values saved with the project, e.g. `extra_instructions`
+    #[serde(default)]
+    pub context: HashMap<String, String>,
+}
+
+lazy_static! {
+    /// Built-in scaffolds, used when the scaffolds folder has no file of that name
+    pub static ref DEFAULT_SCAFFOLDS: HashMap<&'static str, &'static str> = {
+        let mut m = HashMap::new();
+
+        m.insert("web-app", r#"description = "A web application with a browser front end and an HTTP API back end."
+tags = ["web"]
+
+[context]
+extra_instructions = "Target current desktop and mobile browsers. Cover authentication, the API between front end and back end, hosting and responsive layouts."
+"#);
+
+        m.insert("cli-tool", r#"description = "A command-line tool distributed as a single binary."
+tags = ["cli"]
+
+[context]
+extra_instructions = "Design it as a single binary with subcommands, helpful --help output, meaningful exit codes and an option for machine-readable output."
+"#);
+
+        m
+    };
+}
+
+/// Parse a scaffold file
+pub fn parse_scaffold(name: &str, content: &str) -> Result<Scaffold> {
+    toml::from_str(content)
+        .map_err(|e| ToolkitError::Parse(format!("Invalid scaffold '{}': {}", name, e)))
+}
+
+/// Load a scaffold by name, preferring the user's file over the built-in one
+pub fn load_scaffold(name: &str) -> Result<Scaffold> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(ToolkitError::InvalidInput(format!(
+            "Invalid scaffold name '{}': use letters, digits, '-' and '_'",
+            name
+        )));
+    }
+
+    let path = config::get_config_dir()?.join(SCAFFOLDS_DIR).join(format!("{}.toml", name));
+    if path.is_file() {
+        debug!("Loading scaffold from {}", path.display());
+        let content = fs::read_to_string(&path)
+            .map_err(|e| ToolkitError::File(format!("Failed to read scaffold {}: {}", path.display(), e)))?;
+        return parse_scaffold(name, &content);
+    }
+
+    match DEFAULT_SCAFFOLDS.get(name) {
+        Some(content) => parse_scaffold(name, content),
+        None => Err(ToolkitError::InvalidInput(format!(
+            "Scaffold '{}' not found. Available scaffolds: {}",
+            name,
+            scaffold_names().join(", ")
+        ))),
+    }
+}
+
+/// Names of the built-in scaffolds and those in the scaffolds folder, sorted
+pub fn scaffold_names() -> Vec<String> {
+    let mut names: Vec<String> = DEFAULT_SCAFFOLDS.keys().map(|name| name.to_string()).collect();
+
+    if let Ok(entries) = config::get_config_dir().and_then(|dir| Ok(fs::read_dir(dir.join(SCAFFOLDS_DIR))?)) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+    }
+
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_scaffolds_parse() {
+        for (name, content) in DEFAULT_SCAFFOLDS.iter() {
+            let scaffold = parse_scaffold(name, content).unwrap();
+            assert!(!scaffold.description.is_empty(), "{} has no description", name);
+            assert!(scaffold.context.contains_key("extra_instructions"), "{} has no instructions", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_scaffold() {
+        let scaffold = parse_scaffold("mobile", "tags = [\"ios\", \"android\"]\n\n[context]\nstyle = \"concise\"\n").unwrap();
+        assert_eq!(scaffold.description, "");
+        assert_eq!(scaffold.tags, vec!["ios", "android"]);
+        assert_eq!(scaffold.context["style"], "concise");
+
+        assert!(matches!(parse_scaffold("typo", "tag = [\"web\"]"), Err(ToolkitError::Parse(_))));
+    }
+}
@@ -0,0 +1,123 @@
+//! Aggregate statistics across all projects, for the `stats` command.
+
+use crate::models::{Project, StageStatus, TokenUsage};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// A project referenced from the summary
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectRef {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Project> for ProjectRef {
+    fn from(project: &Project) -> Self {
+        Self {
+            id: project.id.clone(),
+            name: project.name.clone(),
+            created_at: project.created_at,
+        }
+    }
+}
+
+/// How many projects have completed a stage
+#[derive(Debug, Clone, Serialize)]
+pub struct StageCompletion {
+    pub number: u8,
+    pub name: String,
+    /// Projects that have completed the stage
+    pub completed: usize,
+    /// Projects that have the stage at all
+    pub projects: usize,
+}
+
+/// Summary of every project
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioStats {
+    pub total_projects: usize,
+    pub archived_projects: usize,
+    pub completed_stages: usize,
+    pub total_stages: usize,
+    pub oldest: Option<ProjectRef>,
+    pub newest: Option<ProjectRef>,
+    /// Tokens recorded for completed stages; streamed runs don't report usage
+    pub tokens: TokenUsage,
+    pub stages: Vec<StageCompletion>,
+}
+
+/// Summarize a set of projects
+///
+/// Projects found twice (e.g. when the current directory is the projects
+/// directory) are counted once.
+pub fn summarize(projects: &[Project]) -> PortfolioStats {
+    let mut seen = HashSet::new();
+    let projects: Vec<&Project> = projects.iter().filter(|p| seen.insert(p.id.as_str())).collect();
+
+    let mut tokens = TokenUsage::default();
+    let mut stages: BTreeMap<u8, StageCompletion> = BTreeMap::new();
+
+    for stage in projects.iter().flat_map(|p| &p.stages) {
+        let entry = stages.entry(stage.number).or_insert_with(|| StageCompletion {
+            number: stage.number,
+            name: stage.name.clone(),
+            completed: 0,
+            projects: 0,
+        });
+        entry.projects += 1;
+
+        if stage.status == StageStatus::Completed {
+            entry.completed += 1;
+            if let Some(usage) = stage.tokens_used {
+                tokens.input_tokens += usage.input_tokens;
+                tokens.output_tokens += usage.output_tokens;
+            }
+        }
+    }
+
+    PortfolioStats {
+        total_projects: projects.len(),
+        archived_projects: projects.iter().filter(|p| p.archived).count(),
+        completed_stages: stages.values().map(|s| s.completed).sum(),
+        total_stages: stages.values().map(|s| s.projects).sum(),
+        oldest: projects.iter().min_by_key(|p| p.created_at).map(|p| ProjectRef::from(*p)),
+        newest: projects.iter().max_by_key(|p| p.created_at).map(|p| ProjectRef::from(*p)),
+        tokens,
+        stages: stages.into_values().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn project(id: &str) -> Project {
+        Project::new(id.to_string(), id.to_string(), "Test".to_string(), PathBuf::from("/tmp").join(id))
+    }
+
+    #[test]
+    fn test_summarize_counts_stages_and_tokens() {
+        let mut first = project("first");
+        first.update_stage(1, "plan".to_string(), StageStatus::Completed);
+        first.record_stage_metrics(1, Some(TokenUsage { input_tokens: 100, output_tokens: 50 }), 10);
+        let mut second = project("second");
+        second.created_at = first.created_at + chrono::Duration::days(1);
+        second.update_stage(1, "plan".to_string(), StageStatus::Completed);
+        second.update_stage(2, "design".to_string(), StageStatus::Completed);
+
+        let stats = summarize(&[first.clone(), second, first]);
+
+        assert_eq!(stats.total_projects, 2);
+        assert_eq!(stats.completed_stages, 3);
+        assert_eq!(stats.tokens.total(), 150);
+        assert_eq!(stats.oldest.unwrap().id, "first");
+        assert_eq!(stats.newest.unwrap().id, "second");
+
+        let stage1 = &stats.stages[0];
+        assert_eq!((stage1.number, stage1.completed, stage1.projects), (1, 2, 2));
+        assert_eq!(stats.stages[1].completed, 1);
+    }
+}
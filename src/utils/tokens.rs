@@ -0,0 +1,63 @@
+//! Token count estimates.
+//!
+//! Everything that needs to know how many tokens some text will use, such as
+//! tokens-per-minute rate limiting, goes through [`estimate_tokens`] so the
+//! estimates agree with each other.
+
+use log::warn;
+use std::panic::{self, AssertUnwindSafe};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+/// Approximate number of characters per token, for models without a known tokenizer
+pub const CHARS_PER_TOKEN: usize = 4;
+
+/// Estimate how many tokens `text` uses with `model`
+///
+/// OpenAI models are counted exactly with their tiktoken encoding. Other
+/// models (Anthropic, Ollama, OpenAI-compatible presets) don't publish a
+/// tokenizer, so their count is estimated from the number of characters.
+/// Unknown models fall back to the same estimate rather than failing.
+pub fn estimate_tokens(text: &str, model: &str) -> usize {
+    if text.is_empty() {
+        return 0;
+    }
+
+    if let Some(bpe) = bpe_for_model(model) {
+        match panic::catch_unwind(AssertUnwindSafe(|| bpe.encode_ordinary(text).len())) {
+            Ok(count) => return count,
+            Err(_) => warn!("Tokenizer for {} failed, estimating tokens from characters instead", model),
+        }
+    }
+
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Get the tiktoken encoding for an OpenAI model, if it has one
+fn bpe_for_model(model: &str) -> Option<&'static CoreBPE> {
+    let tokenizer = get_tokenizer(model)?;
+
+    // Loading an encoding can only fail on a broken build of tiktoken-rs, but
+    // an estimate isn't worth crashing over
+    panic::catch_unwind(|| match tokenizer {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+    })
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens("", "gpt-4o"), 0);
+        assert_eq!(estimate_tokens("hello world", "gpt-4o"), 2);
+        assert_eq!(estimate_tokens("hello world", "claude-3-7-sonnet-20250219"), 3);
+        assert_eq!(estimate_tokens("hello world", "not-a-model"), 3);
+    }
+}
@@ -1,5 +1,34 @@
 use colored::Colorize;
-use std::io::{self, Write};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Whether progress indicators may be drawn; cleared by `--quiet` and `--format json`
+static PROGRESS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Whether yes/no questions are answered yes without asking; set by `--yes`
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable progress indicators for the rest of the run
+pub fn set_progress_enabled(enabled: bool) {
+    PROGRESS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Answer yes to every [`prompt_yes_no`] for the rest of the run
+pub fn set_assume_yes(enabled: bool) {
+    ASSUME_YES.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether `--yes` was given
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Whether questions can be asked, i.e. stdin is a terminal
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal()
+}
 
 /// Print a success message
 pub fn print_success(message: &str) {
@@ -26,19 +55,53 @@ pub fn print_stage_header(stage_number: u8, name: &str) {
     println!("\n{}", format!(">>> Stage {}: {} <<<", stage_number, name).green().bold());
 }
 
+/// Print Markdown styled for the terminal, with headings, lists and code blocks
+pub fn print_markdown(markdown: &str) {
+    let mut skin = termimad::MadSkin::default();
+    skin.set_headers_fg(termimad::crossterm::style::Color::Green);
+    skin.print_text(markdown);
+}
+
 /// Prompt the user for input with a message
+///
+/// Ctrl-C exits the process while waiting for the answer.
 pub fn prompt(message: &str) -> io::Result<String> {
     print!("{} ", message);
     io::stdout().flush()?;
     
+    let _waiting = super::cancel::waiting_for_input();
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
     
     Ok(input.trim().to_string())
 }
 
+/// Prompt the user for a line of input, failing once stdin has no more to give
+fn prompt_line(message: &str) -> io::Result<String> {
+    print!("{} ", message);
+    io::stdout().flush()?;
+    
+    let _waiting = super::cancel::waiting_for_input();
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no answer was given before stdin closed"));
+    }
+    
+    Ok(input.trim().to_string())
+}
+
 /// Prompt the user for a yes/no answer
+///
+/// Returns `true` without asking under `--yes`, and `default` without asking
+/// when stdin isn't a terminal, so scripts never wait for an answer.
 pub fn prompt_yes_no(message: &str, default: bool) -> io::Result<bool> {
+    if assume_yes() {
+        return Ok(true);
+    }
+    if !is_interactive() {
+        return Ok(default);
+    }
+    
     let prompt_suffix = if default { "[Y/n]" } else { "[y/N]" };
     let full_prompt = format!("{} {}", message, prompt_suffix);
     
@@ -60,7 +123,7 @@ pub fn prompt_select<T: AsRef<str>>(message: &str, options: &[T]) -> io::Result<
     }
     
     loop {
-        let input = prompt("Enter your choice (number):")?;
+        let input = prompt_line("Enter your choice (number):")?;
         
         match input.parse::<usize>() {
             Ok(n) if n >= 1 && n <= options.len() => return Ok(n - 1),
@@ -72,13 +135,35 @@ pub fn prompt_select<T: AsRef<str>>(message: &str, options: &[T]) -> io::Result<
     }
 }
 
+/// Open a file in the user's editor and wait for it to close
+///
+/// Uses `$VISUAL`, then `$EDITOR`, falling back to `vi`.
+pub fn open_in_editor(path: &std::path::Path) -> io::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    
+    // Allow editors configured with arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()?;
+    
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("Editor '{}' exited with {}", editor, status)))
+    }
+}
+
 /// Display a spinner while executing a task
 pub async fn with_spinner<F, T, E>(message: &str, task: F) -> Result<T, E>
 where
     F: std::future::Future<Output = Result<T, E>>,
 {
-    use indicatif::{ProgressBar, ProgressStyle};
-    
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(
         ProgressStyle::default_spinner()
@@ -97,8 +182,6 @@ where
 
 /// Display progress for a task with known steps
 pub fn progress_bar(len: u64, message: &str) -> indicatif::ProgressBar {
-    use indicatif::{ProgressBar, ProgressStyle};
-    
     let pb = ProgressBar::new(len);
     pb.set_style(
         ProgressStyle::default_bar()
@@ -109,4 +192,47 @@ pub fn progress_bar(len: u64, message: &str) -> indicatif::ProgressBar {
     pb.set_message(message.to_string());
     
     pb
-} 
\ No newline at end of file
+} 
+/// Overall progress of a multi-stage run
+///
+/// Shows `[2/6] Architecture Design` with a spinner while a stage's AI call is
+/// in flight. It draws nothing when progress is disabled or stdout isn't a
+/// terminal.
+pub struct StageProgress {
+    spinner: ProgressBar,
+    total: usize,
+    position: usize,
+}
+
+impl StageProgress {
+    /// Create a progress indicator for a run of `total` stages
+    pub fn new(total: usize) -> Self {
+        let spinner = if PROGRESS_ENABLED.load(Ordering::Relaxed) && io::stdout().is_terminal() {
+            let spinner = ProgressBar::new_spinner();
+            spinner.set_style(
+                ProgressStyle::default_spinner()
+                    .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
+                    .template("{spinner} {msg}")
+                    .unwrap()
+            );
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner
+        } else {
+            ProgressBar::hidden()
+        };
+        
+        Self { spinner, total, position: 0 }
+    }
+    
+    /// Move on to the next stages; several names mean they run concurrently
+    pub fn start(&mut self, names: &[&str]) {
+        self.position = (self.position + names.len()).min(self.total);
+        self.spinner.set_message(format!("[{}/{}] {}", self.position, self.total, names.join(", ")));
+    }
+}
+
+impl Drop for StageProgress {
+    fn drop(&mut self) {
+        self.spinner.finish_and_clear();
+    }
+}
@@ -0,0 +1,107 @@
+//! Watching a file for edits, for the `watch` command.
+//!
+//! [`FileWatcher`] watches the folder holding a file rather than the file
+//! itself, because many editors save by writing a new file and renaming it
+//! over the old one. Bursts of events, like the several writes of a single
+//! save, are merged into one change by waiting until the file has been quiet
+//! for [`DEBOUNCE`].
+
+use crate::error::{Result, ToolkitError};
+use ::notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use log::{debug, warn};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long a file must go without events before a change is reported
+pub const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Reports debounced changes to a single file
+pub struct FileWatcher {
+    // Dropping the watcher stops it, so it lives as long as the receiver
+    _watcher: RecommendedWatcher,
+    events: mpsc::UnboundedReceiver<()>,
+}
+
+impl FileWatcher {
+    /// Start watching `path`
+    pub fn new(path: &Path) -> Result<Self> {
+        let folder = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| ToolkitError::InvalidInput(format!("Cannot watch {}: not a file", path.display())))?
+            .to_os_string();
+
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher = ::notify::recommended_watcher(move |event: ::notify::Result<Event>| match event {
+            Ok(event) => {
+                // Reading the file, as a stage run does, is not a change
+                let touches_file = event.paths.iter().any(|p| p.file_name() == Some(file_name.as_os_str()));
+                if touches_file && !event.kind.is_access() {
+                    debug!("File event: {:?}", event.kind);
+                    let _ = sender.send(());
+                }
+            }
+            Err(e) => warn!("File watch error: {}", e),
+        })
+        .map_err(|e| watch_error(&folder, e))?;
+
+        watcher
+            .watch(&folder, RecursiveMode::NonRecursive)
+            .map_err(|e| watch_error(&folder, e))?;
+
+        Ok(Self { _watcher: watcher, events })
+    }
+
+    /// Wait for the next change, once the file has been quiet for `debounce`
+    ///
+    /// Returns `None` if the watcher stopped.
+    pub async fn changed(&mut self, debounce: Duration) -> Option<()> {
+        debounced(&mut self.events, debounce).await
+    }
+}
+
+fn watch_error(folder: &Path, error: ::notify::Error) -> ToolkitError {
+    ToolkitError::File(format!("Failed to watch {}: {}", folder.display(), error))
+}
+
+/// Wait for an event, then for `quiet` to pass without another one
+async fn debounced(events: &mut mpsc::UnboundedReceiver<()>, quiet: Duration) -> Option<()> {
+    events.recv().await?;
+    loop {
+        match tokio::time::timeout(quiet, events.recv()).await {
+            Ok(Some(())) => continue,
+            Ok(None) | Err(_) => return Some(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_of_events_is_one_change() {
+        let (sender, mut events) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                sender.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            sender.send(()).unwrap();
+        });
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(debounced(&mut events, DEBOUNCE).await, Some(()));
+        // The last event of the burst was at 600ms, then a second of quiet
+        assert_eq!(start.elapsed(), Duration::from_millis(1600));
+
+        assert_eq!(debounced(&mut events, DEBOUNCE).await, Some(()));
+        assert_eq!(debounced(&mut events, DEBOUNCE).await, None);
+    }
+}